@@ -0,0 +1,47 @@
+/* BS1770 -- Loudness analysis library conforming to ITU-R BS.1770
+ * Copyright 2020 Ruud van Asseldonk
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * A copy of the License has been included in the root of the repository.
+ *
+ * C interface to the bs1770 loudness meter.
+ *
+ * Samples are interleaved floats normalized to [-1.0, 1.0], in WAVE channel
+ * order (L, R, C, LFE, Ls, Rs for 5.1). Loudness is in LKFS, and -infinity
+ * for silence or when not enough audio was pushed yet.
+ */
+
+#ifndef BS1770_H
+#define BS1770_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct Bs1770Meter bs1770_meter;
+
+/* Create a meter for 1 to 8 channels at a sample rate of at least 10 Hz.
+ * Returns NULL for unsupported arguments. */
+bs1770_meter *bs1770_meter_new(uint32_t sample_rate_hz, uint32_t channels);
+
+/* Destroy a meter. Passing NULL is allowed. */
+void bs1770_meter_free(bs1770_meter *meter);
+
+/* Feed interleaved samples. Returns 0 on success, or -1 if num_samples is
+ * not a multiple of the number of channels. */
+int32_t bs1770_meter_push(bs1770_meter *meter, const float *samples, size_t num_samples);
+
+/* Loudness of the audio so far. */
+double bs1770_integrated_lkfs(const bs1770_meter *meter);
+double bs1770_momentary_lkfs(const bs1770_meter *meter);
+double bs1770_short_term_lkfs(const bs1770_meter *meter);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* BS1770_H */
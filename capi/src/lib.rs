@@ -0,0 +1,122 @@
+// BS1770 -- Loudness analysis library conforming to ITU-R BS.1770
+// Copyright 2020 Ruud van Asseldonk
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! C bindings for the `bs1770` crate.
+//!
+//! Audio is passed as interleaved `float` samples, normalized to full scale,
+//! in WAVE channel order (L, R, C, LFE, Ls, Rs for 5.1). The declarations are
+//! in `include/bs1770.h`. A meter is created with `bs1770_meter_new`, fed with
+//! `bs1770_meter_push`, queried with the `*_lkfs` functions, and destroyed
+//! with `bs1770_meter_free`. Loudness is returned as `double`, with -infinity
+//! for silence, or when not enough audio was pushed for the measurement.
+
+use std::slice;
+
+use bs1770::channels::{ChannelMap, ChannelOrder};
+use bs1770::multichannel::MultiChannelLoudnessMeter;
+use bs1770::Power;
+
+/// An opaque meter for interleaved multichannel audio.
+pub struct Bs1770Meter {
+    meter: MultiChannelLoudnessMeter,
+}
+
+fn to_lkfs(power: Option<Power>) -> f64 {
+    power.unwrap_or(Power(0.0)).loudness_lkfs() as f64
+}
+
+/// Create a meter, or return null if the sample rate or channel count is not supported.
+///
+/// The sample rate must be at least 10 Hz, and the number of channels must be
+/// between 1 and 8. The meter must be destroyed with `bs1770_meter_free`.
+#[no_mangle]
+pub extern "C" fn bs1770_meter_new(sample_rate_hz: u32, channels: u32) -> *mut Bs1770Meter {
+    if sample_rate_hz < 10 {
+        return std::ptr::null_mut()
+    }
+    let channel_map = match ChannelMap::from_order(ChannelOrder::Wave, channels) {
+        Some(map) => map,
+        None => return std::ptr::null_mut(),
+    };
+    let meter = Bs1770Meter {
+        meter: MultiChannelLoudnessMeter::new(sample_rate_hz, channel_map),
+    };
+    Box::into_raw(Box::new(meter))
+}
+
+/// Destroy a meter created with `bs1770_meter_new`. Passing null is allowed.
+///
+/// # Safety
+///
+/// The meter must have been returned by `bs1770_meter_new`, and must not be
+/// used after this call.
+#[no_mangle]
+pub unsafe extern "C" fn bs1770_meter_free(meter: *mut Bs1770Meter) {
+    if !meter.is_null() {
+        drop(Box::from_raw(meter));
+    }
+}
+
+/// Feed `num_samples` interleaved samples. Returns 0 on success.
+///
+/// Returns -1, and ignores the samples, if the meter is null, or if the number
+/// of samples is not a multiple of the number of channels.
+///
+/// # Safety
+///
+/// The meter must be valid, and `samples` must point to `num_samples` floats,
+/// or be null if `num_samples` is zero.
+#[no_mangle]
+pub unsafe extern "C" fn bs1770_meter_push(
+    meter: *mut Bs1770Meter,
+    samples: *const f32,
+    num_samples: usize,
+) -> i32 {
+    let meter = match meter.as_mut() {
+        Some(m) => m,
+        None => return -1,
+    };
+    if num_samples == 0 {
+        return 0
+    }
+    if samples.is_null() || !num_samples.is_multiple_of(meter.meter.channel_map().len()) {
+        return -1
+    }
+    let samples = slice::from_raw_parts(samples, num_samples);
+    meter.meter.push_interleaved(samples);
+    0
+}
+
+/// Return the integrated loudness of the audio so far, in LKFS.
+///
+/// # Safety
+///
+/// The meter must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn bs1770_integrated_lkfs(meter: *const Bs1770Meter) -> f64 {
+    to_lkfs((*meter).meter.integrated())
+}
+
+/// Return the momentary loudness (the last 400ms), in LKFS.
+///
+/// # Safety
+///
+/// The meter must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn bs1770_momentary_lkfs(meter: *const Bs1770Meter) -> f64 {
+    to_lkfs((*meter).meter.momentary())
+}
+
+/// Return the short-term loudness (the last 3s), in LKFS.
+///
+/// # Safety
+///
+/// The meter must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn bs1770_short_term_lkfs(meter: *const Bs1770Meter) -> f64 {
+    to_lkfs((*meter).meter.short_term())
+}
@@ -0,0 +1,133 @@
+// BS1770 -- Loudness analysis library conforming to ITU-R BS.1770
+// Copyright 2020 Ruud van Asseldonk
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! This example measures the loudness of the default audio input device, such
+//! as a microphone or a loopback device, with cpal. Once per second it prints
+//! the momentary (400ms), short-term (3s), and integrated loudness so far:
+//!
+//!     cargo run --release --features cpal --example cpalmeter
+//!
+//! The audio callback only copies the samples and sends them to the main
+//! thread, which does the measurement, so the callback never blocks.
+
+extern crate bs1770;
+extern crate cpal;
+
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use bs1770::channels::{ChannelMap, ChannelOrder};
+use bs1770::multichannel::MultiChannelLoudnessMeter;
+use bs1770::Power;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+fn format_lkfs(power: Option<Power>) -> String {
+    match power {
+        Some(p) if p.0 > 0.0 => format!("{:5.1} LKFS", p.loudness_lkfs()),
+        _ => "  -inf LKFS".to_string(),
+    }
+}
+
+fn main() {
+    let host = cpal::default_host();
+    let device = match host.default_input_device() {
+        Some(device) => device,
+        None => {
+            eprintln!("No audio input device available.");
+            std::process::exit(1);
+        }
+    };
+    let config = match device.default_input_config() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("Failed to get the input configuration: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let sample_rate_hz = config.sample_rate().0;
+    let num_channels = config.channels() as u32;
+    let channel_map = match ChannelMap::from_order(ChannelOrder::Wave, num_channels) {
+        Some(map) => map,
+        None => {
+            eprintln!("Unsupported number of channels: {}.", num_channels);
+            std::process::exit(1);
+        }
+    };
+    eprintln!(
+        "Measuring {} at {} Hz, {} channels.",
+        device.name().unwrap_or_else(|_| "input".to_string()),
+        sample_rate_hz,
+        num_channels,
+    );
+
+    // The callbacks deliver interleaved samples, in whole frames.
+    let (sender, receiver) = mpsc::channel::<Vec<f32>>();
+    let on_error = |err| eprintln!("Error on the input stream: {}", err);
+    let stream_config = config.config();
+    let stream = match config.sample_format() {
+        cpal::SampleFormat::F32 => device.build_input_stream(
+            &stream_config,
+            move |data: &[f32], _: &_| { let _ = sender.send(data.to_vec()); },
+            on_error,
+            None,
+        ),
+        cpal::SampleFormat::I16 => device.build_input_stream(
+            &stream_config,
+            move |data: &[i16], _: &_| {
+                let _ = sender.send(data.iter().map(|&x| x as f32 / 32768.0).collect());
+            },
+            on_error,
+            None,
+        ),
+        cpal::SampleFormat::I32 => device.build_input_stream(
+            &stream_config,
+            move |data: &[i32], _: &_| {
+                let _ = sender.send(data.iter().map(|&x| x as f32 / 2147483648.0).collect());
+            },
+            on_error,
+            None,
+        ),
+        format => {
+            eprintln!("Unsupported sample format: {:?}.", format);
+            std::process::exit(1);
+        }
+    };
+    let stream = match stream {
+        Ok(stream) => stream,
+        Err(err) => {
+            eprintln!("Failed to open the input stream: {}", err);
+            std::process::exit(1);
+        }
+    };
+    if let Err(err) = stream.play() {
+        eprintln!("Failed to start the input stream: {}", err);
+        std::process::exit(1);
+    }
+
+    let mut meter = MultiChannelLoudnessMeter::new(sample_rate_hz, channel_map);
+    let mut next_report = Instant::now() + Duration::from_secs(1);
+
+    loop {
+        let timeout = next_report.saturating_duration_since(Instant::now());
+        match receiver.recv_timeout(timeout) {
+            Ok(samples) => meter.push_interleaved(&samples),
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+
+        if Instant::now() >= next_report {
+            println!(
+                "M {}   S {}   I {}",
+                format_lkfs(meter.momentary()),
+                format_lkfs(meter.short_term()),
+                format_lkfs(meter.integrated()),
+            );
+            next_report += Duration::from_secs(1);
+        }
+    }
+}
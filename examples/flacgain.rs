@@ -7,251 +7,1558 @@
 
 extern crate bs1770;
 extern crate claxon;
+extern crate hound;
 
+use std::error::Error;
 use std::str::FromStr;
+use std::fmt;
 use std::fs;
-use std::io::{Read, Seek, Write};
+use std::mem;
+use std::io::Write;
 use std::io;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 
 use claxon::FlacReader;
 use bs1770::{Power, Windows100ms};
+use bs1770::album;
+use bs1770::album::AlbumAccumulator;
+use bs1770::ballistics;
+use bs1770::flac_tags;
+use bs1770::channels::{ChannelMap, ChannelOrder};
+use bs1770::replaygain;
+use bs1770::tags;
+use bs1770::trace;
+use bs1770::true_peak::TruePeakMeter;
 
 /// Loudness measurement for a track, and the flac reader that wraps the file.
 struct TrackResult {
-    reader: FlacReader<fs::File>,
+    /// The reader for FLAC files, to check and write the tags. Other formats
+    /// are only measured, so they have no reader.
+    reader: Option<FlacReader<fs::File>>,
     windows: Windows100ms<Vec<Power>>,
     gated_power: Power,
+
+    /// The largest absolute sample value, where 1.0 is full scale.
+    sample_peak: f32,
+
+    /// The true peak over all channels, where 1.0 is full scale. This is only
+    /// measured when the output template needs it, and zero otherwise.
+    true_peak: f32,
+
+    /// The duration of the measured audio.
+    duration: Duration,
+
+    /// Whether the loudness was taken from the tags, rather than measured.
+    /// Then there are no windows, and the sample peak is unknown.
+    from_tags: bool,
 }
 
 /// Loudness measurement for a collection of tracks.
 struct AlbumResult {
-    /// File name, loudness, and original reader, for each track.
-    tracks: Vec<(PathBuf, Power, FlacReader<fs::File>)>,
+    /// File name, loudness, sample peak, and original reader (for FLAC files), for each track.
+    tracks: Vec<(PathBuf, Power, f32, Option<FlacReader<fs::File>>)>,
 
     /// Loudness for all tracks concatenated.
     gated_power: Power,
+
+    /// The largest sample peak of all tracks.
+    sample_peak: f32,
+
+    /// The windows of every track, in the same order as `tracks`.
+    track_windows: Vec<Windows100ms<Vec<Power>>>,
+
+    /// The true peak of every track, in the same order as `tracks`.
+    track_true_peaks: Vec<f32>,
 }
 
 impl AlbumResult {
     /// Print a summary of the loudness analysis, per track and for the album.
-    fn print(&self) {
-        for &(ref path, track_gated_power, ref _reader) in &self.tracks {
-            println!(
-                "{:>5.1} LKFS  {}",
+    fn print(&self, format: &OutputFormat, highlight: Highlight) {
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        let result = match format {
+            OutputFormat::Text => self.write_summary(&mut out, highlight),
+            OutputFormat::Integer => self.write_summary_integer(&mut out),
+            OutputFormat::Json => self.write_summary_json(&mut out),
+            OutputFormat::Template(template) => self.write_summary_template(&mut out, template),
+        };
+        result.expect("Failed to write to stdout.");
+    }
+
+    /// Write a summary of the loudness analysis, per track and for the album.
+    fn write_summary<W: Write>(&self, out: &mut W, highlight: Highlight) -> io::Result<()> {
+        for (&(ref path, track_gated_power, _, ref _reader), &track_true_peak) in
+            self.tracks.iter().zip(&self.track_true_peaks)
+        {
+            writeln!(
+                out,
+                "{}{:>5.1} LKFS  {}{}",
+                highlight.start(track_gated_power, track_true_peak, false),
                 track_gated_power.loudness_lkfs(),
                 path
                     .file_name()
                     .expect("We decoded this file, it should have a name.")
                     .to_string_lossy(),
-            );
+                highlight.end(),
+            )?;
         }
         if self.tracks.len() > 0 {
-            println!(
-                "{:>5.1} LKFS  ALBUM",
+            let album_true_peak = self.track_true_peaks.iter().fold(0.0_f32, |peak, &p| peak.max(p));
+            writeln!(
+                out,
+                "{}{:>5.1} LKFS  ALBUM{}",
+                highlight.start(self.gated_power, album_true_peak, true),
                 self.gated_power.loudness_lkfs(),
-            );
+                highlight.end(),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Write the summary with loudness and peaks in integer thousandths of a decibel.
+    ///
+    /// The fields are the loudness in thousandths of a loudness unit, the true
+    /// peak in thousandths of a dBTP, the sample peak in thousandths of a
+    /// dBFS, and the file name, separated by a tab. A loudness unit is a
+    /// decibel, so all three numbers have the same scale. The album line has
+    /// the maximum peaks of its tracks, and `ALBUM` in place of the file name.
+    /// Silence, which has a loudness and peak of -∞, prints as `-inf`.
+    fn write_summary_integer<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        for (&(ref path, track_gated_power, track_sample_peak, ref _reader), &track_true_peak) in
+            self.tracks.iter().zip(&self.track_true_peaks)
+        {
+            writeln!(
+                out,
+                "{}\t{}\t{}\t{}",
+                format_milli_lkfs(track_gated_power),
+                format_milli_db(track_true_peak),
+                format_milli_db(track_sample_peak),
+                path
+                    .file_name()
+                    .expect("We decoded this file, it should have a name.")
+                    .to_string_lossy(),
+            )?;
+        }
+        if !self.tracks.is_empty() {
+            let true_peak = self.track_true_peaks.iter().fold(0.0_f32, |peak, &p| peak.max(p));
+            writeln!(
+                out,
+                "{}\t{}\t{}\tALBUM",
+                format_milli_lkfs(self.gated_power),
+                format_milli_db(true_peak),
+                format_milli_db(self.sample_peak),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Write one line per track, formatted with the user-provided template.
+    fn write_summary_template<W: Write>(&self, out: &mut W, template: &[Segment]) -> io::Result<()> {
+        for (&(ref path, track_gated_power, track_sample_peak, ref _reader), &track_true_peak) in
+            self.tracks.iter().zip(&self.track_true_peaks)
+        {
+            for segment in template {
+                match *segment {
+                    Segment::Literal(ref text) => write!(out, "{}", text)?,
+                    Segment::Field(field, precision) => {
+                        let value = match field {
+                            Field::Path => {
+                                write!(out, "{}", path.to_string_lossy())?;
+                                continue
+                            }
+                            Field::Name => {
+                                let name = path
+                                    .file_name()
+                                    .expect("We decoded this file, it should have a name.");
+                                write!(out, "{}", name.to_string_lossy())?;
+                                continue
+                            }
+                            Field::Integrated => track_gated_power.loudness_lkfs(),
+                            Field::Album => self.gated_power.loudness_lkfs(),
+                            Field::Peak => 20.0 * track_sample_peak.log10(),
+                            Field::TruePeak => 20.0 * track_true_peak.log10(),
+                        };
+                        write!(out, "{:.*}", precision.unwrap_or(1), value)?;
+                    }
+                }
+            }
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+
+    /// Write the summary as a json document.
+    fn write_summary_json<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "{{")?;
+        writeln!(out, r#"  "generator": "flacgain (bs1770 {})","#, env!("CARGO_PKG_VERSION"))?;
+        writeln!(out, r#"  "tracks": ["#)?;
+        for (i, &(ref path, track_gated_power, _, ref _reader)) in self.tracks.iter().enumerate() {
+            let file_name = path
+                .file_name()
+                .expect("We decoded this file, it should have a name.")
+                .to_string_lossy();
+            writeln!(
+                out,
+                r#"    {{ "file": {}, "loudness_lkfs": {} }}{}"#,
+                json_string(&file_name),
+                json_lkfs(track_gated_power),
+                if i + 1 < self.tracks.len() { "," } else { "" },
+            )?;
+        }
+        writeln!(out, "  ],")?;
+        writeln!(out, r#"  "album": {{ "loudness_lkfs": {} }}"#, json_lkfs(self.gated_power))?;
+        writeln!(out, "}}")
+    }
+
+    /// Write a report file into every directory that contains one of the tracks.
+    ///
+    /// The report lists the track and album loudness, and the version of the
+    /// program that produced it. Its name is `loudness.txt`, or `loudness.json`
+    /// when `format` is `ReportFormat::Json`.
+    fn write_reports(&self, format: ReportFormat) -> io::Result<()> {
+        let mut dirs: Vec<&Path> = self
+            .tracks
+            .iter()
+            .filter_map(|track| track.0.parent())
+            .collect();
+        dirs.sort();
+        dirs.dedup();
+
+        for dir in dirs {
+            // The parent of a bare file name is the empty path, which refers
+            // to the working directory.
+            let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+            let report_path = match format {
+                ReportFormat::Text => dir.join("loudness.txt"),
+                ReportFormat::Json => dir.join("loudness.json"),
+            };
+            let mut out = io::BufWriter::new(fs::File::create(&report_path)?);
+            match format {
+                ReportFormat::Text => {
+                    writeln!(out, "# Generated by flacgain (bs1770 {}).", env!("CARGO_PKG_VERSION"))?;
+                    self.write_summary(&mut out, Highlight::none())?;
+                }
+                ReportFormat::Json => self.write_summary_json(&mut out)?,
+            }
+            out.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Write the loudness over time of every track next to the track.
+    ///
+    /// For `track.flac`, this writes `track.flac.loudness.csv`, or
+    /// `track.flac.loudness.json` for the json format.
+    fn write_traces(&self, format: ReportFormat) -> io::Result<()> {
+        for (track, windows) in self.tracks.iter().zip(&self.track_windows) {
+            let mut trace_path = track.0.clone().into_os_string();
+            trace_path.push(match format {
+                ReportFormat::Text => ".loudness.csv",
+                ReportFormat::Json => ".loudness.json",
+            });
+            let trace: Vec<_> = ballistics::readings(windows.as_ref()).collect();
+            let mut out = io::BufWriter::new(fs::File::create(&trace_path)?);
+            match format {
+                ReportFormat::Text => trace::write_csv(&mut out, &trace)?,
+                ReportFormat::Json => trace::write_json(&mut out, &trace)?,
+            }
+            out.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Compare the loudness stored in the tags of every track against the measurement.
+    ///
+    /// This checks the `BS17704_*_LOUDNESS` tags, and the loudness implied by
+    /// the `REPLAYGAIN_*_GAIN` tags and their reference loudness. Tracks where
+    /// a tag differs by more than `tolerance_lu`, or has a value that cannot
+    /// be parsed, are reported on stderr. Returns the number of such tracks.
+    fn verify_tags(&self, tolerance_lu: f32, log: Log) -> u32 {
+        let album_lkfs = self.gated_power.loudness_lkfs();
+        let mut num_files_verified = 0_u32;
+        let mut num_files_deviating = 0_u32;
+
+        for &(ref path, track_gated_power, _, ref reader) in &self.tracks {
+            let reader = match *reader {
+                Some(ref r) => r,
+                None => {
+                    log.info(format_args!(
+                        "Cannot verify tags of {}, only FLAC files are supported.",
+                        path.to_string_lossy(),
+                    ));
+                    continue
+                }
+            };
+            let track_lkfs = track_gated_power.loudness_lkfs();
+
+            // ReplayGain stores a gain, which implies a loudness together
+            // with the reference. Without a reference tag, assume the -18 LUFS
+            // of ReplayGain 2.0.
+            let reference_lkfs = reader
+                .get_tag("REPLAYGAIN_REFERENCE_LOUDNESS")
+                .next()
+                .and_then(tags::parse_lufs)
+                .unwrap_or(replaygain::REFERENCE_LUFS);
+            let checks = [
+                ("BS17704_TRACK_LOUDNESS", track_lkfs),
+                ("BS17704_ALBUM_LOUDNESS", album_lkfs),
+                ("REPLAYGAIN_TRACK_GAIN", track_lkfs),
+                ("REPLAYGAIN_ALBUM_GAIN", album_lkfs),
+            ];
+
+            let mut is_deviating = false;
+            for &(key, measured_lkfs) in checks.iter() {
+                let value = match reader.get_tag(key).next() {
+                    Some(v) => v,
+                    None => continue,
+                };
+                let stored_lkfs = if key.starts_with("REPLAYGAIN_") {
+                    replaygain::parse_gain(value).map(|gain_db| reference_lkfs - gain_db)
+                } else {
+                    tags::parse_lufs(value)
+                };
+                match stored_lkfs {
+                    // Silence is -∞ LKFS, the difference would be NaN there.
+                    Some(stored) if stored == measured_lkfs => {}
+                    Some(stored) if (stored - measured_lkfs).abs() <= tolerance_lu => {}
+                    Some(stored) => {
+                        log.clear_status();
+                        eprintln!(
+                            "{}: {} is {:.1} LKFS, but measured {:.1} LKFS.",
+                            path.to_string_lossy(),
+                            key,
+                            stored,
+                            measured_lkfs,
+                        );
+                        is_deviating = true;
+                    }
+                    None => {
+                        log.clear_status();
+                        eprintln!("{}: {} has invalid value {:?}.", path.to_string_lossy(), key, value);
+                        is_deviating = true;
+                    }
+                }
+            }
+
+            num_files_verified += 1;
+            if is_deviating {
+                num_files_deviating += 1;
+            }
         }
+
+        log.summary(format_args!("Verified {} files.", num_files_verified));
+
+        num_files_deviating
     }
 
     /// Write tags for the tracks that do not have the correct tags yet.
-    fn write_tags(self) -> io::Result<()> {
+    ///
+    /// Returns the number of files that could not be updated.
+    fn write_tags(self, format: TagFormat, log: Log) -> u32 {
         if self.tracks.len() == 0 {
-            return Ok(())
+            return 0
         }
 
-        let new_album_loudness_lkfs = self.gated_power.loudness_lkfs();
         let mut num_files_updated = 0_u32;
+        let mut num_files_failed = 0_u32;
+
+        for (path, track_gated_power, track_sample_peak, reader) in self.tracks {
+            let new_tags = format.tags(
+                track_gated_power,
+                track_sample_peak,
+                self.gated_power,
+                self.sample_peak,
+            );
+
+            let reader = match reader {
+                Some(r) => r,
+                None => {
+                    log.clear_status();
+                    eprintln!("Cannot write tags to {}, only FLAC files are supported.", path.to_string_lossy());
+                    num_files_failed += 1;
+                    continue
+                }
+            };
+
+            // If all tags are already present, and they are close to the
+            // values that we computed, then do not rewrite the tags.
+            let needs_update = new_tags.iter().any(|&(key, ref new_value)| {
+                match reader.get_tag(key).next() {
+                    Some(current_value) => !tag_is_close(key, current_value, new_value),
+                    None => true,
+                }
+            });
+
+            if needs_update {
+                log.status(format_args!("Updating {} ...", path.to_string_lossy()));
+                // Close the file before we replace it.
+                mem::drop(reader);
+                let result = write_new_tags(&path, &new_tags);
+                // A single broken file should not abort the entire run, report
+                // the error and continue with the next file.
+                match result {
+                    Ok(()) => num_files_updated += 1,
+                    Err(e) => {
+                        log.clear_status();
+                        eprintln!("Failed to update tags of {}: {}", path.to_string_lossy(), e);
+                        num_files_failed += 1;
+                    }
+                }
+            } else {
+                log.info(format_args!("Tags of {} are up to date.", path.to_string_lossy()));
+            }
+        }
 
-        for (path, track_gated_power, reader) in self.tracks {
-            let new_track_loudness_lkfs = track_gated_power.loudness_lkfs();
+        log.clear_status();
+        log.summary(format_args!("Updated {} files.", num_files_updated));
 
-            // If both the album loudness and track loudness are already
-            // present, and they are within 0.1 loudness unit of the value that
-            // we computed, then do not rewrite the tags.
+        num_files_failed
+    }
+}
 
-            let album_needs_update = reader
-                .get_tag("BS17704_ALBUM_LOUDNESS")
-                .next()
-                .and_then(parse_lufs)
-                .map(|current_lkfs| (new_album_loudness_lkfs - current_lkfs).abs() > 0.1)
-                .unwrap_or(true);
+/// Controls which messages get printed to stderr.
+#[derive(Copy, Clone)]
+struct Log {
+    /// 0 with `--quiet`, 1 by default, 2 with `-v`, 3 with `-vv`.
+    verbosity: u32,
 
-            let track_needs_update = reader
-                .get_tag("BS17704_TRACK_LOUDNESS")
-                .next()
-                .and_then(parse_lufs)
-                .map(|current_lkfs| (new_track_loudness_lkfs - current_lkfs).abs() > 0.1)
-                .unwrap_or(true);
-
-            if album_needs_update || track_needs_update {
-                // Clear the current line, overwite it with the new message.
-                eprint!("\x1b[2K\rUpdating {} ... ", path.to_string_lossy());
-                io::stderr().flush()?;
-                write_new_tags(
-                    &path,
-                    new_track_loudness_lkfs,
-                    new_album_loudness_lkfs,
-                    reader,
-                )?;
-                num_files_updated += 1;
+    /// Whether stderr is a terminal, where we can overwrite the status line.
+    is_tty: bool,
+}
+
+impl Log {
+    fn new(verbosity: u32) -> Log {
+        Log {
+            verbosity,
+            is_tty: is_tty(STDERR_FILENO),
+        }
+    }
+
+    /// Whether progress is shown by repeatedly overwriting a single line.
+    fn uses_status_line(&self) -> bool {
+        self.verbosity == 1 && self.is_tty
+    }
+
+    /// Report progress.
+    ///
+    /// On a terminal, this replaces the previous status message. When stderr
+    /// is not a terminal, progress is only printed with `-v`, one message per
+    /// line, so log files remain readable.
+    fn status(&self, message: fmt::Arguments) {
+        if self.uses_status_line() {
+            // Clear the current line, overwrite it with the new message.
+            eprint!("\x1b[2K\r{}", message);
+            let _ = io::stderr().flush();
+        } else if self.verbosity >= 2 {
+            eprintln!("{}", message);
+        }
+    }
+
+    /// Clear the status line, if there is one.
+    fn clear_status(&self) {
+        if self.uses_status_line() {
+            eprint!("\x1b[2K\r");
+        }
+    }
+
+    /// Print a message that is shown unless `--quiet` is passed.
+    fn summary(&self, message: fmt::Arguments) {
+        if self.verbosity >= 1 {
+            eprintln!("{}", message);
+        }
+    }
+
+    /// Print a message about a file, shown with `-v`.
+    fn info(&self, message: fmt::Arguments) {
+        if self.verbosity >= 2 {
+            eprintln!("{}", message);
+        }
+    }
+
+    /// Print details about individual windows, shown with `-vv`.
+    fn debug(&self, message: fmt::Arguments) {
+        if self.verbosity >= 3 {
+            eprintln!("{}", message);
+        }
+    }
+}
+
+/// How to highlight lines in the text output with ANSI escape codes.
+#[derive(Copy, Clone)]
+struct Highlight {
+    /// Whether to emit escape codes at all.
+    enabled: bool,
+
+    /// Loudness above which a line is printed in red.
+    target_lkfs: Option<f32>,
+
+    /// True peak level in dBTP above which a line is printed in red.
+    ceiling_dbtp: Option<f32>,
+}
+
+impl Highlight {
+    /// Do not highlight anything.
+    fn none() -> Highlight {
+        Highlight {
+            enabled: false,
+            target_lkfs: None,
+            ceiling_dbtp: None,
+        }
+    }
+
+    /// Return whether highlighting compares against the true peak, so it must be measured.
+    fn needs_true_peak(&self) -> bool {
+        self.enabled && self.ceiling_dbtp.is_some()
+    }
+
+    /// Return the escape codes to put before a line for the given loudness and linear true peak.
+    fn start(&self, power: Power, true_peak: f32, is_album: bool) -> &'static str {
+        if !self.enabled {
+            return ""
+        }
+        let is_above_target = match self.target_lkfs {
+            Some(target) => power.loudness_lkfs() > target,
+            None => false,
+        };
+        let is_above_ceiling = match self.ceiling_dbtp {
+            Some(ceiling) => 20.0 * true_peak.log10() > ceiling,
+            None => false,
+        };
+        let is_too_loud = is_above_target || is_above_ceiling;
+        match (is_too_loud, is_album) {
+            (true, true) => "\x1b[1;31m",
+            (true, false) => "\x1b[31m",
+            (false, true) => "\x1b[1m",
+            (false, false) => "",
+        }
+    }
+
+    /// Return the escape code to reset the style at the end of a line.
+    fn end(&self) -> &'static str {
+        if self.enabled { "\x1b[0m" } else { "" }
+    }
+}
+
+/// The file descriptor of stdout.
+const STDOUT_FILENO: i32 = 1;
+
+/// The file descriptor of stderr.
+const STDERR_FILENO: i32 = 2;
+
+/// Return whether the file descriptor refers to a terminal.
+#[cfg(unix)]
+fn is_tty(fd: i32) -> bool {
+    unsafe { libc::isatty(fd) == 1 }
+}
+
+/// Return whether the file descriptor refers to a terminal.
+///
+/// Outside of Unix we do not know how to detect this, and we do not know
+/// whether the terminal supports ANSI escape codes, so we assume it is not,
+/// which disables colors and the status line.
+#[cfg(not(unix))]
+fn is_tty(_fd: i32) -> bool {
+    false
+}
+
+/// Return whether stdout is a terminal that we should print colors to.
+///
+/// Colors are disabled when the `NO_COLOR` environment variable is set,
+/// see also https://no-color.org/.
+fn stdout_supports_color() -> bool {
+    let is_tty = is_tty(STDOUT_FILENO);
+    let term_is_dumb = match std::env::var_os("TERM") {
+        Some(term) => term == "dumb",
+        None => true,
+    };
+    is_tty && !term_is_dumb && std::env::var_os("NO_COLOR").is_none()
+}
+
+/// Format for the summary printed to stdout.
+enum OutputFormat {
+    /// Loudness with one decimal, for humans.
+    Text,
+    /// Loudness as integer thousandths of a loudness unit, for scripts.
+    Integer,
+    /// The same json document as `--write-report-json` writes, for scripts.
+    Json,
+    /// One line per track, formatted with a user-provided template.
+    Template(Vec<Segment>),
+}
+
+impl OutputFormat {
+    /// Return whether the output includes the true peak, so it must be measured.
+    fn needs_true_peak(&self) -> bool {
+        match *self {
+            OutputFormat::Integer => true,
+            OutputFormat::Template(ref segments) => segments
+                .iter()
+                .any(|segment| matches!(segment, Segment::Field(Field::TruePeak, _))),
+            _ => false,
+        }
+    }
+}
+
+/// A value that can be substituted into an output template.
+#[derive(Copy, Clone)]
+enum Field {
+    /// The path of the file, as passed on the command line.
+    Path,
+    /// The file name, without directory.
+    Name,
+    /// Integrated loudness of the track, in LKFS.
+    Integrated,
+    /// Integrated loudness of the album, in LKFS.
+    Album,
+    /// Sample peak of the track, in dBFS.
+    Peak,
+    /// True peak of the track, in dBTP.
+    TruePeak,
+}
+
+/// Part of an output template.
+enum Segment {
+    Literal(String),
+    /// A field, and the number of decimals to format it with, if specified.
+    Field(Field, Option<usize>),
+}
+
+/// Parse an output template such as `{path}\t{integrated:.2}`.
+///
+/// Fields are enclosed in braces, optionally followed by a precision, as in
+/// Rust format strings. Use `{{` and `}}` for literal braces. The escape
+/// sequences `\t`, `\n`, and `\\` are recognized, because shells do not
+/// interpret those in quoted arguments.
+fn parse_template(template: &str) -> Result<Vec<Segment>, String> {
+    let mut segments = Vec::new();
+    let mut literal = String::new();
+    let mut chars = template.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => match chars.next() {
+                Some('t') => literal.push('\t'),
+                Some('n') => literal.push('\n'),
+                Some('\\') => literal.push('\\'),
+                Some(c) => return Err(format!("Unknown escape sequence '\\{}'.", c)),
+                None => return Err("Template ends in a backslash.".to_string()),
+            },
+            '}' => match chars.next() {
+                Some('}') => literal.push('}'),
+                _ => return Err("Unmatched '}', use '}}' for a literal brace.".to_string()),
+            },
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                literal.push('{');
             }
+            '{' => {
+                let mut spec = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => spec.push(c),
+                        None => return Err("Unmatched '{', use '{{' for a literal brace.".to_string()),
+                    }
+                }
+                let mut parts = spec.splitn(2, ':');
+                let name = parts.next().unwrap_or("");
+                let field = match name {
+                    "path" => Field::Path,
+                    "name" => Field::Name,
+                    "integrated" => Field::Integrated,
+                    "album" => Field::Album,
+                    "peak" => Field::Peak,
+                    "true_peak" => Field::TruePeak,
+                    _ => return Err(format!("Unknown field '{{{}}}'.", name)),
+                };
+                let precision = match parts.next() {
+                    None => None,
+                    Some(p) => match p.strip_prefix('.').and_then(|n| usize::from_str(n).ok()) {
+                        Some(n) => Some(n),
+                        None => return Err(format!("Invalid precision '{}', expected e.g. '.2'.", p)),
+                    },
+                };
+                if !literal.is_empty() {
+                    segments.push(Segment::Literal(mem::take(&mut literal)));
+                }
+                segments.push(Segment::Field(field, precision));
+            }
+            c => literal.push(c),
         }
+    }
+
+    if !literal.is_empty() {
+        segments.push(Segment::Literal(literal));
+    }
 
-        // Clear the current line again, print the final status.
-        eprintln!("\x1b[2K\rUpdated {} files.", num_files_updated);
+    Ok(segments)
+}
 
-        Ok(())
+/// Format loudness as an integer number of thousandths of a loudness unit.
+///
+/// For example, -23.1 LKFS formats as `-23100`. Integers are unaffected by
+/// locale settings, and shells can compare them natively.
+fn format_milli_lkfs(power: Power) -> String {
+    let lkfs = power.loudness_lkfs();
+    if lkfs.is_finite() {
+        format!("{}", (lkfs * 1000.0).round() as i64)
+    } else {
+        "-inf".to_string()
     }
 }
 
-/// Parse a numeric value with “LUFS” suffix from a metadata tag.
-fn parse_lufs(value: &str) -> Option<f32> {
-    let num = value.strip_suffix(" LUFS")?;
-    f32::from_str(num).ok()
+/// Format a linear peak amplitude as an integer number of thousandths of a decibel.
+///
+/// For example, a peak of 0.5 formats as `-6021`, the same scale as
+/// `format_milli_lkfs`.
+fn format_milli_db(amplitude: f32) -> String {
+    let db = 20.0 * amplitude.log10();
+    if db.is_finite() {
+        format!("{}", (db * 1000.0).round() as i64)
+    } else {
+        "-inf".to_string()
+    }
 }
 
-/// Measure loudness of an album.
-fn analyze_album(paths: Vec<PathBuf>, skip_when_tags_present: bool) -> claxon::Result<AlbumResult> {
-    let mut windows = Windows100ms::new();
-    let mut tracks = Vec::with_capacity(paths.len());
+/// File format for the per-album report.
+#[derive(Copy, Clone)]
+enum ReportFormat {
+    Text,
+    Json,
+}
 
-    for path in paths {
-        // Clear the current line, overwite it with the new message.
-        eprint!("\x1b[2K\rAnalyzing {} ...", path.to_string_lossy());
-        io::stderr().flush()?;
+/// Format a string as a json string literal, including quotes.
+fn json_string(value: &str) -> String {
+    let mut result = String::with_capacity(value.len() + 2);
+    result.push('"');
+    for ch in value.chars() {
+        match ch {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+/// Format loudness as a json number, or `null` when it is not finite.
+///
+/// Json has no representation for -∞, which is the loudness of silence.
+fn json_lkfs(power: Power) -> String {
+    let lkfs = power.loudness_lkfs();
+    if lkfs.is_finite() {
+        format!("{:.3}", lkfs)
+    } else {
+        "null".to_string()
+    }
+}
+
+/// Return whether the file has the given extension, ignoring case.
+fn has_extension(path: &Path, extension: &str) -> bool {
+    match path.extension() {
+        Some(ext) => ext.eq_ignore_ascii_case(extension),
+        None => false,
+    }
+}
+
+/// The result of analyzing one file, `None` if the file was skipped.
+type AnalysisResult = Result<Option<TrackResult>, Box<dyn Error + Send + Sync>>;
 
-        let file = FlacReader::open(&path)?;
+/// Measure loudness of a single file, unless it should be skipped.
+fn analyze_track(
+    path: &Path,
+    skip_when_tags_present: bool,
+    tag_format: TagFormat,
+    range: TimeRange,
+    input: Input,
+    log: Log,
+) -> AnalysisResult {
+    log.status(format_args!("Analyzing {} ...", path.to_string_lossy()));
+
+    if path == Path::new("-") {
+        match input.raw {
+            Some(raw) => Ok(Some(analyze_raw(io::stdin().lock(), raw, range, input.true_peak)?)),
+            None => Err("Reading from stdin requires --raw, --rate, and --channels.".into()),
+        }
+    } else if has_extension(path, "flac") {
+        let file = FlacReader::open(path)?;
 
         // If the --skip-when-tags-present flag is passed, we early out on files
         // where the tag is already present, regardless of the current value.
         if skip_when_tags_present {
-            let has_track_tag = file.get_tag("bs17704_track_loudness").next().is_some();
-            let has_album_tag = file.get_tag("bs17704_album_loudness").next().is_some();
+            let [track_tag, album_tag] = tag_format.presence_tags();
+            let has_track_tag = file.get_tag(track_tag).next().is_some();
+            let has_album_tag = file.get_tag(album_tag).next().is_some();
             if has_track_tag && has_album_tag {
-                continue
+                log.info(format_args!("Skipping {}, tags are present.", path.to_string_lossy()));
+                return Ok(None)
             }
         }
 
-        let track_result = match analyze_file(file) {
-            Ok(r) => r,
-            Err(e) => {
-                eprintln!("Error while analyzing {}: {}", path.to_string_lossy(), e);
-                return Err(e);
+        if input.duration_weighted {
+            if let Some((gated_power, duration)) = loudness_from_tags(&file) {
+                let track_result = TrackResult {
+                    reader: Some(file),
+                    windows: Windows100ms::new(),
+                    gated_power,
+                    sample_peak: 0.0,
+                    true_peak: 0.0,
+                    duration,
+                    from_tags: true,
+                };
+                return Ok(Some(track_result))
             }
-        };
-        windows.inner.extend(track_result.windows.inner);
-        tracks.push((path, track_result.gated_power, track_result.reader));
+        }
+
+        Ok(Some(analyze_file(file, range, input.true_peak)?))
+    } else if has_extension(path, "wav") {
+        Ok(Some(analyze_wav_file(path, range, input.true_peak)?))
+    } else if input.ffmpeg {
+        analyze_ffmpeg(path, range, input.true_peak).map(Some)
+    } else {
+        analyze_other_file(path, range, input.true_peak).map(Some)
     }
+}
 
-    // Clear the current line again.
-    eprint!("\x1b[2K\r");
+/// Return the track loudness stored in the `BS17704_TRACK_LOUDNESS` tag, and the track duration.
+///
+/// Returns `None` when the tag is missing or invalid, or when the duration of
+/// the track is not known, then the track must be decoded.
+fn loudness_from_tags(reader: &FlacReader<fs::File>) -> Option<(Power, Duration)> {
+    let lkfs = reader.get_tag("BS17704_TRACK_LOUDNESS").next().and_then(tags::parse_lufs)?;
+    let streaminfo = reader.streaminfo();
+    let num_samples = streaminfo.samples?;
+    let duration = Duration::from_secs_f64(num_samples as f64 / streaminfo.sample_rate as f64);
+    Some((Power::from_lkfs(lkfs), duration))
+}
 
-    let gated_power = bs1770::gated_mean(windows.as_ref()).unwrap_or(Power(0.0));
+/// Measure loudness of an album, analyzing up to `jobs` files concurrently.
+fn analyze_album(
+    paths: Vec<PathBuf>,
+    skip_when_tags_present: bool,
+    tag_format: TagFormat,
+    range: TimeRange,
+    input: Input,
+    jobs: usize,
+    log: Log,
+) -> Result<AlbumResult, Box<dyn Error + Send + Sync>> {
+    let num_paths = paths.len();
+    let mut album = AlbumAccumulator::new();
+    let mut tracks = Vec::with_capacity(num_paths);
+    let mut sample_peak = 0.0_f32;
+    let mut track_windows = Vec::with_capacity(num_paths);
+    let mut track_true_peaks = Vec::with_capacity(num_paths);
+    let mut track_durations = Vec::with_capacity(num_paths);
+
+    // Workers take the next file from the queue, and send the result back
+    // together with the index of the file. After an error, they stop taking
+    // new files, because the album result is discarded anyway.
+    let queue = Arc::new(Mutex::new(paths.into_iter().enumerate()));
+    let failed = Arc::new(AtomicBool::new(false));
+    let (sender, receiver) = mpsc::channel();
+    for _ in 0..jobs.min(num_paths) {
+        let queue = queue.clone();
+        let failed = failed.clone();
+        let sender = sender.clone();
+        thread::spawn(move || loop {
+            if failed.load(Ordering::Relaxed) {
+                break
+            }
+            let (i, path) = match queue.lock().unwrap().next() {
+                Some(item) => item,
+                None => break,
+            };
+            let result = analyze_track(&path, skip_when_tags_present, tag_format, range, input, log);
+            if result.is_err() {
+                failed.store(true, Ordering::Relaxed);
+            }
+            if sender.send((i, path, result)).is_err() {
+                break
+            }
+        });
+    }
+    drop(sender);
+
+    // Files can finish out of order, but we add the tracks to the album in
+    // the order of the arguments, so the output does not depend on timing.
+    let mut pending: Vec<Option<(PathBuf, AnalysisResult)>> = (0..num_paths).map(|_| None).collect();
+    let mut next = 0;
+
+    for (i, path, result) in receiver {
+        pending[i] = Some((path, result));
+
+        while let Some((path, result)) = pending.get_mut(next).and_then(|p| p.take()) {
+            next += 1;
+
+            let track_result = match result {
+                Ok(Some(r)) => r,
+                Ok(None) => continue,
+                Err(e) => {
+                    log.clear_status();
+                    eprintln!("Error while analyzing {}: {}", path.to_string_lossy(), e);
+                    return Err(e);
+                }
+            };
+
+            if track_result.from_tags {
+                log.info(format_args!(
+                    "Read loudness of {} from its tags: {:.1} LKFS.",
+                    path.to_string_lossy(),
+                    track_result.gated_power.loudness_lkfs(),
+                ));
+            } else {
+                log.info(format_args!(
+                    "Analyzed {}: {} windows, {:.1} LKFS.",
+                    path.to_string_lossy(),
+                    track_result.windows.len(),
+                    track_result.gated_power.loudness_lkfs(),
+                ));
+            }
+            for (time, power) in track_result.windows.iter_with_time() {
+                log.debug(format_args!(
+                    "  {:>8.1}s  {:>6.1} LKFS",
+                    time.as_secs_f32(),
+                    power.loudness_lkfs(),
+                ));
+            }
+
+            album.add_reduced_track(track_result.windows.as_ref());
+            sample_peak = sample_peak.max(track_result.sample_peak);
+            tracks.push((path, track_result.gated_power, track_result.sample_peak, track_result.reader));
+            track_durations.push((track_result.gated_power, track_result.duration));
+            track_windows.push(track_result.windows);
+            track_true_peaks.push(track_result.true_peak);
+        }
+    }
+
+    log.clear_status();
+
+    let gated_power = if input.duration_weighted {
+        album::duration_weighted_power(&track_durations)
+    } else {
+        album.gated_power()
+    };
+    let gated_power = gated_power.unwrap_or(Power(0.0));
     let result = AlbumResult {
         tracks: tracks,
         gated_power: gated_power,
+        sample_peak,
+        track_windows,
+        track_true_peaks,
     };
 
     Ok(result)
 }
 
-/// Measure loudness of a single track.
-fn analyze_file(mut reader: FlacReader<fs::File>) -> claxon::Result<TrackResult> {
-    let streaminfo = reader.streaminfo();
-    // The maximum amplitude is 1 << (bits per sample - 1), because one bit
-    // is the sign bit.
-    let normalizer = 1.0 / (1_u64 << (streaminfo.bits_per_sample - 1)) as f32;
+/// The portion of every track to analyze, selected with `--start` and `--duration`.
+#[derive(Copy, Clone)]
+struct TimeRange {
+    start: Duration,
+    duration: Option<Duration>,
+}
+
+impl TimeRange {
+    /// Return the first sample and one past the last sample in the range.
+    ///
+    /// This rounds down like the `batch` module does, see `bs1770::duration_to_samples`.
+    fn samples(&self, sample_rate_hz: u32) -> (u64, Option<u64>) {
+        let start = bs1770::duration_to_samples(self.start, sample_rate_hz);
+        (start, self.duration.map(|d| start + bs1770::duration_to_samples(d, sample_rate_hz)))
+    }
+}
+
+/// Sample format of raw PCM input. All formats are little-endian, like ffmpeg's `s16le`.
+#[derive(Copy, Clone)]
+enum RawFormat {
+    S16,
+    S24,
+    S32,
+    F32,
+}
+
+impl RawFormat {
+    fn parse(name: &str) -> Option<RawFormat> {
+        match name {
+            "s16le" => Some(RawFormat::S16),
+            "s24le" => Some(RawFormat::S24),
+            "s32le" => Some(RawFormat::S32),
+            "f32le" => Some(RawFormat::F32),
+            _ => None,
+        }
+    }
+
+    /// Return the size of one sample in bytes.
+    fn sample_len(self) -> usize {
+        match self {
+            RawFormat::S16 => 2,
+            RawFormat::S24 => 3,
+            RawFormat::S32 | RawFormat::F32 => 4,
+        }
+    }
+
+    /// Decode one sample, normalized to full scale.
+    fn decode(self, bytes: &[u8]) -> f32 {
+        match self {
+            RawFormat::S16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 32768.0,
+            // Put the 24 bits in the high bytes, so the sign is preserved.
+            RawFormat::S24 => i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) as f32 / 2147483648.0,
+            RawFormat::S32 => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f32 / 2147483648.0,
+            RawFormat::F32 => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        }
+    }
+}
 
+/// The layout of raw PCM on stdin, selected with `--raw`, `--rate`, and `--channels`.
+#[derive(Copy, Clone)]
+struct RawInput {
+    format: RawFormat,
+    sample_rate_hz: u32,
+    num_channels: u32,
+}
+
+/// How to read inputs that flacgain does not decode by extension.
+#[derive(Copy, Clone)]
+struct Input {
+    /// The layout of raw PCM on stdin, for the file name `-`.
+    raw: Option<RawInput>,
+    /// Whether to decode formats other than FLAC and WAVE with ffmpeg.
+    ffmpeg: bool,
+    /// Whether to compute album loudness from the track loudness, weighted by
+    /// duration. Then the track loudness of FLAC files that have a
+    /// `BS17704_TRACK_LOUDNESS` tag is taken from the tag, without decoding.
+    duration_weighted: bool,
+    /// Whether to measure the true peak, which the `{true_peak}` field needs.
+    /// It is not measured otherwise, because it takes longer than the loudness.
+    true_peak: bool,
+}
+
+/// Parse a time as seconds, optionally preceded by minutes and hours.
+///
+/// Accepts for example `90`, `1:30`, `1:30.5`, and `1:02:03`.
+fn parse_time(time: &str) -> Option<Duration> {
+    let mut seconds = 0.0;
+    let mut parts = time.split(':').peekable();
+    while let Some(part) = parts.next() {
+        let is_last = parts.peek().is_none();
+        let value = if is_last {
+            f64::from_str(part).ok()?
+        } else {
+            u32::from_str(part).ok()? as f64
+        };
+        if !value.is_finite() || value < 0.0 {
+            return None
+        }
+        seconds = seconds * 60.0 + value;
+    }
+    Some(Duration::from_secs_f64(seconds))
+}
+
+/// Combine the channels of a track, and measure its integrated loudness.
+///
+/// `peak_meters` is empty when the true peak is not measured.
+fn finish_track(
+    meters: &[bs1770::ChannelLoudnessMeter],
+    peak_meters: &[TruePeakMeter],
+    channel_map: &ChannelMap,
+    sample_peak: f32,
+    reader: Option<FlacReader<fs::File>>,
+) -> TrackResult {
+    let channel_windows: Vec<_> = meters.iter().map(|m| m.as_100ms_windows()).collect();
+    let windows = channel_map.reduce(&channel_windows);
+    let gated_power = bs1770::gated_mean(windows.as_ref()).unwrap_or(Power(0.0));
+    TrackResult {
+        gated_power,
+        duration: windows.duration(),
+        windows,
+        sample_peak,
+        true_peak: peak_meters.iter().fold(0.0, |peak, m| peak.max(m.peak())),
+        reader,
+        from_tags: false,
+    }
+}
+
+/// Return the largest absolute value of `samples`, or `peak` if that is larger.
+fn max_abs(peak: f32, samples: &[f32]) -> f32 {
+    samples.iter().fold(peak, |p, x| p.max(x.abs()))
+}
+
+/// Return one true-peak meter per channel if `true_peak` is set, or none otherwise.
+fn new_peak_meters(true_peak: bool, num_channels: usize) -> Vec<TruePeakMeter> {
+    if true_peak {
+        vec![TruePeakMeter::new(); num_channels]
+    } else {
+        Vec::new()
+    }
+}
+
+/// Feed interleaved samples to the true-peak meters, if there are any.
+fn push_peaks_interleaved(peak_meters: &mut [TruePeakMeter], samples: &[f32]) {
+    let num_channels = peak_meters.len();
+    for (ch, meter) in peak_meters.iter_mut().enumerate() {
+        meter.push(samples.iter().skip(ch).step_by(num_channels).cloned());
+    }
+}
+
+/// Measure loudness of a single track, or the part of it selected by `range`.
+fn analyze_file(
+    mut reader: FlacReader<fs::File>,
+    range: TimeRange,
+    true_peak: bool,
+) -> claxon::Result<TrackResult> {
+    let streaminfo = reader.streaminfo();
     let mut meters = vec![
         bs1770::ChannelLoudnessMeter::new(streaminfo.sample_rate);
         streaminfo.channels as usize
     ];
+    let mut peak_meters = new_peak_meters(true_peak, streaminfo.channels as usize);
+    let normalizer = 1.0 / (1_u64 << (streaminfo.bits_per_sample - 1)) as f32;
+
+    let (start, end) = range.samples(streaminfo.sample_rate);
 
     let mut blocks = reader.blocks();
     let mut buffer = Vec::new();
+    let mut max_sample = 0_u32;
 
+    // Claxon cannot seek, so we decode from the start of the file, and only
+    // measure the part of every block that lies inside the range.
     while let Some(block) = blocks.read_next_or_eof(buffer)? {
+        let block_start = block.time();
+        let block_len = block.duration() as u64;
+        let from = start.saturating_sub(block_start).min(block_len) as usize;
+        let to = end.map_or(block_len, |e| e.saturating_sub(block_start).min(block_len)) as usize;
+
         for (ch, meter) in meters.iter_mut().enumerate() {
-            meter.push(block.channel(ch as u32).iter().map(|s| *s as f32 * normalizer));
+            let samples = &block.channel(ch as u32)[from..to.max(from)];
+            // The wrapping absolute value of i32::MIN, as u32, is 2^31, as it should be.
+            max_sample = samples.iter().fold(max_sample, |m, &x| m.max(x.wrapping_abs() as u32));
+            meter.push_i32(samples.iter().cloned(), streaminfo.bits_per_sample);
+        }
+        for (ch, meter) in peak_meters.iter_mut().enumerate() {
+            let samples = &block.channel(ch as u32)[from..to.max(from)];
+            meter.push(samples.iter().map(|&x| x as f32 * normalizer));
         }
         buffer = block.into_buffer();
+
+        match end {
+            Some(e) if block_start + block_len >= e => break,
+            _ => {}
+        }
     }
 
-    let zipped = bs1770::reduce_stereo(
-        meters[0].as_100ms_windows(),
-        meters[1].as_100ms_windows(),
-    );
-    let gated_power = bs1770::gated_mean(zipped.as_ref()).unwrap_or(Power(0.0));
+    // Tracks of an album may have different channel counts, such as a mono
+    // bonus track on a stereo album, so reduce every track with its own layout.
+    // FLAC defines a layout for every channel count it supports.
+    let channel_map = ChannelMap::from_order(ChannelOrder::Flac, streaminfo.channels)
+        .expect("FLAC defines the channel order for 1 to 8 channels.");
 
-    let result = TrackResult {
-        gated_power: gated_power,
-        windows: zipped,
-        reader: reader,
+    let sample_peak = max_sample as f32 * normalizer;
+    Ok(finish_track(&meters, &peak_meters, &channel_map, sample_peak, Some(reader)))
+}
+
+/// Measure loudness of a WAVE file, or the part of it selected by `range`.
+///
+/// This supports integer samples of any bit depth, such as 16 and 24 bits,
+/// and 32-bit float samples.
+fn analyze_wav_file(path: &Path, range: TimeRange, true_peak: bool) -> hound::Result<TrackResult> {
+    let mut reader = hound::WavReader::open(path)?;
+    let spec = reader.spec();
+    let num_channels = spec.channels as usize;
+    let mut meters = vec![bs1770::ChannelLoudnessMeter::new(spec.sample_rate); num_channels];
+    let mut peak_meters = new_peak_meters(true_peak, num_channels);
+
+    // Unlike Claxon, Hound can seek, so we skip to the start of the range.
+    let (start, end) = range.samples(spec.sample_rate);
+    let duration = reader.duration() as u64;
+    let start = start.min(duration);
+    let end = end.map_or(duration, |e| e.min(duration)).max(start);
+    reader.seek(start as u32)?;
+    let num_frames = end - start;
+    let num_samples = num_frames as usize * num_channels;
+
+    // Decode about a second at a time, as interleaved samples normalized to
+    // full scale, so we can push them to all meters at once.
+    let chunk_len = spec.sample_rate as usize * num_channels;
+    let mut samples = Vec::with_capacity(chunk_len.min(num_samples));
+    let normalizer = 1.0 / (1_u64 << (spec.bits_per_sample - 1)) as f32;
+    let mut sample_peak = 0.0_f32;
+
+    match spec.sample_format {
+        hound::SampleFormat::Int => {
+            let mut input = reader.samples::<i32>().take(num_samples);
+            loop {
+                samples.clear();
+                for sample in input.by_ref().take(chunk_len) {
+                    samples.push(sample? as f32 * normalizer);
+                }
+                if samples.is_empty() {
+                    break
+                }
+                sample_peak = max_abs(sample_peak, &samples);
+                push_peaks_interleaved(&mut peak_meters, &samples);
+                bs1770::push_interleaved(&mut meters, &samples);
+            }
+        }
+        hound::SampleFormat::Float => {
+            let mut input = reader.samples::<f32>().take(num_samples);
+            loop {
+                samples.clear();
+                for sample in input.by_ref().take(chunk_len) {
+                    samples.push(sample?);
+                }
+                if samples.is_empty() {
+                    break
+                }
+                sample_peak = max_abs(sample_peak, &samples);
+                push_peaks_interleaved(&mut peak_meters, &samples);
+                bs1770::push_interleaved(&mut meters, &samples);
+            }
+        }
+    }
+
+    let channel_map = match ChannelMap::from_order(ChannelOrder::Wave, spec.channels as u32) {
+        Some(map) => map,
+        None => return Err(hound::Error::Unsupported),
     };
+    Ok(finish_track(&meters, &peak_meters, &channel_map, sample_peak, None))
+}
 
-    Ok(result)
+/// Measure loudness of raw interleaved PCM, or the part selected by `range`.
+///
+/// Channels are in WAVE order. A trailing partial frame is ignored.
+fn analyze_raw<R: io::Read>(
+    mut input: R,
+    raw: RawInput,
+    range: TimeRange,
+    true_peak: bool,
+) -> Result<TrackResult, Box<dyn Error + Send + Sync>> {
+    let channel_map = match ChannelMap::from_order(ChannelOrder::Wave, raw.num_channels) {
+        Some(map) => map,
+        None => return Err(format!("Unsupported number of channels: {}.", raw.num_channels).into()),
+    };
+    let num_channels = raw.num_channels as usize;
+    let mut meters = vec![bs1770::ChannelLoudnessMeter::new(raw.sample_rate_hz); num_channels];
+    let mut peak_meters = new_peak_meters(true_peak, num_channels);
+
+    let (start, end) = range.samples(raw.sample_rate_hz);
+    let mut position = 0_u64;
+    let mut sample_peak = 0.0_f32;
+
+    // Read about a second at a time. A read can end in the middle of a frame,
+    // we keep the partial frame at the start of the buffer for the next read.
+    let frame_len = raw.format.sample_len() * num_channels;
+    let mut buffer = vec![0_u8; frame_len * raw.sample_rate_hz as usize];
+    let mut buffer_len = 0;
+    let mut samples = Vec::with_capacity(buffer.len() / raw.format.sample_len());
+
+    loop {
+        let n = match input.read(&mut buffer[buffer_len..]) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        buffer_len += n;
+
+        // Only measure the complete frames that lie inside the range.
+        let num_frames = (buffer_len / frame_len) as u64;
+        let from = start.saturating_sub(position).min(num_frames) as usize;
+        let to = end.map_or(num_frames, |e| e.saturating_sub(position).min(num_frames)) as usize;
+        samples.clear();
+        samples.extend(
+            buffer[from * frame_len..to.max(from) * frame_len]
+                .chunks(raw.format.sample_len())
+                .map(|b| raw.format.decode(b))
+        );
+        sample_peak = max_abs(sample_peak, &samples);
+        push_peaks_interleaved(&mut peak_meters, &samples);
+        bs1770::push_interleaved(&mut meters, &samples);
+
+        let num_bytes = num_frames as usize * frame_len;
+        buffer.copy_within(num_bytes..buffer_len, 0);
+        buffer_len -= num_bytes;
+        position += num_frames;
+
+        match end {
+            Some(e) if position >= e => break,
+            _ => {}
+        }
+    }
+
+    Ok(finish_track(&meters, &peak_meters, &channel_map, sample_peak, None))
 }
 
-/// Return the start offset and length of the VORBIS_COMMENT block in the file.
+/// Measure loudness of a file decoded by ffmpeg, or the part selected by `range`.
 ///
-/// The start position and length do include the 4-byte block header.
-fn locate_vorbis_comment_block(file: &mut fs::File) -> io::Result<Option<(u64, u64)>> {
-    let mut reader = io::BufReader::new(file);
-
-    // The first 4 bytes are the flac header.
-    let mut buf = [0_u8; 4];
-    reader.read_exact(&mut buf[..])?;
-    assert_eq!(&buf, b"fLaC");
-
-    let mut is_last = false;
-
-    while !is_last {
-        // This is a block start boundary, remember the current offset.
-        let pos = reader.seek(io::SeekFrom::Current(0))?;
-
-        // The block header is four bytes, one byte where the first bit
-        // specifies whether this is the last block, and the next 7 bits specify
-        // the block type. Then follows a 24-bit big-endian block length.
-        reader.read_exact(&mut buf[..])?;
-        is_last = (buf[0] >> 7) == 1;
-        let block_type = buf[0] & 0b0111_1111;
-        let is_vorbis_comment = block_type == 4;
-        let block_length = 0
-            | ((buf[1] as u64) << 16)
-            | ((buf[2] as u64) << 8)
-            | ((buf[3] as u64) << 0)
-            ;
-
-        if is_vorbis_comment {
-            // The stored length does not include the length of the 4-byte
-            // header, but we do include it here, because we want to replace the
-            // entire block, including its header.
-            return Ok(Some((pos, block_length + 4)));
-        } else {
-            reader.seek(io::SeekFrom::Current(block_length as i64))?;
+/// We ask ffprobe for the sample rate and number of channels of the first
+/// audio stream, and have ffmpeg decode that stream to 32-bit float samples,
+/// in its native channel order, which for up to 8 channels is the WAVE order.
+fn analyze_ffmpeg(
+    path: &Path,
+    range: TimeRange,
+    true_peak: bool,
+) -> Result<TrackResult, Box<dyn Error + Send + Sync>> {
+    use std::process::{Command, Stdio};
+
+    let probe = Command::new("ffprobe")
+        .args(["-v", "error", "-select_streams", "a:0"].iter())
+        .args(["-show_entries", "stream=sample_rate,channels", "-of", "csv=p=0"].iter())
+        .arg(path)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| format!("Failed to run ffprobe: {}", e))?;
+    if !probe.status.success() {
+        let message = String::from_utf8_lossy(&probe.stderr);
+        return Err(format!("ffprobe failed: {}", message.trim()).into())
+    }
+
+    // The output is the sample rate and the number of channels, such as "48000,2".
+    let stream_info = String::from_utf8_lossy(&probe.stdout);
+    let mut fields = stream_info.trim().split(',').map(|f| u32::from_str(f).ok());
+    let raw = match (fields.next(), fields.next()) {
+        (Some(Some(sample_rate_hz)), Some(Some(num_channels))) if sample_rate_hz >= 10 => RawInput {
+            format: RawFormat::F32,
+            sample_rate_hz,
+            num_channels,
+        },
+        _ => return Err("File does not contain an audio stream.".into()),
+    };
+
+    // Let ffmpeg select the range, so it can seek instead of decoding
+    // everything, and so we read its output to the end.
+    let mut command = Command::new("ffmpeg");
+    command.args(["-v", "error", "-nostdin"].iter());
+    if range.start > Duration::from_secs(0) {
+        command.arg("-ss").arg(format!("{:.6}", range.start.as_secs_f64()));
+    }
+    command.arg("-i").arg(path);
+    if let Some(duration) = range.duration {
+        command.arg("-t").arg(format!("{:.6}", duration.as_secs_f64()));
+    }
+    command.args(["-map", "0:a:0", "-f", "f32le", "-"].iter());
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run ffmpeg: {}", e))?;
+
+    let stdout = child.stdout.take().expect("We requested a pipe for stdout.");
+    let full_range = TimeRange {
+        start: Duration::from_secs(0),
+        duration: None,
+    };
+    let result = analyze_raw(stdout, raw, full_range, true_peak);
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(format!("ffmpeg failed with {}.", status).into())
+    }
+    result
+}
+
+/// Measure loudness of a file in a format other than FLAC.
+#[cfg(not(feature = "symphonia"))]
+fn analyze_other_file(
+    path: &Path,
+    _range: TimeRange,
+    _true_peak: bool,
+) -> Result<TrackResult, Box<dyn Error + Send + Sync>> {
+    let message = format!(
+        "Cannot read {}, only FLAC and WAV are supported without the symphonia feature.",
+        path.to_string_lossy(),
+    );
+    Err(message.into())
+}
+
+/// Measure loudness of a file in a format other than FLAC, decoded with Symphonia.
+///
+/// Symphonia selects the demuxer from the container, so this reads for
+/// example MP3, AAC and ALAC in MP4, and Vorbis in Ogg.
+#[cfg(feature = "symphonia")]
+fn analyze_other_file(
+    path: &Path,
+    range: TimeRange,
+    true_peak: bool,
+) -> Result<TrackResult, Box<dyn Error + Send + Sync>> {
+    use symphonia::core::audio::SampleBuffer;
+    use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+    use symphonia::core::errors::Error as SymphoniaError;
+    use symphonia::core::formats::FormatOptions;
+    use symphonia::core::io::MediaSourceStream;
+    use symphonia::core::meta::MetadataOptions;
+    use symphonia::core::probe::Hint;
+
+    let file = fs::File::open(path)?;
+    let source = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(ext);
+    }
+    let probed = symphonia::default::get_probe().format(
+        &hint,
+        source,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+    let mut format = probed.format;
+
+    let track = match format.tracks().iter().find(|t| t.codec_params.codec != CODEC_TYPE_NULL) {
+        Some(t) => t,
+        None => return Err("File does not contain an audio track.".into()),
+    };
+    let track_id = track.id;
+    let sample_rate_hz = match track.codec_params.sample_rate {
+        Some(rate) => rate,
+        None => return Err("The sample rate of the audio track is unknown.".into()),
+    };
+    let mut decoder = symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    let (start, end) = range.samples(sample_rate_hz);
+    let mut position = 0_u64;
+    let mut sample_peak = 0.0_f32;
+    let mut meters = Vec::new();
+    let mut peak_meters = Vec::new();
+    let mut channel_map = None;
+    let mut buffer: Option<SampleBuffer<f32>> = None;
+
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(ref e)) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        };
+        if packet.track_id() != track_id {
+            continue
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // A corrupt packet is skipped, like a player would.
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        let spec = *decoded.spec();
+        let num_channels = spec.channels.count();
+        if channel_map.is_none() {
+            // Symphonia orders channels like the WAVE channel mask.
+            channel_map = Some(ChannelMap::from_wave_mask(spec.channels.bits(), num_channels as u32));
+            meters = vec![bs1770::ChannelLoudnessMeter::new(sample_rate_hz); num_channels];
+            peak_meters = new_peak_meters(true_peak, num_channels);
+        }
+        let capacity = buffer.as_ref().map_or(0, |b| b.capacity());
+        if capacity < decoded.capacity() * num_channels {
+            buffer = Some(SampleBuffer::new(decoded.capacity() as u64, spec));
+        }
+        let buffer = buffer.as_mut().expect("We just created the buffer.");
+        buffer.copy_planar_ref(decoded);
+
+        // Only measure the part of the packet that lies inside the range.
+        let num_frames = buffer.len() / num_channels;
+        let block_len = num_frames as u64;
+        let from = start.saturating_sub(position).min(block_len) as usize;
+        let to = end.map_or(block_len, |e| e.saturating_sub(position).min(block_len)) as usize;
+        for (ch, meter) in meters.iter_mut().enumerate() {
+            let samples = &buffer.samples()[ch * num_frames..(ch + 1) * num_frames];
+            sample_peak = max_abs(sample_peak, &samples[from..to.max(from)]);
+            meter.push_slice(&samples[from..to.max(from)]);
+        }
+        for (ch, meter) in peak_meters.iter_mut().enumerate() {
+            let samples = &buffer.samples()[ch * num_frames..(ch + 1) * num_frames];
+            meter.push(samples[from..to.max(from)].iter().cloned());
+        }
+        position += block_len;
+
+        match end {
+            Some(e) if position >= e => break,
+            _ => {}
         }
     }
 
-    Ok(None)
+    let channel_map = match channel_map {
+        Some(map) => map,
+        None => return Err("File does not contain any audio.".into()),
+    };
+    Ok(finish_track(&meters, &peak_meters, &channel_map, sample_peak, None))
+}
+
+/// Which loudness tags `--write-tags` writes.
+#[derive(Copy, Clone)]
+enum TagFormat {
+    /// `BS17704_TRACK_LOUDNESS` and `BS17704_ALBUM_LOUDNESS`, the default.
+    Bs17704,
+    /// The ReplayGain 2.0 tags, with `--replaygain`.
+    ReplayGain,
+}
+
+impl TagFormat {
+    /// The track and album tag that `--skip-when-tags-present` looks for.
+    fn presence_tags(self) -> [&'static str; 2] {
+        match self {
+            TagFormat::Bs17704 => ["BS17704_TRACK_LOUDNESS", "BS17704_ALBUM_LOUDNESS"],
+            TagFormat::ReplayGain => ["REPLAYGAIN_TRACK_GAIN", "REPLAYGAIN_ALBUM_GAIN"],
+        }
+    }
+
+    /// Return the tags to write for a track, as key and value.
+    ///
+    /// Silence has no ReplayGain gain, for silent tracks and albums the gain
+    /// tags are omitted.
+    fn tags(
+        self,
+        track_power: Power,
+        track_peak: f32,
+        album_power: Power,
+        album_peak: f32,
+    ) -> Vec<(&'static str, String)> {
+        match self {
+            TagFormat::Bs17704 => vec![
+                ("BS17704_ALBUM_LOUDNESS", tags::format_lufs(album_power.loudness_lkfs())),
+                ("BS17704_TRACK_LOUDNESS", tags::format_lufs(track_power.loudness_lkfs())),
+            ],
+            TagFormat::ReplayGain => {
+                let mut result = Vec::with_capacity(5);
+                if let Some(gain_db) = replaygain::gain_db(album_power) {
+                    result.push(("REPLAYGAIN_ALBUM_GAIN", replaygain::format_gain(gain_db)));
+                }
+                result.push(("REPLAYGAIN_ALBUM_PEAK", format!("{:.6}", album_peak)));
+                let reference = tags::format_lufs(replaygain::REFERENCE_LUFS);
+                result.push(("REPLAYGAIN_REFERENCE_LOUDNESS", reference));
+                if let Some(gain_db) = replaygain::gain_db(track_power) {
+                    result.push(("REPLAYGAIN_TRACK_GAIN", replaygain::format_gain(gain_db)));
+                }
+                result.push(("REPLAYGAIN_TRACK_PEAK", format!("{:.6}", track_peak)));
+                result
+            }
+        }
+    }
 }
 
-/// Update the tags in the file to contain BS.1770 loudness tags.
+/// Return whether a stored tag value is close enough to the new value to keep it.
 ///
-/// This adds or overwrites the following tags:
+/// Loudness and gain may differ by 0.1 dB, peaks by 0.001 of full scale.
+fn tag_is_close(key: &str, current_value: &str, new_value: &str) -> bool {
+    let tolerance = if key.ends_with("_PEAK") { 0.001 } else { 0.1 };
+    let parse = |value: &str| tags::parse_lufs(value).or_else(|| tags::parse_db(value));
+    match (parse(current_value), parse(new_value)) {
+        (Some(current), Some(new)) => (current - new).abs() <= tolerance,
+        _ => false,
+    }
+}
+
+/// Update the tags in the file to contain the given loudness tags.
 ///
-/// * `BS1770_TRACK_LOUDNESS`
-/// * `BS1770_ALBUM_LOUDNESS`
+/// This removes all BS17704 and ReplayGain tags, and then adds `new_tags`.
 ///
-/// This first writes a copy of the original file, with tags updated, and then
-/// moves the new file over the existing one. This uses `copy_file_range` to
-/// take advantage of reflink copies on file systems that support this.
-fn write_new_tags(
-    path: &Path,
-    track_loudness_lkfs: f32,
-    album_loudness_lkfs: f32,
-    reader: FlacReader<fs::File>,
-) -> io::Result<()> {
+/// See `bs1770::flac_tags::write` for how the file is rewritten.
+fn write_new_tags(path: &Path, new_tags: &[(&str, String)]) -> io::Result<()> {
     // Tags to not copy from the existing tags, either because we no longer need
     // them, or because we are going to provide replacements.
     let exclude_tags = [
@@ -264,167 +1571,504 @@ fn write_new_tags(
         "REPLAYGAIN_TRACK_PEAK",
     ];
 
-    let mut vorbis_comments = Vec::with_capacity(reader.tags().len() + 2);
+    let mut file_tags = match flac_tags::read(path)? {
+        Some(t) => t,
+        None => return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "File does not have a VORBIS_COMMENT block yet.",
+        )),
+    };
+    for tag in exclude_tags.iter() {
+        file_tags.remove(tag);
+    }
+    for &(key, ref value) in new_tags {
+        file_tags.add(key, value);
+    }
+
+    flac_tags::write(path, &file_tags)
+}
 
-    // Copy all non-excluded tags.
-    for (key, value) in reader.tags() {
-        if exclude_tags.iter().any(|t| t == &key) { continue }
+/// What to do with every album, as selected on the command line.
+struct AlbumOptions {
+    skip_when_tags_present: bool,
+    tag_format: TagFormat,
+    range: TimeRange,
+    input: Input,
+    jobs: usize,
+    log: Log,
+    output_format: OutputFormat,
+    highlight: Highlight,
+    report_format: Option<ReportFormat>,
+    /// The format for `--write-trace`, `None` when not writing traces.
+    trace_format: Option<ReportFormat>,
+    write_tags: bool,
+    /// The tolerance in LU for `--verify`, `None` when not verifying.
+    verify_tolerance_lu: Option<f32>,
+}
 
-        // TODO: If I expose the raw string including = from Claxon, I could use
-        // it here without having to make a copy.
-        let mut pair = String::with_capacity(key.len() + value.len() + 1);
-        pair.push_str(key);
-        pair.push('=');
-        pair.push_str(value);
-        vorbis_comments.push(pair);
+/// Analyze an album, print the result, and write reports and tags if requested.
+///
+/// Errors are reported on stderr. Returns whether all steps succeeded.
+fn process_album(paths: Vec<PathBuf>, options: &AlbumOptions) -> bool {
+    let album_result = match analyze_album(
+        paths,
+        options.skip_when_tags_present,
+        options.tag_format,
+        options.range,
+        options.input,
+        options.jobs,
+        options.log,
+    ) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("Failed to analzye album: {}", e);
+            return false
+        }
+    };
+
+    album_result.print(&options.output_format, options.highlight);
+
+    if let Some(format) = options.report_format {
+        if let Err(e) = album_result.write_reports(format) {
+            eprintln!("Failed to write report: {}", e);
+            return false
+        }
     }
 
-    // Then add our own.
-    vorbis_comments.push(
-        format!("BS17704_ALBUM_LOUDNESS={:.3} LUFS", album_loudness_lkfs)
-    );
-    vorbis_comments.push(
-        format!("BS17704_TRACK_LOUDNESS={:.3} LUFS", track_loudness_lkfs)
-    );
+    if let Some(format) = options.trace_format {
+        if let Err(e) = album_result.write_traces(format) {
+            eprintln!("Failed to write loudness trace: {}", e);
+            return false
+        }
+    }
 
-    let mut block = Vec::new();
-
-    // The block starts with the length-prefixed vendor string as UTF-8.
-    let vendor = reader.vendor().expect("Expected VORBIS_COMMENT block to be present.");
-    block.write_all(&(vendor.len() as u32).to_le_bytes())?;
-    block.write_all(vendor.as_bytes())?;
-
-    // Then the length-prefixed list of Vorbis comments follows.
-    block.write_all(&(vorbis_comments.len() as u32).to_le_bytes())?;
-    for comment in vorbis_comments {
-        block.write_all(&(comment.len() as u32).to_le_bytes())?;
-        block.write_all(comment.as_bytes())?;
-    }
-
-    // Take the original file and seek back to the start, so we can locate the
-    // VORBIS_COMMENT block. We will make a copy with that block replaced.
-    let mut src_file = reader.into_inner();
-    src_file.seek(io::SeekFrom::Start(0))?;
-    let (offset, old_block_len) = match locate_vorbis_comment_block(&mut src_file)? {
-        Some(result) => result,
-        None => {
-            eprintln!(
-                "File {} does not have a VORBIS_COMMENT block yet.",
-                path.to_string_lossy(),
-            );
-            std::process::exit(1);
+    if let Some(tolerance_lu) = options.verify_tolerance_lu {
+        let num_files_deviating = album_result.verify_tags(tolerance_lu, options.log);
+        if num_files_deviating > 0 {
+            eprintln!("Tags of {} files deviate from the measurement.", num_files_deviating);
+            return false
         }
-    };
+    }
+
+    if options.write_tags {
+        let num_files_failed = album_result.write_tags(options.tag_format, options.log);
+        if num_files_failed > 0 {
+            eprintln!("Failed to update tags of {} files.", num_files_failed);
+            return false
+        }
+    }
+
+    true
+}
 
-    let mut tmp_fname = path.to_path_buf();
-    tmp_fname.set_extension("flac.metadata_edit");
-    let mut dst_file = fs::File::create(&tmp_fname)?;
-
-    // Copy the part up to the VORBIS_COMMENT block. The offset starts at 0, the
-    // length is 1 more than the offset, we also want the first byte of the
-    // block header.
-    copy_file_range(&src_file, &mut dst_file, 0, offset + 1)?;
-
-    // We already have the first byte of the block header, the remaining 3 bytes
-    // of that header are the block size, in big endian. Prepend that to the
-    // block, then write the block.
-    let block_length_u24be = [
-        ((block.len() >> 16) & 0xff) as u8,
-        ((block.len() >>  8) & 0xff) as u8,
-        ((block.len() >>  0) & 0xff) as u8,
+/// Return whether flacgain can analyze the file, based on its extension.
+fn is_supported_file(path: &Path, ffmpeg: bool) -> bool {
+    // These are the formats that Symphonia decodes with the features we enable.
+    let symphonia_extensions = ["aac", "m4a", "mp3", "oga", "ogg"];
+    // Common audio and video formats that ffmpeg decodes. We cannot list all
+    // of them, but when walking a directory, we should not try every file.
+    let ffmpeg_extensions = [
+        "aac", "aif", "aiff", "ape", "m4a", "mka", "mkv", "mov", "mp3", "mp4",
+        "oga", "ogg", "opus", "webm", "wma", "wv",
     ];
-    block.splice(0..0, block_length_u24be.iter().cloned());
-    dst_file.write_all(&block)?;
-
-    // After the new VORBIS_COMMENT block, copy the remainder of the old file.
-    let src_len = src_file.metadata()?.len();
-    let tail_offset = offset + old_block_len;
-    copy_file_range(&src_file, &mut dst_file, tail_offset, src_len - tail_offset)?;
-
-    // Now that we produced the new file with a temporary name, move it over the
-    // old file.
-    fs::rename(&tmp_fname, &path)
-}
-
-fn copy_file_range(
-    file_in: &fs::File,
-    file_out: &mut fs::File,
-    off_in: u64,
-    len: u64,
-) -> io::Result<()> {
-    use std::ptr;
-    use std::os::unix::io::AsRawFd;
-
-    let mut num_left = len as usize;
-    let mut off = off_in as i64;
-
-    while num_left > 0 {
-        let num_copied = unsafe {
-            // We do specify the offset to copy from, but we set the offset to
-            // copy to to null, which means write at the current write position
-            // (and update it).
-            let off_in = &mut off as *mut libc::off64_t;
-            let off_out = ptr::null_mut();
-            let flags = 0;
-
-            libc::copy_file_range(
-                file_in.as_raw_fd(), off_in,
-                file_out.as_raw_fd(), off_out,
-                num_left,
-                flags,
-            )
-        };
+    has_extension(path, "flac")
+        || has_extension(path, "wav")
+        || (cfg!(feature = "symphonia") && symphonia_extensions.iter().any(|ext| has_extension(path, ext)))
+        || (ffmpeg && ffmpeg_extensions.iter().any(|ext| has_extension(path, ext)))
+}
 
-        if num_copied < 0 {
-            let err = io::Error::last_os_error();
-            return Err(err);
+/// Return the album artist, album, and disc number tags of a file.
+///
+/// Only FLAC files are inspected. Other files, and files that cannot be read,
+/// get empty strings, so they end up together in one group.
+fn album_key(path: &Path) -> (String, String, String) {
+    if !has_extension(path, "flac") {
+        return Default::default()
+    }
+    let reader = match FlacReader::open(path) {
+        Ok(r) => r,
+        // Analysis will report the error later.
+        Err(_) => return Default::default(),
+    };
+    let tag = |name| reader.get_tag(name).next().unwrap_or("").to_string();
+    (tag("albumartist"), tag("album"), tag("discnumber"))
+}
+
+/// Split files into albums by their `ALBUMARTIST`, `ALBUM`, and `DISCNUMBER` tags.
+///
+/// Albums are returned in the order in which their first file appears, with a
+/// heading that describes the album. Files keep their relative order.
+fn group_albums_by_tags(paths: Vec<PathBuf>) -> Vec<(String, Vec<PathBuf>)> {
+    let mut keys: Vec<(String, String, String)> = Vec::new();
+    let mut albums: Vec<(String, Vec<PathBuf>)> = Vec::new();
+
+    for path in paths {
+        let key = album_key(&path);
+        match keys.iter().position(|k| *k == key) {
+            Some(i) => albums[i].1.push(path),
+            None => {
+                let (ref artist, ref album, ref disc) = key;
+                let mut heading = match (artist.is_empty(), album.is_empty()) {
+                    (true, true) => "(no album tags)".to_string(),
+                    (true, false) => album.clone(),
+                    (false, _) => format!("{} - {}", artist, album),
+                };
+                if !disc.is_empty() {
+                    heading.push_str(&format!(" (disc {})", disc));
+                }
+                keys.push(key);
+                albums.push((heading, vec![path]));
+            }
         }
+    }
 
-        if num_copied == 0 {
-            let err = io::Error::new(io::ErrorKind::Other, "Failed to copy full range");
-            return Err(err);
+    albums
+}
+
+/// Walk `dir` recursively, and add every directory that contains audio files as an album.
+///
+/// The files of an album are sorted by name. Directories that cannot be read
+/// are reported on stderr and skipped. Returns the number of such directories.
+fn collect_albums(dir: &Path, ffmpeg: bool, albums: &mut Vec<(PathBuf, Vec<PathBuf>)>) -> u32 {
+    let mut entries = match fs::read_dir(dir).and_then(|rd| rd.collect::<io::Result<Vec<_>>>()) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("Failed to read directory {}: {}", dir.to_string_lossy(), e);
+            return 1
+        }
+    };
+    entries.sort_by_key(|entry| entry.file_name());
+
+    let mut files = Vec::new();
+    let mut num_failed = 0;
+    let mut subdirs = Vec::new();
+    for entry in entries {
+        // Do not follow symlinks to directories, they could form a cycle.
+        match entry.file_type() {
+            Ok(t) if t.is_dir() => subdirs.push(entry.path()),
+            Ok(_) if is_supported_file(&entry.path(), ffmpeg) => files.push(entry.path()),
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("Failed to read {}: {}", entry.path().to_string_lossy(), e);
+                num_failed += 1;
+            }
         }
+    }
 
-        // This does not overflow, because `num_copied > 0`.
-        num_left -= num_copied as usize;
+    if !files.is_empty() {
+        albums.push((dir.to_path_buf(), files));
+    }
+    for subdir in subdirs {
+        num_failed += collect_albums(&subdir, ffmpeg, albums);
     }
 
-    Ok(())
+    num_failed
 }
 
 fn main() {
     let mut fnames = Vec::new();
     let mut write_tags = false;
     let mut skip_when_tags_present = false;
+    let mut report_format = None;
+    let mut trace_format = None;
+    let mut output_format = OutputFormat::Text;
+    let mut color = stdout_supports_color();
+    let mut target_lkfs = None;
+    let mut ceiling_dbtp = None;
+    let mut verbosity = 1;
+    let mut jobs = 1;
+    let mut roots = Vec::new();
+    let mut group_by_tags = false;
+    let mut tag_format = TagFormat::Bs17704;
+    let mut verify = false;
+    let mut tolerance_lu = 0.1;
+    let mut raw_format = None;
+    let mut raw_rate = None;
+    let mut raw_channels = None;
+    let mut ffmpeg = false;
+    let mut duration_weighted = false;
+    let mut range = TimeRange {
+        start: Duration::from_secs(0),
+        duration: None,
+    };
 
     // Skip the name of the binary itself.
-    for arg in std::env::args().skip(1) {
-        if arg == "--write-tags" {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--output" {
+            output_format = match args.next().as_ref().map(|s| &s[..]) {
+                Some("text") => OutputFormat::Text,
+                Some("integer") => OutputFormat::Integer,
+                Some("json") => OutputFormat::Json,
+                _ => {
+                    eprintln!("Expected 'text', 'integer', or 'json' after --output.");
+                    std::process::exit(1);
+                }
+            };
+        } else if arg == "-q" || arg == "--quiet" {
+            verbosity = 0;
+        } else if arg == "-v" || arg == "--verbose" {
+            verbosity = 2;
+        } else if arg == "-vv" {
+            verbosity = 3;
+        } else if arg == "--no-color" {
+            color = false;
+        } else if arg == "--target" {
+            target_lkfs = match args.next().and_then(|t| f32::from_str(&t).ok()) {
+                Some(t) => Some(t),
+                None => {
+                    eprintln!("Expected a loudness in LKFS after --target.");
+                    std::process::exit(1);
+                }
+            };
+        } else if arg == "--ceiling-dbtp" {
+            ceiling_dbtp = match args.next().and_then(|t| f32::from_str(&t).ok()) {
+                Some(t) => Some(t),
+                None => {
+                    eprintln!("Expected a true peak level in dBTP after --ceiling-dbtp.");
+                    std::process::exit(1);
+                }
+            };
+        } else if arg == "--format" {
+            let template = match args.next() {
+                Some(t) => t,
+                None => {
+                    eprintln!("Expected a template after --format.");
+                    std::process::exit(1);
+                }
+            };
+            output_format = match parse_template(&template) {
+                Ok(segments) => OutputFormat::Template(segments),
+                Err(e) => {
+                    eprintln!("Invalid --format template: {}", e);
+                    std::process::exit(1);
+                }
+            };
+        } else if arg == "--jobs" || arg == "-j" {
+            jobs = match args.next().and_then(|n| usize::from_str(&n).ok()) {
+                Some(n) if n > 0 => n,
+                _ => {
+                    eprintln!("Expected a positive number of jobs after {}.", arg);
+                    std::process::exit(1);
+                }
+            };
+        } else if arg == "--recursive" || arg == "-r" {
+            match args.next() {
+                Some(dir) => roots.push(PathBuf::from(dir)),
+                None => {
+                    eprintln!("Expected a directory after {}.", arg);
+                    std::process::exit(1);
+                }
+            }
+        } else if arg == "--group-by-tags" {
+            group_by_tags = true;
+        } else if arg == "--replaygain" {
+            tag_format = TagFormat::ReplayGain;
+        } else if arg == "--verify" {
+            verify = true;
+        } else if arg == "--tolerance" {
+            tolerance_lu = match args.next().and_then(|t| f32::from_str(&t).ok()) {
+                Some(t) if t >= 0.0 => t,
+                _ => {
+                    eprintln!("Expected a non-negative number of loudness units after --tolerance.");
+                    std::process::exit(1);
+                }
+            };
+        } else if arg == "--raw" {
+            raw_format = match args.next().as_ref().and_then(|f| RawFormat::parse(f)) {
+                Some(f) => Some(f),
+                None => {
+                    eprintln!("Expected 's16le', 's24le', 's32le', or 'f32le' after --raw.");
+                    std::process::exit(1);
+                }
+            };
+        } else if arg == "--rate" {
+            raw_rate = match args.next().and_then(|r| u32::from_str(&r).ok()) {
+                Some(r) if r >= 10 => Some(r),
+                _ => {
+                    eprintln!("Expected a sample rate of at least 10 Hz after --rate.");
+                    std::process::exit(1);
+                }
+            };
+        } else if arg == "--channels" {
+            raw_channels = match args.next().and_then(|n| u32::from_str(&n).ok()) {
+                Some(n) if n > 0 => Some(n),
+                _ => {
+                    eprintln!("Expected a positive number of channels after --channels.");
+                    std::process::exit(1);
+                }
+            };
+        } else if arg == "--ffmpeg" {
+            ffmpeg = true;
+        } else if arg == "--duration-weighted" {
+            duration_weighted = true;
+        } else if arg == "--write-tags" {
             write_tags = true;
+        } else if arg == "--write-report" {
+            report_format = Some(ReportFormat::Text);
+        } else if arg == "--write-report-json" {
+            report_format = Some(ReportFormat::Json);
+        } else if arg == "--write-trace" {
+            trace_format = Some(ReportFormat::Text);
+        } else if arg == "--write-trace-json" {
+            trace_format = Some(ReportFormat::Json);
         } else if arg == "--skip-when-tags-present" {
             skip_when_tags_present = true;
+        } else if arg == "--start" || arg == "--duration" {
+            let time = match args.next().as_ref().and_then(|t| parse_time(t)) {
+                Some(t) => t,
+                None => {
+                    eprintln!("Expected a time such as '90' or '1:30' after {}.", arg);
+                    std::process::exit(1);
+                }
+            };
+            if arg == "--start" {
+                range.start = time;
+            } else {
+                range.duration = Some(time);
+            }
         } else {
             fnames.push(PathBuf::from(arg));
         }
     }
 
-    let album_result = match analyze_album(fnames, skip_when_tags_present) {
-        Ok(r) => r,
-        Err(e) => {
-            eprintln!("Failed to analzye album: {}", e);
+    // Tags describe the loudness of the entire track, so they should not be
+    // derived from a part of it.
+    let is_partial = range.start > Duration::from_secs(0) || range.duration.is_some();
+    if write_tags && is_partial {
+        eprintln!("--write-tags cannot be combined with --start or --duration.");
+        std::process::exit(1);
+    }
+    if verify && is_partial {
+        eprintln!("--verify cannot be combined with --start or --duration.");
+        std::process::exit(1);
+    }
+    if verify && write_tags {
+        eprintln!("--verify only reads tags, it cannot be combined with --write-tags.");
+        std::process::exit(1);
+    }
+
+    // Tracks whose loudness is read from the tags are not decoded, so we
+    // have neither their peaks nor their windows, nor a fresh measurement.
+    if duration_weighted {
+        let conflicting = if verify {
+            Some("--verify")
+        } else if trace_format.is_some() {
+            Some("--write-trace")
+        } else if let TagFormat::ReplayGain = tag_format {
+            Some("--replaygain")
+        } else {
+            None
+        };
+        if let Some(flag) = conflicting {
+            eprintln!("--duration-weighted cannot be combined with {}.", flag);
+            std::process::exit(1);
+        }
+    }
+
+    // Reports are written per directory, so albums that share a directory
+    // would overwrite each other's report.
+    if group_by_tags && report_format.is_some() {
+        eprintln!("--group-by-tags cannot be combined with --write-report.");
+        std::process::exit(1);
+    }
+
+    // Raw PCM has no header, so the layout must be given in full.
+    let raw = match (raw_format, raw_rate, raw_channels) {
+        (Some(format), Some(sample_rate_hz), Some(num_channels)) => Some(RawInput {
+            format,
+            sample_rate_hz,
+            num_channels,
+        }),
+        (None, None, None) => None,
+        _ => {
+            eprintln!("--raw, --rate, and --channels must be passed together.");
             std::process::exit(1);
         }
     };
+    let num_stdin = fnames.iter().filter(|f| *f == Path::new("-")).count();
+    if num_stdin > 1 {
+        eprintln!("Stdin can only be read once, pass '-' at most once.");
+        std::process::exit(1);
+    }
+    if num_stdin > 0 && trace_format.is_some() {
+        eprintln!("--write-trace writes next to the input file, it cannot be combined with '-'.");
+        std::process::exit(1);
+    }
 
-    album_result.print();
+    if !roots.is_empty() && !fnames.is_empty() {
+        eprintln!("--recursive cannot be combined with file names.");
+        std::process::exit(1);
+    }
 
-    if write_tags {
-        match album_result.write_tags() {
-            Ok(()) => {}
-            Err(e) => {
-                eprintln!("Failed to update tags: {}", e);
-                std::process::exit(1);
+    let log = Log::new(verbosity);
+    let highlight = Highlight {
+        enabled: color,
+        target_lkfs,
+        ceiling_dbtp,
+    };
+    let true_peak = output_format.needs_true_peak() || highlight.needs_true_peak();
+    let options = AlbumOptions {
+        skip_when_tags_present,
+        tag_format,
+        range,
+        input: Input {
+            raw,
+            ffmpeg,
+            duration_weighted,
+            true_peak,
+        },
+        jobs,
+        log,
+        output_format,
+        highlight,
+        report_format,
+        trace_format,
+        write_tags,
+        verify_tolerance_lu: if verify { Some(tolerance_lu) } else { None },
+    };
+
+    if roots.is_empty() && !group_by_tags {
+        if !process_album(fnames, &options) {
+            std::process::exit(1);
+        }
+        return
+    }
+
+    // Every album comes with a heading, the directory or the album tags.
+    let mut albums = Vec::new();
+    let mut num_failed = 0;
+    if roots.is_empty() {
+        albums.push((String::new(), fnames));
+    }
+    for root in &roots {
+        let mut dirs = Vec::new();
+        num_failed += collect_albums(root, ffmpeg, &mut dirs);
+        albums.extend(dirs.into_iter().map(|(dir, paths)| (dir.to_string_lossy().into_owned(), paths)));
+    }
+    if group_by_tags {
+        albums = albums.into_iter().flat_map(|(_, paths)| group_albums_by_tags(paths)).collect();
+    }
+
+    let num_albums = albums.len();
+    for (i, (heading, paths)) in albums.into_iter().enumerate() {
+        // The text summary only names the files, so print a heading above
+        // every album. Other formats are meant for scripts, which can take the
+        // directory from the file names or the report instead.
+        if let OutputFormat::Text = options.output_format {
+            if i > 0 {
+                println!();
             }
+            println!("{}:", heading);
+        }
+        if !process_album(paths, &options) {
+            num_failed += 1;
         }
     }
+
+    log.summary(format_args!("Processed {} albums.", num_albums));
+    if num_failed > 0 {
+        eprintln!("Failed to process {} albums or directories.", num_failed);
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,175 @@
+// BS1770 -- Loudness analysis library conforming to ITU-R BS.1770
+// Copyright 2020 Ruud van Asseldonk
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! This example is a live loudness meter for the terminal. It reads raw
+//! interleaved 16-bit little-endian PCM from stdin, for example from
+//!
+//!     arecord -f S16_LE -r 48000 -c 2 | livemeter --rate 48000 --channels 2
+//!
+//! and shows bars for the momentary (400ms) and short-term (3s) loudness, the
+//! integrated loudness and loudness range so far, and the true peak with hold.
+//! The meter does not keep the audio or its windows, so it can run
+//! indefinitely in a fixed amount of memory.
+
+extern crate bs1770;
+
+use std::io;
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+use bs1770::channels::{ChannelMap, ChannelOrder};
+use bs1770::program::ProgramMeter;
+use bs1770::true_peak::TruePeakMeter;
+use bs1770::{ChannelLoudnessMeter, Power};
+
+/// The loudness at the left end of the bars.
+const BAR_MIN_LKFS: f32 = -60.0;
+
+/// The loudness at the right end of the bars.
+const BAR_MAX_LKFS: f32 = 0.0;
+
+/// Width of the bars, in characters.
+const BAR_WIDTH: usize = 50;
+
+/// Number of lines that `render` prints.
+const NUM_LINES: usize = 5;
+
+fn format_lkfs(power: Option<Power>) -> String {
+    match power {
+        Some(p) if p.0 > 0.0 => format!("{:5.1} LKFS", p.loudness_lkfs()),
+        _ => "  -inf LKFS".to_string(),
+    }
+}
+
+fn bar(power: Option<Power>) -> String {
+    let lkfs = power.map(|p| p.loudness_lkfs()).unwrap_or(BAR_MIN_LKFS);
+    let fraction = (lkfs - BAR_MIN_LKFS) / (BAR_MAX_LKFS - BAR_MIN_LKFS);
+    let n = ((fraction * BAR_WIDTH as f32) as usize).min(BAR_WIDTH);
+    let mut result = String::with_capacity(BAR_WIDTH);
+    for i in 0..BAR_WIDTH {
+        result.push(if i < n { '#' } else { '-' });
+    }
+    result
+}
+
+fn render<W: Write>(
+    out: &mut W,
+    program: &ProgramMeter,
+    peak_meters: &[TruePeakMeter],
+    is_first: bool,
+) -> io::Result<()> {
+    // Move the cursor back up to overwrite the previous frame.
+    if !is_first {
+        write!(out, "\x1b[{}A", NUM_LINES)?;
+    }
+
+    let momentary = program.momentary();
+    let short_term = program.short_term();
+    let integrated = program.integrated();
+    let peak_hold = peak_meters.iter().fold(0.0_f32, |peak, m| peak.max(m.peak()));
+    let peak_dbtp = 20.0 * peak_hold.log10();
+
+    writeln!(out, "\x1b[2KM [{}] {}", bar(momentary), format_lkfs(momentary))?;
+    writeln!(out, "\x1b[2KS [{}] {}", bar(short_term), format_lkfs(short_term))?;
+    writeln!(out, "\x1b[2KIntegrated: {}", format_lkfs(integrated))?;
+    match program.loudness_range_lu() {
+        Some(lra) => writeln!(out, "\x1b[2KRange: {:5.1} LU", lra)?,
+        None => writeln!(out, "\x1b[2KRange:     - LU")?,
+    }
+    writeln!(out, "\x1b[2KTrue peak: {:5.1} dBTP", peak_dbtp)?;
+    out.flush()
+}
+
+fn main() {
+    let mut sample_rate_hz = 48_000;
+    let mut num_channels = 2;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = args.next().and_then(|v| u32::from_str(&v).ok());
+        match (&arg[..], value) {
+            ("--rate", Some(v)) if v >= 10 => sample_rate_hz = v,
+            ("--channels", Some(v)) => num_channels = v,
+            _ => {
+                eprintln!("Usage: livemeter [--rate HZ] [--channels N] < raw_s16le_pcm");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let channel_map = match ChannelMap::from_order(ChannelOrder::Wave, num_channels) {
+        Some(map) => map,
+        None => {
+            eprintln!("Unsupported number of channels: {}.", num_channels);
+            std::process::exit(1);
+        }
+    };
+
+    let mut meters = vec![ChannelLoudnessMeter::new(sample_rate_hz); num_channels as usize];
+    let mut peak_meters = vec![TruePeakMeter::new(); num_channels as usize];
+
+    // The readouts, fed with the power of every 100ms window, summed over channels.
+    let mut program = ProgramMeter::new();
+
+    // Read about 100ms of audio at a time, so we update the display for every
+    // window. Every frame has 2 bytes per channel.
+    let frame_len = 2 * num_channels as usize;
+    let mut buffer = vec![0_u8; frame_len * (sample_rate_hz as usize / 10)];
+    let mut buffer_len = 0;
+    let mut samples = Vec::with_capacity(buffer.len() / 2);
+
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut is_first = true;
+
+    loop {
+        let n = match input.read(&mut buffer[buffer_len..]) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                eprintln!("Failed to read input: {}", e);
+                std::process::exit(1);
+            }
+        };
+        buffer_len += n;
+
+        // Only process complete frames, keep a partial frame for the next read.
+        let num_bytes = buffer_len - buffer_len % frame_len;
+        samples.clear();
+        samples.extend(buffer[..num_bytes].chunks(2).map(|b| {
+            i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0
+        }));
+        buffer.copy_within(num_bytes..buffer_len, 0);
+        buffer_len -= num_bytes;
+
+        for (ch, peak_meter) in peak_meters.iter_mut().enumerate() {
+            peak_meter.push(samples.iter().skip(ch).step_by(num_channels as usize).cloned());
+        }
+
+        bs1770::push_interleaved(&mut meters, &samples);
+
+        // All channels have the same number of windows, because we only push
+        // complete frames. Take the windows that completed since last time out
+        // of the meters, so the meters do not keep every window.
+        let channel_windows: Vec<_> = meters.iter_mut().map(|m| m.drain_windows()).collect();
+        let channel_windows: Vec<_> = channel_windows.iter().map(|w| w.as_ref()).collect();
+        let new_windows = channel_map.reduce(&channel_windows);
+        if new_windows.inner.is_empty() {
+            continue
+        }
+        program.push(new_windows.as_ref());
+
+        if let Err(e) = render(&mut out, &program, &peak_meters, is_first) {
+            eprintln!("Failed to write output: {}", e);
+            std::process::exit(1);
+        }
+        is_first = false;
+    }
+}
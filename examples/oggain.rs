@@ -0,0 +1,344 @@
+// BS1770 -- Loudness analysis library conforming to ITU-R BS.1770
+// Copyright 2020 Ruud van Asseldonk
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! This example measures the loudness of Ogg Vorbis and Opus files, and can
+//! store the gain to reach -23 LUFS in `R128_TRACK_GAIN` and `R128_ALBUM_GAIN`
+//! tags, as RFC 7845 defines for Opus:
+//!
+//!     oggain [--write-tags] FILE...
+//!
+//! Like `flacgain`, it assumes that the files together form an album. The
+//! gains are Q7.8 fixed-point integers in units of 1/256 dB. Vorbis decoding
+//! uses Lewton, Opus decoding uses libopus through Audiopus, and only supports
+//! mono and stereo Opus streams (channel mapping family 0).
+
+extern crate audiopus;
+extern crate bs1770;
+extern crate lewton;
+extern crate ogg;
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use bs1770::album::AlbumAccumulator;
+use bs1770::channels::{ChannelMap, ChannelOrder};
+use bs1770::tags;
+use bs1770::vorbis_comment::Tags;
+use bs1770::{ChannelLoudnessMeter, Power};
+use ogg::{PacketReader, PacketWriteEndInfo, PacketWriter};
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+/// The loudness that R128 gain tags are relative to, in LKFS.
+const R128_REFERENCE_LKFS: f32 = -23.0;
+
+/// The maximum duration of an Opus packet, 120ms, in samples at 48 kHz.
+const OPUS_MAX_PACKET_SAMPLES: usize = 5760;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Codec {
+    Vorbis,
+    Opus,
+}
+
+impl Codec {
+    /// Identify the codec from the first packet of a stream.
+    fn from_id_header(packet: &[u8]) -> Option<Codec> {
+        if packet.starts_with(b"\x01vorbis") {
+            Some(Codec::Vorbis)
+        } else if packet.starts_with(b"OpusHead") {
+            Some(Codec::Opus)
+        } else {
+            None
+        }
+    }
+
+    /// Return the packet type and magic that the comment header starts with.
+    fn comment_magic(self) -> &'static [u8] {
+        match self {
+            Codec::Vorbis => b"\x03vorbis",
+            Codec::Opus => b"OpusTags",
+        }
+    }
+}
+
+/// Loudness measurement for a track.
+struct Track {
+    path: PathBuf,
+    codec: Codec,
+    gated_power: Power,
+}
+
+fn open_packet_reader(path: &Path) -> io::Result<PacketReader<io::BufReader<fs::File>>> {
+    Ok(PacketReader::new(io::BufReader::new(fs::File::open(path)?)))
+}
+
+fn detect_codec(path: &Path) -> Result<Codec> {
+    let id_header = open_packet_reader(path)?.read_packet_expected()?;
+    match Codec::from_id_header(&id_header.data) {
+        Some(codec) => Ok(codec),
+        None => Err("Not an Ogg Vorbis or Opus file.".into()),
+    }
+}
+
+fn vorbis_channel_map(num_channels: u32) -> Result<ChannelMap> {
+    match ChannelMap::from_order(ChannelOrder::Vorbis, num_channels) {
+        Some(map) => Ok(map),
+        None => Err(format!("Unsupported number of channels: {}.", num_channels).into()),
+    }
+}
+
+fn measure_vorbis(path: &Path) -> Result<(ChannelMap, Vec<ChannelLoudnessMeter>)> {
+    let mut reader = lewton::inside_ogg::OggStreamReader::new(io::BufReader::new(fs::File::open(path)?))?;
+    let num_channels = reader.ident_hdr.audio_channels as u32;
+    let channel_map = vorbis_channel_map(num_channels)?;
+    let mut meters = vec![
+        ChannelLoudnessMeter::new(reader.ident_hdr.audio_sample_rate);
+        num_channels as usize
+    ];
+
+    // Lewton trims the last packet to the granule position of the last page.
+    while let Some(channels) = reader.read_dec_packet_generic::<Vec<Vec<f32>>>()? {
+        for (meter, samples) in meters.iter_mut().zip(channels.iter()) {
+            meter.push_slice(samples);
+        }
+    }
+
+    Ok((channel_map, meters))
+}
+
+fn measure_opus(path: &Path) -> Result<(ChannelMap, Vec<ChannelLoudnessMeter>)> {
+    let mut reader = open_packet_reader(path)?;
+
+    // See RFC 7845 section 5.1 for the layout of the identification header.
+    let id_header = reader.read_packet_expected()?.data;
+    if id_header.len() < 19 {
+        return Err("OpusHead packet is truncated.".into())
+    }
+    let num_channels = id_header[9] as usize;
+    let pre_skip = u16::from_le_bytes([id_header[10], id_header[11]]) as u64;
+    let output_gain_db = i16::from_le_bytes([id_header[16], id_header[17]]) as f32 / 256.0;
+    let channels = match (id_header[18], num_channels) {
+        (0, 1) => audiopus::Channels::Mono,
+        (0, 2) => audiopus::Channels::Stereo,
+        _ => return Err("Only mono and stereo Opus streams are supported.".into()),
+    };
+    let channel_map = vorbis_channel_map(num_channels as u32)?;
+
+    // Skip over the comment header, audio packets follow it.
+    reader.read_packet_expected()?;
+
+    // Opus always decodes at 48 kHz. The output gain is part of the stream,
+    // players apply it, so we measure the audio with the gain applied.
+    let mut decoder = audiopus::coder::Decoder::new(audiopus::SampleRate::Hz48000, channels)?;
+    let output_gain = 10.0_f32.powf(output_gain_db / 20.0);
+    let mut meters = vec![ChannelLoudnessMeter::new(48_000); num_channels];
+    let mut buffer = vec![0.0_f32; OPUS_MAX_PACKET_SAMPLES * num_channels];
+
+    // Samples per channel decoded so far, including the pre-skip.
+    let mut num_decoded = 0_u64;
+
+    while let Some(packet) = reader.read_packet()? {
+        if packet.data.is_empty() {
+            continue
+        }
+        let len = decoder.decode_float(
+            Some(audiopus::packet::Packet::try_from(&packet.data[..])?),
+            audiopus::MutSignals::try_from(&mut buffer[..])?,
+            false,
+        )? as u64;
+
+        // The first samples are the decoder's warm-up, which the pre-skip
+        // discards. The granule position of the last page marks the end of
+        // the audio, the last packet may decode to more than that.
+        let start = pre_skip.saturating_sub(num_decoded).min(len);
+        let end = if packet.last_in_stream() {
+            packet.absgp_page().saturating_sub(num_decoded).min(len).max(start)
+        } else {
+            len
+        };
+        num_decoded += len;
+
+        let samples = &mut buffer[start as usize * num_channels..end as usize * num_channels];
+        for x in samples.iter_mut() {
+            *x *= output_gain;
+        }
+        bs1770::push_interleaved(&mut meters, samples);
+    }
+
+    Ok((channel_map, meters))
+}
+
+fn analyze_file(path: &Path, album: &mut AlbumAccumulator) -> Result<Track> {
+    let codec = detect_codec(path)?;
+    let (channel_map, meters) = match codec {
+        Codec::Vorbis => measure_vorbis(path)?,
+        Codec::Opus => measure_opus(path)?,
+    };
+    let channel_windows: Vec<_> = meters.iter().map(|m| m.as_100ms_windows()).collect();
+    let gated_power = album.add_track(&channel_map, &channel_windows);
+    Ok(Track {
+        path: path.to_path_buf(),
+        codec,
+        gated_power,
+    })
+}
+
+/// Return the R128 gain for audio with the given power, in dB.
+fn r128_gain_db(power: Power) -> f32 {
+    if power.0 > 0.0 {
+        R128_REFERENCE_LKFS - power.loudness_lkfs()
+    } else {
+        0.0
+    }
+}
+
+/// Return the comment header with the R128 gain tags replaced.
+fn update_comment_header(
+    codec: Codec,
+    packet: &[u8],
+    track_gain_db: f32,
+    album_gain_db: f32,
+) -> Result<Vec<u8>> {
+    let magic = codec.comment_magic();
+    if !packet.starts_with(magic) {
+        return Err("Second packet is not a comment header.".into())
+    }
+    let mut file_tags = Tags::parse(&packet[magic.len()..])?;
+
+    // RFC 7845 says that Opus files should not contain ReplayGain tags, the
+    // output gain and R128 tags replace them.
+    if codec == Codec::Opus {
+        for tag in ["REPLAYGAIN_ALBUM_GAIN", "REPLAYGAIN_ALBUM_PEAK", "REPLAYGAIN_TRACK_GAIN", "REPLAYGAIN_TRACK_PEAK"].iter() {
+            file_tags.remove(tag);
+        }
+    }
+    file_tags.set("R128_TRACK_GAIN", &tags::format_r128_gain(track_gain_db));
+    file_tags.set("R128_ALBUM_GAIN", &tags::format_r128_gain(album_gain_db));
+
+    let mut result = magic.to_vec();
+    result.extend_from_slice(&file_tags.serialize());
+    if codec == Codec::Vorbis {
+        // The Vorbis comment header ends with a framing bit.
+        result.push(1);
+    }
+    Ok(result)
+}
+
+/// Replace the comment header of the file with one that holds the R128 gains.
+///
+/// Ogg pages are checksummed, and the comment header can grow, so this
+/// rewrites the entire file. All other packets are copied with their granule
+/// positions, and pages end after the same packets as in the original. The new
+/// file is written next to the original, and then renamed over it.
+fn write_tags(track: &Track, track_gain_db: f32, album_gain_db: f32) -> Result<()> {
+    let mut reader = open_packet_reader(&track.path)?;
+    let mut tmp_fname = track.path.clone();
+    tmp_fname.set_extension("ogg.metadata_edit");
+    let mut writer = PacketWriter::new(io::BufWriter::new(fs::File::create(&tmp_fname)?));
+
+    let result = (|| -> Result<()> {
+        let mut first_serial = None;
+        let mut packet_index = 0;
+        while let Some(packet) = reader.read_packet()? {
+            let serial = packet.stream_serial();
+            if *first_serial.get_or_insert(serial) != serial {
+                return Err("Chained or multiplexed Ogg files are not supported.".into())
+            }
+            let end_info = if packet.last_in_stream() {
+                PacketWriteEndInfo::EndStream
+            } else if packet.last_in_page() {
+                PacketWriteEndInfo::EndPage
+            } else {
+                PacketWriteEndInfo::NormalPacket
+            };
+            let absgp = packet.absgp_page();
+            let data = if packet_index == 1 {
+                update_comment_header(track.codec, &packet.data, track_gain_db, album_gain_db)?
+            } else {
+                packet.data
+            };
+            writer.write_packet(data.into_boxed_slice(), serial, end_info, absgp)?;
+            packet_index += 1;
+        }
+        let file = writer.into_inner().into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+        Ok(())
+    })();
+
+    match result {
+        Ok(()) => Ok(fs::rename(&tmp_fname, &track.path)?),
+        Err(err) => {
+            // Do not leave a partial copy behind.
+            let _ = fs::remove_file(&tmp_fname);
+            Err(err)
+        }
+    }
+}
+
+fn format_lkfs(power: Power) -> String {
+    if power.0 > 0.0 {
+        format!("{:5.1} LKFS", power.loudness_lkfs())
+    } else {
+        "  -inf LKFS".to_string()
+    }
+}
+
+fn main() {
+    let mut fnames = Vec::new();
+    let mut write = false;
+
+    for arg in std::env::args().skip(1) {
+        if arg == "--write-tags" {
+            write = true;
+        } else {
+            fnames.push(PathBuf::from(arg));
+        }
+    }
+
+    if fnames.is_empty() {
+        eprintln!("Usage: oggain [--write-tags] FILE...");
+        std::process::exit(1);
+    }
+
+    let mut album = AlbumAccumulator::new();
+    let mut tracks = Vec::with_capacity(fnames.len());
+    for path in &fnames {
+        match analyze_file(path, &mut album) {
+            Ok(track) => tracks.push(track),
+            Err(e) => {
+                eprintln!("Failed to analyze {}: {}", path.to_string_lossy(), e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let album_power = album.gated_power().unwrap_or(Power(0.0));
+    for track in &tracks {
+        let fname = track.path.file_name().unwrap_or_default();
+        println!("{}  {}", format_lkfs(track.gated_power), fname.to_string_lossy());
+    }
+    println!("{}  ALBUM", format_lkfs(album_power));
+
+    if write {
+        let album_gain_db = r128_gain_db(album_power);
+        let mut num_files_failed = 0;
+        for track in &tracks {
+            if let Err(e) = write_tags(track, r128_gain_db(track.gated_power), album_gain_db) {
+                eprintln!("Failed to write tags to {}: {}", track.path.to_string_lossy(), e);
+                num_files_failed += 1;
+            }
+        }
+        if num_files_failed > 0 {
+            eprintln!("Failed to update tags of {} files.", num_files_failed);
+            std::process::exit(1);
+        }
+    }
+}
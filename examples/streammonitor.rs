@@ -0,0 +1,219 @@
+// BS1770 -- Loudness analysis library conforming to ITU-R BS.1770
+// Copyright 2020 Ruud van Asseldonk
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! This example monitors the loudness of an HLS or DASH stream, as delivered.
+//!
+//! Fetching the playlist or manifest and segments, and decoding the audio, is
+//! delegated to `ffmpeg`, which supports both HLS and DASH inputs. This
+//! program measures the decoded audio, and prints the momentary, short-term,
+//! and integrated loudness of the program so far at a fixed interval:
+//!
+//!     streammonitor https://example.com/live/audio.m3u8 --interval-seconds 1
+//!
+//! We ask `ffprobe` for the number of channels of the first audio stream, and
+//! have ffmpeg decode it at that channel count, in its native channel order,
+//! which for up to 8 channels is the WAVE order. Surround streams are
+//! therefore measured with the BS.1770 channel weights, not as a downmix.
+//!
+//! A stream may run indefinitely, so the monitor does not keep the windows it
+//! measured. A `ProgramMeter` tracks the momentary and short-term loudness of
+//! the most recent windows, and gates the program loudness with a histogram,
+//! which uses a fixed amount of memory.
+
+extern crate bs1770;
+
+use std::io;
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+use bs1770::Power;
+use bs1770::channels::{ChannelMap, ChannelOrder};
+use bs1770::multichannel::MultiChannelLoudnessMeter;
+use bs1770::program::ProgramMeter;
+
+const SAMPLE_RATE_HZ: u32 = 48_000;
+
+fn format_lkfs(power: Option<Power>) -> String {
+    match power {
+        Some(p) => format!("{:5.1} LKFS", p.loudness_lkfs()),
+        None => "  --- LKFS".to_string(),
+    }
+}
+
+/// Ask ffprobe for the number of channels of the first audio stream.
+fn probe_num_channels(ffprobe: &str, url: &str) -> Result<u32, String> {
+    let probe = Command::new(ffprobe)
+        .args(["-v", "error", "-select_streams", "a:0"].iter())
+        .args(["-show_entries", "stream=channels", "-of", "csv=p=0"].iter())
+        .arg(url)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| format!("Failed to start {}: {}", ffprobe, e))?;
+    if !probe.status.success() {
+        let message = String::from_utf8_lossy(&probe.stderr);
+        return Err(format!("ffprobe failed: {}", message.trim()))
+    }
+    match u32::from_str(String::from_utf8_lossy(&probe.stdout).trim()) {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err("Stream does not contain audio.".to_string()),
+    }
+}
+
+fn main() {
+    let mut url = None;
+    let mut interval_seconds = 1;
+    let mut ffmpeg = "ffmpeg".to_string();
+    let mut ffprobe = "ffprobe".to_string();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--interval-seconds" {
+            interval_seconds = match args.next().and_then(|s| u32::from_str(&s).ok()) {
+                Some(s) if s > 0 => s,
+                _ => {
+                    eprintln!("Expected a positive number of seconds after --interval-seconds.");
+                    std::process::exit(1);
+                }
+            };
+        } else if arg == "--ffmpeg" || arg == "--ffprobe" {
+            let path = match args.next() {
+                Some(path) => path,
+                None => {
+                    eprintln!("Expected a path after {}.", arg);
+                    std::process::exit(1);
+                }
+            };
+            if arg == "--ffmpeg" {
+                ffmpeg = path;
+            } else {
+                ffprobe = path;
+            }
+        } else {
+            url = Some(arg);
+        }
+    }
+
+    let url = match url {
+        Some(url) => url,
+        None => {
+            eprintln!("Usage: streammonitor URL [--interval-seconds N] [--ffmpeg PATH] [--ffprobe PATH]");
+            std::process::exit(1);
+        }
+    };
+
+    let num_channels = match probe_num_channels(&ffprobe, &url) {
+        Ok(n) => n,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let channel_map = match ChannelMap::from_order(ChannelOrder::Wave, num_channels) {
+        Some(map) => map,
+        None => {
+            eprintln!("Unsupported number of channels: {}.", num_channels);
+            std::process::exit(1);
+        }
+    };
+
+    let child = Command::new(&ffmpeg)
+        .arg("-hide_banner")
+        .arg("-loglevel").arg("error")
+        .arg("-i").arg(&url)
+        // Decode only the audio, as 32-bit float at our sample rate, and write
+        // it to stdout. We pass the channel count explicitly, so it stays the
+        // same if the stream switches to a variant with a different layout.
+        .arg("-vn")
+        .arg("-f").arg("f32le")
+        .arg("-ac").arg(num_channels.to_string())
+        .arg("-ar").arg(SAMPLE_RATE_HZ.to_string())
+        .arg("-")
+        .stdout(Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("Failed to start {}: {}", ffmpeg, e);
+            std::process::exit(1);
+        }
+    };
+    let mut input = child.stdout.take().expect("Stdout is piped.");
+
+    let mut meter = MultiChannelLoudnessMeter::new(SAMPLE_RATE_HZ, channel_map);
+    let mut program = ProgramMeter::new();
+    let windows_per_interval = interval_seconds as u64 * 10;
+    let mut num_windows = 0_u64;
+    let mut next_report = windows_per_interval;
+
+    // Read 100ms of f32 samples at a time.
+    let frame_len = 4 * num_channels as usize;
+    let mut buffer = vec![0_u8; frame_len * SAMPLE_RATE_HZ as usize / 10];
+    let mut buffer_len = 0;
+    let mut samples = Vec::with_capacity(buffer.len() / 4);
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let _ = writeln!(out, "     time   momentary  short-term  integrated");
+
+    loop {
+        let n = match input.read(&mut buffer[buffer_len..]) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                eprintln!("Failed to read from ffmpeg: {}", e);
+                std::process::exit(1);
+            }
+        };
+        buffer_len += n;
+
+        // Only process complete frames, keep a partial frame for the next read.
+        let num_bytes = buffer_len - buffer_len % frame_len;
+        samples.clear();
+        samples.extend(buffer[..num_bytes].chunks(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])));
+        meter.push_interleaved(&samples);
+        buffer.copy_within(num_bytes..buffer_len, 0);
+        buffer_len -= num_bytes;
+
+        // Take the windows that completed since last time out of the meter,
+        // so memory stays bounded however long the stream runs.
+        let new_windows = meter.drain_windows();
+        program.push(new_windows.as_ref());
+        num_windows += new_windows.len() as u64;
+
+        while num_windows >= next_report {
+            let seconds = next_report / 10;
+            let _ = writeln!(
+                out,
+                "{:3}:{:02}:{:02}  {}  {}  {}",
+                seconds / 3600,
+                seconds / 60 % 60,
+                seconds % 60,
+                format_lkfs(program.momentary()),
+                format_lkfs(program.short_term()),
+                format_lkfs(program.integrated()),
+            );
+            let _ = out.flush();
+            next_report += windows_per_interval;
+        }
+    }
+
+    let _ = writeln!(out, "Program: {}", format_lkfs(program.integrated()));
+
+    match child.wait() {
+        Ok(status) if status.success() => {}
+        Ok(status) => {
+            eprintln!("ffmpeg exited with {}.", status);
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("Failed to wait for ffmpeg: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -8,23 +8,97 @@
 //! This example renders a “waveform” that represents the audio file. It does
 //! not show the actual audio wave, but it does give a visual clue of
 //! interesting points in the track.
+//!
+//! For stereo files, the left channel is drawn upwards and the right channel
+//! downwards. Mono files are mirrored, and files with more channels get a
+//! mirrored lane per channel, stacked from top to bottom.
+//!
+//!     waveform [--window-ms MS] [--pixels-per-second N] FILE.flac > waveform.svg
+//!
+//! The power is averaged over a window of 500ms by default, and the image is
+//! one pixel wide per second of audio. For short clips, use a shorter window
+//! and more pixels per second, for long DJ mixes, a longer window and fewer.
 
 extern crate bs1770;
 extern crate claxon;
 
+use std::str::FromStr;
+
+use bs1770::blocks::{self, BlockConfig};
 use claxon::FlacReader;
 
+/// The number of points that the path has per pixel.
+const POINTS_PER_PIXEL: f32 = 10.0;
+
+/// Print a filled path around the horizontal line at `center`.
+///
+/// The upper amplitudes extend above the line, the lower ones below it. The
+/// amplitudes are powers, relative to `max`.
+fn print_lane(upper: &[f32], lower: &[f32], center: f32, max: f32, x_step: f32) {
+    println!(r#"<path d="M 0 {} "#, center + 5.0);
+
+    for (i, amplitude) in upper.iter().enumerate() {
+        let y = center - 5.0 * (amplitude / max + 1e-10).sqrt();
+        assert!(!y.is_nan());
+        print!("L {:.1} {:.1} ", i as f32 * x_step, y);
+    }
+
+    for (i, amplitude) in lower.iter().enumerate().rev() {
+        let y = center + 5.0 * (amplitude / max + 1e-10).sqrt();
+        assert!(!y.is_nan());
+        print!("L {:.1} {:.1} ", i as f32 * x_step, y);
+    }
+
+    println!(r#"" fill="black"/>"#);
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: waveform [--window-ms MS] [--pixels-per-second N] FILE.flac");
+    std::process::exit(1);
+}
+
 fn main() -> claxon::Result<()> {
-    let fname = std::env::args().skip(1).next().expect("Need input filename.");
+    let mut window_ms = 500.0_f32;
+    let mut pixels_per_second = 1.0_f32;
+    let mut fname = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--window-ms" || arg == "--pixels-per-second" {
+            let value = match args.next().and_then(|v| f32::from_str(&v).ok()) {
+                Some(v) if v > 0.0 && v.is_finite() => v,
+                _ => usage(),
+            };
+            if arg == "--window-ms" {
+                window_ms = value;
+            } else {
+                pixels_per_second = value;
+            }
+        } else if fname.is_none() && !arg.starts_with("--") {
+            fname = Some(arg);
+        } else {
+            usage();
+        }
+    }
+
+    let fname = match fname {
+        Some(f) => f,
+        None => usage(),
+    };
     let mut reader = FlacReader::open(fname)?;
 
     let streaminfo = reader.streaminfo();
-    // The maximum amplitude is 1 << (bits per sample - 1), because one bit
-    // is the sign bit.
-    let normalizer = 1.0 / (1_u64 << (streaminfo.bits_per_sample - 1)) as f32;
+
+    // The meters produce one window per point of the path. The smoothing
+    // window spans a whole number of these, but at least one.
+    let windows_per_second = ((POINTS_PER_PIXEL * pixels_per_second).round() as u32)
+        .max(1)
+        .min(streaminfo.sample_rate);
+    let block_len = ((window_ms * 1e-3 * windows_per_second as f32).round() as usize).max(1);
+    let x_step = pixels_per_second / windows_per_second as f32;
 
     let mut meters = vec![
-        bs1770::ChannelLoudnessMeter::new(streaminfo.sample_rate);
+        bs1770::ChannelLoudnessMeter::with_windows_per_second(streaminfo.sample_rate, windows_per_second);
         streaminfo.channels as usize
     ];
 
@@ -33,56 +107,60 @@ fn main() -> claxon::Result<()> {
 
     while let Some(block) = blocks.read_next_or_eof(buffer)? {
         for (ch, meter) in meters.iter_mut().enumerate() {
-            meter.push(block.channel(ch as u32).iter().map(|s| *s as f32 * normalizer));
+            meter.push_i32(block.channel(ch as u32).iter().cloned(), streaminfo.bits_per_sample);
         }
         buffer = block.into_buffer();
     }
 
     let mut amplitudes: Vec<Vec<_>> = meters
         .iter()
-        .map(|m| Vec::with_capacity(m.as_100ms_windows().len()))
+        .map(|m| Vec::with_capacity(m.as_windows().len()))
         .collect();
 
     let mut max = 0.0;
 
     // Extract just the power measurements from the meters.
-    let meters: Vec<_> = meters.drain(..).map(|m| m.into_100ms_windows()).collect();
+    let meters: Vec<_> = meters.drain(..).map(|m| m.into_windows()).collect();
 
     for (ch, meter) in meters.iter().enumerate() {
-        // Measure power over windows of 0.5s long, and sample such windows at
-        // 10 Hz. The 0.5s window provides a good trade-off between graphs that
-        // are too spiky to see at a glance, and graphs that are too smeared out
-        // to have any detail.
-        for window_2s in meter.inner.windows(5) {
-            let power = 0.2 * window_2s.iter().map(|po| po.0).sum::<f32>();
-            if power > max { 
-                max = power;
+        // Measure power over a sliding window, 0.5s long by default, sampled
+        // at every point. The 0.5s window provides a good trade-off between
+        // graphs that are too spiky to see at a glance, and graphs that are too
+        // smeared out to have any detail.
+        let config = BlockConfig { block_len, step: 1 };
+        for power in blocks::block_powers(&meter.inner, config) {
+            if power.0 > max {
+                max = power.0;
             }
-            amplitudes[ch].push(power);
+            amplitudes[ch].push(power.0);
         }
     }
 
+    // For silence, all amplitudes are zero, avoid dividing by zero.
+    if max == 0.0 {
+        max = 1.0;
+    }
+
+    // Stereo shares one lane, every other channel gets a lane of its own.
+    let lanes: Vec<(&[f32], &[f32])> = if amplitudes.len() == 2 {
+        vec![(&amplitudes[0], &amplitudes[1])]
+    } else {
+        amplitudes.iter().map(|a| (&a[..], &a[..])).collect()
+    };
+
     let n = amplitudes[0].len();
 
     println!(
-        r#"<svg width="{:.1}" height="10" xmlns="http://www.w3.org/2000/svg">"#,
-        n as f32 * 0.1
+        r#"<svg width="{:.1}" height="{}" xmlns="http://www.w3.org/2000/svg">"#,
+        n as f32 * x_step,
+        10 * lanes.len(),
     );
-    println!(r#"<path d="M 0 10 "#);
-
-    for (i, amplitude) in amplitudes[0].iter().enumerate() {
-        let y = 5.0 - 5.0 * (amplitude / max + 1e-10).sqrt();
-        assert_eq!(y, y);
-        print!("L {:.1} {:.1} ", i as f32 * 0.1, y);
-    }
 
-    for (i, amplitude) in amplitudes[1].iter().enumerate().rev() {
-        let y = 5.0 + 5.0 * (amplitude / max + 1e-10).sqrt();
-        assert_eq!(y, y);
-        print!("L {:.1} {:.1} ", i as f32 * 0.1, y);
+    for (i, &(upper, lower)) in lanes.iter().enumerate() {
+        print_lane(upper, lower, 10.0 * i as f32 + 5.0, max, x_step);
     }
 
-    println!(r#"" fill="black"/></svg>"#);
+    println!("</svg>");
 
     Ok(())
 }
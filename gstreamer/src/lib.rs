@@ -0,0 +1,36 @@
+// BS1770 -- Loudness analysis library conforming to ITU-R BS.1770
+// Copyright 2020 Ruud van Asseldonk
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! A GStreamer plugin with the `bs1770meter` element.
+//!
+//! The element passes audio through unmodified, and periodically posts element
+//! messages named `bs1770meter` on the bus, with the momentary, short-term,
+//! and integrated loudness in LKFS:
+//!
+//!     gst-launch-1.0 -m filesrc location=in.flac ! decodebin ! audioconvert \
+//!         ! bs1770meter ! fakesink
+
+use gst::glib;
+
+mod meter;
+
+fn plugin_init(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    meter::register(plugin)
+}
+
+gst::plugin_define!(
+    bs1770,
+    env!("CARGO_PKG_DESCRIPTION"),
+    plugin_init,
+    concat!(env!("CARGO_PKG_VERSION"), "-", env!("COMMIT_ID")),
+    // GStreamer does not have an identifier for the Apache 2.0 license.
+    "unknown",
+    env!("CARGO_PKG_NAME"),
+    env!("CARGO_PKG_NAME"),
+    env!("CARGO_PKG_REPOSITORY"),
+    env!("BUILD_REL_DATE")
+);
@@ -0,0 +1,307 @@
+// BS1770 -- Loudness analysis library conforming to ITU-R BS.1770
+// Copyright 2020 Ruud van Asseldonk
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+use std::sync::Mutex;
+
+use bs1770::channels::{Channel, ChannelMap};
+use bs1770::{ChannelLoudnessMeter, Power, Windows100ms};
+use gst::glib;
+use gst::prelude::*;
+use gst::subclass::prelude::*;
+use gst_audio::AudioChannelPosition;
+use gst_base::subclass::prelude::*;
+use once_cell::sync::Lazy;
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "bs1770meter",
+        gst::DebugColorFlags::empty(),
+        Some("BS.1770 loudness meter"),
+    )
+});
+
+/// The default time between messages: one second.
+const DEFAULT_INTERVAL_NS: u64 = 1_000_000_000;
+
+/// The duration of a window produced by `ChannelLoudnessMeter`.
+const WINDOW_NS: u64 = 100_000_000;
+
+struct Settings {
+    /// Time between messages, in nanoseconds. Rounded to a multiple of 100ms.
+    interval_ns: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            interval_ns: DEFAULT_INTERVAL_NS,
+        }
+    }
+}
+
+struct State {
+    channel_map: ChannelMap,
+    meters: Vec<ChannelLoudnessMeter>,
+
+    /// The power of every 100ms window, summed over channels.
+    windows: Vec<Power>,
+
+    /// The number of windows at the time we last posted a message.
+    num_windows_posted: usize,
+}
+
+#[derive(Default)]
+pub struct Bs1770Meter {
+    settings: Mutex<Settings>,
+    state: Mutex<Option<State>>,
+}
+
+/// Map GStreamer channel positions onto BS.1770 loudspeaker positions.
+fn channel_map_from_positions(positions: &[AudioChannelPosition]) -> ChannelMap {
+    use gst_audio::AudioChannelPosition as P;
+
+    // When a layout has both side and rear channels (7.1), the rear channels
+    // are the rear surround pair. Otherwise, they are the surround pair.
+    let has_side = positions.iter().any(|&p| p == P::SideLeft || p == P::SideRight);
+    let (rear_left, rear_right) = if has_side {
+        (Channel::LeftBack, Channel::RightBack)
+    } else {
+        (Channel::LeftSurround, Channel::RightSurround)
+    };
+
+    let channels = positions.iter().map(|&p| match p {
+        P::FrontLeft => Channel::Left,
+        P::FrontRight => Channel::Right,
+        P::FrontCenter => Channel::Center,
+        P::Mono => Channel::Mono,
+        P::Lfe1 | P::Lfe2 => Channel::Lfe,
+        P::RearLeft => rear_left,
+        P::RearRight => rear_right,
+        P::SideLeft | P::SurroundLeft => Channel::LeftSurround,
+        P::SideRight | P::SurroundRight => Channel::RightSurround,
+        P::RearCenter => Channel::BackCenter,
+        P::FrontLeftOfCenter => Channel::LeftOfCenter,
+        P::FrontRightOfCenter => Channel::RightOfCenter,
+        P::TopFrontLeft | P::TopFrontRight | P::TopFrontCenter | P::TopCenter
+        | P::TopRearLeft | P::TopRearRight | P::TopSideLeft | P::TopSideRight
+        | P::TopRearCenter => Channel::Top,
+        _ => Channel::Unknown,
+    }).collect();
+
+    ChannelMap::new(channels)
+}
+
+fn mean_of_last(windows: &[Power], n: usize) -> Option<f64> {
+    if windows.len() < n {
+        return None
+    }
+    let sum: f32 = windows[windows.len() - n..].iter().map(|p| p.0).sum();
+    Some(Power(sum / n as f32).loudness_lkfs() as f64)
+}
+
+impl Bs1770Meter {
+    /// Post a `bs1770meter` element message with the current loudness.
+    fn post_loudness(&self, state: &mut State) {
+        let windows = &state.windows[..];
+        let integrated = bs1770::gated_mean(Windows100ms { inner: windows })
+            .unwrap_or(Power(0.0))
+            .loudness_lkfs() as f64;
+
+        let mut s = gst::Structure::builder("bs1770meter")
+            .field("duration", windows.len() as u64 * WINDOW_NS)
+            .field("integrated", integrated);
+        // Momentary and short-term loudness are measured over 400ms and 3s,
+        // they are absent until that much audio has been measured.
+        if let Some(m) = mean_of_last(windows, 4) {
+            s = s.field("momentary", m);
+        }
+        if let Some(st) = mean_of_last(windows, 30) {
+            s = s.field("short-term", st);
+        }
+
+        state.num_windows_posted = windows.len();
+        let message = gst::message::Element::builder(s.build())
+            .src(&*self.obj())
+            .build();
+        let _ = self.obj().post_message(message);
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for Bs1770Meter {
+    const NAME: &'static str = "GstBs1770Meter";
+    type Type = super::Bs1770Meter;
+    type ParentType = gst_base::BaseTransform;
+}
+
+impl ObjectImpl for Bs1770Meter {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
+            vec![glib::ParamSpecUInt64::builder("interval")
+                .nick("Interval")
+                .blurb("Interval between loudness messages, in nanoseconds")
+                .minimum(WINDOW_NS)
+                .default_value(DEFAULT_INTERVAL_NS)
+                .mutable_playing()
+                .build()]
+        });
+        PROPERTIES.as_ref()
+    }
+
+    fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        match pspec.name() {
+            "interval" => {
+                let mut settings = self.settings.lock().unwrap();
+                settings.interval_ns = value.get().expect("Type checked upstream.");
+            }
+            _ => unreachable!("unknown property {}", pspec.name()),
+        }
+    }
+
+    fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        match pspec.name() {
+            "interval" => self.settings.lock().unwrap().interval_ns.to_value(),
+            _ => unreachable!("unknown property {}", pspec.name()),
+        }
+    }
+}
+
+impl GstObjectImpl for Bs1770Meter {}
+
+impl ElementImpl for Bs1770Meter {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: Lazy<gst::subclass::ElementMetadata> = Lazy::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "BS.1770 loudness meter",
+                "Filter/Analyzer/Audio",
+                "Measures loudness conforming to ITU-R BS.1770 and posts it on the bus",
+                "Ruud van Asseldonk <dev@veniogames.com>",
+            )
+        });
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: Lazy<Vec<gst::PadTemplate>> = Lazy::new(|| {
+            let caps = gst_audio::AudioCapsBuilder::new_interleaved()
+                .format(gst_audio::AUDIO_FORMAT_F32)
+                .build();
+            let src = gst::PadTemplate::new(
+                "src",
+                gst::PadDirection::Src,
+                gst::PadPresence::Always,
+                &caps,
+            ).unwrap();
+            let sink = gst::PadTemplate::new(
+                "sink",
+                gst::PadDirection::Sink,
+                gst::PadPresence::Always,
+                &caps,
+            ).unwrap();
+            vec![src, sink]
+        });
+        PAD_TEMPLATES.as_ref()
+    }
+}
+
+impl BaseTransformImpl for Bs1770Meter {
+    const MODE: gst_base::subclass::BaseTransformMode =
+        gst_base::subclass::BaseTransformMode::AlwaysInPlace;
+    const PASSTHROUGH_ON_SAME_CAPS: bool = true;
+    const TRANSFORM_IP_ON_PASSTHROUGH: bool = true;
+
+    fn set_caps(&self, incaps: &gst::Caps, _outcaps: &gst::Caps) -> Result<(), gst::LoggableError> {
+        let info = gst_audio::AudioInfo::from_caps(incaps)
+            .map_err(|_| gst::loggable_error!(CAT, "Failed to parse caps {:?}", incaps))?;
+
+        if info.rate() < 10 {
+            return Err(gst::loggable_error!(CAT, "Sample rate {} is too low", info.rate()))
+        }
+
+        let channel_map = match info.positions() {
+            Some(positions) => channel_map_from_positions(positions),
+            // Without positions, assume the default order for the channel
+            // count, which is the same as the WAVE order.
+            None => bs1770::channels::ChannelMap::from_order(
+                bs1770::channels::ChannelOrder::Wave,
+                info.channels(),
+            ).ok_or_else(|| gst::loggable_error!(
+                CAT, "Unsupported unpositioned channel count {}", info.channels()
+            ))?,
+        };
+        gst::debug!(CAT, imp: self, "Configured for channels {:?}", channel_map.channels());
+
+        let meters = vec![ChannelLoudnessMeter::new(info.rate()); info.channels() as usize];
+        *self.state.lock().unwrap() = Some(State {
+            channel_map,
+            meters,
+            windows: Vec::new(),
+            num_windows_posted: 0,
+        });
+
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), gst::ErrorMessage> {
+        *self.state.lock().unwrap() = None;
+        Ok(())
+    }
+
+    fn sink_event(&self, event: gst::Event) -> bool {
+        if let gst::EventView::Eos(_) = event.view() {
+            // Post the final measurement, unless we just did.
+            let mut state_guard = self.state.lock().unwrap();
+            if let Some(state) = state_guard.as_mut() {
+                if state.num_windows_posted != state.windows.len() {
+                    self.post_loudness(state);
+                }
+            }
+        }
+        self.parent_sink_event(event)
+    }
+
+    fn transform_ip_passthrough(
+        &self,
+        buffer: &gst::Buffer,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let interval_windows = {
+            let settings = self.settings.lock().unwrap();
+            (settings.interval_ns / WINDOW_NS).max(1) as usize
+        };
+
+        let mut state_guard = self.state.lock().unwrap();
+        let state = match state_guard.as_mut() {
+            Some(state) => state,
+            None => return Err(gst::FlowError::NotNegotiated),
+        };
+
+        let map = buffer.map_readable().map_err(|_| gst::FlowError::Error)?;
+        let frame_len = 4 * state.meters.len();
+        let frames = map.as_slice().chunks_exact(frame_len);
+        for (ch, meter) in state.meters.iter_mut().enumerate() {
+            meter.push(frames.clone().map(|frame| {
+                let b = &frame[ch * 4..ch * 4 + 4];
+                f32::from_ne_bytes([b[0], b[1], b[2], b[3]])
+            }));
+        }
+
+        // Reduce the windows that completed since the previous buffer.
+        let num_done = state.windows.len();
+        let channel_windows: Vec<_> = state.meters
+            .iter()
+            .map(|m| Windows100ms { inner: &m.as_100ms_windows().inner[num_done..] })
+            .collect();
+        let new_windows = state.channel_map.reduce(&channel_windows);
+        state.windows.extend_from_slice(&new_windows.inner);
+
+        if state.windows.len() >= state.num_windows_posted + interval_windows {
+            self.post_loudness(state);
+        }
+
+        Ok(gst::FlowSuccess::Ok)
+    }
+}
@@ -0,0 +1,81 @@
+// BS1770 -- Loudness analysis library conforming to ITU-R BS.1770
+// Copyright 2020 Ruud van Asseldonk
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! Node.js bindings for the `bs1770` crate.
+//!
+//! Audio is passed as a `Float32Array` of interleaved samples, normalized to
+//! full scale, in WAVE channel order (L, R, C, LFE, Ls, Rs for 5.1).
+//! `measureBuffer` measures a complete buffer in one go, `LoudnessMeter`
+//! accepts audio in chunks, for streaming or for large files.
+
+use bs1770::channels::{ChannelMap, ChannelOrder};
+use bs1770::Power;
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+
+/// A meter for interleaved multichannel audio.
+#[napi]
+pub struct LoudnessMeter {
+    channel_map: ChannelMap,
+    meters: Vec<bs1770::ChannelLoudnessMeter>,
+}
+
+#[napi]
+impl LoudnessMeter {
+    #[napi(constructor)]
+    pub fn new(sample_rate_hz: u32, channels: u32) -> Result<LoudnessMeter> {
+        if sample_rate_hz < 10 {
+            return Err(Error::new(Status::InvalidArg, "Sample rate must be at least 10 Hz."))
+        }
+        let channel_map = match ChannelMap::from_order(ChannelOrder::Wave, channels) {
+            Some(map) => map,
+            None => return Err(Error::new(
+                Status::InvalidArg,
+                format!("Unsupported number of channels: {}.", channels),
+            )),
+        };
+        let meters = (0..channels)
+            .map(|_| bs1770::ChannelLoudnessMeter::new(sample_rate_hz))
+            .collect();
+        Ok(LoudnessMeter { channel_map, meters })
+    }
+
+    /// Feed interleaved samples. The length must be a multiple of the channel count.
+    #[napi]
+    pub fn push(&mut self, samples: Float32Array) -> Result<()> {
+        let n = self.meters.len();
+        if !samples.len().is_multiple_of(n) {
+            return Err(Error::new(
+                Status::InvalidArg,
+                "Number of samples is not a multiple of the number of channels.",
+            ))
+        }
+        for (ch, meter) in self.meters.iter_mut().enumerate() {
+            meter.push(samples.iter().skip(ch).step_by(n).cloned());
+        }
+        Ok(())
+    }
+
+    /// Return the integrated loudness of the audio so far, in LKFS.
+    ///
+    /// Returns `-Infinity` for silence.
+    #[napi]
+    pub fn integrated_loudness(&self) -> f64 {
+        let windows: Vec<_> = self.meters.iter().map(|m| m.as_100ms_windows()).collect();
+        let power = self.channel_map.reduce(&windows);
+        let gated_power = bs1770::gated_mean(power.as_ref()).unwrap_or(Power(0.0));
+        gated_power.loudness_lkfs() as f64
+    }
+}
+
+/// Measure the integrated loudness of a buffer of interleaved samples, in LKFS.
+#[napi]
+pub fn measure_buffer(samples: Float32Array, sample_rate_hz: u32, channels: u32) -> Result<f64> {
+    let mut meter = LoudnessMeter::new(sample_rate_hz, channels)?;
+    meter.push(samples)?;
+    Ok(meter.integrated_loudness())
+}
@@ -0,0 +1,175 @@
+// BS1770 -- Loudness analysis library conforming to ITU-R BS.1770
+// Copyright 2020 Ruud van Asseldonk
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! Python bindings for the `bs1770` crate.
+//!
+//! The bindings mirror the building blocks of the Rust library: a meter per
+//! channel that produces the power of 100ms windows, functions to combine
+//! channels, and the gated mean. Windows are exposed as numpy arrays of power.
+//! For the common case, `integrated_loudness` measures a numpy array of shape
+//! (samples, channels) in one go, like `pyloudnorm.Meter.integrated_loudness`.
+
+use bs1770::channels::{ChannelMap, ChannelOrder};
+use bs1770::{Power, Windows100ms};
+use numpy::{IntoPyArray, PyArray1, PyReadonlyArray1, PyReadonlyArray2};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+
+/// Samples as either a float32 or float64 numpy array, normalized to full scale.
+#[derive(FromPyObject)]
+enum Samples<'py> {
+    F32(PyReadonlyArray1<'py, f32>),
+    F64(PyReadonlyArray1<'py, f64>),
+}
+
+#[derive(FromPyObject)]
+enum MultiChannelSamples<'py> {
+    F32(PyReadonlyArray2<'py, f32>),
+    F64(PyReadonlyArray2<'py, f64>),
+}
+
+fn check_sample_rate(sample_rate_hz: u32) -> PyResult<()> {
+    if sample_rate_hz < 10 {
+        return Err(PyValueError::new_err("Sample rate must be at least 10 Hz."))
+    }
+    Ok(())
+}
+
+fn windows_to_array(py: Python, windows: Windows100ms<&[Power]>) -> Py<PyArray1<f32>> {
+    let power: Vec<f32> = windows.inner.iter().map(|p| p.0).collect();
+    power.into_pyarray(py).unbind()
+}
+
+fn array_to_windows(windows: &PyReadonlyArray1<f32>) -> Vec<Power> {
+    windows.as_array().iter().map(|&p| Power(p)).collect()
+}
+
+/// Measures the K-weighted power of 100ms windows of a single channel.
+#[pyclass(name = "ChannelLoudnessMeter")]
+struct ChannelLoudnessMeter {
+    inner: bs1770::ChannelLoudnessMeter,
+}
+
+#[pymethods]
+impl ChannelLoudnessMeter {
+    #[new]
+    fn new(sample_rate_hz: u32) -> PyResult<ChannelLoudnessMeter> {
+        check_sample_rate(sample_rate_hz)?;
+        Ok(ChannelLoudnessMeter {
+            inner: bs1770::ChannelLoudnessMeter::new(sample_rate_hz),
+        })
+    }
+
+    /// Feed a one-dimensional array of samples, normalized to [-1.0, 1.0].
+    fn push(&mut self, samples: Samples) {
+        match samples {
+            Samples::F32(xs) => self.inner.push(xs.as_array().iter().cloned()),
+            Samples::F64(xs) => self.inner.push(xs.as_array().iter().map(|&x| x as f32)),
+        }
+    }
+
+    /// Continue the measurement at a different sample rate.
+    fn set_sample_rate(&mut self, sample_rate_hz: u32) -> PyResult<()> {
+        check_sample_rate(sample_rate_hz)?;
+        self.inner.set_sample_rate(sample_rate_hz);
+        Ok(())
+    }
+
+    /// Return the power of the 100ms windows measured so far, as float32 array.
+    fn windows(&self, py: Python) -> Py<PyArray1<f32>> {
+        windows_to_array(py, self.inner.as_100ms_windows())
+    }
+}
+
+/// Combine the power of the left and right channel by adding them.
+#[pyfunction]
+fn reduce_stereo(
+    py: Python,
+    left: PyReadonlyArray1<f32>,
+    right: PyReadonlyArray1<f32>,
+) -> PyResult<Py<PyArray1<f32>>> {
+    let left = array_to_windows(&left);
+    let right = array_to_windows(&right);
+    if left.len() != right.len() {
+        return Err(PyValueError::new_err("Channels must have the same length."))
+    }
+    let stereo = bs1770::reduce_stereo(
+        Windows100ms { inner: &left[..] },
+        Windows100ms { inner: &right[..] },
+    );
+    Ok(windows_to_array(py, stereo.as_ref()))
+}
+
+/// Return the gated mean power of the windows, or None if no window passes the gate.
+#[pyfunction]
+fn gated_mean(windows: PyReadonlyArray1<f32>) -> Option<f32> {
+    let windows = array_to_windows(&windows);
+    bs1770::gated_mean(Windows100ms { inner: &windows[..] }).map(|p| p.0)
+}
+
+/// Convert power into loudness in LKFS.
+#[pyfunction]
+fn loudness_lkfs(power: f32) -> f32 {
+    Power(power).loudness_lkfs()
+}
+
+/// Convert loudness in LKFS into power.
+#[pyfunction]
+fn power_from_lkfs(lkfs: f32) -> f32 {
+    Power::from_lkfs(lkfs).0
+}
+
+/// Measure the integrated loudness in LKFS of an array of shape (samples, channels).
+///
+/// Channels are assumed to be in WAVE order (L, R, C, LFE, Ls, Rs for 5.1).
+/// Returns -inf for silence.
+#[pyfunction]
+fn integrated_loudness(data: MultiChannelSamples, sample_rate_hz: u32) -> PyResult<f32> {
+    check_sample_rate(sample_rate_hz)?;
+
+    let num_channels = match data {
+        MultiChannelSamples::F32(ref xs) => xs.as_array().ncols(),
+        MultiChannelSamples::F64(ref xs) => xs.as_array().ncols(),
+    };
+    let channel_map = match ChannelMap::from_order(ChannelOrder::Wave, num_channels as u32) {
+        Some(map) => map,
+        None => return Err(PyValueError::new_err(format!(
+            "Unsupported number of channels: {}.", num_channels,
+        ))),
+    };
+
+    let mut windows = Vec::with_capacity(num_channels);
+    for ch in 0..num_channels {
+        let mut meter = bs1770::ChannelLoudnessMeter::new(sample_rate_hz);
+        match data {
+            MultiChannelSamples::F32(ref xs) => {
+                meter.push(xs.as_array().column(ch).iter().cloned());
+            }
+            MultiChannelSamples::F64(ref xs) => {
+                meter.push(xs.as_array().column(ch).iter().map(|&x| x as f32));
+            }
+        }
+        windows.push(meter.into_100ms_windows());
+    }
+
+    let windows_refs: Vec<_> = windows.iter().map(|w| w.as_ref()).collect();
+    let power = channel_map.reduce(&windows_refs);
+    let gated_power = bs1770::gated_mean(power.as_ref()).unwrap_or(Power(0.0));
+    Ok(gated_power.loudness_lkfs())
+}
+
+#[pymodule]
+#[pyo3(name = "bs1770")]
+fn bs1770_module(module: &Bound<PyModule>) -> PyResult<()> {
+    module.add_class::<ChannelLoudnessMeter>()?;
+    module.add_function(wrap_pyfunction!(reduce_stereo, module)?)?;
+    module.add_function(wrap_pyfunction!(gated_mean, module)?)?;
+    module.add_function(wrap_pyfunction!(loudness_lkfs, module)?)?;
+    module.add_function(wrap_pyfunction!(power_from_lkfs, module)?)?;
+    module.add_function(wrap_pyfunction!(integrated_loudness, module)?)?;
+    Ok(())
+}
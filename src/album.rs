@@ -0,0 +1,392 @@
+// BS1770 -- Loudness analysis library conforming to ITU-R BS.1770
+// Copyright 2020 Ruud van Asseldonk
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! Statistics over the loudness of the tracks of an album.
+//!
+//! The integrated loudness of an album is measured by concatenating the
+//! windows of all tracks, and applying `gated_mean` to the result. That single
+//! number does not tell whether the tracks are consistent with one another.
+//! `AlbumStats` summarizes the per-track loudness, to flag albums where a few
+//! tracks stand out, for example due to mastering from different sources.
+//!
+//! ```
+//! # use bs1770::Power;
+//! use bs1770::album::AlbumStats;
+//!
+//! let track_power = [
+//!     Power::from_lkfs(-9.5),
+//!     Power::from_lkfs(-10.0),
+//!     Power::from_lkfs(-9.8),
+//!     Power::from_lkfs(-16.2),
+//! ];
+//! let stats = AlbumStats::new(&track_power).unwrap();
+//! assert_eq!(stats.loudest(), 0);
+//! assert_eq!(stats.quietest(), 3);
+//! assert_eq!(stats.outliers(3.0), vec![3]);
+//! ```
+//!
+//! `AlbumAccumulator` collects the windows of the tracks. Tracks of an album
+//! need not have the same channel layout, for example a mono bonus track on a
+//! stereo album. Every track is reduced with its own `ChannelMap` before its
+//! windows are concatenated with those of the other tracks.
+//!
+//! ```
+//! # use bs1770::{Power, Windows100ms};
+//! use bs1770::album::AlbumAccumulator;
+//! use bs1770::channels::{ChannelMap, ChannelOrder};
+//!
+//! let stereo = ChannelMap::from_order(ChannelOrder::Flac, 2).unwrap();
+//! let mono = ChannelMap::from_order(ChannelOrder::Flac, 1).unwrap();
+//! let windows = [Power::from_lkfs(-20.0); 10];
+//! let channel = Windows100ms { inner: &windows[..] };
+//!
+//! let mut album = AlbumAccumulator::new();
+//! album.add_track(&stereo, &[channel, channel]);
+//! album.add_track(&mono, &[channel]);
+//! assert_eq!(album.track_power().len(), 2);
+//! assert_eq!(album.windows().len(), 20);
+//! ```
+//!
+//! Measuring an album this way needs the windows of every track. When only
+//! the loudness of the tracks is known, for example from their tags,
+//! `duration_weighted_power` combines it into an approximate album loudness.
+//! Then when one track of an album is replaced, only that track needs to be
+//! decoded again.
+//!
+//! ```
+//! # use std::time::Duration;
+//! # use bs1770::Power;
+//! use bs1770::album::duration_weighted_power;
+//!
+//! let tracks = [
+//!     (Power::from_lkfs(-10.0), Duration::from_secs(240)),
+//!     (Power::from_lkfs(-10.0), Duration::from_secs(180)),
+//! ];
+//! let album_power = duration_weighted_power(&tracks).unwrap();
+//! assert!((album_power.loudness_lkfs() - -10.0).abs() < 1e-4);
+//! ```
+
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use crate::{Power, Windows100ms};
+use crate::channels::ChannelMap;
+#[cfg(not(feature = "std"))]
+use crate::math::Float;
+
+/// Summary statistics of the loudness of a collection of tracks.
+///
+/// Statistics are computed over loudness in LKFS, not over power, so the mean
+/// is the average loudness that a listener would perceive when playing the
+/// tracks one by one. Silent tracks, which have a loudness of -∞ LKFS, are
+/// excluded from the statistics.
+#[derive(Clone, Debug)]
+pub struct AlbumStats {
+    /// Loudness of every track, `None` for silent tracks.
+    track_lkfs: Vec<Option<f32>>,
+
+    mean_lkfs: f32,
+    median_lkfs: f32,
+    std_dev_lu: f32,
+    loudest: usize,
+    quietest: usize,
+}
+
+impl AlbumStats {
+    /// Compute statistics over the integrated loudness of every track.
+    ///
+    /// Returns `None` when there are no tracks, or when all tracks are silent.
+    pub fn new(track_power: &[Power]) -> Option<AlbumStats> {
+        let track_lkfs: Vec<Option<f32>> = track_power
+            .iter()
+            .map(|p| Some(p.loudness_lkfs()).filter(|lkfs| lkfs.is_finite()))
+            .collect();
+
+        let mut sorted: Vec<f32> = track_lkfs.iter().filter_map(|&x| x).collect();
+        if sorted.is_empty() {
+            return None
+        }
+        // There are no NaNs, we filtered on finite values above.
+        sorted.sort_by(|x, y| x.partial_cmp(y).unwrap());
+
+        let n = sorted.len();
+        let mean_lkfs = sorted.iter().sum::<f32>() / n as f32;
+        // For an odd number of tracks, both indices refer to the middle one.
+        let median_lkfs = 0.5 * (sorted[(n - 1) / 2] + sorted[n / 2]);
+        let variance = sorted
+            .iter()
+            .map(|x| (x - mean_lkfs) * (x - mean_lkfs))
+            .sum::<f32>() / n as f32;
+
+        let mut loudest = None;
+        let mut quietest = None;
+        for (i, lkfs) in track_lkfs.iter().enumerate() {
+            if let Some(x) = *lkfs {
+                match loudest {
+                    Some((_, max)) if max >= x => {}
+                    _ => loudest = Some((i, x)),
+                }
+                match quietest {
+                    Some((_, min)) if min <= x => {}
+                    _ => quietest = Some((i, x)),
+                }
+            }
+        }
+
+        let stats = AlbumStats {
+            track_lkfs,
+            mean_lkfs,
+            median_lkfs,
+            std_dev_lu: variance.sqrt(),
+            // There is at least one non-silent track at this point.
+            loudest: loudest.unwrap().0,
+            quietest: quietest.unwrap().0,
+        };
+        Some(stats)
+    }
+
+    /// Return the mean of the track loudness, in LKFS.
+    pub fn mean_lkfs(&self) -> f32 {
+        self.mean_lkfs
+    }
+
+    /// Return the median of the track loudness, in LKFS.
+    pub fn median_lkfs(&self) -> f32 {
+        self.median_lkfs
+    }
+
+    /// Return the (population) standard deviation of the track loudness, in LU.
+    pub fn std_dev_lu(&self) -> f32 {
+        self.std_dev_lu
+    }
+
+    /// Return the difference between the loudest and quietest track, in LU.
+    pub fn spread_lu(&self) -> f32 {
+        self.track_lkfs[self.loudest].unwrap() - self.track_lkfs[self.quietest].unwrap()
+    }
+
+    /// Return the index of the loudest track.
+    ///
+    /// If multiple tracks are equally loud, this is the first one.
+    pub fn loudest(&self) -> usize {
+        self.loudest
+    }
+
+    /// Return the index of the quietest non-silent track.
+    ///
+    /// If multiple tracks are equally quiet, this is the first one.
+    pub fn quietest(&self) -> usize {
+        self.quietest
+    }
+
+    /// Return the indices of tracks that deviate from the median by more than `threshold_lu`.
+    ///
+    /// The median is used as reference rather than the mean, so a single
+    /// outlier cannot drag the reference towards itself. Silent tracks are
+    /// never considered outliers.
+    pub fn outliers(&self, threshold_lu: f32) -> Vec<usize> {
+        self.track_lkfs
+            .iter()
+            .enumerate()
+            .filter(|&(_, lkfs)| match *lkfs {
+                Some(x) => (x - self.median_lkfs).abs() > threshold_lu,
+                None => false,
+            })
+            .map(|(i, _)| i)
+            .collect()
+    }
+}
+
+/// Collects the windows of every track of an album, to measure the album as a whole.
+#[derive(Clone, Debug, Default)]
+pub struct AlbumAccumulator {
+    /// The windows of all tracks so far, with channels combined.
+    windows: Windows100ms<Vec<Power>>,
+
+    /// The gated mean power of every track, zero power for silent tracks.
+    track_power: Vec<Power>,
+}
+
+impl AlbumAccumulator {
+    /// Construct an accumulator for an album without tracks.
+    pub fn new() -> AlbumAccumulator {
+        AlbumAccumulator::default()
+    }
+
+    /// Add a track given the windows of every channel, and return its gated power.
+    ///
+    /// The channels are combined with `channel_map`, which applies to this
+    /// track only, see also `ChannelMap::reduce`.
+    pub fn add_track(
+        &mut self,
+        channel_map: &ChannelMap,
+        channels: &[Windows100ms<&[Power]>],
+    ) -> Power {
+        let windows = channel_map.reduce(channels);
+        self.add_reduced_track(windows.as_ref())
+    }
+
+    /// Add a track whose channels have already been combined, and return its gated power.
+    pub fn add_reduced_track(&mut self, windows: Windows100ms<&[Power]>) -> Power {
+        let power = crate::gated_mean(windows).unwrap_or(Power(0.0));
+        self.windows.append(windows);
+        self.track_power.push(power);
+        power
+    }
+
+    /// Return the gated mean power of every track added so far.
+    pub fn track_power(&self) -> &[Power] {
+        &self.track_power[..]
+    }
+
+    /// Return the windows of all tracks, concatenated.
+    pub fn windows(&self) -> Windows100ms<&[Power]> {
+        self.windows.as_ref()
+    }
+
+    /// Return the gated mean power of the album, see `gated_mean`.
+    pub fn gated_power(&self) -> Option<Power> {
+        crate::gated_mean(self.windows())
+    }
+
+    /// Return statistics over the loudness of the tracks, see `AlbumStats::new`.
+    pub fn stats(&self) -> Option<AlbumStats> {
+        AlbumStats::new(&self.track_power)
+    }
+}
+
+/// Combine the gated power of every track into album power, weighted by track duration.
+///
+/// This is the mean power of the tracks, where every track counts in
+/// proportion to its duration. It is not a BS.1770 measurement of the album:
+/// the relative gate applies to every track separately, rather than to the
+/// album as a whole. For tracks of similar loudness the difference with
+/// `AlbumAccumulator::gated_power` is small, but quiet passages that the
+/// album gate would exclude can count towards the track power, and quiet parts
+/// of a track are weighted as if they were as loud as the rest.
+///
+/// Silent tracks, with zero power, are excluded, like the gate excludes them
+/// from an album measurement. Returns `None` when all tracks are silent or
+/// have zero duration.
+pub fn duration_weighted_power(tracks: &[(Power, Duration)]) -> Option<Power> {
+    let mut sum_power = 0.0_f64;
+    let mut sum_seconds = 0.0_f64;
+    for &(power, duration) in tracks {
+        if power.0 > 0.0 {
+            let seconds = duration.as_secs_f64();
+            sum_power += power.0 as f64 * seconds;
+            sum_seconds += seconds;
+        }
+    }
+    if sum_seconds > 0.0 {
+        Some(Power((sum_power / sum_seconds) as f32))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+
+    use super::{duration_weighted_power, AlbumAccumulator, AlbumStats};
+    use crate::{Power, Windows100ms};
+    use crate::channels::{ChannelMap, ChannelOrder};
+
+    fn tracks(lkfs: &[f32]) -> Vec<Power> {
+        lkfs.iter().map(|&x| Power::from_lkfs(x)).collect()
+    }
+
+    #[test]
+    fn album_stats_of_empty_album_is_none() {
+        assert!(AlbumStats::new(&[]).is_none());
+        assert!(AlbumStats::new(&[Power(0.0), Power(0.0)]).is_none());
+    }
+
+    #[test]
+    fn album_stats_computes_mean_median_and_spread() {
+        let stats = AlbumStats::new(&tracks(&[-10.0, -12.0, -14.0, -20.0])).unwrap();
+        assert!((stats.mean_lkfs() - -14.0).abs() < 1e-4);
+        assert!((stats.median_lkfs() - -13.0).abs() < 1e-4);
+        assert!((stats.std_dev_lu() - 14.0_f32.sqrt()).abs() < 1e-4);
+        assert!((stats.spread_lu() - 10.0).abs() < 1e-4);
+        assert_eq!(stats.loudest(), 0);
+        assert_eq!(stats.quietest(), 3);
+        assert_eq!(stats.outliers(5.0), vec![3]);
+    }
+
+    #[test]
+    fn album_stats_ignores_silent_tracks() {
+        let mut track_power = tracks(&[-10.0, -12.0]);
+        track_power.insert(1, Power(0.0));
+        let stats = AlbumStats::new(&track_power).unwrap();
+        assert!((stats.mean_lkfs() - -11.0).abs() < 1e-4);
+        assert_eq!(stats.loudest(), 0);
+        assert_eq!(stats.quietest(), 2);
+        assert!(stats.outliers(0.5).iter().all(|&i| i != 1));
+    }
+
+    #[test]
+    fn album_accumulator_reduces_every_track_with_its_own_layout() {
+        let windows = [Power::from_lkfs(-20.0); 10];
+        let channel = Windows100ms { inner: &windows[..] };
+        let stereo = ChannelMap::from_order(ChannelOrder::Flac, 2).unwrap();
+        let mono = ChannelMap::from_order(ChannelOrder::Flac, 1).unwrap();
+
+        let mut album = AlbumAccumulator::new();
+        let stereo_power = album.add_track(&stereo, &[channel, channel]);
+        let mono_power = album.add_track(&mono, &[channel]);
+
+        // Mono plays over both loudspeakers, so it is as loud as dual mono.
+        assert!((stereo_power.loudness_lkfs() - -16.99).abs() < 0.01);
+        assert!((mono_power.loudness_lkfs() - -16.99).abs() < 0.01);
+        assert_eq!(album.track_power(), &[stereo_power, mono_power]);
+        assert_eq!(album.stats().unwrap().loudest(), 0);
+
+        let album_power = album.gated_power().unwrap();
+        let expected = Power(0.5 * (stereo_power.0 + mono_power.0));
+        assert!((album_power.loudness_lkfs() - expected.loudness_lkfs()).abs() < 1e-3);
+    }
+
+    #[test]
+    fn duration_weighted_power_weights_tracks_by_duration() {
+        let loud = Power::from_lkfs(-10.0);
+        let quiet = Power::from_lkfs(-20.0);
+        let tracks = [(loud, Duration::from_secs(300)), (quiet, Duration::from_secs(100))];
+        let album_power = duration_weighted_power(&tracks).unwrap();
+        let expected = Power(0.75 * loud.0 + 0.25 * quiet.0);
+        assert!((album_power.loudness_lkfs() - expected.loudness_lkfs()).abs() < 1e-4);
+    }
+
+    #[test]
+    fn duration_weighted_power_is_close_to_album_measurement_without_gating() {
+        let loud = [Power::from_lkfs(-12.0); 30];
+        let quiet = [Power::from_lkfs(-15.0); 50];
+        let mut album = AlbumAccumulator::new();
+        let loud_power = album.add_reduced_track(Windows100ms { inner: &loud[..] });
+        let quiet_power = album.add_reduced_track(Windows100ms { inner: &quiet[..] });
+
+        let tracks = [
+            (loud_power, Duration::from_millis(3_000)),
+            (quiet_power, Duration::from_millis(5_000)),
+        ];
+        let weighted = duration_weighted_power(&tracks).unwrap().loudness_lkfs();
+        let measured = album.gated_power().unwrap().loudness_lkfs();
+        // The album has blocks that straddle the track boundary, the tracks
+        // do not, so the two differ slightly.
+        assert!((weighted - measured).abs() < 0.05);
+    }
+
+    #[test]
+    fn duration_weighted_power_ignores_silent_tracks() {
+        let power = Power::from_lkfs(-14.0);
+        let tracks = [(power, Duration::from_secs(200)), (Power(0.0), Duration::from_secs(600))];
+        assert_eq!(duration_weighted_power(&tracks), Some(power));
+        assert_eq!(duration_weighted_power(&tracks[1..]), None);
+        assert_eq!(duration_weighted_power(&[]), None);
+    }
+}
@@ -0,0 +1,154 @@
+// BS1770 -- Loudness analysis library conforming to ITU-R BS.1770
+// Copyright 2020 Ruud van Asseldonk
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! Meter ballistics for displaying momentary and short-term loudness.
+//!
+//! ITU-R BS.1771 and EBU Tech 3341 specify how a loudness meter should
+//! display its readouts:
+//!
+//!  * Momentary loudness (M) is the mean power over a sliding rectangular
+//!    window of 400ms, short-term loudness (S) over a sliding rectangular
+//!    window of 3s. The mean is taken over power, not over loudness units.
+//!  * The readouts update at least 10 times per second.
+//!  * A readout is not shown until its window is filled, rather than showing
+//!    the mean over a partial window, which would exaggerate the loudness of
+//!    a short burst at the start of a measurement.
+//!
+//! `Ballistics` implements these dynamics on top of the 100ms windows, after
+//! the channels have been combined. It produces one `Reading` per window, so
+//! readouts update at exactly 10 Hz, the minimum rate.
+//!
+//! ```
+//! # use bs1770::{Power, Windows100ms};
+//! use bs1770::ballistics;
+//!
+//! let windows = [Power::from_lkfs(-23.0); 5];
+//! let readings: Vec<_> = ballistics::readings(Windows100ms { inner: &windows[..] }).collect();
+//! assert_eq!(readings.len(), 5);
+//! assert!(readings[2].momentary.is_none());
+//! assert!(readings[3].momentary.is_some());
+//! assert!(readings[4].short_term.is_none());
+//! ```
+
+use alloc::collections::VecDeque;
+use core::time::Duration;
+
+use crate::{Power, Windows100ms};
+
+/// The number of 100ms windows in the momentary (400ms) window.
+const MOMENTARY_WINDOWS: usize = 4;
+
+/// The number of 100ms windows in the short-term (3s) window.
+const SHORT_TERM_WINDOWS: usize = 30;
+
+/// The readouts of a meter at one point in time.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Reading {
+    /// The time at the end of the most recent window, since the start of the measurement.
+    pub time: Duration,
+
+    /// The mean power over the last 400ms, or `None` if less than 400ms has been measured.
+    pub momentary: Option<Power>,
+
+    /// The mean power over the last 3s, or `None` if less than 3s has been measured.
+    pub short_term: Option<Power>,
+}
+
+/// Tracks sliding momentary and short-term windows, to produce display readouts.
+#[derive(Clone, Debug)]
+pub struct Ballistics {
+    /// The most recent windows, at most `SHORT_TERM_WINDOWS` of them.
+    recent: VecDeque<Power>,
+
+    /// The number of windows pushed since construction.
+    num_windows: u64,
+}
+
+impl Default for Ballistics {
+    fn default() -> Ballistics {
+        Ballistics::new()
+    }
+}
+
+impl Ballistics {
+    /// Construct ballistics with no windows measured yet.
+    pub fn new() -> Ballistics {
+        Ballistics {
+            recent: VecDeque::with_capacity(SHORT_TERM_WINDOWS),
+            num_windows: 0,
+        }
+    }
+
+    fn mean_of_recent(&self, n: usize) -> Option<Power> {
+        if self.recent.len() < n {
+            return None
+        }
+        let sum: f32 = self.recent.iter().rev().take(n).map(|p| p.0).sum();
+        Some(Power(sum / n as f32))
+    }
+
+    /// Advance by one 100ms window, and return the readouts after it.
+    pub fn push(&mut self, power: Power) -> Reading {
+        if self.recent.len() == SHORT_TERM_WINDOWS {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(power);
+        self.num_windows += 1;
+        self.reading()
+    }
+
+    /// Discard all windows, as if newly constructed.
+    pub fn clear(&mut self) {
+        self.recent.clear();
+        self.num_windows = 0;
+    }
+
+    /// Return the readouts after the most recently pushed window.
+    pub fn reading(&self) -> Reading {
+        Reading {
+            time: Duration::from_millis(100 * self.num_windows),
+            momentary: self.mean_of_recent(MOMENTARY_WINDOWS),
+            short_term: self.mean_of_recent(SHORT_TERM_WINDOWS),
+        }
+    }
+}
+
+/// Return the readouts after every window of a window series.
+pub fn readings<'a>(windows: Windows100ms<&'a [Power]>) -> impl Iterator<Item = Reading> + 'a {
+    let mut ballistics = Ballistics::new();
+    windows.inner.iter().map(move |&power| ballistics.push(power))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::readings;
+    use crate::{Power, Windows100ms};
+
+    #[test]
+    fn readings_use_sliding_rectangular_windows_of_power() {
+        // One second of silence, then a loud window, then silence again.
+        let mut windows = [Power(0.0); 40];
+        windows[10] = Power(1.0);
+        let readings: Vec<_> = readings(Windows100ms { inner: &windows[..] }).collect();
+
+        assert_eq!(readings[0].time, Duration::from_millis(100));
+        assert_eq!(readings[9].momentary, Some(Power(0.0)));
+
+        // The loud window stays in the momentary window for exactly 400ms.
+        for reading in &readings[10..14] {
+            assert_eq!(reading.momentary, Some(Power(0.25)));
+        }
+        assert_eq!(readings[14].momentary, Some(Power(0.0)));
+
+        // The short-term window is filled after 3s, and still contains it.
+        assert_eq!(readings[28].short_term, None);
+        assert_eq!(readings[29].short_term, Some(Power(1.0 / 30.0)));
+        assert_eq!(readings[39].short_term, Some(Power(1.0 / 30.0)));
+    }
+}
@@ -0,0 +1,632 @@
+// BS1770 -- Loudness analysis library conforming to ITU-R BS.1770
+// Copyright 2020 Ruud van Asseldonk
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! Analyzing many files in parallel.
+//!
+//! `analyze_many` decodes and measures files on a pool of worker threads, and
+//! reports the result for every file to a callback on the calling thread, as
+//! soon as it is available. An error in one file does not affect the others;
+//! it is reported to the callback for that file. FLAC files are decoded with
+//! Claxon, WAVE files with Hound.
+//!
+//! ```no_run
+//! use std::path::PathBuf;
+//! use bs1770::batch::{self, Options};
+//!
+//! let paths = vec![PathBuf::from("01.flac"), PathBuf::from("02.wav")];
+//! batch::analyze_many(&paths, &Options::default(), |i, result| {
+//!     match result {
+//!         Ok(track) => println!("{}: {:.1} LKFS", paths[i].display(), track.loudness_lkfs()),
+//!         Err(err) => eprintln!("{}: {}", paths[i].display(), err),
+//!     }
+//! });
+//! ```
+//!
+//! This module is only available with the `batch` feature enabled.
+
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+use crate::channels::{ChannelMap, ChannelOrder};
+use crate::{ChannelLoudnessMeter, Power, Windows100ms};
+
+/// A flag to abort an analysis in progress from a different thread.
+///
+/// Clones of a token share the same flag. Decoding checks the flag regularly,
+/// so after `cancel`, the analysis stops promptly with `Error::Cancelled`.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    /// Construct a new token that is not cancelled.
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Request all analyses that use this token to stop.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Return whether `cancel` was called on this token or one of its clones.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    fn check(&self) -> Result<(), Error> {
+        if self.is_cancelled() {
+            Err(Error::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Options for `analyze_many`.
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// The number of files to analyze concurrently. Must be at least 1.
+    pub num_threads: usize,
+
+    /// Token to abort the analysis, never cancelled by default.
+    pub cancellation: CancellationToken,
+
+    /// Report progress every time this many samples per channel are decoded.
+    ///
+    /// Defaults to 441,000 samples, 10 seconds of audio at 44.1 kHz.
+    pub progress_interval_samples: u64,
+
+    /// Skip this much audio at the start of every file. Defaults to zero.
+    pub start: Duration,
+
+    /// Measure at most this much audio after `start`, or up to the end if `None`.
+    pub duration: Option<Duration>,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            num_threads: 4,
+            cancellation: CancellationToken::new(),
+            progress_interval_samples: 441_000,
+            start: Duration::from_secs(0),
+            duration: None,
+        }
+    }
+}
+
+impl Options {
+    /// Return the first and one past the last sample selected by `start` and `duration`.
+    ///
+    /// If `duration` is `None`, the end is `samples_total`, which may be unknown.
+    fn sample_range(&self, sample_rate_hz: u32, samples_total: Option<u64>) -> (u64, Option<u64>) {
+        let start = crate::duration_to_samples(self.start, sample_rate_hz);
+        let end = self.duration.map(|d| start + crate::duration_to_samples(d, sample_rate_hz));
+        match (end, samples_total) {
+            (Some(end), Some(total)) => (start.min(total), Some(end.min(total))),
+            (Some(end), None) => (start, Some(end)),
+            (None, Some(total)) => (start.min(total), Some(total)),
+            (None, None) => (start, None),
+        }
+    }
+}
+
+/// How far the analysis of a file has progressed.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Progress {
+    /// The number of samples per channel decoded so far.
+    pub samples_processed: u64,
+
+    /// The number of samples per channel in the file, if the file specifies it.
+    pub samples_total: Option<u64>,
+}
+
+impl Progress {
+    /// Return the fraction of the file processed, between 0.0 and 1.0, if known.
+    pub fn fraction(&self) -> Option<f32> {
+        match self.samples_total {
+            Some(0) => Some(1.0),
+            Some(total) => Some((self.samples_processed as f64 / total as f64).min(1.0) as f32),
+            None => None,
+        }
+    }
+}
+
+/// Checks for cancellation and reports progress while decoding.
+struct Monitor<'a> {
+    options: &'a Options,
+    on_progress: &'a mut dyn FnMut(Progress),
+    progress: Progress,
+    next_report: u64,
+}
+
+impl<'a> Monitor<'a> {
+    fn new(options: &'a Options, on_progress: &'a mut dyn FnMut(Progress)) -> Monitor<'a> {
+        Monitor {
+            options,
+            on_progress,
+            progress: Progress { samples_processed: 0, samples_total: None },
+            next_report: options.progress_interval_samples,
+        }
+    }
+
+    /// Record that `n` more samples per channel were decoded.
+    fn advance(&mut self, n: u64) -> Result<(), Error> {
+        self.options.cancellation.check()?;
+        self.progress.samples_processed += n;
+        if self.progress.samples_processed >= self.next_report {
+            (self.on_progress)(self.progress);
+            // Skip intervals that we passed in one go, so we report at most
+            // once per call.
+            let interval = self.options.progress_interval_samples.max(1);
+            while self.next_report <= self.progress.samples_processed {
+                self.next_report += interval;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An error that occurred while analyzing a file.
+#[derive(Debug)]
+pub enum Error {
+    /// The file could not be read.
+    Io(io::Error),
+    /// The file has an unknown extension, the supported ones are `.flac` and `.wav`.
+    UnsupportedFormat,
+    /// The file has a number of channels without a known layout.
+    UnsupportedChannels(u32),
+    /// The FLAC decoder failed.
+    Flac(claxon::Error),
+    /// The WAVE decoder failed.
+    Wav(hound::Error),
+    /// The analysis was aborted through a `CancellationToken`.
+    Cancelled,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref err) => write!(f, "{}", err),
+            Error::UnsupportedFormat => write!(f, "Unsupported file format, expected .flac or .wav."),
+            Error::UnsupportedChannels(n) => write!(f, "Unsupported number of channels: {}.", n),
+            Error::Flac(ref err) => write!(f, "{}", err),
+            Error::Wav(ref err) => write!(f, "{}", err),
+            Error::Cancelled => write!(f, "Analysis was cancelled."),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+impl From<claxon::Error> for Error {
+    fn from(err: claxon::Error) -> Error {
+        Error::Flac(err)
+    }
+}
+
+impl From<hound::Error> for Error {
+    fn from(err: hound::Error) -> Error {
+        Error::Wav(err)
+    }
+}
+
+/// The measurement of a single file.
+#[derive(Clone, Debug)]
+pub struct TrackAnalysis {
+    /// The sample rate of the file.
+    pub sample_rate_hz: u32,
+
+    /// The loudspeaker positions of the channels in the file.
+    pub channel_map: ChannelMap,
+
+    /// The power of every 100ms window, summed over channels.
+    pub windows: Windows100ms<Vec<Power>>,
+
+    /// The gated mean of `windows`, zero power if no window passes the gate.
+    pub gated_power: Power,
+}
+
+impl TrackAnalysis {
+    /// Return the integrated loudness in LKFS.
+    pub fn loudness_lkfs(&self) -> f32 {
+        self.gated_power.loudness_lkfs()
+    }
+
+    /// Return the loudness range in LU, see `lra::loudness_range`.
+    pub fn loudness_range_lu(&self) -> Option<f32> {
+        crate::lra::loudness_range(self.windows.as_ref())
+    }
+
+    fn new(
+        sample_rate_hz: u32,
+        channel_map: ChannelMap,
+        meters: Vec<ChannelLoudnessMeter>,
+    ) -> TrackAnalysis {
+        let channel_windows: Vec<_> = meters.iter().map(|m| m.as_100ms_windows()).collect();
+        let windows = channel_map.reduce(&channel_windows);
+        let gated_power = crate::gated_mean(windows.as_ref()).unwrap_or(Power(0.0));
+        TrackAnalysis {
+            sample_rate_hz,
+            channel_map,
+            windows,
+            gated_power,
+        }
+    }
+}
+
+fn analyze_flac(path: &Path, monitor: &mut Monitor) -> Result<TrackAnalysis, Error> {
+    let mut reader = claxon::FlacReader::open(path)?;
+    let streaminfo = reader.streaminfo();
+    let (start, end) = monitor.options.sample_range(streaminfo.sample_rate, streaminfo.samples);
+    // Claxon cannot seek, so we decode the audio before `start` too, and it
+    // counts towards the progress.
+    monitor.progress.samples_total = end;
+    let channel_map = ChannelMap::from_order(ChannelOrder::Flac, streaminfo.channels)
+        .ok_or(Error::UnsupportedChannels(streaminfo.channels))?;
+
+    let mut meters = vec![
+        ChannelLoudnessMeter::new(streaminfo.sample_rate);
+        streaminfo.channels as usize
+    ];
+
+    let mut blocks = reader.blocks();
+    let mut buffer = Vec::new();
+    while let Some(block) = blocks.read_next_or_eof(buffer)? {
+        monitor.advance(block.duration() as u64)?;
+
+        // Select the part of the block that lies in the requested range.
+        let block_start = block.time();
+        let block_len = block.duration() as u64;
+        let from = start.saturating_sub(block_start).min(block_len) as usize;
+        let to = end.map_or(block_len, |e| e.saturating_sub(block_start).min(block_len)) as usize;
+
+        for (ch, meter) in meters.iter_mut().enumerate() {
+            let samples = &block.channel(ch as u32)[from..to.max(from)];
+            meter.push_i32(samples.iter().cloned(), streaminfo.bits_per_sample);
+        }
+        buffer = block.into_buffer();
+
+        match end {
+            Some(e) if block_start + block_len >= e => break,
+            _ => {}
+        }
+    }
+
+    Ok(TrackAnalysis::new(streaminfo.sample_rate, channel_map, meters))
+}
+
+fn analyze_wav(path: &Path, monitor: &mut Monitor) -> Result<TrackAnalysis, Error> {
+    let file = io::BufReader::new(fs::File::open(path)?);
+    let mut reader = hound::WavReader::new(file)?;
+    let spec = reader.spec();
+    let (start, end) = monitor.options.sample_range(spec.sample_rate, Some(reader.duration() as u64));
+    // The range is clamped to the duration of the file, so the end is known.
+    let end = end.expect("The end is known when the total is known.");
+    monitor.progress.samples_total = Some(end - start);
+    // WAVE files have a fixed frame size, so we can seek to the start.
+    // The duration is a u32, so after clamping, so is `start`.
+    reader.seek(start as u32)?;
+    let num_channels = spec.channels as u32;
+    let channel_map = ChannelMap::from_order(ChannelOrder::Wave, num_channels)
+        .ok_or(Error::UnsupportedChannels(num_channels))?;
+
+    let mut meters = vec![ChannelLoudnessMeter::new(spec.sample_rate); num_channels as usize];
+
+    // Decode in chunks of interleaved samples, and then distribute them over
+    // the channels.
+    let chunk_len = 4096 * num_channels as usize;
+    let mut chunk = Vec::with_capacity(chunk_len);
+    let num_samples = ((end - start) * num_channels as u64) as usize;
+    let mut samples: Box<dyn Iterator<Item = hound::Result<f32>>> = match spec.sample_format {
+        // Float samples are already normalized to full scale.
+        hound::SampleFormat::Float => Box::new(reader.samples::<f32>().take(num_samples)),
+        hound::SampleFormat::Int => {
+            // The maximum amplitude is 1 << (bits per sample - 1), because one
+            // bit is the sign bit.
+            let normalizer = 1.0 / (1_u64 << (spec.bits_per_sample - 1)) as f32;
+            Box::new(
+                reader
+                    .samples::<i32>()
+                    .take(num_samples)
+                    .map(move |s| s.map(|x| x as f32 * normalizer))
+            )
+        }
+    };
+
+    loop {
+        chunk.clear();
+        for sample in samples.by_ref().take(chunk_len) {
+            chunk.push(sample?);
+        }
+        let n = num_channels as usize;
+        let num_frames = chunk.len() / n;
+        monitor.advance(num_frames as u64)?;
+        crate::push_interleaved(&mut meters, &chunk[..num_frames * n]);
+        if chunk.len() < chunk_len {
+            break
+        }
+    }
+
+    Ok(TrackAnalysis::new(spec.sample_rate, channel_map, meters))
+}
+
+/// Decode and measure a single FLAC or WAVE file, based on its extension.
+pub fn analyze_file(path: &Path) -> Result<TrackAnalysis, Error> {
+    analyze_file_cancellable(path, &CancellationToken::new())
+}
+
+/// Like `analyze_file`, but return `Error::Cancelled` when `cancellation` is cancelled.
+pub fn analyze_file_cancellable(
+    path: &Path,
+    cancellation: &CancellationToken,
+) -> Result<TrackAnalysis, Error> {
+    let options = Options {
+        cancellation: cancellation.clone(),
+        ..Options::default()
+    };
+    analyze_file_with_progress(path, &options, |_| {})
+}
+
+/// Like `analyze_file`, but report progress to `on_progress`.
+///
+/// Progress is reported every `options.progress_interval_samples` samples.
+/// The analysis stops with `Error::Cancelled` when `options.cancellation` is
+/// cancelled. `options.num_threads` is not used.
+pub fn analyze_file_with_progress<F>(
+    path: &Path,
+    options: &Options,
+    mut on_progress: F,
+) -> Result<TrackAnalysis, Error>
+where
+    F: FnMut(Progress),
+{
+    options.cancellation.check()?;
+    let mut monitor = Monitor::new(options, &mut on_progress);
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+    match extension.as_ref().map(|ext| &ext[..]) {
+        Some("flac") => analyze_flac(path, &mut monitor),
+        Some("wav") | Some("wave") => analyze_wav(path, &mut monitor),
+        _ => Err(Error::UnsupportedFormat),
+    }
+}
+
+enum Message {
+    Progress(usize, Progress),
+    Done(usize, Result<TrackAnalysis, Error>),
+}
+
+/// Analyze all files in `paths` on a pool of worker threads.
+///
+/// For every file, `callback` is called with the index of the file in `paths`
+/// and the result. The callback runs on the calling thread, in the order in
+/// which files complete, which is not necessarily the order of `paths`. This
+/// function returns after the callback has been called for every file.
+///
+/// When `options.cancellation` is cancelled, files in progress stop promptly,
+/// and the remaining files are not started. For those files, the callback
+/// receives `Error::Cancelled`.
+pub fn analyze_many<F>(paths: &[PathBuf], options: &Options, callback: F)
+where
+    F: FnMut(usize, Result<TrackAnalysis, Error>),
+{
+    analyze_many_with_progress(paths, options, |_, _| {}, callback)
+}
+
+/// Like `analyze_many`, but also report progress of every file to `on_progress`.
+///
+/// Like `callback`, `on_progress` runs on the calling thread. It receives the
+/// index of the file in `paths`, and the progress of that file.
+pub fn analyze_many_with_progress<P, F>(
+    paths: &[PathBuf],
+    options: &Options,
+    mut on_progress: P,
+    mut callback: F,
+)
+where
+    P: FnMut(usize, Progress),
+    F: FnMut(usize, Result<TrackAnalysis, Error>),
+{
+    assert!(options.num_threads > 0, "Need at least one thread.");
+
+    let paths: Arc<Vec<PathBuf>> = Arc::new(paths.to_vec());
+    let next_index = Arc::new(AtomicUsize::new(0));
+    let (sender, receiver) = mpsc::channel();
+
+    let num_threads = options.num_threads.min(paths.len());
+    let workers: Vec<_> = (0..num_threads).map(|_| {
+        let paths = paths.clone();
+        let next_index = next_index.clone();
+        let sender = sender.clone();
+        let options = options.clone();
+        thread::spawn(move || loop {
+            let i = next_index.fetch_add(1, Ordering::SeqCst);
+            if i >= paths.len() {
+                break
+            }
+            let progress_sender = sender.clone();
+            let result = analyze_file_with_progress(&paths[i], &options, |progress| {
+                // If the receiver is gone, there is nobody to report to.
+                let _ = progress_sender.send(Message::Progress(i, progress));
+            });
+            if sender.send(Message::Done(i, result)).is_err() {
+                break
+            }
+        })
+    }).collect();
+
+    // Drop our own sender, so the receiver ends when all workers are done.
+    drop(sender);
+
+    for message in receiver {
+        match message {
+            Message::Progress(i, progress) => on_progress(i, progress),
+            Message::Done(i, result) => callback(i, result),
+        }
+    }
+
+    for worker in workers {
+        // A worker can only panic if the decoder panics; propagate it.
+        if let Err(panic) = worker.join() {
+            std::panic::resume_unwind(panic);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use super::{Error, Options, Progress, analyze_file_cancellable, analyze_many};
+    use super::{analyze_file_with_progress, analyze_many_with_progress};
+
+    #[test]
+    fn analyze_many_reports_errors_per_file() {
+        let paths = vec![
+            PathBuf::from("does_not_exist.flac"),
+            PathBuf::from("unsupported.mp3"),
+            PathBuf::from("does_not_exist.wav"),
+        ];
+        let mut seen = vec![false; paths.len()];
+        let options = Options { num_threads: 2, ..Options::default() };
+        analyze_many(&paths, &options, |i, result| {
+            assert!(!seen[i]);
+            seen[i] = true;
+            match (i, result) {
+                (0, Err(Error::Flac(..))) => {}
+                (1, Err(Error::UnsupportedFormat)) => {}
+                (2, Err(Error::Io(..))) => {}
+                (i, result) => panic!("Unexpected result for file {}: {:?}", i, result.map(|_| ())),
+            }
+        });
+        assert!(seen.iter().all(|&s| s));
+    }
+
+    /// Write 5 seconds of a 1 kHz stereo sine at -23 dBFS to a temporary file.
+    fn write_tone(fname: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join("bs1770_batch_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(fname);
+
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 48_000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        let amplitude = 10.0_f32.powf(-23.0 / 20.0) * 32767.0;
+        for i in 0..5 * 48_000 {
+            let x = (i as f32 * 2.0 * std::f32::consts::PI * 1_000.0 / 48_000.0).sin();
+            let sample = (x * amplitude) as i16;
+            writer.write_sample(sample).unwrap();
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+        path
+    }
+
+    #[test]
+    fn analyze_many_measures_wav_files() {
+        let path = write_tone("measure.wav");
+        let paths = vec![path.clone(), path];
+        let mut num_results = 0;
+        analyze_many(&paths, &Options::default(), |_, result| {
+            let track = result.unwrap();
+            assert_eq!(track.windows.len(), 50);
+            assert!((track.loudness_lkfs() - -23.0).abs() < 0.1);
+            num_results += 1;
+        });
+        assert_eq!(num_results, 2);
+    }
+
+    #[test]
+    fn analyze_file_with_progress_measures_only_the_selected_range() {
+        let path = write_tone("range.wav");
+        let options = Options {
+            start: Duration::from_millis(1500),
+            duration: Some(Duration::from_secs(2)),
+            progress_interval_samples: 1,
+            ..Options::default()
+        };
+        let mut last_progress = None;
+        let track = analyze_file_with_progress(&path, &options, |p| last_progress = Some(p)).unwrap();
+        assert_eq!(track.windows.len(), 20);
+        assert!((track.loudness_lkfs() - -23.0).abs() < 0.1);
+        assert_eq!(last_progress.unwrap().samples_total, Some(96_000));
+
+        // A range past the end of the file selects nothing.
+        let options = Options { start: Duration::from_secs(60), ..Options::default() };
+        let track = analyze_file_with_progress(&path, &options, |_| {}).unwrap();
+        assert_eq!(track.windows.len(), 0);
+    }
+
+    #[test]
+    fn analyze_many_reports_progress() {
+        let path = write_tone("progress.wav");
+        let paths = vec![path];
+        let options = Options {
+            progress_interval_samples: 48_000,
+            ..Options::default()
+        };
+        let mut reports = Vec::new();
+        analyze_many_with_progress(
+            &paths,
+            &options,
+            |_, progress| reports.push(progress),
+            |_, result| assert!(result.is_ok()),
+        );
+        // The file is 5 seconds at 48 kHz. Samples are decoded in chunks, so
+        // the reports are not at exact multiples of the interval.
+        assert_eq!(reports.len(), 5);
+        assert!(reports.iter().all(|p| p.samples_total == Some(240_000)));
+        assert!(reports.windows(2).all(|w| w[0].samples_processed < w[1].samples_processed));
+        assert_eq!(reports[4], Progress { samples_processed: 240_000, samples_total: Some(240_000) });
+        assert_eq!(reports[4].fraction(), Some(1.0));
+    }
+
+    #[test]
+    fn analyze_many_reports_cancellation_for_every_file() {
+        let path = write_tone("cancel.wav");
+        let paths = vec![path.clone(), path.clone(), path];
+        let options = Options::default();
+        options.cancellation.cancel();
+
+        let mut num_results = 0;
+        analyze_many(&paths, &options, |_, result| {
+            match result {
+                Err(Error::Cancelled) => num_results += 1,
+                _ => panic!("Expected analysis to be cancelled."),
+            }
+        });
+        assert_eq!(num_results, 3);
+
+        match analyze_file_cancellable(&paths[0], &options.cancellation) {
+            Err(Error::Cancelled) => {}
+            _ => panic!("Expected analysis to be cancelled."),
+        }
+    }
+}
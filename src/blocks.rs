@@ -0,0 +1,221 @@
+// BS1770 -- Loudness analysis library conforming to ITU-R BS.1770
+// Copyright 2020 Ruud van Asseldonk
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! Blocks of configurable length and overlap, for research and visualization.
+//!
+//! BS.1770-4 forms gating blocks of 400ms from 100ms windows, with an overlap
+//! of 75%. This module forms blocks of any number of windows instead, and can
+//! gate them like `gated_mean`. Together with
+//! `ChannelLoudnessMeter::with_windows_per_second`, which changes the length
+//! of the windows, this can for example produce a momentary loudness trace in
+//! steps of 10ms, rather than 100ms, for a smoother plot. A measurement with
+//! non-standard blocks is not a BS.1770-4 measurement.
+//!
+//! ```
+//! use bs1770::ChannelLoudnessMeter;
+//! use bs1770::blocks::{self, BlockConfig};
+//!
+//! // 10ms windows, so 400ms blocks that advance by 10ms are 40 windows long.
+//! let mut meter = ChannelLoudnessMeter::with_windows_per_second(48_000, 100);
+//! meter.push((0..48_000).map(|i| 0.1 * (i as f32 * 0.05).sin()));
+//! let windows = meter.as_windows();
+//! assert_eq!(windows.len(), 100);
+//!
+//! let config = BlockConfig { block_len: 40, step: 1 };
+//! let trace: Vec<_> = blocks::block_powers(windows.inner, config).collect();
+//! assert_eq!(trace.len(), 61);
+//! ```
+
+use core::time::Duration;
+
+use crate::{GateConfig, GatingStats, Power, Windows100ms};
+
+/// A `T` value for non-overlapping windows of audio, of any length.
+///
+/// `ChannelLoudnessMeter::with_windows_per_second` produces these. Unlike
+/// `Windows100ms`, the windows carry their length, so the times they report
+/// are correct for any window length. Only windows of exactly 100ms convert
+/// to `Windows100ms`, to compute momentary or gated loudness.
+#[derive(Copy, Clone, Debug)]
+pub struct Windows<T> {
+    pub inner: T,
+
+    /// The number of windows per second of audio, 10 for 100ms windows.
+    pub windows_per_second: u32,
+}
+
+impl<T> Windows<T> {
+    /// Apply `as_ref` to the inner value.
+    pub fn as_ref(&self) -> Windows<&[Power]> where T: AsRef<[Power]> {
+        Windows {
+            inner: self.inner.as_ref(),
+            windows_per_second: self.windows_per_second,
+        }
+    }
+
+    /// Apply `len` to the inner value.
+    pub fn len(&self) -> usize where T: AsRef<[Power]> {
+        self.inner.as_ref().len()
+    }
+
+    /// Apply `is_empty` to the inner value.
+    pub fn is_empty(&self) -> bool where T: AsRef<[Power]> {
+        self.inner.as_ref().is_empty()
+    }
+
+    /// Return the time at which the window with the given index starts.
+    fn start_time(&self, index: usize) -> Duration {
+        let nanos = index as u128 * 1_000_000_000 / self.windows_per_second as u128;
+        Duration::from_nanos(nanos as u64)
+    }
+
+    /// Return the total duration of the windows.
+    pub fn duration(&self) -> Duration where T: AsRef<[Power]> {
+        self.start_time(self.len())
+    }
+
+    /// Iterate over the windows, paired with the time at which each window starts.
+    ///
+    /// ```
+    /// # use std::time::Duration;
+    /// # use bs1770::Power;
+    /// # use bs1770::blocks::Windows;
+    /// let windows = Windows { inner: vec![Power(0.1), Power(0.2)], windows_per_second: 100 };
+    /// let timed: Vec<_> = windows.iter_with_time().collect();
+    /// assert!(timed[1] == (Duration::from_millis(10), Power(0.2)));
+    /// ```
+    pub fn iter_with_time(&self) -> impl Iterator<Item = (Duration, Power)> + '_
+    where T: AsRef<[Power]> {
+        self.inner
+            .as_ref()
+            .iter()
+            .enumerate()
+            .map(move |(i, &power)| (self.start_time(i), power))
+    }
+
+    /// Return the windows as `Windows100ms`, or `None` if they are not 100ms long.
+    pub fn into_100ms(self) -> Option<Windows100ms<T>> {
+        if self.windows_per_second == 10 {
+            Some(Windows100ms { inner: self.inner })
+        } else {
+            None
+        }
+    }
+}
+
+/// The length of blocks and the distance between them, in windows.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BlockConfig {
+    /// The number of windows per block.
+    pub block_len: usize,
+
+    /// The number of windows from the start of one block to the start of the next.
+    ///
+    /// When this is less than `block_len`, blocks overlap.
+    pub step: usize,
+}
+
+impl Default for BlockConfig {
+    /// The gating blocks of BS.1770-4, for 100ms windows: 400ms with 75% overlap.
+    fn default() -> BlockConfig {
+        BlockConfig {
+            block_len: 4,
+            step: 1,
+        }
+    }
+}
+
+/// Return the mean power of every block of windows.
+///
+/// The first block starts at the first window. A trailing part that does not
+/// fill a complete block is not included. Panics if the block length or step
+/// is zero.
+pub fn block_powers(windows: &[Power], config: BlockConfig) -> impl ExactSizeIterator<Item = Power> + '_ {
+    assert!(config.block_len > 0, "Blocks must contain at least one window.");
+    assert!(config.step > 0, "Blocks must advance by at least one window.");
+    let normalizer = 1.0 / config.block_len as f32;
+    windows
+        .windows(config.block_len)
+        .step_by(config.step)
+        .map(move |block| Power(block.iter().map(|p| p.0).sum::<f32>() * normalizer))
+}
+
+/// Gate the blocks like `gated_mean_stats_with`, and return the results.
+///
+/// With the default block and gate configuration, this is the same as
+/// `gated_mean_stats`. Like there, windows that do not fill a single block are
+/// treated as one block.
+pub fn gated_mean_stats(windows: &[Power], blocks: BlockConfig, gate: GateConfig) -> GatingStats {
+    if !windows.is_empty() && windows.len() < blocks.block_len {
+        let sum_power: f32 = windows.iter().map(|p| p.0).sum();
+        let block = Power(sum_power / windows.len() as f32);
+        return crate::gate_blocks(|| core::iter::once(block), gate)
+    }
+    crate::gate_blocks(|| block_powers(windows, blocks), gate)
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+
+    use super::{BlockConfig, Windows, block_powers, gated_mean_stats};
+    use crate::{ChannelLoudnessMeter, GateConfig, Power, Windows100ms};
+
+    #[test]
+    fn default_blocks_match_gating_blocks() {
+        let windows: Vec<Power> = (0..300).map(|i| Power::from_lkfs(-40.0 + (i % 23) as f32)).collect();
+        let windows_100ms = Windows100ms { inner: &windows[..] };
+        let expected: Vec<Power> = windows_100ms.gating_blocks().collect();
+        let actual: Vec<Power> = block_powers(&windows, BlockConfig::default()).collect();
+        assert_eq!(actual.len(), expected.len());
+        for (p, q) in actual.iter().zip(&expected) {
+            assert!((p.0 - q.0).abs() < 1e-6 * q.0);
+        }
+
+        let stats = gated_mean_stats(&windows, BlockConfig::default(), GateConfig::default());
+        let expected = crate::gated_mean_stats(windows_100ms);
+        assert_eq!(stats.num_blocks_relative_gated, expected.num_blocks_relative_gated);
+        let (p, q) = (stats.gated_power.unwrap(), expected.gated_power.unwrap());
+        assert!((p.0 - q.0).abs() < 1e-6 * q.0);
+    }
+
+    #[test]
+    fn block_powers_respects_step() {
+        let windows = [Power(1.0), Power(2.0), Power(3.0), Power(4.0), Power(5.0)];
+        let config = BlockConfig { block_len: 2, step: 2 };
+        let blocks: Vec<Power> = block_powers(&windows, config).collect();
+        assert_eq!(blocks, vec![Power(1.5), Power(3.5)]);
+    }
+
+    #[test]
+    fn finer_windows_measure_the_same_loudness() {
+        let samples: Vec<f32> = (0..96_000).map(|i| 0.1 * (i as f32 * 0.05).sin()).collect();
+        let mut meter_100ms = ChannelLoudnessMeter::new(48_000);
+        let mut meter_10ms = ChannelLoudnessMeter::with_windows_per_second(48_000, 100);
+        meter_100ms.push(samples.iter().cloned());
+        meter_10ms.push(samples.iter().cloned());
+        assert_eq!(meter_10ms.as_windows().len(), 200);
+        assert_eq!(meter_10ms.as_windows().duration(), Duration::from_secs(2));
+
+        let config = BlockConfig { block_len: 40, step: 10 };
+        let stats = gated_mean_stats(meter_10ms.as_windows().inner, config, GateConfig::default());
+        let expected = crate::gated_mean(meter_100ms.as_100ms_windows()).unwrap();
+        let actual = stats.gated_power.unwrap();
+        assert!((actual.loudness_lkfs() - expected.loudness_lkfs()).abs() < 1e-3);
+    }
+
+    #[test]
+    fn windows_convert_to_100ms_windows_only_when_100ms_long() {
+        let meter_10ms = ChannelLoudnessMeter::with_windows_per_second(48_000, 100);
+        assert!(meter_10ms.into_windows().into_100ms().is_none());
+        let meter_100ms = ChannelLoudnessMeter::new(48_000);
+        assert!(meter_100ms.into_windows().into_100ms().is_some());
+
+        let windows = Windows { inner: vec![Power(0.0); 3], windows_per_second: 3 };
+        assert_eq!(windows.duration(), Duration::from_secs(1));
+    }
+}
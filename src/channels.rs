@@ -0,0 +1,429 @@
+// BS1770 -- Loudness analysis library conforming to ITU-R BS.1770
+// Copyright 2020 Ruud van Asseldonk
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! Mapping container channel orders to loudspeaker positions.
+//!
+//! BS.1770-4 weighs channels by the position of their loudspeaker: surround
+//! channels count for more than front channels, and the LFE channel is
+//! excluded entirely. To apply the weights, we need to know which channel is
+//! which, but containers disagree about the order of the channels. For
+//! example, 5.1 audio in a WAVE or FLAC file is ordered L, R, C, LFE, Ls, Rs,
+//! but in an Ogg Vorbis or Opus file it is ordered L, C, R, Ls, Rs, LFE.
+//!
+//! A `ChannelMap` records the position of every channel, so the right weight
+//! gets applied to the right channel:
+//!
+//! ```
+//! use bs1770::channels::{Channel, ChannelMap, ChannelOrder};
+//!
+//! let map = ChannelMap::from_order(ChannelOrder::Vorbis, 6).unwrap();
+//! assert_eq!(map.channels()[1], Channel::Center);
+//! assert_eq!(map.weights(), vec![1.0, 1.0, 1.0, 1.41, 1.41, 0.0]);
+//! ```
+//!
+//! To measure only some of the channels, such as the front pair of a 5.1
+//! stream, select them on the map. The other channels then get weight 0:
+//!
+//! ```
+//! # use bs1770::channels::{Channel, ChannelMap, ChannelOrder};
+//! let map = ChannelMap::from_order(ChannelOrder::Wave, 6)
+//!     .unwrap()
+//!     .select_positions(&[Channel::Left, Channel::Right]);
+//! assert_eq!(map.weights(), vec![1.0, 1.0, 0.0, 0.0, 0.0, 0.0]);
+//! ```
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::{Power, Windows100ms, reduce_channels};
+#[cfg(not(feature = "std"))]
+use crate::math::Float;
+
+/// The loudspeaker position that a channel is intended for.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Channel {
+    /// Front left, at 30° azimuth.
+    Left,
+    /// Front right, at -30° azimuth.
+    Right,
+    /// Front center, at 0° azimuth.
+    Center,
+    /// A single channel reproduced by both the left and right loudspeakers.
+    ///
+    /// BS.1770-4 measures this as two identical channels, so it has the
+    /// combined weight of `Left` and `Right`.
+    Mono,
+    /// Low-frequency effects.
+    Lfe,
+    /// Left surround, between 60° and 120° azimuth (typically 110°).
+    LeftSurround,
+    /// Right surround, between -60° and -120° azimuth (typically -110°).
+    RightSurround,
+    /// Left rear surround in 7.1 layouts, at 135° to 150° azimuth.
+    LeftBack,
+    /// Right rear surround in 7.1 layouts, at -135° to -150° azimuth.
+    RightBack,
+    /// Rear center, at 180° azimuth.
+    ///
+    /// This is the mono surround channel of layouts such as 4.0 and 6.1. Per
+    /// BS.775 the surround loudspeakers reproduce it, so it has the weight of
+    /// a surround channel.
+    BackCenter,
+    /// Front left of center, at about 15° azimuth.
+    LeftOfCenter,
+    /// Front right of center, at about -15° azimuth.
+    RightOfCenter,
+    /// Any elevated (height) channel.
+    Top,
+    /// A channel with an unknown position.
+    Unknown,
+}
+
+impl Channel {
+    /// Return the weight of the channel, per table 3 of BS.1770-4.
+    ///
+    /// Surround channels (positioned at an azimuth between 60° and 120°) and
+    /// the mono surround channel `BackCenter` have weight 1.41 (+1.5 dB), and
+    /// the LFE channel is excluded with weight 0.
+    /// A mono channel counts as both front channels, with weight 2.0. All
+    /// other channels have weight 1.0, including elevated channels, rear
+    /// channels positioned behind 120°, and channels of unknown position. For
+    /// loudspeakers at a known angle, see `position_weight`.
+    pub fn weight(self) -> f32 {
+        match self {
+            Channel::Lfe => 0.0,
+            Channel::Mono => 2.0,
+            Channel::LeftSurround | Channel::RightSurround | Channel::BackCenter => 1.41,
+            _ => 1.0,
+        }
+    }
+}
+
+/// Return the weight of a loudspeaker at the given position, per BS.1770-4.
+///
+/// Table 3 of BS.1770-4 defines the weight by position: a loudspeaker with an
+/// elevation of less than 30° and an azimuth between 60° and 120° to either
+/// side has weight 1.41 (+1.5 dB), all other loudspeakers have weight 1.0.
+/// Angles are in degrees, azimuth is measured from the front center, and the
+/// sign of either angle does not matter. This covers layouts for advanced
+/// sound systems (BS.2051), such as 4+5+0 and 9+10+3. The LFE channel is not
+/// a position; exclude it with weight zero.
+///
+/// ```
+/// use bs1770::channels::position_weight;
+///
+/// assert_eq!(position_weight(30.0, 0.0), 1.0);
+/// assert_eq!(position_weight(-110.0, 0.0), 1.41);
+/// assert_eq!(position_weight(110.0, 45.0), 1.0);
+/// ```
+pub fn position_weight(azimuth_deg: f32, elevation_deg: f32) -> f32 {
+    // Normalize the azimuth to [-180, 180), so e.g. 250° counts as -110°.
+    let azimuth = (azimuth_deg + 180.0).rem_euclid(360.0) - 180.0;
+    if elevation_deg.abs() < 30.0 && azimuth.abs() >= 60.0 && azimuth.abs() <= 120.0 {
+        1.41
+    } else {
+        1.0
+    }
+}
+
+/// Conventions for the default order of channels.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ChannelOrder {
+    /// WAVE files without a channel mask, see also `ChannelMap::from_wave_mask`.
+    Wave,
+    /// The channel order defined in the FLAC format specification.
+    Flac,
+    /// The default channel layouts of FFmpeg.
+    Ffmpeg,
+    /// SMPTE ST 2036-2, as used in broadcast, e.g. L, R, C, LFE, Ls, Rs, Lrs, Rrs.
+    Smpte,
+    /// The order defined by the Vorbis specification, also used by Opus.
+    Vorbis,
+}
+
+/// The loudspeaker position of every channel in a stream.
+///
+/// A map also records which channels are selected for measurement. By
+/// default all channels are selected, `select` and `select_positions` narrow
+/// the measurement down to a subset.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ChannelMap {
+    channels: Vec<Channel>,
+    selected: Vec<bool>,
+}
+
+use self::Channel::*;
+
+impl ChannelMap {
+    /// Construct a channel map from explicit channel positions.
+    pub fn new(channels: Vec<Channel>) -> ChannelMap {
+        let selected = vec![true; channels.len()];
+        ChannelMap { channels, selected }
+    }
+
+    /// Return the default channel map for the given number of channels.
+    ///
+    /// Returns `None` if the convention does not define a layout for that
+    /// number of channels. A single channel is taken to be `Mono`, played back
+    /// over both front loudspeakers, so it measures as loud as the same audio
+    /// in dual-mono stereo.
+    pub fn from_order(order: ChannelOrder, num_channels: u32) -> Option<ChannelMap> {
+        let channels: &[Channel] = match (order, num_channels) {
+            (_, 1) => &[Mono],
+            (_, 2) => &[Left, Right],
+
+            // The default 4-channel layout of FFmpeg is 4.0 rather than quad.
+            (ChannelOrder::Ffmpeg, 4) => &[Left, Right, Center, BackCenter],
+
+            // WAVE, FLAC, and FFmpeg otherwise agree with one another; they all
+            // follow the order of the WAVEFORMATEXTENSIBLE speaker bits.
+            (ChannelOrder::Wave, n) |
+            (ChannelOrder::Flac, n) |
+            (ChannelOrder::Ffmpeg, n) => match n {
+                3 => &[Left, Right, Center],
+                4 => &[Left, Right, LeftSurround, RightSurround],
+                5 => &[Left, Right, Center, LeftSurround, RightSurround],
+                6 => &[Left, Right, Center, Lfe, LeftSurround, RightSurround],
+                7 => &[Left, Right, Center, Lfe, BackCenter, LeftSurround, RightSurround],
+                8 => &[Left, Right, Center, Lfe, LeftBack, RightBack, LeftSurround, RightSurround],
+                _ => return None,
+            },
+
+            // SMPTE differs from WAVE for 7.1: the side surround channels
+            // come before the rear surround channels.
+            (ChannelOrder::Smpte, n) => match n {
+                3 => &[Left, Right, Center],
+                6 => &[Left, Right, Center, Lfe, LeftSurround, RightSurround],
+                8 => &[Left, Right, Center, Lfe, LeftSurround, RightSurround, LeftBack, RightBack],
+                _ => return None,
+            },
+
+            (ChannelOrder::Vorbis, n) => match n {
+                3 => &[Left, Center, Right],
+                4 => &[Left, Right, LeftSurround, RightSurround],
+                5 => &[Left, Center, Right, LeftSurround, RightSurround],
+                6 => &[Left, Center, Right, LeftSurround, RightSurround, Lfe],
+                7 => &[Left, Center, Right, LeftSurround, RightSurround, BackCenter, Lfe],
+                8 => &[Left, Center, Right, LeftSurround, RightSurround, LeftBack, RightBack, Lfe],
+                _ => return None,
+            },
+        };
+        Some(ChannelMap::new(channels.to_vec()))
+    }
+
+    /// Construct a channel map from a WAVEFORMATEXTENSIBLE `dwChannelMask`.
+    ///
+    /// Channels are assigned to the speaker bits that are set, in order of
+    /// increasing bit position. If the mask has fewer bits set than there are
+    /// channels, the remaining channels get position `Unknown`.
+    pub fn from_wave_mask(mask: u32, num_channels: u32) -> ChannelMap {
+        const SPEAKER_FRONT_LEFT: u32 = 0x1;
+        const SPEAKER_BACK_LEFT: u32 = 0x10;
+        const SPEAKER_SIDE_LEFT: u32 = 0x200;
+        const SPEAKER_SIDE_RIGHT: u32 = 0x400;
+
+        // When a layout has both side and back channels (7.1), the back
+        // channels are the rear surround pair. Without side channels (e.g.
+        // 5.1 with back channels), the back channels are the surround pair.
+        let has_side = mask & (SPEAKER_SIDE_LEFT | SPEAKER_SIDE_RIGHT) != 0;
+        let (back_left, back_right) = if has_side {
+            (LeftBack, RightBack)
+        } else {
+            (LeftSurround, RightSurround)
+        };
+
+        let mut channels = Vec::with_capacity(num_channels as usize);
+        for bit in 0..32 {
+            if channels.len() == num_channels as usize {
+                break
+            }
+            let speaker = SPEAKER_FRONT_LEFT << bit;
+            if mask & speaker == 0 {
+                continue
+            }
+            let channel = match speaker {
+                0x1 => Left,
+                0x2 => Right,
+                0x4 => Center,
+                0x8 => Lfe,
+                SPEAKER_BACK_LEFT => back_left,
+                0x20 => back_right,
+                0x40 => LeftOfCenter,
+                0x80 => RightOfCenter,
+                0x100 => BackCenter,
+                SPEAKER_SIDE_LEFT => LeftSurround,
+                SPEAKER_SIDE_RIGHT => RightSurround,
+                0x800..=0x20000 => Top,
+                _ => Unknown,
+            };
+            channels.push(channel);
+        }
+        channels.resize(num_channels as usize, Unknown);
+        ChannelMap::new(channels)
+    }
+
+    /// Return the position of every channel.
+    pub fn channels(&self) -> &[Channel] {
+        &self.channels[..]
+    }
+
+    /// Return the number of channels.
+    pub fn len(&self) -> usize {
+        self.channels.len()
+    }
+
+    /// Return whether the map contains no channels.
+    pub fn is_empty(&self) -> bool {
+        self.channels.is_empty()
+    }
+
+    /// Measure only the channels for which `mask` is true.
+    ///
+    /// The mask is indexed by channel, and replaces any previous selection.
+    /// Panics if the mask does not have one element per channel.
+    pub fn select(mut self, mask: &[bool]) -> ChannelMap {
+        assert_eq!(mask.len(), self.channels.len(), "Need a mask element for every channel.");
+        self.selected.copy_from_slice(mask);
+        self
+    }
+
+    /// Measure only the channels at one of the given positions.
+    ///
+    /// A `Mono` channel is selected along with `Left` or `Right`, because it
+    /// is reproduced by those loudspeakers. This replaces any previous selection.
+    pub fn select_positions(mut self, positions: &[Channel]) -> ChannelMap {
+        let front = positions.contains(&Left) || positions.contains(&Right);
+        for (selected, channel) in self.selected.iter_mut().zip(&self.channels) {
+            *selected = positions.contains(channel) || (*channel == Mono && front);
+        }
+        self
+    }
+
+    /// Return whether the channel at the given index is selected for measurement.
+    pub fn is_selected(&self, index: usize) -> bool {
+        self.selected[index]
+    }
+
+    /// Return the BS.1770-4 weight of every channel.
+    ///
+    /// Channels that are not selected have weight 0.
+    pub fn weights(&self) -> Vec<f32> {
+        self.channels
+            .iter()
+            .zip(&self.selected)
+            .map(|(ch, &selected)| if selected { ch.weight() } else { 0.0 })
+            .collect()
+    }
+
+    /// Combine the power of the selected channels by taking the weighted sum.
+    ///
+    /// The windows must be in the same order as the channels in the map, and
+    /// all channels must have the same number of windows. Windows must be
+    /// provided for unselected channels too, but they do not contribute.
+    pub fn reduce(&self, channels: &[Windows100ms<&[Power]>]) -> Windows100ms<Vec<Power>> {
+        assert_eq!(channels.len(), self.channels.len(), "Need windows for every channel.");
+        reduce_channels(channels, &self.weights())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Channel, ChannelMap, ChannelOrder, position_weight};
+    use crate::{Power, Windows100ms};
+
+    #[test]
+    fn wave_and_vorbis_5_1_map_the_same_positions_to_different_indices() {
+        let wave = ChannelMap::from_order(ChannelOrder::Wave, 6).unwrap();
+        let vorbis = ChannelMap::from_order(ChannelOrder::Vorbis, 6).unwrap();
+        assert_eq!(wave.channels()[3], Channel::Lfe);
+        assert_eq!(vorbis.channels()[5], Channel::Lfe);
+        assert_eq!(wave.weights(), vec![1.0, 1.0, 1.0, 0.0, 1.41, 1.41]);
+        assert_eq!(vorbis.weights(), vec![1.0, 1.0, 1.0, 1.41, 1.41, 0.0]);
+    }
+
+    #[test]
+    fn mono_weighs_as_much_as_dual_mono_stereo() {
+        let mono = ChannelMap::from_order(ChannelOrder::Flac, 1).unwrap();
+        let stereo = ChannelMap::from_order(ChannelOrder::Flac, 2).unwrap();
+        assert_eq!(mono.channels(), &[Channel::Mono]);
+        assert_eq!(mono.weights().iter().sum::<f32>(), stereo.weights().iter().sum::<f32>());
+
+        let mono = mono.select_positions(&[Channel::Left, Channel::Right]);
+        assert_eq!(mono.weights(), vec![2.0]);
+    }
+
+    #[test]
+    fn ffmpeg_4_channels_is_4_0_and_flac_is_quad() {
+        let ffmpeg = ChannelMap::from_order(ChannelOrder::Ffmpeg, 4).unwrap();
+        let flac = ChannelMap::from_order(ChannelOrder::Flac, 4).unwrap();
+        assert_eq!(ffmpeg.channels()[2], Channel::Center);
+        assert_eq!(ffmpeg.channels()[3], Channel::BackCenter);
+        assert_eq!(ffmpeg.weights(), vec![1.0, 1.0, 1.0, 1.41]);
+        assert_eq!(flac.weights(), vec![1.0, 1.0, 1.41, 1.41]);
+    }
+
+    #[test]
+    fn smpte_and_wave_7_1_differ_in_surround_order() {
+        let wave = ChannelMap::from_order(ChannelOrder::Wave, 8).unwrap();
+        let smpte = ChannelMap::from_order(ChannelOrder::Smpte, 8).unwrap();
+        assert_eq!(wave.channels()[4], Channel::LeftBack);
+        assert_eq!(smpte.channels()[4], Channel::LeftSurround);
+    }
+
+    #[test]
+    fn from_wave_mask_distinguishes_back_and_side() {
+        // 5.1 with back channels: the back channels are the surrounds.
+        let map = ChannelMap::from_wave_mask(0x3f, 6);
+        assert_eq!(map, ChannelMap::from_order(ChannelOrder::Wave, 6).unwrap());
+
+        // 7.1: the side channels are the surrounds, back are rear surround.
+        let map = ChannelMap::from_wave_mask(0x63f, 8);
+        assert_eq!(map, ChannelMap::from_order(ChannelOrder::Wave, 8).unwrap());
+
+        // Not enough bits set.
+        let map = ChannelMap::from_wave_mask(0x3, 3);
+        assert_eq!(map.channels(), &[Channel::Left, Channel::Right, Channel::Unknown]);
+    }
+
+    #[test]
+    fn reduce_applies_weights_and_excludes_lfe() {
+        let map = ChannelMap::from_order(ChannelOrder::Vorbis, 6).unwrap();
+        let ones = [Power(1.0); 3];
+        let channels = vec![Windows100ms { inner: &ones[..] }; 6];
+        let reduced = map.reduce(&channels);
+        assert_eq!(reduced.len(), 3);
+        assert!(reduced.inner.iter().all(|p| (p.0 - 5.82).abs() < 1e-6));
+    }
+
+    #[test]
+    fn reduce_excludes_unselected_channels() {
+        let map = ChannelMap::from_order(ChannelOrder::Wave, 6)
+            .unwrap()
+            .select(&[false, false, true, false, true, true]);
+        assert!(map.is_selected(2));
+        assert!(!map.is_selected(0));
+
+        let ones = [Power(1.0); 2];
+        let channels = vec![Windows100ms { inner: &ones[..] }; 6];
+        let reduced = map.reduce(&channels);
+        assert!(reduced.inner.iter().all(|p| (p.0 - 3.82).abs() < 1e-6));
+
+        // Selecting positions replaces the previous selection.
+        let map = map.select_positions(&[Channel::Left, Channel::Right]);
+        assert_eq!(map.weights(), vec![1.0, 1.0, 0.0, 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn position_weight_matches_table_3() {
+        assert_eq!(position_weight(0.0, 0.0), 1.0);
+        assert_eq!(position_weight(60.0, 0.0), 1.41);
+        assert_eq!(position_weight(-120.0, 29.0), 1.41);
+        assert_eq!(position_weight(135.0, 0.0), 1.0);
+        assert_eq!(position_weight(90.0, 30.0), 1.0);
+        assert_eq!(position_weight(250.0, -10.0), 1.41);
+    }
+}
@@ -0,0 +1,484 @@
+// BS1770 -- Loudness analysis library conforming to ITU-R BS.1770
+// Copyright 2020 Ruud van Asseldonk
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! Compliance tests against the EBU Tech 3341 and Tech 3342 test signals.
+//!
+//! The unit tests of this library verify the library itself, but an
+//! application that measures loudness consists of more than that: a decoder
+//! that produces samples, a normalization step, a channel mapping, and the
+//! meter. This module generates the synthetic test signals of [EBU Tech
+//! 3341][tech3341] and [EBU Tech 3342][tech3342], runs them through any
+//! implementation of the `Meter` trait, and reports the measured value per
+//! case. To verify a full pipeline, implement `Meter` by encoding the signal in
+//! your format of choice, and then decoding and measuring it the way your
+//! application does.
+//!
+//! The Tech 3341 cases covered are the integrated loudness cases 1 through 6,
+//! the short-term loudness case 9, and the momentary loudness case 12. Cases 7
+//! and 8 use authentic program material rather than synthetic signals; they
+//! can be downloaded from the EBU. The remaining cases verify the maximum
+//! momentary and short-term loudness over series of tone bursts, and are not
+//! included. The Tech 3342 cases covered are the loudness range cases 1
+//! through 4; cases 5 and 6 are authentic program material.
+//!
+//! [tech3341]: https://tech.ebu.ch/publications/tech3341
+//! [tech3342]: https://tech.ebu.ch/publications/tech3342
+//!
+//! ```
+//! use bs1770::compliance::{self, ReferenceMeter};
+//!
+//! let outcomes = compliance::run_tech_3341(&mut ReferenceMeter, 48_000);
+//! for outcome in &outcomes {
+//!     assert!(outcome.passed(), "{} failed", outcome.case);
+//! }
+//! ```
+
+use alloc::vec::Vec;
+use core::f64;
+
+use crate::channels::{Channel, ChannelMap};
+use crate::{ChannelLoudnessMeter, Power, Windows100ms};
+use crate::lra;
+#[cfg(not(feature = "std"))]
+use crate::math::Float;
+
+/// A multichannel test signal.
+#[derive(Clone, Debug)]
+pub struct Signal {
+    /// The sample rate of the signal.
+    pub sample_rate_hz: u32,
+
+    /// The loudspeaker position of every channel in `samples`.
+    pub channel_map: ChannelMap,
+
+    /// Samples for every channel, normalized to full scale [-1.0, 1.0].
+    pub samples: Vec<Vec<f32>>,
+}
+
+impl Signal {
+    /// Measure the power of every 100ms window, with the channel weights applied.
+    pub(crate) fn windows(&self) -> Windows100ms<Vec<Power>> {
+        let windows: Vec<Windows100ms<Vec<Power>>> = self.samples.iter().map(|samples| {
+            let mut meter = ChannelLoudnessMeter::new(self.sample_rate_hz);
+            meter.push(samples.iter().cloned());
+            meter.into_100ms_windows()
+        }).collect();
+        let windows_refs: Vec<_> = windows.iter().map(|w| w.as_ref()).collect();
+        self.channel_map.reduce(&windows_refs)
+    }
+}
+
+/// A loudness meter under test.
+pub trait Meter {
+    /// Measure the integrated loudness of the signal, in LKFS.
+    ///
+    /// Return -∞ when the signal is silent.
+    fn integrated_loudness_lkfs(&mut self, signal: &Signal) -> f32;
+
+    /// Measure the momentary loudness of the signal every 100ms, in LKFS.
+    ///
+    /// The first value covers the first 400ms of the signal, as for
+    /// `Windows100ms::momentary`.
+    fn momentary_loudness_lkfs(&mut self, signal: &Signal) -> Vec<f32>;
+
+    /// Measure the short-term loudness of the signal every 100ms, in LKFS.
+    ///
+    /// The first value covers the first 3s of the signal, as for
+    /// `Windows100ms::short_term`.
+    fn short_term_loudness_lkfs(&mut self, signal: &Signal) -> Vec<f32>;
+
+    /// Measure the loudness range of the signal, in LU.
+    ///
+    /// Return NaN when the loudness range is undefined, such as for silence.
+    fn loudness_range_lu(&mut self, signal: &Signal) -> f32;
+}
+
+/// A `Meter` that measures with this library directly.
+///
+/// It applies the channel weights of the signal's `ChannelMap`, and computes
+/// the loudness range with `lra::loudness_range`.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ReferenceMeter;
+
+impl Meter for ReferenceMeter {
+    fn integrated_loudness_lkfs(&mut self, signal: &Signal) -> f32 {
+        crate::gated_mean(signal.windows().as_ref())
+            .unwrap_or(Power(0.0))
+            .loudness_lkfs()
+    }
+
+    fn momentary_loudness_lkfs(&mut self, signal: &Signal) -> Vec<f32> {
+        signal.windows().momentary().map(|p| p.loudness_lkfs()).collect()
+    }
+
+    fn short_term_loudness_lkfs(&mut self, signal: &Signal) -> Vec<f32> {
+        signal.windows().short_term().map(|p| p.loudness_lkfs()).collect()
+    }
+
+    fn loudness_range_lu(&mut self, signal: &Signal) -> f32 {
+        lra::loudness_range(signal.windows().as_ref()).unwrap_or(f32::NAN)
+    }
+}
+
+/// The quantity that a test case verifies.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Quantity {
+    /// The integrated loudness, in LKFS.
+    Integrated,
+
+    /// The momentary loudness, in LKFS, which must be within tolerance throughout.
+    Momentary,
+
+    /// The short-term loudness, in LKFS, which must be within tolerance throughout.
+    ShortTerm,
+
+    /// The loudness range, in LU.
+    LoudnessRange,
+}
+
+/// A test case from EBU Tech 3341 or Tech 3342.
+#[derive(Copy, Clone, Debug)]
+pub struct TestCase {
+    /// Name of the case, e.g. “Tech 3341 case 1”.
+    pub name: &'static str,
+
+    /// The quantity that the case verifies.
+    pub quantity: Quantity,
+
+    /// The expected value, in LKFS, or in LU for the loudness range.
+    pub expected: f32,
+
+    /// The maximum allowed deviation from the expected value, in LU.
+    pub tolerance_lu: f32,
+
+    /// Consecutive 1 kHz tones as (duration in milliseconds, amplitude in dBFS).
+    tones: &'static [(u32, f32)],
+
+    /// The number of times that the sequence of tones is repeated.
+    repetitions: u32,
+
+    /// Position of every channel, and its gain in dB relative to the tones.
+    channels: &'static [(Channel, f32)],
+}
+
+const STEREO: &[(Channel, f32)] = &[(Channel::Left, 0.0), (Channel::Right, 0.0)];
+
+/// The synthetic cases of EBU Tech 3341-2016, table 1.
+pub const TECH_3341_CASES: &[TestCase] = &[
+    TestCase {
+        name: "Tech 3341 case 1",
+        quantity: Quantity::Integrated,
+        expected: -23.0,
+        tolerance_lu: 0.1,
+        tones: &[(20_000, -23.0)],
+        repetitions: 1,
+        channels: STEREO,
+    },
+    TestCase {
+        name: "Tech 3341 case 2",
+        quantity: Quantity::Integrated,
+        expected: -33.0,
+        tolerance_lu: 0.1,
+        tones: &[(20_000, -33.0)],
+        repetitions: 1,
+        channels: STEREO,
+    },
+    TestCase {
+        name: "Tech 3341 case 3",
+        quantity: Quantity::Integrated,
+        expected: -23.0,
+        tolerance_lu: 0.1,
+        tones: &[(10_000, -36.0), (60_000, -23.0), (10_000, -36.0)],
+        repetitions: 1,
+        channels: STEREO,
+    },
+    TestCase {
+        name: "Tech 3341 case 4",
+        quantity: Quantity::Integrated,
+        expected: -23.0,
+        tolerance_lu: 0.1,
+        tones: &[
+            (10_000, -72.0),
+            (10_000, -36.0),
+            (60_000, -23.0),
+            (10_000, -36.0),
+            (10_000, -72.0),
+        ],
+        repetitions: 1,
+        channels: STEREO,
+    },
+    TestCase {
+        name: "Tech 3341 case 5",
+        quantity: Quantity::Integrated,
+        expected: -23.0,
+        tolerance_lu: 0.1,
+        tones: &[(20_000, -26.0), (20_100, -20.0), (20_000, -26.0)],
+        repetitions: 1,
+        channels: STEREO,
+    },
+    TestCase {
+        name: "Tech 3341 case 6",
+        quantity: Quantity::Integrated,
+        expected: -23.0,
+        tolerance_lu: 0.1,
+        tones: &[(20_000, 0.0)],
+        repetitions: 1,
+        channels: &[
+            (Channel::Left, -28.0),
+            (Channel::Right, -28.0),
+            (Channel::Center, -24.0),
+            (Channel::LeftSurround, -30.0),
+            (Channel::RightSurround, -30.0),
+        ],
+    },
+    TestCase {
+        name: "Tech 3341 case 9",
+        quantity: Quantity::ShortTerm,
+        expected: -23.0,
+        tolerance_lu: 0.1,
+        tones: &[(1_340, -20.0), (1_660, -30.0)],
+        repetitions: 5,
+        channels: STEREO,
+    },
+    TestCase {
+        name: "Tech 3341 case 12",
+        quantity: Quantity::Momentary,
+        expected: -23.0,
+        tolerance_lu: 0.1,
+        tones: &[(180, -20.0), (220, -30.0)],
+        repetitions: 25,
+        channels: STEREO,
+    },
+];
+
+/// The synthetic loudness range cases of EBU Tech 3342-2016, table 1.
+pub const TECH_3342_CASES: &[TestCase] = &[
+    TestCase {
+        name: "Tech 3342 case 1",
+        quantity: Quantity::LoudnessRange,
+        expected: 10.0,
+        tolerance_lu: 1.0,
+        tones: &[(20_000, -20.0), (20_000, -30.0)],
+        repetitions: 1,
+        channels: STEREO,
+    },
+    TestCase {
+        name: "Tech 3342 case 2",
+        quantity: Quantity::LoudnessRange,
+        expected: 5.0,
+        tolerance_lu: 1.0,
+        tones: &[(20_000, -20.0), (20_000, -15.0)],
+        repetitions: 1,
+        channels: STEREO,
+    },
+    TestCase {
+        name: "Tech 3342 case 3",
+        quantity: Quantity::LoudnessRange,
+        expected: 20.0,
+        tolerance_lu: 1.0,
+        tones: &[(20_000, -40.0), (20_000, -20.0)],
+        repetitions: 1,
+        channels: STEREO,
+    },
+    TestCase {
+        name: "Tech 3342 case 4",
+        quantity: Quantity::LoudnessRange,
+        expected: 15.0,
+        tolerance_lu: 1.0,
+        tones: &[
+            (20_000, -50.0),
+            (20_000, -35.0),
+            (20_000, -20.0),
+            (20_000, -35.0),
+            (20_000, -50.0),
+        ],
+        repetitions: 1,
+        channels: STEREO,
+    },
+];
+
+impl TestCase {
+    /// Generate the test signal at the given sample rate.
+    pub fn signal(&self, sample_rate_hz: u32) -> Signal {
+        let frequency_hz = 1_000;
+        let mut tone = Vec::new();
+        let tones = (0..self.repetitions).flat_map(|_| self.tones.iter());
+        for &(duration_ms, amplitude_dbfs) in tones {
+            let amplitude = 10.0_f64.powf(amplitude_dbfs as f64 / 20.0);
+            let num_samples = duration_ms as u64 * sample_rate_hz as u64 / 1000;
+            let start = tone.len() as u64;
+            tone.extend((start..start + num_samples).map(|i| {
+                // Compute the phase with integers, so it does not lose
+                // precision during long signals.
+                let phase = (i * frequency_hz) % sample_rate_hz as u64;
+                let angle = 2.0 * f64::consts::PI * phase as f64 / sample_rate_hz as f64;
+                (angle.sin() * amplitude) as f32
+            }));
+        }
+
+        let samples = self.channels.iter().map(|&(_, gain_db)| {
+            let gain = 10.0_f32.powf(gain_db / 20.0);
+            tone.iter().map(|&x| x * gain).collect()
+        }).collect();
+
+        Signal {
+            sample_rate_hz,
+            channel_map: ChannelMap::new(self.channels.iter().map(|&(ch, _)| ch).collect()),
+            samples,
+        }
+    }
+
+    /// Generate the test signal, measure it with `meter`, and compare.
+    ///
+    /// For the momentary and short-term cases, the measured value is the
+    /// value in the series that deviates most from the expected value.
+    pub fn run<M: Meter + ?Sized>(&self, meter: &mut M, sample_rate_hz: u32) -> Outcome {
+        let signal = self.signal(sample_rate_hz);
+        let measured = match self.quantity {
+            Quantity::Integrated => meter.integrated_loudness_lkfs(&signal),
+            Quantity::Momentary => self.worst(meter.momentary_loudness_lkfs(&signal)),
+            Quantity::ShortTerm => self.worst(meter.short_term_loudness_lkfs(&signal)),
+            Quantity::LoudnessRange => meter.loudness_range_lu(&signal),
+        };
+        Outcome {
+            case: self.name,
+            sample_rate_hz,
+            quantity: self.quantity,
+            expected: self.expected,
+            tolerance_lu: self.tolerance_lu,
+            measured,
+        }
+    }
+
+    /// Return the value that deviates most from the expected value, or NaN if there are none.
+    fn worst(&self, values: Vec<f32>) -> f32 {
+        let deviation = |x: f32| {
+            // Treat NaN as the worst possible value, so it does not pass.
+            let d = (x - self.expected).abs();
+            if d.is_nan() { f32::INFINITY } else { d }
+        };
+        values
+            .into_iter()
+            .fold(None, |worst: Option<f32>, x| match worst {
+                Some(w) if deviation(w) >= deviation(x) => Some(w),
+                _ => Some(x),
+            })
+            .unwrap_or(f32::NAN)
+    }
+}
+
+/// The result of running one test case.
+#[derive(Copy, Clone, Debug)]
+pub struct Outcome {
+    /// Name of the case.
+    pub case: &'static str,
+
+    /// The sample rate that the test signal was generated at.
+    pub sample_rate_hz: u32,
+
+    /// The quantity that the case verifies.
+    pub quantity: Quantity,
+
+    /// The expected value, in LKFS, or in LU for the loudness range.
+    pub expected: f32,
+
+    /// The maximum allowed deviation from the expected value, in LU.
+    pub tolerance_lu: f32,
+
+    /// The value that the meter measured, in the same unit as `expected`.
+    pub measured: f32,
+}
+
+impl Outcome {
+    /// Return whether the measured value is within tolerance.
+    pub fn passed(&self) -> bool {
+        // Written such that NaN does not pass.
+        (self.measured - self.expected).abs() <= self.tolerance_lu
+    }
+}
+
+/// Run all cases in `TECH_3341_CASES` at the given sample rate.
+pub fn run_tech_3341<M: Meter + ?Sized>(meter: &mut M, sample_rate_hz: u32) -> Vec<Outcome> {
+    TECH_3341_CASES
+        .iter()
+        .map(|case| case.run(meter, sample_rate_hz))
+        .collect()
+}
+
+/// Run all cases in `TECH_3342_CASES` at the given sample rate.
+pub fn run_tech_3342<M: Meter + ?Sized>(meter: &mut M, sample_rate_hz: u32) -> Vec<Outcome> {
+    TECH_3342_CASES
+        .iter()
+        .map(|case| case.run(meter, sample_rate_hz))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Meter, ReferenceMeter, Signal, run_tech_3341, run_tech_3342};
+
+    #[test]
+    fn reference_meter_passes_tech_3341_at_common_sample_rates() {
+        for &sample_rate_hz in &[44_100, 48_000, 96_000] {
+            for outcome in run_tech_3341(&mut ReferenceMeter, sample_rate_hz) {
+                assert!(
+                    outcome.passed(),
+                    "{} at {} Hz: measured {:.2}, expected {:.1}.",
+                    outcome.case,
+                    sample_rate_hz,
+                    outcome.measured,
+                    outcome.expected,
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn reference_meter_passes_tech_3342() {
+        for outcome in run_tech_3342(&mut ReferenceMeter, 48_000) {
+            assert!(
+                outcome.passed(),
+                "{}: measured {:.2} LU, expected {:.1} LU.",
+                outcome.case,
+                outcome.measured,
+                outcome.expected,
+            );
+        }
+    }
+
+    #[test]
+    fn broken_meter_fails() {
+        // A pipeline that drops a channel should be caught.
+        struct DropsChannel;
+        impl DropsChannel {
+            fn drop_channel(signal: &Signal) -> Signal {
+                let mut signal = signal.clone();
+                for x in signal.samples[0].iter_mut() {
+                    *x = 0.0;
+                }
+                signal
+            }
+        }
+        impl Meter for DropsChannel {
+            fn integrated_loudness_lkfs(&mut self, signal: &Signal) -> f32 {
+                ReferenceMeter.integrated_loudness_lkfs(&DropsChannel::drop_channel(signal))
+            }
+            fn momentary_loudness_lkfs(&mut self, signal: &Signal) -> Vec<f32> {
+                ReferenceMeter.momentary_loudness_lkfs(&DropsChannel::drop_channel(signal))
+            }
+            fn short_term_loudness_lkfs(&mut self, signal: &Signal) -> Vec<f32> {
+                ReferenceMeter.short_term_loudness_lkfs(&DropsChannel::drop_channel(signal))
+            }
+            fn loudness_range_lu(&mut self, signal: &Signal) -> f32 {
+                ReferenceMeter.loudness_range_lu(&DropsChannel::drop_channel(signal))
+            }
+        }
+        let outcomes = run_tech_3341(&mut DropsChannel, 48_000);
+        assert!(outcomes.iter().all(|outcome| !outcome.passed()));
+        assert!((outcomes[0].measured - -26.0).abs() < 0.1);
+    }
+}
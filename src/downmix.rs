@@ -0,0 +1,245 @@
+// BS1770 -- Loudness analysis library conforming to ITU-R BS.1770
+// Copyright 2020 Ruud van Asseldonk
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! Downmixing multichannel audio to stereo or mono, per ITU-R BS.775.
+//!
+//! A 5.1 mix is often also heard as a stereo fold-down, and the loudness of
+//! the fold-down can differ from the loudness of the 5.1 mix, because BS.1770
+//! weighs the surround channels by +1.5 dB, while the downmix attenuates them
+//! by 3 dB. A `Downmix` computes the fold-down from the same sample buffers
+//! that feed the native measurement, so both can be measured in one pass:
+//!
+//! ```
+//! use bs1770::channels::{ChannelMap, ChannelOrder};
+//! use bs1770::downmix::Downmix;
+//! use bs1770::multichannel::MultiChannelLoudnessMeter;
+//!
+//! let channel_map = ChannelMap::from_order(ChannelOrder::Wave, 6).unwrap();
+//! let downmix = Downmix::stereo(&channel_map);
+//! let stereo_map = ChannelMap::from_order(ChannelOrder::Wave, 2).unwrap();
+//! let mut native = MultiChannelLoudnessMeter::new(48_000, channel_map);
+//! let mut folded = MultiChannelLoudnessMeter::new(48_000, stereo_map);
+//!
+//! // One second of interleaved 5.1 samples, with signal in the left surround.
+//! let samples: Vec<f32> = (0..6 * 48_000)
+//!     .map(|i| if i % 6 == 4 { 0.1 * ((i / 6) as f32 * 0.1).sin() } else { 0.0 })
+//!     .collect();
+//! let mut stereo = Vec::new();
+//! downmix.push_interleaved(&samples, &mut stereo);
+//! native.push_interleaved(&samples);
+//! folded.push_interleaved(&stereo);
+//!
+//! // The surround channel loses 4.5 dB in the fold-down.
+//! let difference = native.integrated_lkfs() - folded.integrated_lkfs();
+//! assert!((difference - 4.5).abs() < 0.1);
+//! ```
+//!
+//! The downmix does not prevent clipping: when several channels are loud at
+//! the same time, the output can exceed full scale. This does not affect the
+//! loudness measurement, but it does matter when the output is played back.
+
+use alloc::vec::Vec;
+
+use crate::channels::{Channel, ChannelMap};
+
+/// The gain of -3 dB that BS.775 applies to center and surround channels.
+const K: f32 = core::f32::consts::FRAC_1_SQRT_2;
+
+/// A matrix that maps input channels onto fewer output channels.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Downmix {
+    /// The number of input channels.
+    num_inputs: usize,
+
+    /// The gain of every input channel in every output channel.
+    ///
+    /// The gains are stored per output channel, so the gain of input `j` in
+    /// output `i` is at index `i * num_inputs + j`.
+    gains: Vec<f32>,
+}
+
+impl Downmix {
+    /// Return the left and right gain of a channel in the stereo downmix.
+    ///
+    /// The LFE channel is dropped, as are elevated channels and channels of
+    /// unknown position.
+    fn stereo_gains(channel: Channel) -> (f32, f32) {
+        match channel {
+            Channel::Left | Channel::LeftOfCenter => (1.0, 0.0),
+            Channel::Right | Channel::RightOfCenter => (0.0, 1.0),
+            Channel::Center => (K, K),
+            Channel::Mono => (1.0, 1.0),
+            Channel::LeftSurround | Channel::LeftBack => (K, 0.0),
+            Channel::RightSurround | Channel::RightBack => (0.0, K),
+            Channel::BackCenter => (0.5, 0.5),
+            Channel::Lfe | Channel::Top | Channel::Unknown => (0.0, 0.0),
+        }
+    }
+
+    /// Construct the BS.775 downmix to stereo (L, R) for the given channels.
+    ///
+    /// Front channels go to their own side, the center and surround channels
+    /// are attenuated by 3 dB, so for 5.1 the outputs are `L + 0.707 C + 0.707
+    /// Ls` and `R + 0.707 C + 0.707 Rs`. A mono surround channel goes to both
+    /// sides with a gain of 0.5, and a mono channel goes to both sides
+    /// unattenuated. The channel selection of the map is ignored.
+    pub fn stereo(channel_map: &ChannelMap) -> Downmix {
+        let (left, right): (Vec<f32>, Vec<f32>) = channel_map
+            .channels()
+            .iter()
+            .map(|&ch| Downmix::stereo_gains(ch))
+            .unzip();
+        let mut gains = left;
+        gains.extend_from_slice(&right);
+        Downmix {
+            num_inputs: channel_map.len(),
+            gains,
+        }
+    }
+
+    /// Construct the BS.775 downmix to mono for the given channels.
+    ///
+    /// This is the stereo downmix, with both sides attenuated by 3 dB and
+    /// summed. For 5.1, the output is `0.707 L + 0.707 R + C + 0.5 Ls + 0.5 Rs`.
+    pub fn mono(channel_map: &ChannelMap) -> Downmix {
+        let stereo = Downmix::stereo(channel_map);
+        let (left, right) = stereo.gains.split_at(stereo.num_inputs);
+        Downmix {
+            num_inputs: stereo.num_inputs,
+            gains: left.iter().zip(right).map(|(l, r)| K * (l + r)).collect(),
+        }
+    }
+
+    /// Return the number of input channels.
+    pub fn num_inputs(&self) -> usize {
+        self.num_inputs
+    }
+
+    /// Return the number of output channels, 2 for stereo and 1 for mono.
+    pub fn num_outputs(&self) -> usize {
+        self.gains.len() / self.num_inputs.max(1)
+    }
+
+    /// Return the gain of input channel `input` in output channel `output`.
+    pub fn gain(&self, output: usize, input: usize) -> f32 {
+        assert!(input < self.num_inputs, "Input channel out of range.");
+        self.gains[output * self.num_inputs + input]
+    }
+
+    /// Downmix interleaved samples, and append the interleaved output to `output`.
+    ///
+    /// The number of samples must be a multiple of the number of input channels.
+    pub fn push_interleaved(&self, samples: &[f32], output: &mut Vec<f32>) {
+        let n = self.num_inputs;
+        assert_eq!(samples.len() % n.max(1), 0, "Need a sample for every input channel.");
+        if n == 0 {
+            return
+        }
+        output.reserve(samples.len() / n * self.num_outputs());
+        for frame in samples.chunks_exact(n) {
+            for gains in self.gains.chunks_exact(n) {
+                output.push(gains.iter().zip(frame).map(|(g, x)| g * x).sum());
+            }
+        }
+    }
+
+    /// Downmix planar samples, and append every output channel to its own buffer.
+    ///
+    /// All input slices must have the same length, and there must be one
+    /// output buffer per output channel.
+    pub fn push_planar(&self, channels: &[&[f32]], outputs: &mut [Vec<f32>]) {
+        assert_eq!(channels.len(), self.num_inputs, "Need samples for every input channel.");
+        assert_eq!(outputs.len(), self.num_outputs(), "Need a buffer for every output channel.");
+        let len = channels.first().map_or(0, |ch| ch.len());
+        assert!(channels.iter().all(|ch| ch.len() == len), "Channels must have the same length.");
+        if self.num_inputs == 0 {
+            return
+        }
+
+        for (output, gains) in outputs.iter_mut().zip(self.gains.chunks_exact(self.num_inputs)) {
+            let start = output.len();
+            output.resize(start + len, 0.0);
+            for (&gain, samples) in gains.iter().zip(channels) {
+                if gain == 0.0 {
+                    continue
+                }
+                for (y, x) in output[start..].iter_mut().zip(samples.iter()) {
+                    *y += gain * x;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Downmix;
+    use crate::channels::{ChannelMap, ChannelOrder};
+    use crate::ChannelLoudnessMeter;
+
+    #[test]
+    fn downmix_matches_bs775_coefficients() {
+        // Vorbis order is L, C, R, Ls, Rs, LFE.
+        let map = ChannelMap::from_order(ChannelOrder::Vorbis, 6).unwrap();
+        let stereo = Downmix::stereo(&map);
+        assert_eq!(stereo.num_outputs(), 2);
+        assert_eq!(stereo.gain(0, 0), 1.0);
+        assert_eq!(stereo.gain(0, 2), 0.0);
+        assert_eq!(stereo.gain(1, 4), super::K);
+        assert_eq!(stereo.gain(1, 5), 0.0);
+
+        let mono = Downmix::mono(&map);
+        assert_eq!(mono.num_outputs(), 1);
+        let expected = [0.707, 1.0, 0.707, 0.5, 0.5, 0.0];
+        for (input, &gain) in expected.iter().enumerate() {
+            assert!((mono.gain(0, input) - gain).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn planar_and_interleaved_downmix_agree() {
+        let map = ChannelMap::from_order(ChannelOrder::Wave, 6).unwrap();
+        let downmix = Downmix::stereo(&map);
+        let channels: Vec<Vec<f32>> = (0..6)
+            .map(|ch| (0..1000).map(|i| 0.1 * (i as f32 * 0.01 * (ch + 1) as f32).sin()).collect())
+            .collect();
+        let planar: Vec<&[f32]> = channels.iter().map(|ch| &ch[..]).collect();
+        let interleaved: Vec<f32> = (0..1000).flat_map(|i| channels.iter().map(move |ch| ch[i])).collect();
+
+        let mut outputs = vec![Vec::new(), Vec::new()];
+        let mut output = Vec::new();
+        downmix.push_planar(&planar, &mut outputs);
+        downmix.push_interleaved(&interleaved, &mut output);
+        assert_eq!(output.len(), 2000);
+        for (i, frame) in output.chunks(2).enumerate() {
+            assert!((frame[0] - outputs[0][i]).abs() < 1e-6);
+            assert!((frame[1] - outputs[1][i]).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn center_keeps_its_loudness_in_stereo_downmix() {
+        let map = ChannelMap::from_order(ChannelOrder::Wave, 3).unwrap();
+        let downmix = Downmix::stereo(&map);
+        let center: Vec<f32> = (0..48_000).map(|i| 0.1 * (i as f32 * 0.1).sin()).collect();
+        let silence = vec![0.0; center.len()];
+        let mut outputs = vec![Vec::new(), Vec::new()];
+        downmix.push_planar(&[&silence, &silence, &center], &mut outputs);
+
+        let mut meter_center = ChannelLoudnessMeter::new(48_000);
+        let mut meter_left = ChannelLoudnessMeter::new(48_000);
+        let mut meter_right = ChannelLoudnessMeter::new(48_000);
+        meter_center.push(center.iter().cloned());
+        meter_left.push(outputs[0].iter().cloned());
+        meter_right.push(outputs[1].iter().cloned());
+
+        let native = crate::gated_mean(meter_center.as_100ms_windows()).unwrap();
+        let folded = crate::reduce_stereo(meter_left.as_100ms_windows(), meter_right.as_100ms_windows());
+        let folded = crate::gated_mean(folded.as_ref()).unwrap();
+        assert!((native.loudness_lkfs() - folded.loudness_lkfs()).abs() < 0.01);
+    }
+}
@@ -0,0 +1,693 @@
+// BS1770 -- Loudness analysis library conforming to ITU-R BS.1770
+// Copyright 2020 Ruud van Asseldonk
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! Reading and decimating 1-bit DSD audio, such as from SACD rips.
+//!
+//! BS.1770 is defined for PCM audio. To measure DSD audio, it first needs to
+//! be converted to PCM. This module reads the DSD bitstream from DSF and
+//! DSDIFF (`.dff`) files with `DsdReader`, and converts it to PCM with a
+//! `DsdDecimator` per channel. The PCM output can then be fed into a
+//! `ChannelLoudnessMeter`:
+//!
+//! ```no_run
+//! # fn main() -> std::io::Result<()> {
+//! use bs1770::dsd::{DsdDecimator, DsdReader};
+//!
+//! let file = std::fs::File::open("album.dsf")?;
+//! let mut reader = DsdReader::new(std::io::BufReader::new(file))?;
+//! let mut decimators: Vec<_> = (0..reader.channels())
+//!     .map(|_| DsdDecimator::new(reader.dsd_rate_hz()))
+//!     .collect();
+//! let mut meters = vec![
+//!     bs1770::ChannelLoudnessMeter::new(decimators[0].pcm_rate_hz());
+//!     reader.channels() as usize
+//! ];
+//!
+//! let mut dsd = vec![Vec::new(); reader.channels() as usize];
+//! let mut pcm = Vec::new();
+//! while reader.read_next(&mut dsd)? {
+//!     for ((bytes, decimator), meter) in dsd.iter().zip(&mut decimators).zip(&mut meters) {
+//!         pcm.clear();
+//!         decimator.push(bytes, &mut pcm);
+//!         meter.push(pcm.iter().cloned());
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! This module is only available with the `dsd` feature enabled.
+
+use std::f64;
+use std::io;
+use std::io::Read;
+
+/// The sample rate of the PCM signal that `DsdDecimator` produces.
+///
+/// This is high enough that the audible band is unaffected by the low-pass
+/// filter that removes the ultrasonic noise of the DSD signal.
+pub const PCM_RATE_HZ: u32 = 88_200;
+
+/// The number of bytes per channel that `DsdReader::read_next` reads at most.
+const READ_SIZE: usize = 4096;
+
+/// Converts a 1-bit DSD signal into PCM at 88.2 kHz.
+///
+/// DSD has a large amount of (shaped) quantization noise above the audible
+/// band. Because K-weighting emphasizes high frequencies, that noise would
+/// dominate the loudness measurement. The decimator applies a linear-phase
+/// low-pass filter with a pass band up to 20 kHz, which removes the noise,
+/// and then reduces the sample rate.
+///
+/// Full modulation (all bits set or all bits clear) maps to full scale in the
+/// PCM output. Note that the SACD reference level (0 dB SACD) is defined at
+/// 50% modulation, which corresponds to -6 dBFS in the output.
+#[derive(Clone)]
+pub struct DsdDecimator {
+    /// For every byte position in the history, the filter output contribution
+    /// of every possible byte value at that position.
+    tables: Vec<[f32; 256]>,
+
+    /// Ring buffer of the most recent input bytes, the newest at `pos`.
+    history: Vec<u8>,
+
+    /// Index into `history` of the most recently written byte.
+    pos: usize,
+
+    /// The number of input bytes per output sample.
+    bytes_per_sample: usize,
+
+    /// The number of input bytes since the last output sample.
+    count: usize,
+
+    /// The output sample rate.
+    pcm_rate_hz: u32,
+}
+
+impl DsdDecimator {
+    /// Construct a decimator for a DSD signal of the given bit rate.
+    ///
+    /// The rate must be a multiple of 8 × 88.2 kHz. In practice, it is one of
+    /// 2.8224 MHz (DSD64), 5.6448 MHz (DSD128), or 11.2896 MHz (DSD256).
+    pub fn new(dsd_rate_hz: u32) -> DsdDecimator {
+        let bytes_per_sample = dsd_rate_hz / (8 * PCM_RATE_HZ);
+        assert_eq!(
+            bytes_per_sample * 8 * PCM_RATE_HZ, dsd_rate_hz,
+            "DSD rate must be a multiple of 8 × 88.2 kHz.",
+        );
+        let bytes_per_sample = bytes_per_sample as usize;
+
+        // A Blackman-windowed sinc low-pass filter with its cutoff at 25 kHz.
+        // The window has a transition width of about 5.5 × rate / length, we
+        // pick the length such that the transition band is 20 kHz to 30 kHz.
+        // The length is a whole number of bytes.
+        let rate = dsd_rate_hz as f64;
+        let cutoff_hz = 25_000.0;
+        let num_bytes = ((5.5 * rate / 10_000.0) / 8.0).ceil() as usize;
+        let n = num_bytes * 8;
+        let mut taps = Vec::with_capacity(n);
+        for i in 0..n {
+            let t = i as f64 - (n - 1) as f64 * 0.5;
+            let x = 2.0 * cutoff_hz / rate * t;
+            let sinc = if x == 0.0 { 1.0 } else { (f64::consts::PI * x).sin() / (f64::consts::PI * x) };
+            let phase = 2.0 * f64::consts::PI * i as f64 / (n - 1) as f64;
+            let window = 0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos();
+            taps.push(sinc * window);
+        }
+        // Normalize for unit gain at DC.
+        let sum: f64 = taps.iter().sum();
+        for tap in taps.iter_mut() {
+            *tap /= sum;
+        }
+
+        // Byte k in the history (k = 0 is the newest byte) holds input samples
+        // n - 8k - 7 up to n - 8k, in MSB-first order, so bit b of that byte
+        // gets multiplied by tap 8k + b.
+        let mut tables = vec![[0.0_f32; 256]; num_bytes];
+        for (k, table) in tables.iter_mut().enumerate() {
+            for (byte, entry) in table.iter_mut().enumerate() {
+                let mut y = 0.0;
+                for b in 0..8 {
+                    let x = if (byte >> b) & 1 == 1 { 1.0 } else { -1.0 };
+                    y += taps[8 * k + b] * x;
+                }
+                *entry = y as f32;
+            }
+        }
+
+        DsdDecimator {
+            tables,
+            // A history of alternating bits is silence.
+            history: vec![0x55; num_bytes],
+            pos: 0,
+            bytes_per_sample,
+            count: 0,
+            pcm_rate_hz: PCM_RATE_HZ,
+        }
+    }
+
+    /// Return the sample rate of the PCM output.
+    pub fn pcm_rate_hz(&self) -> u32 {
+        self.pcm_rate_hz
+    }
+
+    /// Feed DSD bytes for a single channel, append PCM output samples to `out`.
+    ///
+    /// The bytes must be in MSB-first order: the most significant bit of a
+    /// byte is the earliest sample. This is the order that `DsdReader` produces.
+    pub fn push(&mut self, bytes: &[u8], out: &mut Vec<f32>) {
+        let len = self.history.len();
+        for &byte in bytes {
+            self.pos = if self.pos == 0 { len - 1 } else { self.pos - 1 };
+            self.history[self.pos] = byte;
+            self.count += 1;
+
+            if self.count == self.bytes_per_sample {
+                self.count = 0;
+                let mut y = 0.0;
+                // The ring buffer stores the newest byte at `pos`, and older
+                // bytes at increasing indices, wrapping around at the end.
+                let (oldest, newest) = self.history.split_at(self.pos);
+                let history = newest.iter().chain(oldest.iter());
+                for (table, &b) in self.tables.iter().zip(history) {
+                    y += table[b as usize];
+                }
+                out.push(y);
+            }
+        }
+    }
+}
+
+/// How the DSD data is laid out in the file.
+enum Layout {
+    /// DSF: per channel blocks of `block_size` bytes, LSB-first.
+    Dsf {
+        block_size: usize,
+        /// The number of bytes per channel that contain audio.
+        bytes_left: u64,
+        /// The number of bytes that remain in the data chunk, including padding.
+        chunk_bytes_left: u64,
+    },
+    /// DSDIFF: channels interleaved per byte, MSB-first.
+    Dff {
+        /// The number of bytes that remain in the DSD chunk.
+        chunk_bytes_left: u64,
+    },
+}
+
+/// Reads the DSD bitstream from a DSF or DSDIFF file.
+pub struct DsdReader<R: Read> {
+    inner: R,
+    dsd_rate_hz: u32,
+    channels: u32,
+    layout: Layout,
+    buffer: Vec<u8>,
+}
+
+fn invalid_data(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Return an error if `DsdDecimator` does not support the rate, rather than let it panic.
+fn check_dsd_rate(dsd_rate_hz: u32) -> io::Result<u32> {
+    let bytes_per_sample = dsd_rate_hz / (8 * PCM_RATE_HZ);
+    if bytes_per_sample == 0 || bytes_per_sample * 8 * PCM_RATE_HZ != dsd_rate_hz {
+        return Err(invalid_data("Unsupported DSD rate, expected a multiple of 8 × 88.2 kHz."));
+    }
+    Ok(dsd_rate_hz)
+}
+
+fn read_u32_le<R: Read>(input: &mut R) -> io::Result<u32> {
+    let mut buf = [0_u8; 4];
+    input.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64_le<R: Read>(input: &mut R) -> io::Result<u64> {
+    let mut buf = [0_u8; 8];
+    input.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_u64_be<R: Read>(input: &mut R) -> io::Result<u64> {
+    let mut buf = [0_u8; 8];
+    input.read_exact(&mut buf)?;
+    Ok(u64::from_be_bytes(buf))
+}
+
+/// Read and discard `len` bytes.
+fn skip<R: Read>(input: &mut R, len: u64) -> io::Result<()> {
+    let num_skipped = io::copy(&mut input.take(len), &mut io::sink())?;
+    if num_skipped < len {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Unexpected end of file."));
+    }
+    Ok(())
+}
+
+impl<R: Read> DsdReader<R> {
+    /// Read the header of a DSF or DSDIFF file, up to the start of the audio data.
+    ///
+    /// The format is detected from the first bytes of the file. Compressed
+    /// (DST) DSDIFF files are not supported, and neither are rates that
+    /// `DsdDecimator` does not support, such as the 48 kHz family.
+    pub fn new(mut inner: R) -> io::Result<DsdReader<R>> {
+        let mut magic = [0_u8; 4];
+        inner.read_exact(&mut magic)?;
+        match &magic {
+            b"DSD " => DsdReader::new_dsf(inner),
+            b"FRM8" => DsdReader::new_dff(inner),
+            _ => Err(invalid_data("Not a DSF or DSDIFF file.")),
+        }
+    }
+
+    fn new_dsf(mut inner: R) -> io::Result<DsdReader<R>> {
+        // The DSD chunk: its size, the total file size, and the metadata
+        // offset. We already read the chunk id.
+        let dsd_chunk_size = read_u64_le(&mut inner)?;
+        if dsd_chunk_size != 28 {
+            return Err(invalid_data("Invalid DSF header chunk."));
+        }
+        skip(&mut inner, 16)?;
+
+        let mut id = [0_u8; 4];
+        inner.read_exact(&mut id)?;
+        if &id != b"fmt " {
+            return Err(invalid_data("Expected DSF fmt chunk."));
+        }
+        let fmt_chunk_size = read_u64_le(&mut inner)?;
+        let _format_version = read_u32_le(&mut inner)?;
+        let format_id = read_u32_le(&mut inner)?;
+        let _channel_type = read_u32_le(&mut inner)?;
+        let channels = read_u32_le(&mut inner)?;
+        let dsd_rate_hz = read_u32_le(&mut inner)?;
+        let bits_per_sample = read_u32_le(&mut inner)?;
+        let sample_count = read_u64_le(&mut inner)?;
+        let block_size = read_u32_le(&mut inner)? as usize;
+        let _reserved = read_u32_le(&mut inner)?;
+
+        if format_id != 0 {
+            return Err(invalid_data("Unsupported DSF format, expected raw DSD."));
+        }
+        if bits_per_sample != 1 {
+            return Err(invalid_data("Unsupported DSF bit order, expected LSB-first."));
+        }
+        if channels == 0 || block_size == 0 {
+            return Err(invalid_data("Invalid DSF fmt chunk."));
+        }
+        check_dsd_rate(dsd_rate_hz)?;
+        if fmt_chunk_size < 52 {
+            return Err(invalid_data("Invalid DSF fmt chunk."));
+        }
+        skip(&mut inner, fmt_chunk_size - 52)?;
+
+        inner.read_exact(&mut id)?;
+        if &id != b"data" {
+            return Err(invalid_data("Expected DSF data chunk."));
+        }
+        let data_chunk_size = read_u64_le(&mut inner)?;
+        if data_chunk_size < 12 {
+            return Err(invalid_data("Invalid DSF data chunk."));
+        }
+
+        Ok(DsdReader {
+            inner,
+            dsd_rate_hz,
+            channels,
+            layout: Layout::Dsf {
+                block_size,
+                bytes_left: sample_count / 8 + (sample_count % 8 != 0) as u64,
+                chunk_bytes_left: data_chunk_size - 12,
+            },
+            buffer: Vec::new(),
+        })
+    }
+
+    fn new_dff(mut inner: R) -> io::Result<DsdReader<R>> {
+        let _form_size = read_u64_be(&mut inner)?;
+        let mut id = [0_u8; 4];
+        inner.read_exact(&mut id)?;
+        if &id != b"DSD " {
+            return Err(invalid_data("Expected DSDIFF form type DSD."));
+        }
+
+        let mut dsd_rate_hz = None;
+        let mut channels = None;
+
+        // Walk the chunks until we find the one that holds the audio.
+        loop {
+            inner.read_exact(&mut id)?;
+            let size = read_u64_be(&mut inner)?;
+            // Chunks are padded to an even number of bytes.
+            let padded_size = size.checked_add(size & 1)
+                .ok_or_else(|| invalid_data("Invalid DSDIFF chunk size."))?;
+
+            match &id {
+                b"PROP" => {
+                    let mut prop_left = size.checked_sub(4)
+                        .ok_or_else(|| invalid_data("Invalid DSDIFF PROP chunk."))?;
+                    let mut prop_type = [0_u8; 4];
+                    inner.read_exact(&mut prop_type)?;
+                    if &prop_type != b"SND " {
+                        skip(&mut inner, padded_size - 4)?;
+                        continue
+                    }
+                    while prop_left > 0 {
+                        inner.read_exact(&mut id)?;
+                        let sub_size = read_u64_be(&mut inner)?;
+                        let padded_sub_size = sub_size.checked_add(sub_size & 1)
+                            .ok_or_else(|| invalid_data("Invalid DSDIFF chunk size."))?;
+                        prop_left = padded_sub_size.checked_add(12)
+                            .and_then(|n| prop_left.checked_sub(n))
+                            .ok_or_else(|| invalid_data("Invalid DSDIFF PROP chunk."))?;
+                        match &id {
+                            b"FS  " => {
+                                let rest = padded_sub_size.checked_sub(4)
+                                    .ok_or_else(|| invalid_data("Invalid DSDIFF FS chunk."))?;
+                                let mut buf = [0_u8; 4];
+                                inner.read_exact(&mut buf)?;
+                                dsd_rate_hz = Some(check_dsd_rate(u32::from_be_bytes(buf))?);
+                                skip(&mut inner, rest)?;
+                            }
+                            b"CHNL" => {
+                                let rest = padded_sub_size.checked_sub(2)
+                                    .ok_or_else(|| invalid_data("Invalid DSDIFF CHNL chunk."))?;
+                                let mut buf = [0_u8; 2];
+                                inner.read_exact(&mut buf)?;
+                                channels = Some(u16::from_be_bytes(buf) as u32);
+                                skip(&mut inner, rest)?;
+                            }
+                            b"CMPR" => {
+                                let rest = padded_sub_size.checked_sub(4)
+                                    .ok_or_else(|| invalid_data("Invalid DSDIFF CMPR chunk."))?;
+                                let mut compression = [0_u8; 4];
+                                inner.read_exact(&mut compression)?;
+                                if &compression != b"DSD " {
+                                    return Err(invalid_data("Compressed (DST) DSDIFF is not supported."));
+                                }
+                                skip(&mut inner, rest)?;
+                            }
+                            _ => skip(&mut inner, padded_sub_size)?,
+                        }
+                    }
+                    // Skip the pad byte of the PROP chunk itself, if any.
+                    skip(&mut inner, padded_size - size)?;
+                }
+                b"DSD " => {
+                    let channels = channels
+                        .ok_or_else(|| invalid_data("DSDIFF file lacks CHNL chunk."))?;
+                    let dsd_rate_hz = dsd_rate_hz
+                        .ok_or_else(|| invalid_data("DSDIFF file lacks FS chunk."))?;
+                    if channels == 0 {
+                        return Err(invalid_data("Invalid DSDIFF CHNL chunk."));
+                    }
+                    return Ok(DsdReader {
+                        inner,
+                        dsd_rate_hz,
+                        channels,
+                        layout: Layout::Dff {
+                            chunk_bytes_left: size,
+                        },
+                        buffer: Vec::new(),
+                    });
+                }
+                b"DST " => return Err(invalid_data("Compressed (DST) DSDIFF is not supported.")),
+                _ => skip(&mut inner, padded_size)?,
+            }
+        }
+    }
+
+    /// Return the bit rate of the DSD signal per channel, e.g. 2.8224 MHz for DSD64.
+    pub fn dsd_rate_hz(&self) -> u32 {
+        self.dsd_rate_hz
+    }
+
+    /// Return the number of channels.
+    pub fn channels(&self) -> u32 {
+        self.channels
+    }
+
+    /// Read the next batch of DSD bytes into one buffer per channel.
+    ///
+    /// The buffers are cleared first. The bytes are in MSB-first order,
+    /// regardless of the bit order in the file. Returns false when the end
+    /// of the audio data has been reached.
+    pub fn read_next(&mut self, buffers: &mut [Vec<u8>]) -> io::Result<bool> {
+        assert_eq!(buffers.len(), self.channels as usize, "Need one buffer per channel.");
+        for buffer in buffers.iter_mut() {
+            buffer.clear();
+        }
+
+        let channels = self.channels as usize;
+        match self.layout {
+            Layout::Dsf { block_size, ref mut bytes_left, ref mut chunk_bytes_left } => {
+                let group_size = (block_size * channels) as u64;
+                if *bytes_left == 0 || *chunk_bytes_left < group_size {
+                    return Ok(false)
+                }
+                self.buffer.resize(block_size * channels, 0);
+                self.inner.read_exact(&mut self.buffer)?;
+                *chunk_bytes_left -= group_size;
+
+                // The final block is padded, only take the bytes that hold audio.
+                let num_audio = (*bytes_left).min(block_size as u64) as usize;
+                *bytes_left -= num_audio as u64;
+
+                for (block, buffer) in self.buffer.chunks(block_size).zip(buffers.iter_mut()) {
+                    buffer.extend(block[..num_audio].iter().map(|b| b.reverse_bits()));
+                }
+                Ok(true)
+            }
+            Layout::Dff { ref mut chunk_bytes_left } => {
+                let num_frames = (*chunk_bytes_left / channels as u64).min(READ_SIZE as u64) as usize;
+                if num_frames == 0 {
+                    return Ok(false)
+                }
+                self.buffer.resize(num_frames * channels, 0);
+                self.inner.read_exact(&mut self.buffer)?;
+                *chunk_bytes_left -= (num_frames * channels) as u64;
+
+                for frame in self.buffer.chunks(channels) {
+                    for (&b, buffer) in frame.iter().zip(buffers.iter_mut()) {
+                        buffer.push(b);
+                    }
+                }
+                Ok(true)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::{DsdDecimator, DsdReader};
+    use crate::{ChannelLoudnessMeter, reduce_stereo, gated_mean};
+
+    /// Modulate a 1 kHz sine wave with a first-order sigma-delta modulator.
+    ///
+    /// Returns bytes in MSB-first order.
+    fn modulate_sine(dsd_rate_hz: u32, amplitude: f64, duration_seconds: f64) -> Vec<u8> {
+        let num_bits = (dsd_rate_hz as f64 * duration_seconds) as usize;
+        let mut bytes = Vec::with_capacity(num_bits / 8);
+        let mut integrator = 0.0;
+        let mut byte = 0_u8;
+        for i in 0..num_bits {
+            let t = i as f64 / dsd_rate_hz as f64;
+            let x = amplitude * (2.0 * std::f64::consts::PI * 1_000.0 * t).sin();
+            let y = if integrator >= 0.0 { 1.0 } else { -1.0 };
+            integrator += x - y;
+            byte = (byte << 1) | if y > 0.0 { 1 } else { 0 };
+            if i % 8 == 7 {
+                bytes.push(byte);
+                byte = 0;
+            }
+        }
+        bytes
+    }
+
+    fn measure_lkfs(dsd_rate_hz: u32, bytes: &[u8]) -> f32 {
+        let mut decimator = DsdDecimator::new(dsd_rate_hz);
+        let mut pcm = Vec::new();
+        decimator.push(bytes, &mut pcm);
+        let mut meter = ChannelLoudnessMeter::new(decimator.pcm_rate_hz());
+        meter.push(pcm.iter().cloned());
+        let windows = meter.as_100ms_windows();
+        let stereo = reduce_stereo(windows, windows);
+        gated_mean(stereo.as_ref()).unwrap().loudness_lkfs()
+    }
+
+    #[test]
+    fn decimator_maps_full_modulation_to_full_scale() {
+        let mut decimator = DsdDecimator::new(2_822_400);
+        let mut pcm = Vec::new();
+        decimator.push(&[0xff; 4096], &mut pcm);
+        assert_eq!(pcm.len(), 4096 / 4);
+        assert!((pcm[pcm.len() - 1] - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn decimator_maps_idle_pattern_to_silence() {
+        let mut decimator = DsdDecimator::new(2_822_400);
+        let mut pcm = Vec::new();
+        decimator.push(&[0x69; 4096], &mut pcm);
+        // Skip the start, where the history still contains the initial state.
+        let max = pcm[pcm.len() / 2..].iter().fold(0.0_f32, |m, x| m.max(x.abs()));
+        assert!(max < 1e-3, "Expected silence, got amplitude {}.", max);
+    }
+
+    #[test]
+    fn loudness_of_dsd64_and_dsd128_sine_matches_pcm() {
+        for &dsd_rate_hz in &[2_822_400, 5_644_800] {
+            // A sine at half modulation is a -6 dBFS sine.
+            let bytes = modulate_sine(dsd_rate_hz, 0.5, 2.0);
+            let lkfs = measure_lkfs(dsd_rate_hz, &bytes);
+            assert!(
+                (lkfs - -6.02).abs() < 0.1,
+                "Expected -6.0 LKFS, got {:.2} LKFS at {} Hz.", lkfs, dsd_rate_hz,
+            );
+        }
+    }
+
+    /// Build a stereo DSF file with block size 8 and two block groups of 12 bytes of audio.
+    fn dsf_file(dsd_rate_hz: u32) -> Vec<u8> {
+        let block_size = 8_u32;
+        let sample_count = 12 * 8_u64;
+        let mut file = Vec::new();
+        file.extend_from_slice(b"DSD ");
+        file.extend_from_slice(&28_u64.to_le_bytes());
+        file.extend_from_slice(&0_u64.to_le_bytes());
+        file.extend_from_slice(&0_u64.to_le_bytes());
+        file.extend_from_slice(b"fmt ");
+        file.extend_from_slice(&52_u64.to_le_bytes());
+        file.extend_from_slice(&1_u32.to_le_bytes());
+        file.extend_from_slice(&0_u32.to_le_bytes());
+        file.extend_from_slice(&2_u32.to_le_bytes());
+        file.extend_from_slice(&2_u32.to_le_bytes());
+        file.extend_from_slice(&dsd_rate_hz.to_le_bytes());
+        file.extend_from_slice(&1_u32.to_le_bytes());
+        file.extend_from_slice(&sample_count.to_le_bytes());
+        file.extend_from_slice(&block_size.to_le_bytes());
+        file.extend_from_slice(&0_u32.to_le_bytes());
+        file.extend_from_slice(b"data");
+        file.extend_from_slice(&(12 + 32_u64).to_le_bytes());
+        // Two block groups, the second one is partially padding.
+        file.extend_from_slice(&[0x01; 8]);
+        file.extend_from_slice(&[0x02; 8]);
+        file.extend_from_slice(&[0x01, 0x01, 0x01, 0x01, 0, 0, 0, 0]);
+        file.extend_from_slice(&[0x02, 0x02, 0x02, 0x02, 0, 0, 0, 0]);
+        file
+    }
+
+    /// Build a stereo DSDIFF file with 3 bytes of audio per channel.
+    fn dff_file(dsd_rate_hz: u32) -> Vec<u8> {
+        let mut prop = Vec::new();
+        prop.extend_from_slice(b"SND ");
+        prop.extend_from_slice(b"FS  ");
+        prop.extend_from_slice(&4_u64.to_be_bytes());
+        prop.extend_from_slice(&dsd_rate_hz.to_be_bytes());
+        prop.extend_from_slice(b"CHNL");
+        prop.extend_from_slice(&10_u64.to_be_bytes());
+        prop.extend_from_slice(&2_u16.to_be_bytes());
+        prop.extend_from_slice(b"SLFTSRGT");
+        prop.extend_from_slice(b"CMPR");
+        prop.extend_from_slice(&9_u64.to_be_bytes());
+        prop.extend_from_slice(b"DSD \x04none\0");
+
+        let mut file = Vec::new();
+        file.extend_from_slice(b"FRM8");
+        file.extend_from_slice(&0_u64.to_be_bytes());
+        file.extend_from_slice(b"DSD ");
+        file.extend_from_slice(b"FVER");
+        file.extend_from_slice(&4_u64.to_be_bytes());
+        file.extend_from_slice(&[1, 5, 0, 0]);
+        file.extend_from_slice(b"PROP");
+        file.extend_from_slice(&(prop.len() as u64).to_be_bytes());
+        file.extend_from_slice(&prop);
+        file.extend_from_slice(b"DSD ");
+        file.extend_from_slice(&6_u64.to_be_bytes());
+        file.extend_from_slice(&[1, 2, 3, 4, 5, 6]);
+        file
+    }
+
+    #[test]
+    fn dsf_reader_reads_blocks_and_reverses_bit_order() {
+        let file = dsf_file(2_822_400);
+        let mut reader = DsdReader::new(&file[..]).unwrap();
+        assert_eq!(reader.channels(), 2);
+        assert_eq!(reader.dsd_rate_hz(), 2_822_400);
+
+        let mut buffers = vec![Vec::new(), Vec::new()];
+        let mut left = Vec::new();
+        let mut right = Vec::new();
+        while reader.read_next(&mut buffers).unwrap() {
+            left.extend_from_slice(&buffers[0]);
+            right.extend_from_slice(&buffers[1]);
+        }
+        assert_eq!(left, vec![0x80; 12]);
+        assert_eq!(right, vec![0x40; 12]);
+    }
+
+    #[test]
+    fn dff_reader_deinterleaves_channels() {
+        let file = dff_file(5_644_800);
+        let mut reader = DsdReader::new(&file[..]).unwrap();
+        assert_eq!(reader.channels(), 2);
+        assert_eq!(reader.dsd_rate_hz(), 5_644_800);
+
+        let mut buffers = vec![Vec::new(), Vec::new()];
+        assert!(reader.read_next(&mut buffers).unwrap());
+        assert_eq!(buffers[0], vec![1, 3, 5]);
+        assert_eq!(buffers[1], vec![2, 4, 6]);
+        assert!(!reader.read_next(&mut buffers).unwrap());
+    }
+
+    #[test]
+    fn reader_rejects_other_formats() {
+        assert!(DsdReader::new(&b"fLaC\0\0\0\x22"[..]).is_err());
+    }
+
+    #[test]
+    fn reader_rejects_rates_that_the_decimator_does_not_support() {
+        // 3.072 MHz is DSD64 of the 48 kHz family.
+        for file in &[dsf_file(3_072_000), dff_file(3_072_000), dsf_file(0)] {
+            let err = DsdReader::new(&file[..]).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn dff_reader_rejects_chunks_smaller_than_their_header() {
+        let mut header = Vec::new();
+        header.extend_from_slice(b"FRM8");
+        header.extend_from_slice(&0_u64.to_be_bytes());
+        header.extend_from_slice(b"DSD ");
+
+        // A PROP chunk too small for its type, an FS chunk too small for its
+        // rate, and a chunk size that overflows when padded.
+        let mut prop_too_small = header.clone();
+        prop_too_small.extend_from_slice(b"PROP");
+        prop_too_small.extend_from_slice(&2_u64.to_be_bytes());
+        prop_too_small.extend_from_slice(b"SND ");
+
+        let mut fs_too_small = header.clone();
+        fs_too_small.extend_from_slice(b"PROP");
+        fs_too_small.extend_from_slice(&18_u64.to_be_bytes());
+        fs_too_small.extend_from_slice(b"SND FS  ");
+        fs_too_small.extend_from_slice(&2_u64.to_be_bytes());
+        fs_too_small.extend_from_slice(&[0, 0, 0, 0]);
+
+        let mut size_overflows = header;
+        size_overflows.extend_from_slice(b"FVER");
+        size_overflows.extend_from_slice(&u64::MAX.to_be_bytes());
+
+        for file in &[prop_too_small, fs_too_small, size_overflows] {
+            let err = DsdReader::new(&file[..]).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
@@ -0,0 +1,469 @@
+// BS1770 -- Loudness analysis library conforming to ITU-R BS.1770
+// Copyright 2020 Ruud van Asseldonk
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! Reading and replacing metadata blocks of FLAC files.
+//!
+//! A FLAC file starts with a sequence of metadata blocks, such as STREAMINFO,
+//! VORBIS_COMMENT, and PICTURE, followed by the audio frames. `read_block`
+//! returns the body of a block, and `replace_block` replaces it, leaving all
+//! other parts of the file untouched. This is what `flac_tags` builds upon,
+//! and it can be used to edit other blocks in the same way.
+//!
+//! When the block is followed by a PADDING block, and the new block fits in
+//! the space of both blocks, `replace_block` overwrites only these blocks in
+//! place, and shrinks or grows the padding to make up for the difference.
+//! This is a single small write, but it is not atomic. Otherwise it writes a
+//! copy of the file with the block replaced, and then moves the copy over the
+//! original, so the original is never left half-written. On Linux, the copy is
+//! made with `copy_file_range`, which on file systems that support reflinks
+//! shares the audio data between the old and new file. On other platforms it
+//! copies through a buffer.
+//!
+//! ```no_run
+//! use std::path::Path;
+//! use bs1770::flac_meta;
+//!
+//! let path = Path::new("track.flac");
+//! let mut picture = flac_meta::read_block(path, flac_meta::BLOCK_TYPE_PICTURE)?
+//!     .expect("File has a PICTURE block.");
+//! // Set the picture type to the front cover, see the FLAC format.
+//! picture[..4].copy_from_slice(&3_u32.to_be_bytes());
+//! flac_meta::replace_block(path, flac_meta::BLOCK_TYPE_PICTURE, &picture)?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! This module is only available with the `flac_meta` feature enabled, which
+//! the `flac_tags` feature enables too.
+
+use std::fs;
+use std::io;
+use std::io::{Read, Seek, Write};
+use std::mem;
+use std::path::Path;
+
+/// The metadata block type of a STREAMINFO block, which is always the first block.
+pub const BLOCK_TYPE_STREAMINFO: u8 = 0;
+
+/// The metadata block type of a PADDING block.
+pub const BLOCK_TYPE_PADDING: u8 = 1;
+
+/// The metadata block type of an APPLICATION block.
+pub const BLOCK_TYPE_APPLICATION: u8 = 2;
+
+/// The metadata block type of a SEEKTABLE block.
+pub const BLOCK_TYPE_SEEKTABLE: u8 = 3;
+
+/// The metadata block type of a VORBIS_COMMENT block, which holds the tags.
+pub const BLOCK_TYPE_VORBIS_COMMENT: u8 = 4;
+
+/// The metadata block type of a CUESHEET block.
+pub const BLOCK_TYPE_CUESHEET: u8 = 5;
+
+/// The metadata block type of a PICTURE block.
+pub const BLOCK_TYPE_PICTURE: u8 = 6;
+
+/// The largest body of a metadata block; the length is stored in 24 bits.
+pub const MAX_BLOCK_LEN: usize = (1 << 24) - 1;
+
+/// The position of a metadata block in a FLAC file.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct BlockLocation {
+    /// The offset of the block header from the start of the file.
+    pub offset: u64,
+
+    /// The length of the block, including the 4-byte block header.
+    pub len: u64,
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Return the name of a block type, for use in error messages.
+fn block_type_name(block_type: u8) -> String {
+    match block_type {
+        BLOCK_TYPE_STREAMINFO => "STREAMINFO".to_string(),
+        BLOCK_TYPE_PADDING => "PADDING".to_string(),
+        BLOCK_TYPE_APPLICATION => "APPLICATION".to_string(),
+        BLOCK_TYPE_SEEKTABLE => "SEEKTABLE".to_string(),
+        BLOCK_TYPE_VORBIS_COMMENT => "VORBIS_COMMENT".to_string(),
+        BLOCK_TYPE_CUESHEET => "CUESHEET".to_string(),
+        BLOCK_TYPE_PICTURE => "PICTURE".to_string(),
+        _ => format!("type {}", block_type),
+    }
+}
+
+/// Return the location of the first metadata block of the given type.
+///
+/// Returns `None` if the file has no such block. This validates the structure
+/// of the metadata blocks up to the requested block, and returns an error if
+/// the file is not a FLAC file, or if the metadata is corrupt or truncated.
+/// Callers rewrite the file based on these offsets, so we should not trust
+/// them blindly.
+pub fn locate_block(file: &mut fs::File, block_type: u8) -> io::Result<Option<BlockLocation>> {
+    let file_len = file.metadata()?.len();
+    file.seek(io::SeekFrom::Start(0))?;
+    let mut reader = io::BufReader::new(file);
+
+    // The first 4 bytes are the flac header.
+    let mut buf = [0_u8; 4];
+    if file_len < 4 {
+        return Err(invalid_data("File is too short to be a FLAC file.".to_string()))
+    }
+    reader.read_exact(&mut buf[..])?;
+    if &buf[..3] == b"ID3" {
+        return Err(invalid_data(
+            "File starts with an ID3 tag, which is not supported when writing tags.".to_string()
+        ))
+    }
+    if &buf != b"fLaC" {
+        return Err(invalid_data("File does not start with the FLAC signature.".to_string()))
+    }
+
+    let mut pos = 4;
+    let mut is_last = false;
+    let mut is_first = true;
+
+    while !is_last {
+        if pos + 4 > file_len {
+            return Err(invalid_data(format!(
+                "Metadata block header at offset {} is truncated.", pos,
+            )))
+        }
+
+        // The block header is four bytes, one byte where the first bit
+        // specifies whether this is the last block, and the next 7 bits specify
+        // the block type. Then follows a 24-bit big-endian block length.
+        reader.read_exact(&mut buf[..])?;
+        is_last = (buf[0] >> 7) == 1;
+        let current_type = buf[0] & 0b0111_1111;
+        let block_length = u32::from_be_bytes([0, buf[1], buf[2], buf[3]]) as u64;
+
+        // The FLAC format requires the first block to be STREAMINFO, and
+        // block type 127 is invalid, to avoid confusion with frame sync codes.
+        if is_first && current_type != 0 {
+            return Err(invalid_data("First metadata block is not STREAMINFO.".to_string()))
+        }
+        if current_type == 127 {
+            return Err(invalid_data(format!(
+                "Invalid metadata block type at offset {}.", pos,
+            )))
+        }
+        if pos + 4 + block_length > file_len {
+            return Err(invalid_data(format!(
+                "Metadata block at offset {} with length {} extends past the end of the file.",
+                pos,
+                block_length,
+            )))
+        }
+        is_first = false;
+
+        if current_type == block_type {
+            return Ok(Some(BlockLocation { offset: pos, len: block_length + 4 }))
+        }
+        reader.seek(io::SeekFrom::Current(block_length as i64))?;
+        pos += 4 + block_length;
+    }
+
+    Ok(None)
+}
+
+/// Read the body of the first metadata block of the given type, excluding the block header.
+///
+/// Returns `None` if the file has no such block.
+pub fn read_block(path: &Path, block_type: u8) -> io::Result<Option<Vec<u8>>> {
+    let mut file = fs::File::open(path)?;
+    let location = match locate_block(&mut file, block_type)? {
+        Some(location) => location,
+        None => return Ok(None),
+    };
+    let mut block = vec![0_u8; (location.len - 4) as usize];
+    file.seek(io::SeekFrom::Start(location.offset + 4))?;
+    file.read_exact(&mut block)?;
+    Ok(Some(block))
+}
+
+/// Return the length of the region that `replace_block` may overwrite in place, and whether it ends the metadata.
+///
+/// The region starts at the block at `location`, and includes the `PADDING`
+/// block that directly follows it, if there is one.
+fn locate_region(file: &mut fs::File, location: BlockLocation) -> io::Result<(u64, bool)> {
+    let BlockLocation { offset, len: block_len } = location;
+    let mut header = [0_u8; 4];
+    file.seek(io::SeekFrom::Start(offset))?;
+    file.read_exact(&mut header[..1])?;
+    if (header[0] >> 7) == 1 {
+        return Ok((block_len, true))
+    }
+
+    file.seek(io::SeekFrom::Start(offset + block_len))?;
+    file.read_exact(&mut header)?;
+    let is_last = (header[0] >> 7) == 1;
+    let padding_len = u32::from_be_bytes([0, header[1], header[2], header[3]]) as u64 + 4;
+    let file_len = file.metadata()?.len();
+    if header[0] & 0b0111_1111 == BLOCK_TYPE_PADDING && offset + block_len + padding_len <= file_len {
+        Ok((block_len + padding_len, is_last))
+    } else {
+        Ok((block_len, false))
+    }
+}
+
+/// Return the new block and padding that fill a region of `region_len` bytes.
+///
+/// The result includes the block headers. It is `None` when the block does
+/// not fit, or when the remainder is too small for a padding block header.
+fn fill_region(block_type: u8, block: &[u8], region_len: u64, is_last: bool) -> Option<Vec<u8>> {
+    let block_len = 4 + block.len() as u64;
+    let padding_len = region_len.checked_sub(block_len)?;
+    let last_flag = if is_last { 0x80 } else { 0 };
+
+    let mut result = Vec::with_capacity(region_len as usize);
+    if padding_len == 0 {
+        result.push(block_type | last_flag);
+    } else {
+        result.push(block_type);
+    }
+    result.extend_from_slice(&(block.len() as u32).to_be_bytes()[1..]);
+    result.extend_from_slice(block);
+
+    if padding_len > 0 {
+        // The padding needs a header of its own, and its length is 24 bits.
+        let padding_body_len = padding_len.checked_sub(4).filter(|&n| n < 1 << 24)?;
+        result.push(BLOCK_TYPE_PADDING | last_flag);
+        result.extend_from_slice(&(padding_body_len as u32).to_be_bytes()[1..]);
+        result.resize(region_len as usize, 0);
+    }
+
+    Some(result)
+}
+
+/// Replace the body of the first metadata block of the given type.
+///
+/// The file must already have a block of this type, this function does not
+/// add one. If the new block fits in the old block and the `PADDING` block
+/// after it, it overwrites these blocks in place. Otherwise it writes the new
+/// file next to the original, with extension `.flac.metadata_edit`, and then
+/// renames it over the original.
+pub fn replace_block(path: &Path, block_type: u8, body: &[u8]) -> io::Result<()> {
+    let name = block_type_name(block_type);
+    if body.len() > MAX_BLOCK_LEN {
+        return Err(invalid_data(format!("New {} block is too large.", name)))
+    }
+
+    let mut src_file = fs::File::open(path)?;
+    let location = match locate_block(&mut src_file, block_type)? {
+        Some(location) => location,
+        None => return Err(invalid_data(format!("File does not have a {} block yet.", name))),
+    };
+
+    let (region_len, is_last) = locate_region(&mut src_file, location)?;
+    if let Some(region) = fill_region(block_type, body, region_len, is_last) {
+        mem::drop(src_file);
+        let mut file = fs::OpenOptions::new().write(true).open(path)?;
+        file.seek(io::SeekFrom::Start(location.offset))?;
+        file.write_all(&region)?;
+        return file.sync_data()
+    }
+
+    let mut tmp_fname = path.to_path_buf();
+    tmp_fname.set_extension("flac.metadata_edit");
+    let mut dst_file = fs::File::create(&tmp_fname)?;
+
+    let result = (|| {
+        // Copy the part up to the block. The offset starts at 0, the length
+        // is 1 more than the offset, we also want the first byte of the block
+        // header, which holds the block type and last-block flag.
+        copy_range(&mut src_file, &mut dst_file, 0, location.offset + 1)?;
+
+        // The remaining 3 bytes of the header are the block size, in big
+        // endian. Write that before the block.
+        dst_file.write_all(&(body.len() as u32).to_be_bytes()[1..])?;
+        dst_file.write_all(body)?;
+
+        // After the new block, copy the remainder of the old file.
+        let src_len = src_file.metadata()?.len();
+        let tail_offset = location.offset + location.len;
+        copy_range(&mut src_file, &mut dst_file, tail_offset, src_len - tail_offset)?;
+        dst_file.sync_all()
+    })();
+
+    // Close both files before the rename. On Windows, a file that is open
+    // cannot be replaced.
+    mem::drop(src_file);
+    mem::drop(dst_file);
+
+    match result {
+        // Now that we produced the new file with a temporary name, move it
+        // over the old file.
+        Ok(()) => fs::rename(&tmp_fname, path),
+        Err(err) => {
+            // Do not leave a partial copy behind.
+            let _ = fs::remove_file(&tmp_fname);
+            Err(err)
+        }
+    }
+}
+
+/// Append `len` bytes of `file_in`, starting at `off_in`, to `file_out`.
+///
+/// On Linux this uses `copy_file_range`, to take advantage of reflink copies
+/// on file systems that support this.
+#[cfg(target_os = "linux")]
+fn copy_range(file_in: &mut fs::File, file_out: &mut fs::File, off_in: u64, len: u64) -> io::Result<()> {
+    use std::ptr;
+    use std::os::unix::io::AsRawFd;
+
+    let mut num_left = len as usize;
+    let mut off = off_in as i64;
+
+    while num_left > 0 {
+        let num_copied = unsafe {
+            // We do specify the offset to copy from, but we set the offset to
+            // copy to to null, which means write at the current write position
+            // (and update it).
+            let off_in = &mut off as *mut libc::off64_t;
+            let off_out = ptr::null_mut();
+            let flags = 0;
+
+            libc::copy_file_range(
+                file_in.as_raw_fd(), off_in,
+                file_out.as_raw_fd(), off_out,
+                num_left,
+                flags,
+            )
+        };
+
+        if num_copied < 0 {
+            let err = io::Error::last_os_error();
+            // Older kernels do not support copy_file_range at all, or not
+            // across file systems. Fall back to copying through userspace.
+            return match err.raw_os_error() {
+                Some(libc::ENOSYS) | Some(libc::EXDEV) | Some(libc::EINVAL) => {
+                    let off = off as u64;
+                    copy_range_generic(file_in, file_out, off, off_in + len - off)
+                }
+                _ => Err(err),
+            }
+        }
+
+        if num_copied == 0 {
+            let err = io::Error::new(io::ErrorKind::UnexpectedEof, "Failed to copy full range");
+            return Err(err);
+        }
+
+        // This does not overflow, because `num_copied > 0`.
+        num_left -= num_copied as usize;
+    }
+
+    Ok(())
+}
+
+/// Append `len` bytes of `file_in`, starting at `off_in`, to `file_out`.
+///
+/// On platforms other than Linux, this copies through userspace. macOS has
+/// `clonefile`, but it clones entire files, and Windows can only duplicate
+/// extents at cluster-aligned offsets in both files. Because the new block
+/// generally differs in length from the old one, the audio data ends up at a
+/// different alignment, so neither applies.
+#[cfg(not(target_os = "linux"))]
+fn copy_range(file_in: &mut fs::File, file_out: &mut fs::File, off_in: u64, len: u64) -> io::Result<()> {
+    copy_range_generic(file_in, file_out, off_in, len)
+}
+
+/// Copy a range through a userspace buffer, supported on every platform.
+fn copy_range_generic(
+    file_in: &mut fs::File,
+    file_out: &mut fs::File,
+    off_in: u64,
+    len: u64,
+) -> io::Result<()> {
+    file_in.seek(io::SeekFrom::Start(off_in))?;
+    let num_copied = io::copy(&mut file_in.take(len), file_out)?;
+    if num_copied < len {
+        let err = io::Error::new(io::ErrorKind::UnexpectedEof, "Failed to copy full range");
+        return Err(err);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::{BLOCK_TYPE_APPLICATION, BLOCK_TYPE_PICTURE, BLOCK_TYPE_STREAMINFO, BlockLocation};
+    use super::{locate_block, read_block, replace_block};
+
+    /// Write a FLAC file with a STREAMINFO block, the given blocks, and stand-in audio.
+    fn write_flac(fname: &str, blocks: &[(u8, &[u8])]) -> PathBuf {
+        let dir = std::env::temp_dir().join("bs1770_flac_meta_test");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(fname);
+
+        let mut data = b"fLaC".to_vec();
+        data.extend_from_slice(&[0, 0, 0, 34]);
+        data.extend_from_slice(&[0; 34]);
+        for (i, &(block_type, body)) in blocks.iter().enumerate() {
+            let last_flag = if i + 1 == blocks.len() { 0x80 } else { 0 };
+            data.push(block_type | last_flag);
+            data.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+            data.extend_from_slice(body);
+        }
+        data.extend_from_slice(b"\xff\xf8audio");
+        fs::write(&path, data).unwrap();
+        path
+    }
+
+    #[test]
+    fn locate_block_finds_first_block_of_type() {
+        let path = write_flac("locate.flac", &[(2, b"abcd"), (6, b"pic1"), (6, b"pic2")]);
+        let mut file = fs::File::open(&path).unwrap();
+        assert_eq!(
+            locate_block(&mut file, BLOCK_TYPE_STREAMINFO).unwrap(),
+            Some(BlockLocation { offset: 4, len: 38 }),
+        );
+        assert_eq!(
+            locate_block(&mut file, BLOCK_TYPE_PICTURE).unwrap(),
+            Some(BlockLocation { offset: 50, len: 8 }),
+        );
+        assert_eq!(locate_block(&mut file, 5).unwrap(), None);
+        assert_eq!(read_block(&path, BLOCK_TYPE_PICTURE).unwrap(), Some(b"pic1".to_vec()));
+    }
+
+    #[test]
+    fn replace_block_copies_file_without_padding() {
+        let path = write_flac("copy.flac", &[(2, b"abcd"), (6, b"picture")]);
+        let before = fs::read(&path).unwrap();
+        replace_block(&path, BLOCK_TYPE_APPLICATION, b"longer application data").unwrap();
+        let after = fs::read(&path).unwrap();
+
+        assert_eq!(read_block(&path, BLOCK_TYPE_APPLICATION).unwrap(), Some(b"longer application data".to_vec()));
+        assert_eq!(read_block(&path, BLOCK_TYPE_PICTURE).unwrap(), Some(b"picture".to_vec()));
+        assert_eq!(after.len(), before.len() + 19);
+        assert_eq!(&before[before.len() - 18..], &after[after.len() - 18..]);
+        assert!(!path.with_extension("flac.metadata_edit").exists());
+    }
+
+    #[test]
+    fn replace_block_uses_padding_in_place() {
+        let path = write_flac("in_place.flac", &[(6, b"pic"), (1, &[0; 10])]);
+        let before = fs::read(&path).unwrap();
+        replace_block(&path, BLOCK_TYPE_PICTURE, b"picture").unwrap();
+        let after = fs::read(&path).unwrap();
+        assert_eq!(after.len(), before.len());
+        assert_eq!(read_block(&path, BLOCK_TYPE_PICTURE).unwrap(), Some(b"picture".to_vec()));
+        // The last-block flag stays on the padding, which shrank by 4 bytes.
+        assert_eq!(&after[53..57], &[0x81, 0, 0, 6]);
+    }
+
+    #[test]
+    fn replace_block_requires_existing_block() {
+        let path = write_flac("missing.flac", &[(2, b"abcd")]);
+        let err = replace_block(&path, BLOCK_TYPE_PICTURE, b"picture").unwrap_err();
+        assert_eq!(err.to_string(), "File does not have a PICTURE block yet.");
+    }
+}
@@ -0,0 +1,142 @@
+// BS1770 -- Loudness analysis library conforming to ITU-R BS.1770
+// Copyright 2020 Ruud van Asseldonk
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! Reading and rewriting the Vorbis comments (tags) of FLAC files.
+//!
+//! `read` returns the tags of a file as `vorbis_comment::Tags`, and `write`
+//! replaces them. Writing replaces only the `VORBIS_COMMENT` metadata block
+//! with `flac_meta::replace_block`, see the `flac_meta` module for how the
+//! file is rewritten.
+//!
+//! ```no_run
+//! use std::path::Path;
+//! use bs1770::flac_tags;
+//!
+//! let path = Path::new("track.flac");
+//! let mut tags = flac_tags::read(path)?.expect("File has a VORBIS_COMMENT block.");
+//! tags.remove("REPLAYGAIN_TRACK_GAIN");
+//! tags.set("BS17704_TRACK_LOUDNESS", "-9.234 LUFS");
+//! flac_tags::write(path, &tags)?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! This module is only available with the `flac_tags` feature enabled.
+
+use std::io;
+use std::path::Path;
+
+use crate::flac_meta;
+use crate::vorbis_comment::Tags;
+
+/// Read the tags of a FLAC file.
+///
+/// Returns `None` if the file has no `VORBIS_COMMENT` block.
+pub fn read(path: &Path) -> io::Result<Option<Tags>> {
+    match flac_meta::read_block(path, flac_meta::BLOCK_TYPE_VORBIS_COMMENT)? {
+        Some(block) => Tags::parse(&block).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Replace the tags of a FLAC file.
+///
+/// The file must already have a `VORBIS_COMMENT` block, this function does
+/// not add one. If the new block fits in the old block and the `PADDING` block
+/// after it, it overwrites these blocks in place. Otherwise it writes the new
+/// file next to the original, with extension `.flac.metadata_edit`, and then
+/// renames it over the original. See `flac_meta::replace_block`.
+pub fn write(path: &Path, tags: &Tags) -> io::Result<()> {
+    flac_meta::replace_block(path, flac_meta::BLOCK_TYPE_VORBIS_COMMENT, &tags.serialize())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::{read, write};
+    use crate::vorbis_comment::Tags;
+
+    /// Write a minimal FLAC file with a STREAMINFO, VORBIS_COMMENT, and PADDING block.
+    fn write_flac(fname: &str, tags: &Tags) -> PathBuf {
+        let dir = std::env::temp_dir().join("bs1770_flac_tags_test");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(fname);
+
+        let mut data = b"fLaC".to_vec();
+        data.extend_from_slice(&[0, 0, 0, 34]);
+        data.extend_from_slice(&[0; 34]);
+        let block = tags.serialize();
+        data.push(4);
+        data.extend_from_slice(&(block.len() as u32).to_be_bytes()[1..]);
+        data.extend_from_slice(&block);
+        data.extend_from_slice(&[0x81, 0, 0, 8]);
+        data.extend_from_slice(&[0; 8]);
+        // Stand-in for the audio frames.
+        data.extend_from_slice(b"\xff\xf8audio");
+        fs::write(&path, data).unwrap();
+        path
+    }
+
+    #[test]
+    fn write_replaces_only_the_vorbis_comment_block() {
+        let mut tags = Tags::new("bs1770 test".to_string());
+        tags.add("TITLE", "Tëst");
+        let path = write_flac("rewrite.flac", &tags);
+        let before = fs::read(&path).unwrap();
+
+        let mut tags = read(&path).unwrap().unwrap();
+        assert_eq!(tags.vendor(), "bs1770 test");
+        assert_eq!(tags.get("TITLE").next(), Some("Tëst"));
+
+        tags.set("BS17704_TRACK_LOUDNESS", "-9.234 LUFS");
+        write(&path, &tags).unwrap();
+        let after = fs::read(&path).unwrap();
+
+        assert_eq!(read(&path).unwrap(), Some(tags));
+        // The header and STREAMINFO are the same, and so are the padding and audio.
+        assert_eq!(&before[..42], &after[..42]);
+        assert_eq!(&before[before.len() - 19..], &after[after.len() - 19..]);
+        assert_eq!(after.len(), before.len() + 4 + 34);
+    }
+
+    #[test]
+    fn read_rejects_non_flac_files() {
+        let dir = std::env::temp_dir().join("bs1770_flac_tags_test");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("not_flac.flac");
+        fs::write(&path, b"RIFF....WAVE").unwrap();
+        assert!(read(&path).is_err());
+    }
+
+    #[test]
+    fn write_uses_padding_in_place() {
+        let mut tags = Tags::new("bs1770 test".to_string());
+        tags.add("TITLE", "Test");
+        let path = write_flac("in_place.flac", &tags);
+        let before = fs::read(&path).unwrap();
+
+        // The new comment takes 8 of the 12 bytes of padding, including its
+        // header, the remaining 4 bytes become an empty padding block.
+        tags.add("A", "BC");
+        write(&path, &tags).unwrap();
+        let after = fs::read(&path).unwrap();
+        assert_eq!(read(&path).unwrap(), Some(tags.clone()));
+        assert_eq!(after.len(), before.len());
+        assert_eq!(&after[after.len() - 11..after.len() - 7], &[0x81, 0, 0, 0]);
+
+        // A comment that grows by exactly 4 bytes replaces the padding block,
+        // and becomes the last metadata block.
+        tags.set("A", "BCDEFG");
+        write(&path, &tags).unwrap();
+        let after = fs::read(&path).unwrap();
+        assert_eq!(read(&path).unwrap(), Some(tags.clone()));
+        assert_eq!(after.len(), before.len());
+        assert_eq!(after[42], 0x84);
+        assert_eq!(&after[after.len() - 7..], b"\xff\xf8audio");
+    }
+}
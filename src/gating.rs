@@ -0,0 +1,166 @@
+// BS1770 -- Loudness analysis library conforming to ITU-R BS.1770
+// Copyright 2020 Ruud van Asseldonk
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! Incremental gating with bounded memory.
+//!
+//! `gated_mean` needs all 100ms windows of a measurement, which for a stream
+//! that runs for days grows without bound. `GatingHistogram` instead counts
+//! the gating blocks in bins of 0.1 LU as they are formed, like libebur128
+//! does in its histogram mode. It uses a fixed amount of memory, regardless of
+//! the duration of the measurement.
+//!
+//! Every bin also tracks the sum of the power of its blocks, so the mean over
+//! the bins that pass the relative gate is exact. Only the relative gate itself
+//! is applied at the granularity of a bin: the blocks in the bin that contains
+//! the threshold are either all included or all excluded. In practice the
+//! result is within a few hundredths of an LU of `gated_mean`.
+//!
+//! ```
+//! # use bs1770::{Power, Windows100ms};
+//! use bs1770::gating::GatingHistogram;
+//!
+//! let mut histogram = GatingHistogram::new();
+//! for _ in 0..100 {
+//!     let windows = [Power::from_lkfs(-23.0); 10];
+//!     histogram.push(Windows100ms { inner: &windows[..] });
+//! }
+//! let integrated = histogram.gated_mean().unwrap();
+//! assert!((integrated.loudness_lkfs() - -23.0).abs() < 1e-3);
+//! ```
+
+use crate::{Power, Windows100ms};
+use crate::histogram::LoudnessHistogram;
+
+/// Accumulates gating blocks, to compute the integrated loudness in bounded memory.
+#[derive(Clone, Debug)]
+pub struct GatingHistogram {
+    /// The gating blocks that passed the absolute gate.
+    blocks: LoudnessHistogram,
+
+    /// The three most recent windows, the most recent one last.
+    ///
+    /// A gating block spans four windows, so these form a block together
+    /// with the next window.
+    recent: [Power; 3],
+
+    /// The number of windows pushed since construction or the last `clear`.
+    num_windows: u64,
+}
+
+impl Default for GatingHistogram {
+    fn default() -> GatingHistogram {
+        GatingHistogram::new()
+    }
+}
+
+impl GatingHistogram {
+    /// Construct an empty histogram.
+    pub fn new() -> GatingHistogram {
+        GatingHistogram {
+            blocks: LoudnessHistogram::new(),
+            recent: [Power(0.0); 3],
+            num_windows: 0,
+        }
+    }
+
+    /// Feed the power of 100ms windows, with the channels already combined.
+    ///
+    /// The windows continue the windows pushed before, so a gating block can
+    /// span two calls.
+    pub fn push(&mut self, windows: Windows100ms<&[Power]>) {
+        for &power in windows.inner {
+            if self.num_windows >= 3 {
+                let sum = self.recent[0].0 + self.recent[1].0 + self.recent[2].0 + power.0;
+                // The histogram applies the absolute gate.
+                self.blocks.push(Power(0.25 * sum));
+            }
+            self.recent = [self.recent[1], self.recent[2], power];
+            self.num_windows += 1;
+        }
+    }
+
+    /// Discard all measurements.
+    pub fn clear(&mut self) {
+        self.blocks.clear();
+        self.num_windows = 0;
+    }
+
+    /// Return the number of 100ms windows pushed so far.
+    pub fn len(&self) -> u64 {
+        self.num_windows
+    }
+
+    /// Return whether no windows have been pushed.
+    pub fn is_empty(&self) -> bool {
+        self.num_windows == 0
+    }
+
+    /// Return the gated mean power of the windows pushed so far.
+    ///
+    /// This is the equivalent of `gated_mean` over all windows pushed, including
+    /// its behavior for inputs shorter than 400ms, see the module documentation
+    /// for the accuracy.
+    pub fn gated_mean(&self) -> Option<Power> {
+        if self.num_windows == 0 {
+            return None
+        }
+        if self.num_windows < 4 {
+            let recent = &self.recent[3 - self.num_windows as usize..];
+            return crate::gated_mean(Windows100ms { inner: recent })
+        }
+
+        // The absolute gate has already been applied when counting blocks,
+        // the relative gate is applied at bin granularity.
+        let absolute_gated_power = self.blocks.mean_power()?;
+        let relative_threshold = absolute_gated_power.loudness_lkfs() - 10.0;
+        self.blocks.mean_power_above(relative_threshold)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GatingHistogram;
+    use crate::{Power, Windows100ms, gated_mean};
+
+    #[test]
+    fn gating_histogram_matches_gated_mean() {
+        // A signal that varies between -50 and -10 LKFS in an irregular way,
+        // so many blocks are close to the relative gate.
+        let windows: Vec<Power> = (0..5_000)
+            .map(|i| Power::from_lkfs(-30.0 + 20.0 * ((i * i % 97) as f32 / 48.0 - 1.0)))
+            .collect();
+        let exact = gated_mean(Windows100ms { inner: &windows[..] }).unwrap();
+
+        // Pushing in chunks that do not align with the gating blocks should
+        // not make a difference.
+        let mut histogram = GatingHistogram::new();
+        for chunk in windows.chunks(7) {
+            histogram.push(Windows100ms { inner: chunk });
+        }
+        assert_eq!(histogram.len(), 5_000);
+        let estimate = histogram.gated_mean().unwrap();
+        let error = estimate.loudness_lkfs() - exact.loudness_lkfs();
+        assert!(error.abs() < 0.05, "Exact {:?}, histogram {:?}.", exact, estimate);
+    }
+
+    #[test]
+    fn gating_histogram_handles_short_and_silent_input() {
+        let mut histogram = GatingHistogram::new();
+        assert_eq!(histogram.gated_mean(), None);
+
+        let windows = [Power::from_lkfs(-20.0), Power::from_lkfs(-30.0)];
+        histogram.push(Windows100ms { inner: &windows[..] });
+        let exact = gated_mean(Windows100ms { inner: &windows[..] });
+        assert_eq!(histogram.gated_mean(), exact);
+
+        histogram.clear();
+        assert!(histogram.is_empty());
+        let silence = [Power(0.0); 50];
+        histogram.push(Windows100ms { inner: &silence[..] });
+        assert_eq!(histogram.gated_mean(), None);
+    }
+}
@@ -0,0 +1,250 @@
+// BS1770 -- Loudness analysis library conforming to ITU-R BS.1770
+// Copyright 2020 Ruud van Asseldonk
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! A histogram of loudness values.
+//!
+//! Statistics about the dynamics of a signal, such as the loudness range or
+//! the distribution of the loudness of the gating blocks, are based on the
+//! distribution of loudness values over time. `LoudnessHistogram` counts
+//! loudness values in bins of 0.1 LU, for percentile queries and plotting.
+//! Every bin also tracks the sum of the power of its values, so the mean power
+//! above a gate is exact. `gating::GatingHistogram` and
+//! `lra::LoudnessRangeHistogram` build on it to gate in bounded memory.
+//!
+//! ```
+//! # use bs1770::{Power, Windows100ms};
+//! use bs1770::histogram::LoudnessHistogram;
+//!
+//! // Windows that ramp from -40 LKFS to -20.1 LKFS.
+//! let windows: Windows100ms<Vec<Power>> = (0..200)
+//!     .map(|i| Power::from_lkfs(-40.0 + 0.1 * i as f32))
+//!     .collect();
+//! let histogram = LoudnessHistogram::from_gating_blocks(windows.as_ref());
+//! let median = histogram.percentile_lkfs(0.5).unwrap();
+//! assert!((median - -30.0).abs() < 0.2);
+//! ```
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::{Power, Windows100ms};
+#[cfg(not(feature = "std"))]
+use crate::math::Float;
+
+/// The loudness of the lower edge of the lowest bin, which is also the absolute gate.
+const MIN_LKFS: f32 = -70.0;
+
+/// The width of a bin, in loudness units.
+const BIN_LU: f32 = 0.1;
+
+/// The number of bins, which cover -70 LKFS up to +10 LKFS.
+///
+/// Louder values are counted in the highest bin.
+const NUM_BINS: usize = 800;
+
+/// Counts loudness values in bins of 0.1 LU, from -70 LKFS up to +10 LKFS.
+///
+/// Values below -70 LKFS, the absolute gate of BS.1770, are not counted.
+#[derive(Clone, Debug)]
+pub struct LoudnessHistogram {
+    /// The number of values per bin, starting at -70 LKFS.
+    bins: Vec<u64>,
+
+    /// The sum of the power of the values in every bin.
+    power_sums: Vec<f64>,
+
+    /// The total number of values counted.
+    count: u64,
+}
+
+impl Default for LoudnessHistogram {
+    fn default() -> LoudnessHistogram {
+        LoudnessHistogram::new()
+    }
+}
+
+impl LoudnessHistogram {
+    /// Construct an empty histogram.
+    pub fn new() -> LoudnessHistogram {
+        LoudnessHistogram {
+            bins: vec![0; NUM_BINS],
+            power_sums: vec![0.0; NUM_BINS],
+            count: 0,
+        }
+    }
+
+    /// Construct a histogram of the loudness of the 400ms gating blocks.
+    ///
+    /// See `Windows100ms::gating_blocks`.
+    pub fn from_gating_blocks(windows: Windows100ms<&[Power]>) -> LoudnessHistogram {
+        let mut histogram = LoudnessHistogram::new();
+        for power in windows.gating_blocks() {
+            histogram.push(power);
+        }
+        histogram
+    }
+
+    /// Return the loudness at the lower edge of the bin with the given index.
+    fn bin_lkfs(index: usize) -> f32 {
+        MIN_LKFS + index as f32 * BIN_LU
+    }
+
+    /// Return the index of the first bin whose center is at or above `threshold_lkfs`.
+    fn first_bin_above(threshold_lkfs: f32) -> usize {
+        (0..NUM_BINS)
+            .find(|&i| LoudnessHistogram::bin_lkfs(i) + 0.5 * BIN_LU >= threshold_lkfs)
+            .unwrap_or(NUM_BINS)
+    }
+
+    /// Count one loudness value, if it is not below -70 LKFS.
+    pub fn push(&mut self, power: Power) {
+        let lkfs = power.loudness_lkfs();
+        if lkfs.is_nan() || lkfs < MIN_LKFS {
+            return
+        }
+        let i = (((lkfs - MIN_LKFS) / BIN_LU) as usize).min(NUM_BINS - 1);
+        self.bins[i] += 1;
+        self.power_sums[i] += power.0 as f64;
+        self.count += 1;
+    }
+
+    /// Discard all values.
+    pub fn clear(&mut self) {
+        for bin in self.bins.iter_mut() {
+            *bin = 0;
+        }
+        for sum in self.power_sums.iter_mut() {
+            *sum = 0.0;
+        }
+        self.count = 0;
+    }
+
+    /// Return the number of values counted.
+    pub fn len(&self) -> u64 {
+        self.count
+    }
+
+    /// Return whether no values were counted.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Iterate over the bins, as pairs of the loudness at the lower edge of the bin and its count.
+    ///
+    /// This includes the empty bins, so consecutive bins are 0.1 LU apart.
+    pub fn bins(&self) -> impl ExactSizeIterator<Item = (f32, u64)> + '_ {
+        self.bins
+            .iter()
+            .enumerate()
+            .map(|(i, &count)| (LoudnessHistogram::bin_lkfs(i), count))
+    }
+
+    /// Return the loudness below which a fraction `p` of the values lies.
+    ///
+    /// The result is the center of the bin that contains the value at rank
+    /// round((n - 1) * p) in sorted order, as used for the loudness range in
+    /// EBU Tech 3342. Panics if `p` is not between 0.0 and 1.0. Returns `None`
+    /// when the histogram is empty.
+    pub fn percentile_lkfs(&self, p: f32) -> Option<f32> {
+        self.percentile_lkfs_above(f32::NEG_INFINITY, p)
+    }
+
+    /// Like `percentile_lkfs`, but only over the bins whose center is at or above the threshold.
+    ///
+    /// This applies a gate at the granularity of a bin, such as the relative
+    /// gate of the loudness range.
+    pub fn percentile_lkfs_above(&self, threshold_lkfs: f32, p: f32) -> Option<f32> {
+        assert!((0.0..=1.0).contains(&p), "Percentile must be between 0.0 and 1.0.");
+        let start = LoudnessHistogram::first_bin_above(threshold_lkfs);
+        let gated = &self.bins[start..];
+        let n: u64 = gated.iter().sum();
+        if n == 0 {
+            return None
+        }
+        let rank = ((n - 1) as f64 * p as f64).round() as u64;
+        let mut seen = 0;
+        for (i, &count) in gated.iter().enumerate() {
+            seen += count;
+            if seen > rank {
+                return Some(LoudnessHistogram::bin_lkfs(start + i) + 0.5 * BIN_LU)
+            }
+        }
+        unreachable!("The rank is less than the total count.")
+    }
+
+    /// Return the mean power of the values counted, or `None` when the histogram is empty.
+    pub fn mean_power(&self) -> Option<Power> {
+        self.mean_power_above(f32::NEG_INFINITY)
+    }
+
+    /// Return the mean power of the values in the bins whose center is at or above the threshold.
+    ///
+    /// The mean is exact, only the gate is applied at the granularity of a
+    /// bin. Returns `None` when no value passes the gate.
+    pub fn mean_power_above(&self, threshold_lkfs: f32) -> Option<Power> {
+        let start = LoudnessHistogram::first_bin_above(threshold_lkfs);
+        let count: u64 = self.bins[start..].iter().sum();
+        if count == 0 {
+            return None
+        }
+        let power_sum: f64 = self.power_sums[start..].iter().sum();
+        Some(Power((power_sum / count as f64) as f32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LoudnessHistogram;
+    use crate::Power;
+
+    #[test]
+    fn percentile_lkfs_finds_bin_of_rank() {
+        let mut histogram = LoudnessHistogram::new();
+        assert_eq!(histogram.percentile_lkfs(0.5), None);
+
+        // 10 values at -30 LKFS, 30 at -20 LKFS, and some below the gate.
+        for _ in 0..10 {
+            histogram.push(Power::from_lkfs(-29.95));
+        }
+        for _ in 0..30 {
+            histogram.push(Power::from_lkfs(-19.95));
+        }
+        histogram.push(Power::from_lkfs(-80.0));
+        histogram.push(Power(0.0));
+        assert_eq!(histogram.len(), 40);
+
+        let p10 = histogram.percentile_lkfs(0.1).unwrap();
+        let p50 = histogram.percentile_lkfs(0.5).unwrap();
+        assert!((p10 - -29.95).abs() < 1e-3, "{}", p10);
+        assert!((p50 - -19.95).abs() < 1e-3, "{}", p50);
+        assert_eq!(histogram.percentile_lkfs(0.0), Some(p10));
+
+        let nonempty: Vec<(f32, u64)> = histogram.bins().filter(|&(_, n)| n > 0).collect();
+        assert_eq!(nonempty.len(), 2);
+        assert_eq!(nonempty[0].1, 10);
+        assert!((nonempty[0].0 - -30.0).abs() < 1e-3);
+
+        histogram.clear();
+        assert!(histogram.is_empty());
+    }
+
+    #[test]
+    fn mean_power_above_gates_at_bin_granularity() {
+        let mut histogram = LoudnessHistogram::new();
+        assert_eq!(histogram.mean_power(), None);
+        histogram.push(Power(0.01));
+        histogram.push(Power(0.03));
+        histogram.push(Power(0.0001));
+        let mean = histogram.mean_power().unwrap();
+        assert!((mean.0 - 0.0401 / 3.0).abs() < 1e-7);
+
+        let gated = histogram.mean_power_above(Power(0.001).loudness_lkfs()).unwrap();
+        assert!((gated.0 - 0.02).abs() < 1e-7);
+        assert_eq!(histogram.mean_power_above(0.0), None);
+        assert_eq!(histogram.percentile_lkfs_above(0.0, 0.5), None);
+    }
+}
@@ -8,10 +8,25 @@
 //! Loudness analysis conforming to [ITU-R BS.1770-4][bs17704].
 //!
 //! This library offers the building blocks to perform BS.1770 loudness
-//! measurements, but you need to put the pieces together yourself.
+//! measurements, but you need to put the pieces together yourself. For the
+//! common case of audio that is in memory, `analyze` puts them together, and
+//! returns the standard EBU R128 measurements.
 //!
 //! [bs17704]: https://www.itu.int/rec/R-REC-BS.1770-4-201510-I/en
 //!
+//! # One-call analysis example
+//!
+//! ```
+//! # let left = vec![0.0_f32; 48_000];
+//! # let right = vec![0.0_f32; 48_000];
+//! let stats = bs1770::analyze(&[&left, &right], 48_000, &[1.0, 1.0]);
+//! match stats.integrated {
+//!     Some(power) => println!("Integrated loudness: {:.1} LUFS", power.loudness_lkfs()),
+//!     None => println!("Integrated loudness: silence"),
+//! }
+//! println!("True peak: {:.1} dBTP", stats.true_peak.dbtp());
+//! ```
+//!
 //! # Stereo integrated loudness example
 //!
 //! ```
@@ -20,16 +35,11 @@
 //! # }
 //! #
 //! let sample_rate_hz = 44_100;
-//! let bits_per_sample = 16;
 //! let channel_samples: [Vec<i16>; 2] = load_stereo_audio();
 //!
-//! // When converting integer samples to float, note that the maximum amplitude
-//! // is `1 << (bits_per_sample - 1)`, one bit is the sign bit.
-//! let normalizer = 1.0 / (1_u64 << (bits_per_sample - 1)) as f32;
-//!
 //! let channel_power: Vec<_> = channel_samples.iter().map(|samples| {
 //!     let mut meter = bs1770::ChannelLoudnessMeter::new(sample_rate_hz);
-//!     meter.push(samples.iter().map(|&s| s as f32 * normalizer));
+//!     meter.push_i16(samples.iter().cloned());
 //!     meter.into_100ms_windows()
 //! }).collect();
 //!
@@ -43,30 +53,208 @@
 //! ).unwrap_or(bs1770::Power(0.0));
 //! println!("Integrated loudness: {:.1} LUFS", gated_power.loudness_lkfs());
 //! ```
+//!
+//! # Without the standard library
+//!
+//! The meters need only `alloc`, so they can run on embedded audio devices.
+//! Disable the default `std` feature, and enable the `libm` feature to provide
+//! the math functions that `core` lacks. The modules that do I/O, such as
+//! `persist` and `trace`, and the `batch`, `dsd`, `flac_meta`, `flac_tags`,
+//! `rayon`, `rtp`, and `vorbis_comment` features, require the standard library.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(any(feature = "std", feature = "libm")))]
+compile_error!("Without the `std` feature, the `libm` feature is required for math functions.");
+
+extern crate alloc;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::f32;
+use core::f64;
+use core::fmt;
+use core::iter;
+use core::iter::FromIterator;
+use core::ops::{Add, Sub};
+use core::time::Duration;
+
+#[cfg(not(feature = "std"))]
+use crate::math::Float;
+
+pub mod album;
+pub mod ballistics;
+
+#[cfg(feature = "batch")]
+pub mod batch;
+
+pub mod blocks;
+pub mod channels;
+pub mod compliance;
+pub mod downmix;
+
+#[cfg(feature = "dsd")]
+pub mod dsd;
+
+#[cfg(feature = "flac_meta")]
+pub mod flac_meta;
+
+#[cfg(feature = "flac_tags")]
+pub mod flac_tags;
+
+pub mod gating;
+pub mod histogram;
+
+pub mod lra;
+pub mod multichannel;
+pub mod normalize;
+
+#[cfg(feature = "rayon")]
+pub mod parallel;
+
+#[cfg(feature = "std")]
+pub mod persist;
+
+pub mod program;
+pub mod pyloudnorm;
+pub mod replaygain;
+pub mod rolling;
+
+#[cfg(feature = "rtp")]
+pub mod rtp;
+
+pub mod tags;
+
+#[cfg(feature = "std")]
+pub mod trace;
+
+pub mod true_peak;
+
+#[cfg(feature = "vorbis_comment")]
+pub mod vorbis_comment;
+
+#[cfg(not(feature = "std"))]
+mod math;
+mod simd;
+
+#[cfg(test)]
+mod test_util;
+
+/// An error for invalid input to one of the fallible functions.
+///
+/// Most functions in this crate panic on invalid input, because it indicates a
+/// bug in the caller. Where the input can come from elsewhere, such as the
+/// sample rate from a file header, a `try_` variant returns this error instead.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Error {
+    /// The sample rate is too low for a window to contain any samples.
+    InvalidSampleRate(u32),
+
+    /// Two channels that must have the same length do not.
+    LengthMismatch(usize, usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::InvalidSampleRate(hz) => write!(f, "Invalid sample rate: {} Hz.", hz),
+            Error::LengthMismatch(a, b) => write!(f, "Channels have different lengths: {} and {}.", a, b),
+        }
+    }
+}
 
-use std::f32;
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
 
 /// Coefficients for a 2nd-degree infinite impulse response filter.
 ///
 /// Coefficient a0 is implicitly 1.0.
+///
+/// The coefficients and state are stored as `f64`. At high sample rates the
+/// poles of the filters lie very close to the unit circle, and `f32` is not
+/// precise enough to represent them; at 384 kHz the response at 20 Hz would
+/// be off by 0.3 dB.
 #[derive(Clone)]
 struct Filter {
-    a1: f32,
-    a2: f32,
-    b0: f32,
-    b1: f32,
-    b2: f32,
+    a1: f64,
+    a2: f64,
+    b0: f64,
+    b1: f64,
+    b2: f64,
 
     // The past two input and output samples.
-    x1: f32,
-    x2: f32,
-    y1: f32,
-    y2: f32,
+    x1: f64,
+    x2: f64,
+    y1: f64,
+    y2: f64,
 }
 
+/// Coefficients `[a1, a2, b0, b1, b2]` of the two K-weighting stages.
+type KWeightingStages = ([f64; 5], [f64; 5]);
+
+/// The K-weighting coefficients at common sample rates.
+///
+/// The 48 kHz coefficients are those of table 1 of BS.1770-4. The formulas in
+/// `Filter::compute_high_shelf` and `Filter::compute_high_pass` deviate from
+/// the table slightly; using the table makes the filters match it exactly. The
+/// coefficients at the other rates were computed with the formulas, so using
+/// them only saves the computation when constructing a meter.
+const K_WEIGHTING_TABLE: [(u32, KWeightingStages); 5] = [
+    (44_100, (
+        [-1.6636551132560204, 0.7125954280732254, 1.5308412300498355, -2.6509799951536985, 1.1690790799210682],
+        [-1.9891696736297957, 0.9891990357870394, 1.0, -2.0, 1.0],
+    )),
+    (48_000, (
+        [-1.69065929318241, 0.73248077421585, 1.53512485958697, -2.69169618940638, 1.19839281085285],
+        [-1.99004745483398, 0.99007225036621, 1.0, -2.0, 1.0],
+    )),
+    (88_200, (
+        [-1.8309199879623321, 0.8441422610878528, 1.5575153755790945, -2.905627079925223, 1.3613339774716493],
+        [-1.9945775154503445, 0.9945848758780549, 1.0, -2.0, 1.0],
+    )),
+    (96_000, (
+        [-1.8446094698901085, 0.8558433229306414, 1.559714228975233, -2.926741578249952, 1.3782612023152518],
+        [-1.9950175447247156, 0.9950237590409233, 1.0, -2.0, 1.0],
+    )),
+    (192_000, (
+        [-1.9222022306074886, 0.925117735116826, 1.5722272150906924, -3.0472830515603757, 1.477971340979021],
+        [-1.9975072228407, 0.9975087783555097, 1.0, -2.0, 1.0],
+    )),
+];
+
 impl Filter {
+    /// Construct a filter from coefficients `[a1, a2, b0, b1, b2]`.
+    fn from_coefficients(c: &[f64; 5]) -> Filter {
+        Filter {
+            a1: c[0], a2: c[1],
+            b0: c[2], b1: c[3], b2: c[4],
+
+            x1: 0.0, x2: 0.0,
+            y1: 0.0, y2: 0.0,
+        }
+    }
+
+    /// Return the precomputed K-weighting coefficients for the sample rate, if there are any.
+    fn k_weighting_table(sample_rate_hz: f64) -> Option<&'static KWeightingStages> {
+        K_WEIGHTING_TABLE
+            .iter()
+            .find(|&&(rate, _)| rate as f64 == sample_rate_hz)
+            .map(|(_, stages)| stages)
+    }
+
     /// Stage 1 of th BS.1770-4 pre-filter.
-    pub fn high_shelf(sample_rate_hz: f32) -> Filter {
+    ///
+    /// For common sample rates this uses precomputed coefficients, see
+    /// `K_WEIGHTING_TABLE`, for other rates it computes them.
+    pub fn high_shelf(sample_rate_hz: f64) -> Filter {
+        match Filter::k_weighting_table(sample_rate_hz) {
+            Some((stage1, _)) => Filter::from_coefficients(stage1),
+            None => Filter::compute_high_shelf(sample_rate_hz),
+        }
+    }
+
+    /// Compute the coefficients of stage 1 of the BS.1770-4 pre-filter.
+    fn compute_high_shelf(sample_rate_hz: f64) -> Filter {
         // Coefficients taken from https://github.com/csteinmetz1/pyloudnorm/blob/
         // 6baa64d59b7794bc812e124438692e7fd2e65c0c/pyloudnorm/meter.py#L135-L136.
         let gain_db = 3.99984385397;
@@ -75,8 +263,8 @@ impl Filter {
 
         // Formula taken from https://github.com/csteinmetz1/pyloudnorm/blob/
         // 6baa64d59b7794bc812e124438692e7fd2e65c0c/pyloudnorm/iirfilter.py#L134-L143.
-        let k = (f32::consts::PI * center_hz / sample_rate_hz).tan();
-        let vh = 10.0_f32.powf(gain_db / 20.0);
+        let k = (f64::consts::PI * center_hz / sample_rate_hz).tan();
+        let vh = 10.0_f64.powf(gain_db / 20.0);
         let vb = vh.powf(0.499666774155);
         let a0 = 1.0 + k / q + k * k;
         Filter {
@@ -92,7 +280,17 @@ impl Filter {
     }
 
     /// Stage 2 of th BS.1770-4 pre-filter.
-    pub fn high_pass(sample_rate_hz: f32) -> Filter {
+    ///
+    /// Like `high_shelf`, this uses precomputed coefficients when available.
+    pub fn high_pass(sample_rate_hz: f64) -> Filter {
+        match Filter::k_weighting_table(sample_rate_hz) {
+            Some((_, stage2)) => Filter::from_coefficients(stage2),
+            None => Filter::compute_high_pass(sample_rate_hz),
+        }
+    }
+
+    /// Compute the coefficients of stage 2 of the BS.1770-4 pre-filter.
+    fn compute_high_pass(sample_rate_hz: f64) -> Filter {
         // Coefficients taken from https://github.com/csteinmetz1/pyloudnorm/blob/
         // 6baa64d59b7794bc812e124438692e7fd2e65c0c/pyloudnorm/meter.py#L135-L136.
         let q = 0.5003270373253953;
@@ -100,7 +298,7 @@ impl Filter {
 
         // Formula taken from https://github.com/csteinmetz1/pyloudnorm/blob/
         // 6baa64d59b7794bc812e124438692e7fd2e65c0c/pyloudnorm/iirfilter.py#L145-L151
-        let k = (f32::consts::PI * center_hz / sample_rate_hz).tan();
+        let k = (f64::consts::PI * center_hz / sample_rate_hz).tan();
         Filter {
             a1:  2.0 * (k * k - 1.0) / (1.0 + k / q + k * k),
             a2: (1.0 - k / q + k * k) / (1.0 + k / q + k * k),
@@ -113,9 +311,81 @@ impl Filter {
         }
     }
 
+    /// A filter that passes its input unchanged.
+    pub fn identity() -> Filter {
+        Filter {
+            a1: 0.0, a2: 0.0,
+            b0: 1.0, b1: 0.0, b2: 0.0,
+
+            x1: 0.0, x2: 0.0,
+            y1: 0.0, y2: 0.0,
+        }
+    }
+
+    /// Discretize the analog filter (b0 s² + b1 s + b2) / (s² + a1 s + a2) with the bilinear transform.
+    fn bilinear(b: [f64; 3], a: [f64; 2], sample_rate_hz: f64) -> Filter {
+        let k = 2.0 * sample_rate_hz;
+        let kk = k * k;
+        let a0 = kk + a[0] * k + a[1];
+        Filter {
+            b0: (b[0] * kk + b[1] * k + b[2]) / a0,
+            b1: 2.0 * (b[2] - b[0] * kk) / a0,
+            b2: (b[0] * kk - b[1] * k + b[2]) / a0,
+            a1: 2.0 * (a[1] - kk) / a0,
+            a2: (kk - a[0] * k + a[1]) / a0,
+
+            x1: 0.0, x2: 0.0,
+            y1: 0.0, y2: 0.0,
+        }
+    }
+
+    /// The three stages of an A-weighting filter, per IEC 61672-1.
+    ///
+    /// The analog filter has two poles at 20.6 Hz, one at 107.7 Hz, one at
+    /// 737.9 Hz, and two at 12194 Hz, and four zeros at 0 Hz. The pole
+    /// frequencies are prewarped, so the bilinear transform keeps them in place.
+    /// The gain is normalized to 0 dB at 1 kHz.
+    pub fn a_weighting(sample_rate_hz: f64) -> [Filter; 3] {
+        let omega = |f: f64| 2.0 * sample_rate_hz * (f64::consts::PI * f / sample_rate_hz).tan();
+        let w1 = omega(20.598997);
+        let w2 = omega(107.65265);
+        let w3 = omega(737.86223);
+        let w4 = omega(12194.217);
+        let mut stages = [
+            Filter::bilinear([1.0, 0.0, 0.0], [2.0 * w1, w1 * w1], sample_rate_hz),
+            Filter::bilinear([1.0, 0.0, 0.0], [w2 + w3, w2 * w3], sample_rate_hz),
+            Filter::bilinear([0.0, 0.0, w4 * w4], [2.0 * w4, w4 * w4], sample_rate_hz),
+        ];
+        let gain: f64 = stages.iter().map(|f| f.gain_at(1000.0, sample_rate_hz)).product();
+        stages[0].b0 /= gain;
+        stages[0].b1 /= gain;
+        stages[0].b2 /= gain;
+        stages
+    }
+
+    /// Return the magnitude of the frequency response at the given frequency.
+    fn gain_at(&self, frequency_hz: f64, sample_rate_hz: f64) -> f64 {
+        let omega = 2.0 * f64::consts::PI * frequency_hz / sample_rate_hz;
+        let (c1, s1) = (omega.cos(), omega.sin());
+        let (c2, s2) = ((2.0 * omega).cos(), (2.0 * omega).sin());
+        let num_re = self.b0 + self.b1 * c1 + self.b2 * c2;
+        let num_im = -self.b1 * s1 - self.b2 * s2;
+        let den_re = 1.0 + self.a1 * c1 + self.a2 * c2;
+        let den_im = -self.a1 * s1 - self.a2 * s2;
+        ((num_re * num_re + num_im * num_im) / (den_re * den_re + den_im * den_im)).sqrt()
+    }
+
+    /// Clear the past input and output samples, as if no input was fed yet.
+    pub fn reset(&mut self) {
+        self.x1 = 0.0;
+        self.x2 = 0.0;
+        self.y1 = 0.0;
+        self.y2 = 0.0;
+    }
+
     /// Feed the next input sample, get the next output sample.
     #[inline(always)]
-    pub fn apply(&mut self, x0: f32) -> f32 {
+    pub fn apply(&mut self, x0: f64) -> f64 {
         let y0 = 0.0
             + self.b0 * x0
             + self.b1 * self.x1
@@ -132,6 +402,27 @@ impl Filter {
     }
 }
 
+/// The frequency weighting that a meter applies before measuring power.
+///
+/// BS.1770 loudness uses K-weighting, which `ChannelLoudnessMeter::new`
+/// applies. The other weightings are not part of BS.1770, but they allow
+/// measuring for example the equivalent continuous level Leq(A) or Leq(Z) of
+/// environmental noise reports with the same pipeline. Note that
+/// `Power::loudness_lkfs` includes the -0.691 dB offset that is specific to
+/// K-weighting; the level of an A- or Z-weighted measurement in dB relative to
+/// full scale is `10 log10(power)`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Weighting {
+    /// The K-weighting of BS.1770, a high shelf followed by a high-pass filter.
+    K,
+
+    /// A-weighting, as specified in IEC 61672-1.
+    A,
+
+    /// No weighting, also called Z-weighting or zero weighting.
+    Z,
+}
+
 /// Compensated sum, for summing many values of different orders of magnitude
 /// accurately.
 #[derive(Copy, Clone, PartialEq)]
@@ -173,14 +464,18 @@ impl Sum {
 /// K-weighting refers to a high-shelf and high-pass filter that model the
 /// effect that humans perceive a certain amount of power in low frequencies to
 /// be less loud than the same amount of power in higher frequencies. In this
-/// library the `Power` type is used exclusively to refer to power after applying K-weighting.
+/// library the `Power` type refers to power after applying K-weighting, unless
+/// it was measured by a meter with a different `Weighting`, see
+/// `ChannelLoudnessMeter::with_weighting`. Only K-weighted power is loudness.
 ///
 /// The nominal “full scale” is the range [-1.0, 1.0]. Because the power is the
 /// mean square of the samples, if no input samples exceeded the full scale, the
 /// power will be in the range [0.0, 1.0]. However, the power delivered by
 /// multiple channels, which is a weighted sum over individual channel powers,
 /// can exceed this range, because the weighted sum is not normalized.
-#[derive(Copy, Clone, PartialEq, PartialOrd)]
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 pub struct Power(pub f32);
 
 impl Power {
@@ -199,6 +494,153 @@ impl Power {
         // Equation 2 (p.5) of BS.1770-4.
         -0.691 + 10.0 * self.0.log10()
     }
+
+    /// Return the loudness of this window, see `loudness_lkfs`.
+    pub fn loudness(&self) -> Lufs {
+        Lufs(self.loudness_lkfs())
+    }
+}
+
+/// Loudness in Loudness Units relative to Full Scale.
+///
+/// LUFS and LKFS are two names for the same unit. Unlike `Power`, loudness is
+/// on a logarithmic scale, so the difference between two loudness values is a
+/// relative loudness in LU, and adding LU to a loudness value applies a gain.
+///
+/// ```
+/// # use bs1770::{Lu, Lufs, Power};
+/// let measured = Lufs(-18.25);
+/// let target = Lufs(-23.0);
+/// assert_eq!(target - measured, Lu(-4.75));
+/// assert_eq!(measured + Lu(-4.75), target);
+/// assert_eq!(format!("{}", measured), "-18.2 LUFS");
+/// assert_eq!(format!("{:.2}", measured), "-18.25 LUFS");
+///
+/// let power = Power::from(target);
+/// assert!((power.loudness().0 - -23.0).abs() < 1e-4);
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct Lufs(pub f32);
+
+/// An alias for `Lufs`, for those who prefer the name used in BS.1770.
+pub type Lkfs = Lufs;
+
+/// A relative loudness, or a gain, in Loudness Units.
+///
+/// One LU is one dB.
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct Lu(pub f32);
+
+impl From<Power> for Lufs {
+    fn from(power: Power) -> Lufs {
+        power.loudness()
+    }
+}
+
+impl From<Lufs> for Power {
+    fn from(loudness: Lufs) -> Power {
+        Power::from_lkfs(loudness.0)
+    }
+}
+
+impl Sub for Lufs {
+    type Output = Lu;
+
+    fn sub(self, rhs: Lufs) -> Lu {
+        Lu(self.0 - rhs.0)
+    }
+}
+
+impl Add<Lu> for Lufs {
+    type Output = Lufs;
+
+    fn add(self, rhs: Lu) -> Lufs {
+        Lufs(self.0 + rhs.0)
+    }
+}
+
+impl Sub<Lu> for Lufs {
+    type Output = Lufs;
+
+    fn sub(self, rhs: Lu) -> Lufs {
+        Lufs(self.0 - rhs.0)
+    }
+}
+
+impl Add for Lu {
+    type Output = Lu;
+
+    fn add(self, rhs: Lu) -> Lu {
+        Lu(self.0 + rhs.0)
+    }
+}
+
+impl Sub for Lu {
+    type Output = Lu;
+
+    fn sub(self, rhs: Lu) -> Lu {
+        Lu(self.0 - rhs.0)
+    }
+}
+
+/// Formats with one decimal by default, such as `-23.0 LUFS`.
+impl fmt::Display for Lufs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let precision = f.precision().unwrap_or(1);
+        write!(f, "{:.*} LUFS", precision, self.0)
+    }
+}
+
+/// Formats with one decimal by default, such as `-4.5 LU`.
+impl fmt::Display for Lu {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let precision = f.precision().unwrap_or(1);
+        write!(f, "{:.*} LU", precision, self.0)
+    }
+}
+
+/// A fixed offset between loudness relative to full scale and a calibrated scale.
+///
+/// For acoustic measurements, the level of digital full scale depends on the
+/// microphone and audio interface. After calibrating the chain, for example
+/// with a reference tone of known sound pressure level, the calibration offset
+/// maps loudness in LKFS onto the calibrated scale, such as dB SPL.
+///
+/// The offset only applies to reported values. Gating must be performed on
+/// uncalibrated power, because the gate thresholds of BS.1770 are defined
+/// relative to full scale. Therefore, compute the `Power` as usual, and apply
+/// the calibration when reporting it. This works for any value expressed as
+/// `Power`, whether it is the integrated loudness or the loudness of a window.
+///
+/// ```
+/// # use bs1770::{Calibration, Power};
+/// // A reference tone that measures -20 LKFS was measured at 74 dB SPL.
+/// let calibration = Calibration::new(74.0 - -20.0);
+/// let power = Power::from_lkfs(-26.0);
+/// assert!((calibration.loudness(power) - 68.0).abs() < 1e-4);
+/// ```
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Calibration {
+    /// The offset in dB that is added to the loudness in LKFS.
+    pub offset_db: f32,
+}
+
+impl Calibration {
+    /// Construct a calibration that adds `offset_db` to reported loudness.
+    pub fn new(offset_db: f32) -> Calibration {
+        Calibration { offset_db }
+    }
+
+    /// Return the loudness of `power` on the calibrated scale.
+    ///
+    /// Silence (zero power) remains at -∞.
+    pub fn loudness(&self, power: Power) -> f32 {
+        power.loudness_lkfs() + self.offset_db
+    }
 }
 
 /// A `T` value for non-overlapping windows of audio, 100ms in length.
@@ -210,7 +652,13 @@ impl Power {
 /// windows of 400ms, spaced 100ms apart, to compute instantaneous loudness or
 /// to perform a gated measurement, or they can be combined into even larger
 /// windows for a momentary loudness measurement.
-#[derive(Copy, Clone, Debug)]
+///
+/// With the `serde` feature, `Power` and `Windows100ms` implement `Serialize`
+/// and `Deserialize`, so measured windows can be cached or sent elsewhere.
+/// Both serialize transparently, so windows serialize as a sequence of numbers.
+#[derive(Copy, Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 pub struct Windows100ms<T> {
     pub inner: T
 }
@@ -241,6 +689,168 @@ impl<T> Windows100ms<T> {
     pub fn len(&self) -> usize where T: AsRef<[Power]> {
         self.inner.as_ref().len()
     }
+
+    /// Return the total duration of the windows.
+    pub fn duration(&self) -> Duration where T: AsRef<[Power]> {
+        Duration::from_millis(100 * self.len() as u64)
+    }
+
+    /// Iterate over the windows, paired with the time at which each window starts.
+    ///
+    /// ```
+    /// # use std::time::Duration;
+    /// # use bs1770::{Power, Windows100ms};
+    /// let windows = Windows100ms { inner: vec![Power(0.1), Power(0.2)] };
+    /// let timed: Vec<_> = windows.iter_with_time().collect();
+    /// assert!(timed[0] == (Duration::from_millis(0), Power(0.1)));
+    /// assert!(timed[1] == (Duration::from_millis(100), Power(0.2)));
+    /// ```
+    pub fn iter_with_time(&self) -> impl Iterator<Item = (Duration, Power)> + '_
+    where T: AsRef<[Power]> {
+        self.inner
+            .as_ref()
+            .iter()
+            .enumerate()
+            .map(|(i, &power)| (Duration::from_millis(100 * i as u64), power))
+    }
+
+    /// Return the momentary loudness series, the mean power over a sliding 400ms window.
+    ///
+    /// This is the momentary loudness of EBU R128, and these are also the
+    /// gating blocks of BS.1770-4: 400ms blocks that overlap by 75%. The first
+    /// value covers the first 400ms. When there is less than 400ms of audio,
+    /// the series is empty.
+    ///
+    /// ```
+    /// # use bs1770::{Power, Windows100ms};
+    /// let windows = Windows100ms { inner: vec![Power(0.4), Power(0.0), Power(0.0), Power(0.0), Power(0.0)] };
+    /// let momentary: Vec<Power> = windows.momentary().collect();
+    /// assert_eq!(momentary, vec![Power(0.1), Power(0.0)]);
+    /// ```
+    pub fn momentary(&self) -> impl DoubleEndedIterator<Item = Power> + ExactSizeIterator + '_
+    where T: AsRef<[Power]> {
+        // The sum is written out rather than a fold over an iterator, so the
+        // loop body is straight-line code that is cheap to evaluate.
+        self.inner
+            .as_ref()
+            .windows(4)
+            .map(|w| Power(0.25 * (w[0].0 + w[1].0 + w[2].0 + w[3].0)))
+    }
+
+    /// Return the maximum momentary loudness, the loudest 400ms window.
+    ///
+    /// This is the Max Momentary loudness of EBU R128 and EBU Tech 3341.
+    /// Returns `None` when there is less than 400ms of audio.
+    pub fn max_momentary(&self) -> Option<Power> where T: AsRef<[Power]> {
+        max_power(self.momentary())
+    }
+
+    /// Return the maximum short-term loudness, the loudest 3s window.
+    ///
+    /// This is the Max Short-term loudness of EBU R128 and EBU Tech 3341.
+    /// Returns `None` when there is less than 3s of audio.
+    ///
+    /// ```
+    /// # use bs1770::{Power, Windows100ms};
+    /// let mut windows = Windows100ms { inner: vec![Power::from_lkfs(-23.0); 50] };
+    /// windows.inner[20] = Power::from_lkfs(-3.0);
+    /// let max_momentary = windows.max_momentary().unwrap().loudness_lkfs();
+    /// let max_short_term = windows.max_short_term().unwrap().loudness_lkfs();
+    /// assert!(max_momentary > max_short_term);
+    /// assert!(max_short_term > -23.0);
+    /// ```
+    pub fn max_short_term(&self) -> Option<Power> where T: AsRef<[Power]> {
+        max_power(self.short_term())
+    }
+
+    /// Return the power of the gating blocks that `gated_mean` gates and averages.
+    ///
+    /// Gating blocks are 400ms long and overlap by 75%, so they are the same as
+    /// the momentary loudness series, see `momentary`. Block `i` starts at
+    /// `i * 100ms`. These are exposed so callers can implement custom gating,
+    /// statistics, or visualization on the same data that `gated_mean` uses.
+    ///
+    /// ```
+    /// # use bs1770::{Power, Windows100ms};
+    /// let windows = Windows100ms { inner: vec![Power::from_lkfs(-23.0); 10] };
+    /// let num_loud = windows
+    ///     .gating_blocks()
+    ///     .filter(|&p| p > Power::from_lkfs(-30.0))
+    ///     .count();
+    /// assert_eq!(num_loud, 7);
+    /// ```
+    pub fn gating_blocks(&self) -> impl DoubleEndedIterator<Item = Power> + ExactSizeIterator + '_
+    where T: AsRef<[Power]> {
+        self.momentary()
+    }
+
+    /// Return the short-term loudness series, the mean power over a sliding 3s window.
+    ///
+    /// This is the short-term loudness of EBU R128 and EBU Tech 3341. The
+    /// window slides in steps of 100ms, the first value covers the first 3s.
+    /// When there is less than 3s of audio, the series is empty. The last
+    /// value is the current short-term loudness of a live measurement:
+    ///
+    /// ```
+    /// # use bs1770::{Power, Windows100ms};
+    /// let windows = Windows100ms { inner: vec![Power::from_lkfs(-23.0); 35] };
+    /// assert_eq!(windows.short_term().len(), 6);
+    /// let current = windows.short_term().next_back().unwrap();
+    /// assert!((current.loudness_lkfs() - -23.0).abs() < 1e-3);
+    /// ```
+    pub fn short_term(&self) -> impl DoubleEndedIterator<Item = Power> + ExactSizeIterator + '_
+    where T: AsRef<[Power]> {
+        self.inner
+            .as_ref()
+            .windows(30)
+            .map(|window| Power(window.iter().map(|p| p.0).sum::<f32>() / 30.0))
+    }
+}
+
+impl Windows100ms<Vec<Power>> {
+    /// Append the windows of `other` after these windows.
+    ///
+    /// This is useful for measuring multiple sources as one program, such as
+    /// the tracks of an album.
+    pub fn append(&mut self, other: Windows100ms<&[Power]>) {
+        self.inner.extend_from_slice(other.inner);
+    }
+
+    /// Concatenate a sequence of window series into one.
+    ///
+    /// ```
+    /// # use bs1770::{Power, Windows100ms};
+    /// let track1 = [Power(0.1); 3];
+    /// let track2 = [Power(0.2); 2];
+    /// let album = Windows100ms::concat(&[
+    ///     Windows100ms { inner: &track1[..] },
+    ///     Windows100ms { inner: &track2[..] },
+    /// ]);
+    /// assert_eq!(album.len(), 5);
+    /// ```
+    pub fn concat(parts: &[Windows100ms<&[Power]>]) -> Windows100ms<Vec<Power>> {
+        let mut result = Windows100ms {
+            inner: Vec::with_capacity(parts.iter().map(|w| w.len()).sum()),
+        };
+        for part in parts {
+            result.append(*part);
+        }
+        result
+    }
+}
+
+impl Extend<Power> for Windows100ms<Vec<Power>> {
+    fn extend<I: IntoIterator<Item = Power>>(&mut self, iter: I) {
+        self.inner.extend(iter)
+    }
+}
+
+impl FromIterator<Power> for Windows100ms<Vec<Power>> {
+    fn from_iter<I: IntoIterator<Item = Power>>(iter: I) -> Windows100ms<Vec<Power>> {
+        Windows100ms {
+            inner: iter.into_iter().collect(),
+        }
+    }
 }
 
 /// Measures K-weighted power of non-overlapping 100ms windows of a single channel of audio.
@@ -262,17 +872,15 @@ impl<T> Windows100ms<T> {
 /// `ChannelLoudnessMeter` per channel, and later combine the measured power
 /// with e.g. `reduce_stereo`.
 ///
-/// # Instantaneous loudness
+/// # Momentary loudness
 ///
-/// The instantaneous loudness is the power over a 400ms window, so you can
-/// average four 100ms windows. No special functionality is implemented to help
-/// with that at this time. ([Pull requests would be accepted.][contribute])
+/// The momentary loudness is the power over a 400ms window, the average of
+/// four 100ms windows. `Windows100ms::momentary` returns this series.
 ///
-/// # Momentary loudness
+/// # Short-term loudness
 ///
-/// The momentary loudness is the power over a 3-second window, so you can
-/// average thirty 100ms windows. No special functionality is implemented to
-/// help with that at this time. ([Pull requests would be accepted.][contribute])
+/// The short-term loudness is the power over a 3-second window, the average of
+/// thirty 100ms windows. `Windows100ms::short_term` returns this series.
 ///
 /// # Integrated loudness
 ///
@@ -289,12 +897,29 @@ impl<T> Windows100ms<T> {
 ///     .unwrap_or(bs1770::Power(0.0))
 ///     .loudness_lkfs();
 /// ```
-///
-/// [contribute]: https://github.com/ruuda/bs1770/blob/master/CONTRIBUTING.md
 #[derive(Clone)]
 pub struct ChannelLoudnessMeter {
-    /// The number of samples that fit in 100ms of audio.
-    samples_per_100ms: u32,
+    /// The sample rate of the input, in Hz.
+    sample_rate_hz: u32,
+
+    /// The number of windows per second, 10 for the standard 100ms windows.
+    windows_per_second: u32,
+
+    /// The number of samples in the current window.
+    ///
+    /// When the sample rate is not a multiple of 10 Hz, 100ms is not a whole
+    /// number of samples, and the length of the windows varies by one sample,
+    /// such that on average, windows are exactly 100ms.
+    samples_per_window: u32,
+
+    /// The fractional part of the window boundary, in units of 1/`windows_per_second` sample.
+    ///
+    /// Window `k` ends at sample `(k + 1) * sample_rate_hz / windows_per_second`.
+    /// This is the remainder of that division for the end of the current window.
+    boundary_remainder: u32,
+
+    /// The frequency weighting that the filters implement.
+    weighting: Weighting,
 
     /// Stage 1 filter (head effects, high shelf).
     filter_stage1: Filter,
@@ -302,9 +927,17 @@ pub struct ChannelLoudnessMeter {
     /// Stage 2 filter (high-pass).
     filter_stage2: Filter,
 
+    /// Stage 3 filter, only for weightings that need more than two stages.
+    filter_stage3: Option<Filter>,
+
     /// Sum of the squares over non-overlapping windows of 100ms.
     windows: Windows100ms<Vec<Power>>,
 
+    /// The most recent windows, for the momentary and short-term loudness.
+    ///
+    /// Unlike `windows`, this is not affected by `drain_windows`.
+    ballistics: ballistics::Ballistics,
+
     /// The number of samples in the current unfinished window.
     count: u32,
 
@@ -314,33 +947,118 @@ pub struct ChannelLoudnessMeter {
 
 impl ChannelLoudnessMeter {
     /// Construct a new loudness meter for the given sample rate.
+    ///
+    /// Panics if the sample rate is less than 10 Hz, because then a window of
+    /// 100ms would not contain any samples.
     pub fn new(sample_rate_hz: u32) -> ChannelLoudnessMeter {
+        ChannelLoudnessMeter::with_windows_per_second(sample_rate_hz, 10)
+    }
+
+    /// Construct a new loudness meter, or return an error if the sample rate is invalid.
+    ///
+    /// This is like `new`, but it returns `Error::InvalidSampleRate` instead of
+    /// panicking when the sample rate is less than 10 Hz.
+    pub fn try_new(sample_rate_hz: u32) -> Result<ChannelLoudnessMeter, Error> {
+        if sample_rate_hz < 10 {
+            return Err(Error::InvalidSampleRate(sample_rate_hz))
+        }
+        Ok(ChannelLoudnessMeter::new(sample_rate_hz))
+    }
+
+    /// Construct a loudness meter with windows shorter or longer than 100ms.
+    ///
+    /// BS.1770 builds all measurements on 100ms windows, and `new` uses those.
+    /// For research, or for finer-grained loudness traces for visualization,
+    /// this meter instead produces `windows_per_second` windows per second,
+    /// for example 100 for 10ms windows. Get them with `as_windows` or
+    /// `into_windows`, and use the `blocks` module to form blocks and gate
+    /// them. The methods that assume 100ms windows, such as `momentary` and
+    /// `as_100ms_windows`, panic on such a meter.
+    ///
+    /// Panics if the sample rate is less than `windows_per_second`, because then
+    /// a window would not contain any samples.
+    pub fn with_windows_per_second(sample_rate_hz: u32, windows_per_second: u32) -> ChannelLoudnessMeter {
+        assert!(windows_per_second > 0, "Need at least one window per second.");
+        assert!(sample_rate_hz >= windows_per_second, "Sample rate must be at least one sample per window.");
         ChannelLoudnessMeter {
-            samples_per_100ms: sample_rate_hz / 10,
-            filter_stage1: Filter::high_shelf(sample_rate_hz as f32),
-            filter_stage2: Filter::high_pass(sample_rate_hz as f32),
+            sample_rate_hz,
+            windows_per_second,
+            samples_per_window: sample_rate_hz / windows_per_second,
+            boundary_remainder: sample_rate_hz % windows_per_second,
+            weighting: Weighting::K,
+            filter_stage1: Filter::high_shelf(sample_rate_hz as f64),
+            filter_stage2: Filter::high_pass(sample_rate_hz as f64),
+            filter_stage3: None,
             windows: Windows100ms::new(),
+            ballistics: ballistics::Ballistics::new(),
             count: 0,
             square_sum: Sum::zero(),
         }
     }
 
+    /// Construct a meter that applies a different frequency weighting than K-weighting.
+    ///
+    /// The power that this meter measures is not loudness as defined by
+    /// BS.1770, see `Weighting`.
+    ///
+    /// ```
+    /// # use bs1770::{ChannelLoudnessMeter, Weighting};
+    /// let mut meter = ChannelLoudnessMeter::with_weighting(48_000, Weighting::A);
+    /// meter.push((0..48_000).map(|i| (i as f32 * 0.05).sin()));
+    /// let windows = meter.as_100ms_windows();
+    /// let mean: f32 = windows.inner.iter().map(|p| p.0).sum::<f32>() / windows.len() as f32;
+    /// println!("Leq(A): {:.1} dBFS", 10.0 * mean.log10());
+    /// ```
+    ///
+    /// Panics if the sample rate is less than 10 Hz.
+    pub fn with_weighting(sample_rate_hz: u32, weighting: Weighting) -> ChannelLoudnessMeter {
+        let mut meter = ChannelLoudnessMeter::new(sample_rate_hz);
+        meter.weighting = weighting;
+        meter.init_filters();
+        meter
+    }
+
+    /// Construct the filters for the weighting at the current sample rate.
+    fn init_filters(&mut self) {
+        let sample_rate_hz = self.sample_rate_hz as f64;
+        match self.weighting {
+            Weighting::K => {
+                self.filter_stage1 = Filter::high_shelf(sample_rate_hz);
+                self.filter_stage2 = Filter::high_pass(sample_rate_hz);
+                self.filter_stage3 = None;
+            }
+            Weighting::A => {
+                let [stage1, stage2, stage3] = Filter::a_weighting(sample_rate_hz);
+                self.filter_stage1 = stage1;
+                self.filter_stage2 = stage2;
+                self.filter_stage3 = Some(stage3);
+            }
+            Weighting::Z => {
+                self.filter_stage1 = Filter::identity();
+                self.filter_stage2 = Filter::identity();
+                self.filter_stage3 = None;
+            }
+        }
+    }
+
+    /// Return the frequency weighting that this meter applies.
+    pub fn weighting(&self) -> Weighting {
+        self.weighting
+    }
+
     /// Feed input samples for loudness analysis.
     ///
     /// # Full scale
     ///
-    /// Full scale for the input samples is the interval [-1.0, 1.0]. If your
-    /// input consists of signed integer samples, you can convert as follows:
+    /// Full scale for the input samples is the interval [-1.0, 1.0]. For
+    /// signed integer samples, use `push_i16` or `push_i32`, which normalize
+    /// the samples to full scale.
     ///
-    /// ```
-    /// # let mut meter = bs1770::ChannelLoudnessMeter::new(44_100);
-    /// # let bits_per_sample = 16_usize;
-    /// # let samples = &[0_i16];
-    /// // Note that the maximum amplitude is `1 << (bits_per_sample - 1)`,
-    /// // one bit is the sign bit.
-    /// let normalizer = 1.0 / (1_u64 << (bits_per_sample - 1)) as f32;
-    /// meter.push(samples.iter().map(|&s| s as f32 * normalizer));
-    /// ```
+    /// Floating-point input, such as 32-bit float WAV, is by convention already
+    /// normalized to full scale, and should be passed in as-is. Samples outside
+    /// of the interval [-1.0, 1.0] are not clipped: they contribute their full
+    /// power, so a signal that exceeds full scale can measure louder than
+    /// 0 LKFS.
     ///
     /// # Repeated calls
     ///
@@ -362,49 +1080,486 @@ impl ChannelLoudnessMeter {
     /// assert_eq!(meter.as_100ms_windows().len(), 1);
     /// ```
     pub fn push<I: Iterator<Item = f32>>(&mut self, samples: I) {
-        let normalizer = 1.0 / self.samples_per_100ms as f32;
-
-        // LLVM, if you could go ahead and inline those apply calls, and then
-        // unroll and vectorize the loop, that'd be terrific.
-        for x in samples {
-            let y = self.filter_stage1.apply(x);
-            let z = self.filter_stage2.apply(y);
-
-            self.square_sum.add(z * z);
-            self.count += 1;
-
-            // TODO: Should this branch be marked cold?
-            if self.count == self.samples_per_100ms {
-                let mean_squares = Power(self.square_sum.sum * normalizer);
-                self.windows.inner.push(mean_squares);
-                // We intentionally do not reset the residue. That way, leftover
-                // energy from this window is not lost, so for the file overall,
-                // the sum remains more accurate.
-                self.square_sum.sum = 0.0;
-                self.count = 0;
-            }
-        }
+        self.push_f64(samples.map(|x| x as f64))
     }
 
-    /// Return a reference to the 100ms windows analyzed so far.
-    pub fn as_100ms_windows(&self) -> Windows100ms<&[Power]> {
-        self.windows.as_ref()
+    /// Feed 16-bit signed integer samples for loudness analysis.
+    ///
+    /// This is equivalent to `push_i32` with 16 bits per sample.
+    pub fn push_i16<I: Iterator<Item = i16>>(&mut self, samples: I) {
+        self.push_i32(samples.map(i32::from), 16)
     }
 
-    /// Return all 100ms windows analyzed so far.
-    pub fn into_100ms_windows(self) -> Windows100ms<Vec<Power>> {
-        self.windows
+    /// Feed signed integer samples with the given bit depth for loudness analysis.
+    ///
+    /// The samples are normalized to full scale. The maximum amplitude is
+    /// `1 << (bits_per_sample - 1)`, because one bit is the sign bit. For
+    /// example, 24-bit samples as decoded from FLAC or WAV lie in the range
+    /// [-8388608, 8388607], and should be passed with `bits_per_sample` 24.
+    /// The samples are converted to `f64`, which is exact for all bit depths.
+    ///
+    /// ```
+    /// # let mut meter = bs1770::ChannelLoudnessMeter::new(44_100);
+    /// let samples = [0_i32, 1 << 23, -(1 << 23)];
+    /// meter.push_i32(samples.iter().cloned(), 24);
+    /// ```
+    ///
+    /// Panics if `bits_per_sample` is not between 1 and 32 inclusive.
+    pub fn push_i32<I: Iterator<Item = i32>>(&mut self, samples: I, bits_per_sample: u32) {
+        assert!((1..=32).contains(&bits_per_sample), "Bits per sample must be between 1 and 32.");
+        let normalizer = 1.0 / (1_u64 << (bits_per_sample - 1)) as f64;
+        self.push_f64(samples.map(|s| s as f64 * normalizer))
     }
-}
 
-/// Combine power for multiple channels by taking a weighted sum.
-///
-/// Note that BS.1770-4 defines power for a multi-channel signal as a weighted
-/// sum over channels which is not normalized. This means that a stereo signal
-/// is inherently louder than a mono signal. For a mono signal played back on
-/// stereo speakers, you should therefore still apply `reduce_stereo`, passing
-/// in the same signal for both channels.
-pub fn reduce_stereo(
+    /// Feed input samples for loudness analysis, in double precision.
+    ///
+    /// This is like `push`, but for samples that are already `f64`, such as
+    /// the output of a mixing or resampling stage that works in `f64`. The
+    /// filters always run in `f64`, so this avoids rounding the input to `f32`
+    /// before filtering. Full scale is the interval [-1.0, 1.0], as for `push`,
+    /// and calls to `push` and `push_f64` can be mixed.
+    pub fn push_f64<I: Iterator<Item = f64>>(&mut self, samples: I) {
+        let mut samples = samples;
+
+        loop {
+            // Process samples up to the end of the current window, or until
+            // the input is exhausted, whichever comes first. This keeps the
+            // window bookkeeping out of the inner loop.
+            let num_left = (self.samples_per_window - self.count) as usize;
+            let mut num_processed = 0_u32;
+
+            // LLVM, if you could go ahead and inline those apply calls, and then
+            // unroll and vectorize the loop, that'd be terrific.
+            match self.filter_stage3 {
+                None => for x in samples.by_ref().take(num_left) {
+                    let y = self.filter_stage1.apply(x);
+                    let z = self.filter_stage2.apply(y);
+                    self.square_sum.add((z * z) as f32);
+                    num_processed += 1;
+                }
+                Some(ref mut filter_stage3) => for x in samples.by_ref().take(num_left) {
+                    let y = self.filter_stage1.apply(x);
+                    let z = filter_stage3.apply(self.filter_stage2.apply(y));
+                    self.square_sum.add((z * z) as f32);
+                    num_processed += 1;
+                }
+            }
+
+            self.count += num_processed;
+
+            if self.count < self.samples_per_window {
+                // The input ran out before the window was complete.
+                break
+            }
+
+            self.complete_window();
+        }
+    }
+
+    /// Feed a slice of input samples for loudness analysis.
+    ///
+    /// This is equivalent to `push`, and produces the same windows, but it is
+    /// often faster. Because the length of the slice is known, the samples can
+    /// be split at the window boundaries up front, and the inner loop over the
+    /// samples of a window contains only the filters and the sum, which the
+    /// compiler can unroll. Prefer this method when the samples are in memory.
+    ///
+    /// ```
+    /// # use bs1770::ChannelLoudnessMeter;
+    /// let samples: Vec<f32> = (0..48_000).map(|i| 0.1 * (i as f32 * 0.05).sin()).collect();
+    /// let mut meter = ChannelLoudnessMeter::new(48_000);
+    /// meter.push_slice(&samples);
+    /// assert_eq!(meter.as_100ms_windows().len(), 10);
+    /// ```
+    pub fn push_slice(&mut self, samples: &[f32]) {
+        let mut samples = samples;
+        while !samples.is_empty() {
+            let num_left = (self.samples_per_window - self.count) as usize;
+            let (window, rest) = samples.split_at(num_left.min(samples.len()));
+            self.filter_window(window);
+            self.count += window.len() as u32;
+            samples = rest;
+
+            if self.count == self.samples_per_window {
+                self.complete_window();
+            }
+        }
+    }
+
+    /// Filter samples, and add their squares to the sum of the current window.
+    ///
+    /// The samples must not extend past the end of the current window. The
+    /// filters and sum are copied into locals for the duration of the loop,
+    /// so the compiler can keep them in registers.
+    #[inline]
+    fn filter_window(&mut self, window: &[f32]) {
+        let mut stage1 = self.filter_stage1.clone();
+        let mut stage2 = self.filter_stage2.clone();
+        let mut square_sum = self.square_sum;
+
+        match self.filter_stage3 {
+            None => for &x in window {
+                let z = stage2.apply(stage1.apply(x as f64));
+                square_sum.add((z * z) as f32);
+            }
+            Some(ref mut stage3) => for &x in window {
+                let z = stage3.apply(stage2.apply(stage1.apply(x as f64)));
+                square_sum.add((z * z) as f32);
+            }
+        }
+
+        self.filter_stage1 = stage1;
+        self.filter_stage2 = stage2;
+        self.square_sum = square_sum;
+    }
+
+    /// Append the current window, after all of its samples have been processed.
+    fn complete_window(&mut self) {
+        let normalizer = 1.0 / self.samples_per_window as f32;
+        let mean_squares = Power(self.square_sum.sum * normalizer);
+        self.windows.inner.push(mean_squares);
+        self.ballistics.push(mean_squares);
+        // We intentionally do not reset the residue. That way, leftover
+        // energy from this window is not lost, so for the file overall,
+        // the sum remains more accurate.
+        self.square_sum.sum = 0.0;
+        self.count = 0;
+
+        // Determine the length of the next window, carrying over the fraction
+        // of a sample, so the windows do not drift from real time.
+        let end = self.boundary_remainder + self.sample_rate_hz;
+        self.samples_per_window = end / self.windows_per_second;
+        self.boundary_remainder = end % self.windows_per_second;
+    }
+
+    /// Return whether two meters are at the same position in the same window structure.
+    fn is_aligned_with(&self, other: &ChannelLoudnessMeter) -> bool {
+        let key = |m: &ChannelLoudnessMeter| {
+            (m.sample_rate_hz, m.weighting, m.windows_per_second, m.samples_per_window, m.boundary_remainder, m.count)
+        };
+        key(self) == key(other)
+    }
+
+    /// Continue the measurement with samples at a different sample rate.
+    ///
+    /// This is useful for measuring a sequence of sources as one program, for
+    /// example a podcast assembled from segments recorded at different sample
+    /// rates. The windows of the new segment are appended to the windows
+    /// analyzed so far, so they form a single timeline.
+    ///
+    /// The filter state is reset, because the new source is not a continuation
+    /// of the previous signal. Samples that did not fill a full 100ms window
+    /// yet are not discarded: the remainder of the window is filled with
+    /// samples at the new sample rate.
+    ///
+    /// ```
+    /// # use std::iter;
+    /// # use bs1770::ChannelLoudnessMeter;
+    /// let mut meter = ChannelLoudnessMeter::new(44_100);
+    /// meter.push(iter::repeat(0.0).take(2_205));
+    /// assert_eq!(meter.as_100ms_windows().len(), 0);
+    ///
+    /// // 50ms at 44.1 kHz, followed by 50ms at 48 kHz, make up one window.
+    /// meter.set_sample_rate(48_000);
+    /// meter.push(iter::repeat(0.0).take(2_400));
+    /// assert_eq!(meter.as_100ms_windows().len(), 1);
+    /// ```
+    ///
+    /// Panics if the sample rate is less than 10 Hz, or less than the number of
+    /// windows per second for a meter constructed with `with_windows_per_second`.
+    pub fn set_sample_rate(&mut self, sample_rate_hz: u32) {
+        assert!(sample_rate_hz >= self.windows_per_second, "Sample rate must be at least one sample per window.");
+        let old_samples_per_window = self.samples_per_window as u64;
+        let new_samples_per_window = (sample_rate_hz / self.windows_per_second) as u64;
+
+        // Express the unfinished window in samples at the new rate. The sum of
+        // squares is scaled by the same factor, so the mean over the full
+        // window weighs both parts by their duration.
+        let scale = new_samples_per_window as f32 / old_samples_per_window as f32;
+        let count = (self.count as u64 * new_samples_per_window + old_samples_per_window / 2)
+            / old_samples_per_window;
+        self.square_sum.sum *= scale;
+        self.square_sum.residue *= scale;
+        // Because of rounding, the window could become full. Leave at least
+        // one sample, so the window gets completed by the next `push`.
+        self.count = count.min(new_samples_per_window - 1) as u32;
+
+        self.sample_rate_hz = sample_rate_hz;
+        self.samples_per_window = new_samples_per_window as u32;
+        self.boundary_remainder = sample_rate_hz % self.windows_per_second;
+        self.init_filters();
+    }
+
+    /// Discard all state, to start a new measurement at the same sample rate.
+    ///
+    /// This clears the filter state, the unfinished window, and the windows
+    /// analyzed so far, so the meter behaves like a newly constructed one. It
+    /// retains the memory allocated for the windows, so reusing a meter for
+    /// many files avoids reallocating.
+    pub fn reset(&mut self) {
+        self.samples_per_window = self.sample_rate_hz / self.windows_per_second;
+        self.boundary_remainder = self.sample_rate_hz % self.windows_per_second;
+        self.filter_stage1.reset();
+        self.filter_stage2.reset();
+        if let Some(filter_stage3) = self.filter_stage3.as_mut() {
+            filter_stage3.reset();
+        }
+        self.windows.inner.clear();
+        self.ballistics.clear();
+        self.count = 0;
+        self.square_sum = Sum::zero();
+    }
+
+    /// Return the momentary loudness of this channel, the mean power over the last 400ms.
+    ///
+    /// This can be polled while samples are streaming in, to drive a live
+    /// meter. Only complete 100ms windows are taken into account, so the value
+    /// changes every 100ms. Returns `None` until 400ms of audio has been pushed.
+    /// The meter keeps the most recent windows for this separately, so the
+    /// value is unaffected by `drain_windows`.
+    ///
+    /// This is the power of a single channel. For a multi-channel signal, the
+    /// loudness is the weighted sum over channels, see `reduce_stereo`, or use
+    /// `program::ProgramMeter` on the combined windows.
+    ///
+    /// ```
+    /// # use std::iter;
+    /// # use bs1770::ChannelLoudnessMeter;
+    /// let mut meter = ChannelLoudnessMeter::new(48_000);
+    /// meter.push(iter::repeat(0.1).take(14_400));
+    /// assert!(meter.momentary().is_none());
+    /// meter.push(iter::repeat(0.1).take(4_800));
+    /// assert!(meter.momentary().is_some());
+    /// assert!(meter.short_term().is_none());
+    /// ```
+    pub fn momentary(&self) -> Option<Power> {
+        self.assert_100ms_windows();
+        self.ballistics.reading().momentary
+    }
+
+    /// Return the short-term loudness of this channel, the mean power over the last 3s.
+    ///
+    /// Like `momentary`, this can be polled while samples are streaming in.
+    /// Returns `None` until 3s of audio has been pushed.
+    pub fn short_term(&self) -> Option<Power> {
+        self.assert_100ms_windows();
+        self.ballistics.reading().short_term
+    }
+
+    /// Panic if the windows of this meter are not 100ms long.
+    fn assert_100ms_windows(&self) {
+        assert_eq!(
+            self.windows_per_second, 10,
+            "Windows are not 100ms long, use as_windows or into_windows instead."
+        );
+    }
+
+    /// Return a reference to the 100ms windows analyzed so far.
+    ///
+    /// Panics if the meter was constructed with `with_windows_per_second` for
+    /// windows other than 100ms, use `as_windows` for those.
+    pub fn as_100ms_windows(&self) -> Windows100ms<&[Power]> {
+        self.assert_100ms_windows();
+        self.windows.as_ref()
+    }
+
+    /// Return all 100ms windows analyzed so far.
+    ///
+    /// Panics if the windows are not 100ms long, like `as_100ms_windows`.
+    pub fn into_100ms_windows(self) -> Windows100ms<Vec<Power>> {
+        self.assert_100ms_windows();
+        self.windows
+    }
+
+    /// Return a reference to the windows analyzed so far, of any length.
+    pub fn as_windows(&self) -> blocks::Windows<&[Power]> {
+        blocks::Windows {
+            inner: &self.windows.inner[..],
+            windows_per_second: self.windows_per_second,
+        }
+    }
+
+    /// Return all windows analyzed so far, of any length.
+    pub fn into_windows(self) -> blocks::Windows<Vec<Power>> {
+        blocks::Windows {
+            inner: self.windows.inner,
+            windows_per_second: self.windows_per_second,
+        }
+    }
+
+    /// Remove the 100ms windows analyzed so far from the meter, and return them.
+    ///
+    /// The meter keeps every window, so a meter that runs indefinitely, such
+    /// as a monitor for a 24/7 stream, would grow without bound. Calling this
+    /// periodically hands the completed windows to the caller, which can feed
+    /// them into its own gating or rolling structures, while the meter keeps
+    /// its filter state and the unfinished window, so the measurement continues
+    /// seamlessly. Concatenating the drained windows yields the same windows as
+    /// a meter that was never drained.
+    ///
+    /// The momentary and short-term loudness of the meter are unaffected,
+    /// the meter keeps the windows they need separately.
+    ///
+    /// ```
+    /// # use std::iter;
+    /// # use bs1770::{ChannelLoudnessMeter, Windows100ms};
+    /// let mut meter = ChannelLoudnessMeter::new(48_000);
+    /// let mut history = Windows100ms::new();
+    /// for _ in 0..10 {
+    ///     meter.push(iter::repeat(0.1).take(4_000));
+    ///     history.append(meter.drain_windows().as_ref());
+    /// }
+    /// assert_eq!(meter.as_100ms_windows().len(), 0);
+    /// assert_eq!(history.len(), 8);
+    /// ```
+    ///
+    /// Panics if the windows are not 100ms long, like `as_100ms_windows`.
+    pub fn drain_windows(&mut self) -> Windows100ms<Vec<Power>> {
+        self.assert_100ms_windows();
+        // Drain rather than take the vector, so the meter keeps its allocation.
+        self.windows.inner.drain(..).collect()
+    }
+}
+
+/// Feed the samples of the two channels of a stereo signal to their meters.
+///
+/// This is equivalent to calling `push` on both meters, but it filters both
+/// channels at once. On x86_64 it uses SSE2 to run the filters of the two
+/// channels in the two lanes of a vector register, which is about twice as
+/// fast as filtering the channels one after another. On other platforms it
+/// falls back to the scalar filter. The result is identical in either case.
+///
+/// The two meters must have the same sample rate, and they must have been fed
+/// the same number of samples before. This holds when they are only fed
+/// through `push_stereo`, or when they were fed equal-length batches.
+///
+/// ```
+/// # use bs1770::ChannelLoudnessMeter;
+/// let mut left_meter = ChannelLoudnessMeter::new(44_100);
+/// let mut right_meter = ChannelLoudnessMeter::new(44_100);
+/// let left = vec![0.25; 44_100];
+/// let right = vec![-0.25; 44_100];
+/// bs1770::push_stereo(&mut left_meter, &mut right_meter, &left, &right);
+/// assert_eq!(left_meter.as_100ms_windows().len(), 10);
+/// ```
+pub fn push_stereo(
+    left_meter: &mut ChannelLoudnessMeter,
+    right_meter: &mut ChannelLoudnessMeter,
+    left: &[f32],
+    right: &[f32],
+) {
+    assert_eq!(left.len(), right.len(), "Channels must have the same length.");
+    assert!(
+        left_meter.is_aligned_with(right_meter),
+        "Meters must have the same sample rate and weighting, and be at the same position.",
+    );
+
+    // The vectorized filters implement only two stages.
+    if left_meter.filter_stage3.is_some() {
+        left_meter.push(left.iter().cloned());
+        right_meter.push(right.iter().cloned());
+        return
+    }
+
+    let mut offset = 0;
+    loop {
+        let num_left = (left_meter.samples_per_window - left_meter.count) as usize;
+        let n = num_left.min(left.len() - offset);
+        simd::filter_pair(
+            left_meter,
+            right_meter,
+            &left[offset..offset + n],
+            &right[offset..offset + n],
+        );
+        offset += n;
+        left_meter.count += n as u32;
+        right_meter.count += n as u32;
+
+        if left_meter.count < left_meter.samples_per_window {
+            break
+        }
+
+        left_meter.complete_window();
+        right_meter.complete_window();
+    }
+}
+
+/// Return the number of samples per channel in `duration`, rounded down.
+///
+/// The `batch` module converts `Options::start` and `Options::duration` to
+/// sample indices with it. Convert other time ranges with it too, so that the
+/// same range selects the same samples everywhere.
+///
+/// ```
+/// # use std::time::Duration;
+/// assert_eq!(bs1770::duration_to_samples(Duration::from_millis(1_500), 44_100), 66_150);
+/// assert_eq!(bs1770::duration_to_samples(Duration::from_nanos(22_675), 44_100), 0);
+/// ```
+pub fn duration_to_samples(duration: Duration, sample_rate_hz: u32) -> u64 {
+    let rate = sample_rate_hz as u64;
+    duration.as_secs() * rate + duration.subsec_nanos() as u64 * rate / 1_000_000_000
+}
+
+/// Feed interleaved samples of a multi-channel signal to one meter per channel.
+///
+/// The samples are frames of one sample per meter, in the order of `meters`,
+/// as most decoders produce them. The number of samples must be a multiple of
+/// the number of meters. Pairs of channels are filtered with `push_stereo`
+/// when the meters of the pair are at the same position, which they are when
+/// they are only fed through this function.
+///
+/// ```
+/// # use bs1770::ChannelLoudnessMeter;
+/// let mut meters = vec![ChannelLoudnessMeter::new(44_100); 2];
+/// // Left and right samples alternate.
+/// let samples = vec![0.25, -0.25].repeat(44_100);
+/// bs1770::push_interleaved(&mut meters, &samples);
+/// assert_eq!(meters[1].as_100ms_windows().len(), 10);
+/// ```
+pub fn push_interleaved(meters: &mut [ChannelLoudnessMeter], samples: &[f32]) {
+    let n = meters.len();
+    if n == 0 {
+        assert!(samples.is_empty(), "Need at least one meter for samples.");
+        return
+    }
+    assert_eq!(samples.len() % n, 0, "Samples must consist of complete frames.");
+
+    // De-interleave a bounded number of frames at a time, so we don't need
+    // to allocate buffers proportional to the input.
+    const FRAMES_PER_CHUNK: usize = 1024;
+    let mut left = [0.0_f32; FRAMES_PER_CHUNK];
+    let mut right = [0.0_f32; FRAMES_PER_CHUNK];
+
+    for frames in samples.chunks(FRAMES_PER_CHUNK * n) {
+        let num_frames = frames.len() / n;
+        for (pair_index, pair) in meters.chunks_mut(2).enumerate() {
+            let ch = 2 * pair_index;
+            match pair {
+                [l, r] if l.is_aligned_with(r) => {
+                    for (i, frame) in frames.chunks_exact(n).enumerate() {
+                        left[i] = frame[ch];
+                        right[i] = frame[ch + 1];
+                    }
+                    push_stereo(l, r, &left[..num_frames], &right[..num_frames]);
+                }
+                _ => {
+                    for (offset, meter) in pair.iter_mut().enumerate() {
+                        meter.push(frames.iter().skip(ch + offset).step_by(n).cloned());
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Combine power for multiple channels by taking a weighted sum.
+///
+/// Note that BS.1770-4 defines power for a multi-channel signal as a weighted
+/// sum over channels which is not normalized. This means that a stereo signal
+/// is inherently louder than a mono signal. For a mono signal played back on
+/// stereo speakers, you should therefore still apply `reduce_stereo`, passing
+/// in the same signal for both channels.
+pub fn reduce_stereo(
     left: Windows100ms<&[Power]>,
     right: Windows100ms<&[Power]>,
 ) -> Windows100ms<Vec<Power>> {
@@ -418,6 +1573,20 @@ pub fn reduce_stereo(
     }
 }
 
+/// Version of `reduce_stereo` that returns an error instead of panicking.
+///
+/// Returns `Error::LengthMismatch` when the channels do not have the same
+/// number of windows.
+pub fn try_reduce_stereo(
+    left: Windows100ms<&[Power]>,
+    right: Windows100ms<&[Power]>,
+) -> Result<Windows100ms<Vec<Power>>, Error> {
+    if left.len() != right.len() {
+        return Err(Error::LengthMismatch(left.len(), right.len()))
+    }
+    Ok(reduce_stereo(left, right))
+}
+
 /// In-place version of `reduce_stereo` that stores the result in the former left channel.
 pub fn reduce_stereo_in_place(
     left: Windows100ms<&mut [Power]>,
@@ -429,6 +1598,53 @@ pub fn reduce_stereo_in_place(
     }
 }
 
+/// Combine power for a 5.1 signal by taking a weighted sum, per BS.1770-4.
+///
+/// Table 3 of BS.1770-4 assigns a weight of 1.0 to the left, right, and center
+/// channels, and a weight of 1.41 to the left and right surround channels. The
+/// low-frequency effects (LFE) channel has weight zero, it is not included in
+/// the measurement, so it is not an argument to this function. For other
+/// layouts, see `channels::ChannelMap::reduce`.
+pub fn reduce_surround(
+    left: Windows100ms<&[Power]>,
+    right: Windows100ms<&[Power]>,
+    center: Windows100ms<&[Power]>,
+    left_surround: Windows100ms<&[Power]>,
+    right_surround: Windows100ms<&[Power]>,
+) -> Windows100ms<Vec<Power>> {
+    reduce_channels(
+        &[left, right, center, left_surround, right_surround],
+        &[1.0, 1.0, 1.0, 1.41, 1.41],
+    )
+}
+
+/// Combine power for any number of channels by taking a weighted sum.
+///
+/// This is the general form of `reduce_stereo` and `reduce_surround`, for
+/// layouts such as quad, 7.1, or layouts with height channels. There must be
+/// one weight per channel. Table 3 of BS.1770-4 lists the weights for common
+/// loudspeaker positions, `channels::Channel::weight` returns them.
+pub fn reduce_channels(
+    channels: &[Windows100ms<&[Power]>],
+    weights: &[f32],
+) -> Windows100ms<Vec<Power>> {
+    assert_eq!(channels.len(), weights.len(), "Need one weight per channel.");
+    let len = channels.first().map_or(0, |ch| ch.len());
+    let mut result = vec![Power(0.0); len];
+    for (windows, &weight) in channels.iter().zip(weights) {
+        assert_eq!(windows.len(), len, "Channels must have the same length.");
+        if weight == 0.0 {
+            continue
+        }
+        for (r, p) in result.iter_mut().zip(windows.inner) {
+            r.0 += weight * p.0;
+        }
+    }
+    Windows100ms {
+        inner: result
+    }
+}
+
 /// Perform gating and averaging for a BS.1770-4 integrated loudness measurement.
 ///
 /// The integrated loudness measurement is not just the average power over the
@@ -442,57 +1658,268 @@ pub fn reduce_stereo_in_place(
 /// When no signal remains after applying the gate, this function returns
 /// `None`. In particular, this happens when all of the signal is softer than
 /// -70 LKFS, including a signal that consists of pure silence.
+///
+/// A signal shorter than 400ms, such as a short sound effect, does not fill a
+/// single gating block. In that case, the whole signal is treated as a single
+/// gating block, so the result is its mean power, subject to the absolute
+/// gate. Note that this is not a BS.1770-4 measurement, the standard does not
+/// define the loudness of such short signals.
 pub fn gated_mean(windows_100ms: Windows100ms<&[Power]>) -> Option<Power> {
-    let mut gating_blocks = Vec::with_capacity(windows_100ms.len());
+    gated_mean_with(windows_100ms, GateConfig::default())
+}
 
-    // Stage 1: an absolute threshold of -70 LKFS. (Equation 6, p.6.)
-    let absolute_threshold = Power::from_lkfs(-70.0);
+/// The thresholds of the two gates of an integrated loudness measurement.
+///
+/// The default is the gating of BS.1770-4: an absolute gate at -70 LKFS, and a
+/// relative gate 10 LU below the loudness after absolute gating. Earlier
+/// revisions of the standard and other meters use different thresholds, so
+/// the thresholds can be changed to reproduce those measurements, or to
+/// compare gating strategies. A measurement with non-default thresholds is not
+/// a BS.1770-4 measurement.
+///
+/// ```
+/// # use bs1770::{GateConfig, Power, Windows100ms};
+/// let windows = Windows100ms { inner: vec![Power::from_lkfs(-23.0); 10] };
+/// let config = GateConfig { relative_threshold_lu: -8.0, ..GateConfig::default() };
+/// let power = bs1770::gated_mean_with(windows.as_ref(), config).unwrap();
+/// assert!((power.loudness_lkfs() - -23.0).abs() < 1e-3);
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GateConfig {
+    /// Blocks at or below this loudness are excluded, in LKFS.
+    pub absolute_threshold_lkfs: f32,
 
-    // Iterate over all 400ms windows.
-    for window in windows_100ms.inner.windows(4) {
-        // Note that the sum over channels has already been performed at this point.
-        let gating_block_power = Power(0.25 * window.iter().map(|mean| mean.0).sum::<f32>());
+    /// The relative threshold, in LU relative to the loudness after absolute gating.
+    ///
+    /// Blocks at or below this threshold are excluded. This is negative for
+    /// a threshold below the absolute-gated loudness.
+    pub relative_threshold_lu: f32,
+}
 
-        if gating_block_power > absolute_threshold {
-            gating_blocks.push(gating_block_power);
+impl Default for GateConfig {
+    fn default() -> GateConfig {
+        GateConfig {
+            absolute_threshold_lkfs: -70.0,
+            relative_threshold_lu: -10.0,
         }
     }
+}
+
+/// Perform gating and averaging like `gated_mean`, but with different thresholds.
+pub fn gated_mean_with(windows_100ms: Windows100ms<&[Power]>, config: GateConfig) -> Option<Power> {
+    gated_mean_stats_with(windows_100ms, config).gated_power
+}
+
+/// The intermediate results of the gating in `gated_mean`.
+///
+/// These are useful to investigate why a measurement differs from that of
+/// another meter, such as the ebur128 filter of ffmpeg, which can print the
+/// same values.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GatingStats {
+    /// The number of gating blocks before gating.
+    pub num_blocks: usize,
+
+    /// The number of gating blocks that pass the absolute gate, by default -70 LKFS.
+    pub num_blocks_absolute_gated: usize,
+
+    /// The mean power of the blocks that pass the absolute gate.
+    ///
+    /// This is `None` when no blocks pass the absolute gate.
+    pub absolute_gated_power: Option<Power>,
+
+    /// The threshold of the relative gate, by default 10 LU below the absolute-gated loudness.
+    ///
+    /// This is `None` when no blocks pass the absolute gate. When the relative
+    /// threshold is below the absolute gate, the absolute gate determines which blocks
+    /// pass, but this is still the relative threshold itself.
+    pub relative_threshold: Option<Power>,
+
+    /// The number of gating blocks that pass both gates.
+    pub num_blocks_relative_gated: usize,
+
+    /// The mean power of the blocks that pass both gates, the result of `gated_mean`.
+    pub gated_power: Option<Power>,
+}
+
+/// Perform the gating of `gated_mean`, and return its intermediate results too.
+///
+/// ```
+/// # use bs1770::{Power, Windows100ms};
+/// let windows = Windows100ms { inner: vec![Power::from_lkfs(-23.0); 10] };
+/// let stats = bs1770::gated_mean_stats(windows.as_ref());
+/// assert_eq!(stats.num_blocks, 7);
+/// assert_eq!(stats.num_blocks_relative_gated, 7);
+/// assert_eq!(stats.gated_power, bs1770::gated_mean(windows.as_ref()));
+/// ```
+pub fn gated_mean_stats(windows_100ms: Windows100ms<&[Power]>) -> GatingStats {
+    gated_mean_stats_with(windows_100ms, GateConfig::default())
+}
+
+/// Perform the gating of `gated_mean_with`, and return its intermediate results too.
+pub fn gated_mean_stats_with(windows_100ms: Windows100ms<&[Power]>, config: GateConfig) -> GatingStats {
+    let n_windows = windows_100ms.len();
+
+    // A signal shorter than 400ms is treated as a single gating block, see
+    // `gated_mean`.
+    if n_windows > 0 && n_windows < 4 {
+        let sum_power: f32 = windows_100ms.inner.iter().map(|p| p.0).sum();
+        let block = Power(sum_power / n_windows as f32);
+        return gate_blocks(|| iter::once(block), config)
+    }
 
-    if gating_blocks.len() == 0 {
-        return None;
+    gate_blocks(|| windows_100ms.gating_blocks(), config)
+}
+
+/// Apply the absolute and relative gate to gating blocks, and average the remaining blocks.
+///
+/// Rather than materializing the power of all gating blocks, which for long
+/// recordings takes a lot of memory, we make two passes over the blocks, so
+/// `blocks` is called twice, and it should compute the blocks on the fly.
+pub(crate) fn gate_blocks<F, I>(blocks: F, config: GateConfig) -> GatingStats
+where
+    F: Fn() -> I,
+    I: ExactSizeIterator<Item = Power>,
+{
+    let mut stats = GatingStats {
+        num_blocks: blocks().len(),
+        num_blocks_absolute_gated: 0,
+        absolute_gated_power: None,
+        relative_threshold: None,
+        num_blocks_relative_gated: 0,
+        gated_power: None,
+    };
+
+    // Stage 1: an absolute threshold, -70 LKFS by default. (Equation 6, p.6.)
+    let absolute_threshold = Power::from_lkfs(config.absolute_threshold_lkfs);
+    let (sum_power, n_blocks) = sum_blocks_above(blocks(), absolute_threshold);
+    stats.num_blocks_absolute_gated = n_blocks;
+
+    if n_blocks == 0 {
+        return stats
     }
 
     // Compute the loudness after applying the absolute gate, in order to
     // determine the threshold for the relative gate.
-    let mut sum_power = Sum::zero();
-    for &gating_block_power in &gating_blocks {
-        sum_power.add(gating_block_power.0);
+    let absolute_gated_power = Power(sum_power / (n_blocks as f32));
+    stats.absolute_gated_power = Some(absolute_gated_power);
+
+    // Stage 2: Apply the relative gate. The relative threshold can be lower
+    // than the absolute threshold, in which case the absolute gate still
+    // applies.
+    let relative_threshold = Power::from_lkfs(absolute_gated_power.loudness_lkfs() + config.relative_threshold_lu);
+    stats.relative_threshold = Some(relative_threshold);
+    let threshold = if relative_threshold > absolute_threshold {
+        relative_threshold
+    } else {
+        absolute_threshold
+    };
+    let (sum_power, n_blocks) = sum_blocks_above(blocks(), threshold);
+    stats.num_blocks_relative_gated = n_blocks;
+
+    if n_blocks == 0 {
+        return stats
     }
-    let absolute_gated_power = Power(sum_power.sum / (gating_blocks.len() as f32));
 
-    // Stage 2: Apply the relative gate.
-    let relative_threshold = Power::from_lkfs(absolute_gated_power.loudness_lkfs() - 10.0);
+    stats.gated_power = Some(Power(sum_power / n_blocks as f32));
+    stats
+}
+
+/// The standard EBU R128 measurements of a program, as returned by `analyze`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LoudnessStats {
+    /// The integrated loudness, or `None` when no block passes the gates, see `gated_mean`.
+    pub integrated: Option<Power>,
+
+    /// The loudness range in LU, or `None` when it is undefined, see `lra::loudness_range`.
+    pub loudness_range_lu: Option<f32>,
+
+    /// The maximum momentary loudness, or `None` for less than 400ms of audio.
+    pub max_momentary: Option<Power>,
+
+    /// The maximum short-term loudness, or `None` for less than 3s of audio.
+    pub max_short_term: Option<Power>,
+
+    /// The true peak, the maximum over all channels.
+    pub true_peak: true_peak::Peak,
+}
+
+/// Measure the integrated loudness, loudness range, maxima, and true peak of a program.
+///
+/// The samples of every channel are normalized to the range [-1.0, 1.0], and
+/// all channels have the same length. There must be one weight per channel,
+/// as for `reduce_channels`; `channels::ChannelMap::weights` returns them for
+/// a channel layout. A channel with weight zero, such as the LFE channel, does
+/// not contribute to the loudness, but it does contribute to the true peak.
+///
+/// This measures everything at once, which is convenient when the audio is in
+/// memory. To measure audio as it is decoded, use the meters directly. Panics
+/// if the sample rate is not supported, see `ChannelLoudnessMeter::new`.
+///
+/// ```
+/// let samples: Vec<f32> = (0..48_000 * 5).map(|i| 0.1 * (i as f32 * 0.13).sin()).collect();
+/// let stats = bs1770::analyze(&[&samples], 48_000, &[1.0]);
+/// let integrated = stats.integrated.unwrap().loudness_lkfs();
+/// let max_short_term = stats.max_short_term.unwrap().loudness_lkfs();
+/// assert!((integrated - max_short_term).abs() < 0.1);
+/// assert!(stats.loudness_range_lu.unwrap() < 0.1);
+/// assert!(stats.true_peak.linear() >= 0.1);
+/// ```
+pub fn analyze(samples_per_channel: &[&[f32]], sample_rate_hz: u32, weights: &[f32]) -> LoudnessStats {
+    assert_eq!(samples_per_channel.len(), weights.len(), "Need one weight per channel.");
+    let mut channel_windows = Vec::with_capacity(samples_per_channel.len());
+    let mut peak = 0.0_f32;
+    for &samples in samples_per_channel {
+        let mut meter = ChannelLoudnessMeter::new(sample_rate_hz);
+        meter.push_slice(samples);
+        channel_windows.push(meter.into_100ms_windows());
+
+        let mut peak_meter = true_peak::TruePeakMeter::new();
+        peak_meter.push(samples.iter().cloned());
+        peak = peak.max(peak_meter.peak());
+    }
+
+    let channel_windows: Vec<_> = channel_windows.iter().map(|w| w.as_ref()).collect();
+    let windows = reduce_channels(&channel_windows, weights);
+    LoudnessStats {
+        integrated: gated_mean(windows.as_ref()),
+        loudness_range_lu: lra::loudness_range(windows.as_ref()),
+        max_momentary: windows.max_momentary(),
+        max_short_term: windows.max_short_term(),
+        true_peak: true_peak::Peak(peak),
+    }
+}
+
+/// Return the largest power of the iterator, or `None` if it is empty.
+fn max_power<I: Iterator<Item = Power>>(powers: I) -> Option<Power> {
+    powers.fold(None, |max, p| match max {
+        Some(m) if m >= p => Some(m),
+        _ => Some(p),
+    })
+}
+
+/// Return the sum and count of the power of gating blocks louder than `threshold`.
+fn sum_blocks_above<I: Iterator<Item = Power>>(blocks: I, threshold: Power) -> (f32, usize) {
     let mut sum_power = Sum::zero();
     let mut n_blocks = 0_usize;
-    for &gating_block_power in &gating_blocks {
-        if gating_block_power > relative_threshold {
-            sum_power.add(gating_block_power.0);
+
+    for block_power in blocks {
+        if block_power > threshold {
+            sum_power.add(block_power.0);
             n_blocks += 1;
         }
     }
 
-    if n_blocks == 0 {
-        return None;
-    }
-
-    let relative_gated_power = Power(sum_power.sum / n_blocks as f32);
-    Some(relative_gated_power)
+    (sum_power.sum, n_blocks)
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{ChannelLoudnessMeter, Filter, Power, Windows100ms};
-    use super::{reduce_stereo, gated_mean};
+    use super::{ChannelLoudnessMeter, Error, Filter, Lu, Lufs, Power, Weighting, Windows100ms};
+    use super::{push_interleaved, push_stereo, reduce_channels, reduce_stereo, reduce_surround, try_reduce_stereo};
+    use super::{GateConfig, gated_mean, gated_mean_stats, gated_mean_with};
+    use super::analyze;
 
     #[test]
     fn filter_high_shelf_matches_spec() {
@@ -520,6 +1947,45 @@ mod tests {
         assert!((f.b2 -  1.0).abs() < 1e-6);
     }
 
+    /// Return the magnitude response of the two filter stages combined, in dB.
+    fn k_weighting_response_db(sample_rate_hz: f64, frequency_hz: f64) -> f64 {
+        let omega = 2.0 * std::f64::consts::PI * frequency_hz / sample_rate_hz;
+        let mut magnitude = 1.0;
+        for f in &[Filter::high_shelf(sample_rate_hz), Filter::high_pass(sample_rate_hz)] {
+            // Evaluate the transfer function at z = e^(iω), as (re, im) pairs.
+            let (c1, s1) = (omega.cos(), -omega.sin());
+            let (c2, s2) = ((2.0 * omega).cos(), -(2.0 * omega).sin());
+            let num_re = f.b0 + f.b1 * c1 + f.b2 * c2;
+            let num_im = f.b1 * s1 + f.b2 * s2;
+            let den_re = 1.0 + f.a1 * c1 + f.a2 * c2;
+            let den_im = f.a1 * s1 + f.a2 * s2;
+            magnitude *= (num_re.hypot(num_im)) / (den_re.hypot(den_im));
+        }
+        20.0 * magnitude.log10()
+    }
+
+    #[test]
+    fn filter_response_at_high_sample_rates_matches_48_khz() {
+        // Below a few kHz, where the bilinear transform does not yet warp the
+        // frequency axis noticeably, the response at high sample rates should
+        // match the response at 48 kHz.
+        let frequencies_hz = [20.0, 38.0, 100.0, 500.0, 1_000.0, 1_682.0, 4_000.0];
+        for &sample_rate_hz in &[352_800.0, 384_000.0] {
+            for &frequency_hz in &frequencies_hz {
+                let expected_db = k_weighting_response_db(48_000.0, frequency_hz);
+                let actual_db = k_weighting_response_db(sample_rate_hz, frequency_hz);
+                assert!(
+                    (expected_db - actual_db).abs() < 0.05,
+                    "Response at {} Hz is {:.3} dB at {} Hz sample rate, but {:.3} dB at 48 kHz.",
+                    frequency_hz,
+                    actual_db,
+                    sample_rate_hz,
+                    expected_db,
+                );
+            }
+        }
+    }
+
     fn append_pure_tone(
         samples: &mut Vec<f32>,
         sample_rate_hz: usize,
@@ -569,7 +2035,7 @@ mod tests {
     fn loudness_matches_tech_3341_2016_case_1_and_2() {
         // Case 1 and 2 on p.10 of EBU Tech 3341-2016, a stereo sine wave of
         // 1000 Hz at -23.0 dBFS and -33.0 dBFS for 20 seconds.
-        let sample_rates = [44_100, 48_000, 96_000, 192_000];
+        let sample_rates = [44_100, 48_000, 96_000, 192_000, 352_800, 384_000];
         let amplitudes = [-23.0, -33.0];
         for &sample_rate_hz in &sample_rates {
             for &amplitude_dbfs in &amplitudes {
@@ -609,7 +2075,7 @@ mod tests {
     fn loudness_matches_tech_3341_2016_case_3_and_4_and_5() {
         // Case 3, 4, and 5 on p.10 of EBU Tech 3341-2016. Their expected
         // outputs are the same, but the tones are different.
-        let sample_rates = [44_100, 48_000, 96_000, 192_000];
+        let sample_rates = [44_100, 48_000, 96_000, 192_000, 352_800, 384_000];
         let tones_duration_milliseconds_amplitude_dbfs = [
             &[
                 (10_000, -36.0),
@@ -660,6 +2126,66 @@ mod tests {
         }
     }
 
+    #[test]
+    fn loudness_of_32_bit_integer_samples_matches_float() {
+        let sample_rate_hz = 48_000;
+        let mut samples = Vec::new();
+        append_pure_tone(&mut samples, sample_rate_hz, 1_000, 5_000, -23.0);
+
+        // Quantize to 32-bit integers, and normalize them again the way the
+        // documentation of `push` prescribes.
+        let samples_i32: Vec<i32> = samples
+            .iter()
+            .map(|&x| (x as f64 * (1_u64 << 31) as f64) as i32)
+            .collect();
+        let normalizer = 1.0 / (1_u64 << 31) as f32;
+
+        let mut meter = ChannelLoudnessMeter::new(sample_rate_hz as u32);
+        meter.push(samples_i32.iter().map(|&s| s as f32 * normalizer));
+        let windows = meter.as_100ms_windows();
+        let windows_stereo = reduce_stereo(windows, windows);
+        let power = gated_mean(windows_stereo.as_ref()).unwrap();
+        assert_loudness_in_range_lkfs(power, -23.0, 0.1, "32-bit integer samples");
+    }
+
+    #[test]
+    fn loudness_of_float_samples_beyond_full_scale_is_not_clipped() {
+        // A float signal at +6 dBFS peaks at twice full scale. Such samples
+        // should contribute their full power, rather than being clipped.
+        let sample_rate_hz = 48_000;
+        let mut samples = Vec::new();
+        append_pure_tone(&mut samples, sample_rate_hz, 1_000, 5_000, 6.0);
+        assert!(samples.iter().any(|&x| x > 1.0));
+
+        let mut meter = ChannelLoudnessMeter::new(sample_rate_hz as u32);
+        meter.push(samples.iter().cloned());
+        let windows = meter.as_100ms_windows();
+        let windows_stereo = reduce_stereo(windows, windows);
+        let power = gated_mean(windows_stereo.as_ref()).unwrap();
+        assert_loudness_in_range_lkfs(power, 6.0, 0.1, "+6 dBFS float samples");
+    }
+
+    #[test]
+    fn loudness_is_preserved_across_sample_rate_changes() {
+        let sample_rates = [44_100, 48_000, 32_000, 96_000];
+        let mut meter = ChannelLoudnessMeter::new(sample_rates[0] as u32);
+
+        for &sample_rate_hz in &sample_rates {
+            // Use segments of 2.55s, so sample rate changes happen in the
+            // middle of a window.
+            let mut samples = Vec::new();
+            append_pure_tone(&mut samples, sample_rate_hz, 1_000, 2_550, -23.0);
+            meter.set_sample_rate(sample_rate_hz as u32);
+            meter.push(samples.iter().cloned());
+        }
+
+        let windows = meter.as_100ms_windows();
+        assert_eq!(windows.len(), 102);
+        let windows_stereo = reduce_stereo(windows, windows);
+        let power = gated_mean(windows_stereo.as_ref()).unwrap();
+        assert_loudness_in_range_lkfs(power, -23.0, 0.1, "segments at different sample rates");
+    }
+
     /// Analyze a single channel of a wave file.
     ///
     /// This is a bit inefficient because we have to read the file twice to get
@@ -668,20 +2194,30 @@ mod tests {
         let mut reader = hound::WavReader::open(fname)
             .expect("Failed to open reference file, run ./download_test_data.sh to download it.");
         let spec = reader.spec();
-        // The maximum amplitude is 1 << (bits per sample - 1), because one bit
-        // is the sign bit.
-        let normalizer = 1.0 / (1_u64 << (spec.bits_per_sample - 1)) as f32;
+        let mut meter = ChannelLoudnessMeter::new(spec.sample_rate);
 
         // Step the sampes by 2, because the audio is stereo, skipping `channel`
         // at the start to ensure that we select the right channel.
-        let channel_samples = reader
-            .samples()
-            .skip(channel)
-            .step_by(2)
-            .map(|s: hound::Result<i32>| s.unwrap() as f32 * normalizer);
+        match spec.sample_format {
+            hound::SampleFormat::Float => {
+                // Float samples are already normalized to full scale.
+                let channel_samples = reader
+                    .samples()
+                    .skip(channel)
+                    .step_by(2)
+                    .map(|s: hound::Result<f32>| s.unwrap());
+                meter.push(channel_samples);
+            }
+            hound::SampleFormat::Int => {
+                let channel_samples = reader
+                    .samples()
+                    .skip(channel)
+                    .step_by(2)
+                    .map(|s: hound::Result<i32>| s.unwrap());
+                meter.push_i32(channel_samples, spec.bits_per_sample as u32);
+            }
+        }
 
-        let mut meter = ChannelLoudnessMeter::new(spec.sample_rate);
-        meter.push(channel_samples);
         meter
     }
 
@@ -725,4 +2261,455 @@ mod tests {
             inner: &[below_abs_threshold; 10]
         }).is_none());
     }
+
+    #[test]
+    fn short_term_slides_a_3s_window_in_steps_of_100ms() {
+        let mut windows = Windows100ms { inner: vec![Power(0.0); 40] };
+        windows.inner[5] = Power(3.0);
+        let short_term: Vec<Power> = windows.short_term().collect();
+        assert_eq!(short_term.len(), 11);
+        // The loud window is in the first 6 short-term windows, at 1/30 weight.
+        assert!(short_term[..6].iter().all(|p| (p.0 - 0.1).abs() < 1e-6));
+        assert!(short_term[6..].iter().all(|p| p.0 == 0.0));
+
+        let short = Windows100ms { inner: vec![Power(1.0); 29] };
+        assert_eq!(short.short_term().next(), None);
+    }
+
+    #[test]
+    fn momentary_is_mean_of_four_windows() {
+        let windows = Windows100ms { inner: vec![Power(0.4), Power(0.8), Power(0.0), Power(0.0), Power(1.2)] };
+        let momentary: Vec<Power> = windows.momentary().collect();
+        assert_eq!(momentary.len(), 2);
+        assert!((momentary[0].0 - 0.3).abs() < 1e-6);
+        assert!((momentary[1].0 - 0.5).abs() < 1e-6);
+        assert_eq!(windows.momentary().next_back(), Some(momentary[1]));
+    }
+
+    #[test]
+    fn reduce_surround_weights_surround_channels() {
+        let front = [Power(1.0), Power(2.0)];
+        let surround = [Power(1.0), Power(0.0)];
+        let reduced = reduce_surround(
+            Windows100ms { inner: &front[..] },
+            Windows100ms { inner: &front[..] },
+            Windows100ms { inner: &front[..] },
+            Windows100ms { inner: &surround[..] },
+            Windows100ms { inner: &surround[..] },
+        );
+        assert!((reduced.inner[0].0 - 5.82).abs() < 1e-6);
+        assert!((reduced.inner[1].0 - 6.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn reduce_channels_applies_weights() {
+        let a = [Power(1.0), Power(2.0)];
+        let b = [Power(0.5), Power(0.5)];
+        let reduced = reduce_channels(
+            &[Windows100ms { inner: &a[..] }, Windows100ms { inner: &b[..] }],
+            &[2.0, 0.0],
+        );
+        assert_eq!(reduced.inner, vec![Power(2.0), Power(4.0)]);
+        assert_eq!(reduce_channels(&[], &[]).len(), 0);
+    }
+
+    #[test]
+    fn gated_mean_of_input_shorter_than_400ms_is_ungated_mean() {
+        let windows = [Power::from_lkfs(-20.0), Power::from_lkfs(-30.0)];
+        let power = gated_mean(Windows100ms { inner: &windows[..] }).unwrap();
+        assert!((power.0 - 0.5 * (windows[0].0 + windows[1].0)).abs() < 1e-9);
+
+        let quiet = [Power::from_lkfs(-75.0); 3];
+        assert!(gated_mean(Windows100ms { inner: &quiet[..] }).is_none());
+    }
+
+    #[test]
+    fn push_f64_matches_push() {
+        let samples: Vec<f32> = (0..48_000).map(|i| 0.5 * (i as f32 * 0.05).sin()).collect();
+        let mut meter_f32 = ChannelLoudnessMeter::new(48_000);
+        let mut meter_f64 = ChannelLoudnessMeter::new(48_000);
+        meter_f32.push(samples.iter().cloned());
+        meter_f64.push_f64(samples[..1_000].iter().map(|&x| x as f64));
+        meter_f64.push(samples[1_000..].iter().cloned());
+        assert_eq!(meter_f32.as_100ms_windows().inner, meter_f64.as_100ms_windows().inner);
+    }
+
+    #[test]
+    fn push_stereo_matches_push() {
+        let left: Vec<f32> = (0..30_000).map(|i| 0.5 * (i as f32 * 0.05).sin()).collect();
+        let right: Vec<f32> = (0..30_000).map(|i| 0.3 * (i as f32 * 0.002).cos()).collect();
+        let mut meters = [ChannelLoudnessMeter::new(44_100), ChannelLoudnessMeter::new(44_100)];
+        let mut left_meter = ChannelLoudnessMeter::new(44_100);
+        let mut right_meter = ChannelLoudnessMeter::new(44_100);
+
+        meters[0].push(left.iter().cloned());
+        meters[1].push(right.iter().cloned());
+        // Push in batches that do not align with the windows.
+        for (l, r) in left.chunks(1_000).zip(right.chunks(1_000)) {
+            push_stereo(&mut left_meter, &mut right_meter, l, r);
+        }
+
+        assert_eq!(left_meter.as_100ms_windows().len(), 6);
+        assert_eq!(meters[0].as_100ms_windows().inner, left_meter.as_100ms_windows().inner);
+        assert_eq!(meters[1].as_100ms_windows().inner, right_meter.as_100ms_windows().inner);
+    }
+
+    #[test]
+    fn push_interleaved_matches_push() {
+        let samples: Vec<f32> = (0..5 * 20_000).map(|i| 0.5 * (i as f32 * 0.013).sin()).collect();
+        let mut meters = vec![ChannelLoudnessMeter::new(48_000); 5];
+        let mut expected = meters.clone();
+
+        // Unaligned batches, and one meter that is ahead of the others, so the
+        // first pair cannot use the stereo path, but the second pair can.
+        meters[0].push(samples.iter().take(100).cloned());
+        expected[0].push(samples.iter().take(100).cloned());
+        for frames in samples.chunks(5 * 7_000) {
+            push_interleaved(&mut meters, frames);
+        }
+        for (ch, meter) in expected.iter_mut().enumerate() {
+            meter.push(samples.iter().skip(ch).step_by(5).cloned());
+        }
+
+        for (meter, expected) in meters.iter().zip(&expected) {
+            assert_eq!(meter.as_100ms_windows().inner, expected.as_100ms_windows().inner);
+        }
+    }
+
+    #[test]
+    fn push_i16_and_push_i32_normalize_to_full_scale() {
+        let samples: Vec<i16> = (0..48_000).map(|i| ((i as f32 * 0.05).sin() * 16_000.0) as i16).collect();
+        let mut meter_f32 = ChannelLoudnessMeter::new(48_000);
+        let mut meter_i16 = ChannelLoudnessMeter::new(48_000);
+        let mut meter_i32 = ChannelLoudnessMeter::new(48_000);
+        meter_f32.push_f64(samples.iter().map(|&s| s as f64 / 32_768.0));
+        meter_i16.push_i16(samples.iter().cloned());
+        meter_i32.push_i32(samples.iter().map(|&s| s as i32 * 256), 24);
+        assert_eq!(meter_f32.as_100ms_windows().inner, meter_i16.as_100ms_windows().inner);
+        assert_eq!(meter_f32.as_100ms_windows().inner, meter_i32.as_100ms_windows().inner);
+    }
+
+    #[test]
+    fn windows_do_not_drift_at_sample_rates_not_divisible_by_10() {
+        // At 11025 Hz, 100ms is 1102.5 samples, so one minute is exactly 600
+        // windows, and the windows alternate between 1102 and 1103 samples.
+        let mut meter = ChannelLoudnessMeter::new(11_025);
+        meter.push(vec![0.5; 11_025 * 60 - 1].into_iter());
+        assert_eq!(meter.as_100ms_windows().len(), 599);
+        meter.push(std::iter::once(0.5));
+        assert_eq!(meter.as_100ms_windows().len(), 600);
+
+        let mut meter = ChannelLoudnessMeter::new(11_025);
+        meter.push(vec![0.5; 1_102].into_iter());
+        assert_eq!(meter.as_100ms_windows().len(), 1);
+        meter.push(vec![0.5; 1_102].into_iter());
+        assert_eq!(meter.as_100ms_windows().len(), 1);
+        meter.push(std::iter::once(0.5));
+        assert_eq!(meter.as_100ms_windows().len(), 2);
+    }
+
+    #[test]
+    fn reset_meter_behaves_like_new_meter() {
+        let samples: Vec<f32> = (0..20_000).map(|i| 0.5 * (i as f32 * 0.05).sin()).collect();
+        let mut fresh = ChannelLoudnessMeter::new(11_025);
+        fresh.push(samples.iter().cloned());
+
+        let mut reused = ChannelLoudnessMeter::new(11_025);
+        reused.push(samples.iter().rev().take(12_345).cloned());
+        reused.reset();
+        assert_eq!(reused.as_100ms_windows().len(), 0);
+        reused.push(samples.iter().cloned());
+
+        assert_eq!(fresh.as_100ms_windows().inner, reused.as_100ms_windows().inner);
+    }
+
+    #[test]
+    fn drained_windows_match_windows_of_undrained_meter() {
+        let samples: Vec<f32> = (0..44_100).map(|i| 0.5 * (i as f32 * 0.05).sin()).collect();
+        let mut whole = ChannelLoudnessMeter::new(44_100);
+        whole.push(samples.iter().cloned());
+
+        let mut drained = ChannelLoudnessMeter::new(44_100);
+        let mut windows = Windows100ms::new();
+        for chunk in samples.chunks(1_234) {
+            drained.push(chunk.iter().cloned());
+            windows.append(drained.drain_windows().as_ref());
+            assert_eq!(drained.as_100ms_windows().len(), 0);
+        }
+
+        assert_eq!(windows.inner, whole.as_100ms_windows().inner);
+    }
+
+    #[test]
+    fn momentary_and_short_term_survive_draining() {
+        let samples: Vec<f32> = (0..4 * 44_100).map(|i| 0.5 * (i as f32 * 0.05).sin()).collect();
+        let mut whole = ChannelLoudnessMeter::new(44_100);
+        let mut drained = ChannelLoudnessMeter::new(44_100);
+        for chunk in samples.chunks(4_410) {
+            whole.push(chunk.iter().cloned());
+            drained.push(chunk.iter().cloned());
+            drained.drain_windows();
+            assert_eq!(drained.momentary(), whole.momentary());
+            assert_eq!(drained.short_term(), whole.short_term());
+        }
+        assert!(drained.short_term().is_some());
+
+        drained.reset();
+        assert_eq!(drained.momentary(), None);
+    }
+
+    #[test]
+    fn windows_can_be_collected_and_extended() {
+        let mut windows: Windows100ms<Vec<Power>> = (0..3).map(|i| Power(i as f32)).collect();
+        windows.extend(vec![Power(3.0)]);
+        windows.append(Windows100ms { inner: &[Power(4.0)] });
+        let expected: Vec<Power> = (0..5).map(|i| Power(i as f32)).collect();
+        assert_eq!(windows.inner, expected);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn windows_deserialize_from_a_sequence_of_numbers() {
+        use serde::Deserialize;
+        use serde::de::IntoDeserializer;
+        use serde::de::value::{Error, SeqDeserializer};
+
+        let values = vec![0.5_f32, 0.25];
+        let deserializer: SeqDeserializer<_, Error> = values.into_deserializer();
+        let windows = Windows100ms::<Vec<Power>>::deserialize(deserializer).unwrap();
+        assert_eq!(windows.inner, vec![Power(0.5), Power(0.25)]);
+    }
+
+    #[test]
+    fn gated_mean_gates_the_gating_blocks() {
+        let windows: Windows100ms<Vec<Power>> = (0..200)
+            .map(|i| Power::from_lkfs(if i % 50 < 10 { -60.0 } else { -20.0 - (i % 7) as f32 }))
+            .collect();
+        let blocks: Vec<Power> = windows.gating_blocks().collect();
+        assert_eq!(blocks.len(), 197);
+
+        // Recompute the gated mean from the blocks, with both gates applied by hand.
+        let mean = |threshold: Power| {
+            let passed: Vec<f32> = blocks.iter().filter(|&&p| p > threshold).map(|p| p.0).collect();
+            Power(passed.iter().sum::<f32>() / passed.len() as f32)
+        };
+        let absolute_gated = mean(Power::from_lkfs(-70.0));
+        let expected = mean(Power::from_lkfs(absolute_gated.loudness_lkfs() - 10.0));
+        let actual = gated_mean(windows.as_ref()).unwrap();
+        assert!((actual.0 - expected.0).abs() < 1e-6 * expected.0);
+    }
+
+    #[test]
+    fn gated_mean_stats_reports_both_gates() {
+        // 20 windows at -20 LKFS, then 20 at -40 LKFS, then 20 of silence.
+        let windows: Windows100ms<Vec<Power>> = (0..60)
+            .map(|i| match i / 20 {
+                0 => Power::from_lkfs(-20.0),
+                1 => Power::from_lkfs(-40.0),
+                _ => Power(0.0),
+            })
+            .collect();
+        let stats = gated_mean_stats(windows.as_ref());
+        assert_eq!(stats.num_blocks, 57);
+        // The blocks that overlap the silence by 3 windows are still loud
+        // enough for the absolute gate.
+        assert_eq!(stats.num_blocks_absolute_gated, 40);
+        let threshold = stats.relative_threshold.unwrap().loudness_lkfs();
+        let expected = stats.absolute_gated_power.unwrap().loudness_lkfs() - 10.0;
+        assert!((threshold - expected).abs() < 1e-4);
+        // Only the -20 LKFS blocks and some transition blocks pass the relative gate.
+        assert!(stats.num_blocks_relative_gated > 17 && stats.num_blocks_relative_gated < 25);
+        assert_eq!(stats.gated_power, gated_mean(windows.as_ref()));
+
+        let silence = [Power(0.0); 10];
+        let stats = gated_mean_stats(Windows100ms { inner: &silence[..] });
+        assert_eq!(stats.num_blocks, 7);
+        assert_eq!(stats.num_blocks_absolute_gated, 0);
+        assert_eq!(stats.relative_threshold, None);
+        assert_eq!(stats.gated_power, None);
+    }
+
+    #[test]
+    fn max_momentary_and_short_term_find_loudest_window() {
+        let mut windows: Windows100ms<Vec<Power>> = (0..100).map(|_| Power::from_lkfs(-30.0)).collect();
+        windows.inner[60] = Power::from_lkfs(-10.0);
+        let max_momentary = windows.max_momentary().unwrap();
+        let max_short_term = windows.max_short_term().unwrap();
+        // Every window that contains the loud window is loud.
+        let expected_momentary = Power(0.25 * (Power::from_lkfs(-10.0).0 + 3.0 * Power::from_lkfs(-30.0).0));
+        let expected_short_term = Power((Power::from_lkfs(-10.0).0 + 29.0 * Power::from_lkfs(-30.0).0) / 30.0);
+        assert!((max_momentary.0 - expected_momentary.0).abs() < 1e-6 * expected_momentary.0);
+        assert!((max_short_term.0 - expected_short_term.0).abs() < 1e-6 * expected_short_term.0);
+
+        let short = Windows100ms { inner: vec![Power(0.1); 10] };
+        assert!(short.max_momentary().is_some());
+        assert_eq!(short.max_short_term(), None);
+    }
+
+    #[test]
+    fn gated_mean_with_applies_configured_thresholds() {
+        // Half of the windows at -20 LKFS, half at -32 LKFS.
+        let windows: Windows100ms<Vec<Power>> = (0..200)
+            .map(|i| Power::from_lkfs(if (i / 20) % 2 == 0 { -20.0 } else { -32.0 }))
+            .collect();
+        let default = gated_mean(windows.as_ref()).unwrap();
+        assert_eq!(gated_mean_with(windows.as_ref(), GateConfig::default()), Some(default));
+
+        // With a relative gate of -8 LU, the quiet windows no longer pass.
+        let strict = GateConfig { relative_threshold_lu: -8.0, ..GateConfig::default() };
+        let strict_power = gated_mean_with(windows.as_ref(), strict).unwrap();
+        assert!(strict_power > default);
+        assert!(strict_power.loudness_lkfs() > -21.0);
+
+        // An absolute gate above all windows excludes everything.
+        let silent = GateConfig { absolute_threshold_lkfs: -10.0, ..GateConfig::default() };
+        assert_eq!(gated_mean_with(windows.as_ref(), silent), None);
+    }
+
+    #[test]
+    fn a_weighting_matches_iec_61672_table() {
+        // Nominal A-weighting in dB from IEC 61672-1, for a few frequencies.
+        let table = [
+            (31.5, -39.4), (100.0, -19.1), (500.0, -3.2), (1_000.0, 0.0),
+            (4_000.0, 1.0), (8_000.0, -1.1), (10_000.0, -2.5),
+        ];
+        for &sample_rate_hz in &[44_100.0, 48_000.0, 96_000.0] {
+            let stages = Filter::a_weighting(sample_rate_hz);
+            for &(frequency_hz, expected_db) in &table {
+                let gain: f64 = stages.iter().map(|f| f.gain_at(frequency_hz, sample_rate_hz)).product();
+                let actual_db = 20.0 * gain.log10();
+                // The bilinear transform compresses the response towards the
+                // Nyquist frequency, which the class 1 tolerance allows for. The
+                // table lists nominal frequencies, hence the tolerance below that.
+                let tolerance_db = if frequency_hz > 2_000.0 { 1.0 } else { 0.2 };
+                assert!(
+                    (actual_db - expected_db).abs() < tolerance_db,
+                    "At {} Hz and {} Hz sample rate: expected {:.2} dB, got {:.2} dB.",
+                    frequency_hz, sample_rate_hz, expected_db, actual_db,
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn weightings_measure_expected_power() {
+        // A 1 kHz sine at full scale has a mean square of 0.5, and all
+        // weightings have a gain of 0 dB at 1 kHz, except K-weighting.
+        let sine: Vec<f32> = (0..48_000)
+            .map(|i| (i as f32 * 2.0 * std::f32::consts::PI * 1_000.0 / 48_000.0).sin())
+            .collect();
+        for &weighting in &[Weighting::A, Weighting::Z] {
+            let mut meters = vec![ChannelLoudnessMeter::with_weighting(48_000, weighting); 2];
+            assert_eq!(meters[0].weighting(), weighting);
+            let stereo: Vec<f32> = sine.iter().flat_map(|&x| vec![x, x]).collect();
+            push_interleaved(&mut meters, &stereo);
+            let power = meters[1].as_100ms_windows().inner[9];
+            assert!((power.0 - 0.5).abs() < 1e-3, "{:?}: {:?}", weighting, power);
+        }
+
+        // A 20 Hz tone is attenuated by about 50 dB in A-weighting.
+        let low: Vec<f32> = (0..48_000)
+            .map(|i| (i as f32 * 2.0 * std::f32::consts::PI * 20.0 / 48_000.0).sin())
+            .collect();
+        let mut meter = ChannelLoudnessMeter::with_weighting(48_000, Weighting::A);
+        meter.push(low.iter().cloned());
+        let level_db = 10.0 * (meter.as_100ms_windows().inner[9].0 / 0.5).log10();
+        assert!((level_db - -50.5).abs() < 0.5, "{}", level_db);
+    }
+
+    #[test]
+    fn try_variants_return_errors_instead_of_panicking() {
+        assert_eq!(ChannelLoudnessMeter::try_new(0).err(), Some(Error::InvalidSampleRate(0)));
+        assert_eq!(ChannelLoudnessMeter::try_new(9).err(), Some(Error::InvalidSampleRate(9)));
+        assert!(ChannelLoudnessMeter::try_new(10).is_ok());
+
+        let left = [Power(0.1), Power(0.2)];
+        let right = [Power(0.3)];
+        let result = try_reduce_stereo(Windows100ms { inner: &left }, Windows100ms { inner: &right });
+        assert_eq!(result.err(), Some(Error::LengthMismatch(2, 1)));
+        let result = try_reduce_stereo(Windows100ms { inner: &left }, Windows100ms { inner: &left }).unwrap();
+        assert_eq!(result.inner, vec![Power(0.2), Power(0.4)]);
+    }
+
+    #[test]
+    fn lufs_converts_to_and_from_power() {
+        for &lkfs in &[-70.0, -23.0, -0.691, 3.0] {
+            let power = Power::from(Lufs(lkfs));
+            assert!((Lufs::from(power).0 - lkfs).abs() < 1e-4);
+        }
+        assert_eq!(Power(0.0).loudness(), Lufs(f32::NEG_INFINITY));
+        assert_eq!(format!("{}", Lufs(f32::NEG_INFINITY)), "-inf LUFS");
+        assert_eq!(format!("{}", Lufs(-23.0) - Lufs(-14.0)), "-9.0 LU");
+        assert_eq!(Lufs(-23.0) - Lu(1.5), Lufs(-24.5));
+        assert_eq!(Lu(1.5) + Lu(2.0) - Lu(0.5), Lu(3.0));
+    }
+
+    #[test]
+    fn precomputed_k_weighting_matches_formula() {
+        for &(rate, (stage1, stage2)) in super::K_WEIGHTING_TABLE.iter() {
+            let computed = [
+                Filter::compute_high_shelf(rate as f64),
+                Filter::compute_high_pass(rate as f64),
+            ];
+            for (f, c) in computed.iter().zip(&[stage1, stage2]) {
+                let coefficients = [f.a1, f.a2, f.b0, f.b1, f.b2];
+                for (x, y) in coefficients.iter().zip(c.iter()) {
+                    assert!((x - y).abs() < 1e-6, "Mismatch at {} Hz: {} != {}", rate, x, y);
+                }
+            }
+        }
+
+        // At 48 kHz, the coefficients are exactly those of table 1 of BS.1770-4.
+        let f = Filter::high_shelf(48_000.0);
+        assert_eq!(f.a1, -1.69065929318241);
+        assert_eq!(Filter::high_pass(48_000.0).a2, 0.99007225036621);
+    }
+
+    #[test]
+    fn push_slice_matches_push() {
+        let samples: Vec<f32> = (0..50_000).map(|i| 0.5 * (i as f32 * 0.013).sin()).collect();
+        for &weighting in &[Weighting::K, Weighting::A] {
+            let mut meter_iter = ChannelLoudnessMeter::with_weighting(44_100, weighting);
+            let mut meter_slice = meter_iter.clone();
+            meter_iter.push(samples.iter().cloned());
+            // Chunks that do not align with the windows, and an empty one.
+            meter_slice.push_slice(&samples[..1_234]);
+            meter_slice.push_slice(&[]);
+            for chunk in samples[1_234..].chunks(3_000) {
+                meter_slice.push_slice(chunk);
+            }
+            assert_eq!(meter_slice.as_100ms_windows().len(), 11);
+            assert_eq!(meter_iter.as_100ms_windows().inner, meter_slice.as_100ms_windows().inner);
+        }
+    }
+
+    #[test]
+    fn analyze_matches_separate_measurements() {
+        let left: Vec<f32> = (0..48_000 * 6).map(|i| 0.2 * (i as f32 * 0.031).sin()).collect();
+        let right: Vec<f32> = (0..48_000 * 6).map(|i| if i < 48_000 * 3 { 0.0 } else { 0.6 * (i as f32 * 0.07).sin() }).collect();
+        let stats = analyze(&[&left, &right], 48_000, &[1.0, 1.0]);
+
+        let mut meters = vec![ChannelLoudnessMeter::new(48_000); 2];
+        meters[0].push_slice(&left);
+        meters[1].push_slice(&right);
+        let windows = reduce_stereo(meters[0].as_100ms_windows(), meters[1].as_100ms_windows());
+        assert_eq!(stats.integrated, gated_mean(windows.as_ref()));
+        assert_eq!(stats.loudness_range_lu, crate::lra::loudness_range(windows.as_ref()));
+        assert_eq!(stats.max_momentary, windows.max_momentary());
+        assert_eq!(stats.max_short_term, windows.max_short_term());
+        // The abrupt onset of the right channel overshoots a bit.
+        assert!(stats.true_peak.linear() >= 0.6 && stats.true_peak.linear() < 0.7);
+        assert!(stats.loudness_range_lu.unwrap() > 5.0);
+    }
+
+    #[test]
+    fn analyze_silence_has_no_loudness() {
+        let silence = vec![0.0_f32; 48_000];
+        let stats = analyze(&[&silence, &silence, &silence], 48_000, &[1.0, 1.0, 0.0]);
+        assert_eq!(stats.integrated, None);
+        assert_eq!(stats.loudness_range_lu, None);
+        assert_eq!(stats.max_momentary, Some(Power(0.0)));
+        assert_eq!(stats.max_short_term, None);
+        assert_eq!(stats.true_peak.linear(), 0.0);
+    }
 }
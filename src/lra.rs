@@ -0,0 +1,209 @@
+// BS1770 -- Loudness analysis library conforming to ITU-R BS.1770
+// Copyright 2020 Ruud van Asseldonk
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! Loudness range (LRA), as specified in EBU Tech 3342.
+//!
+//! The loudness range is the spread between the 10th and 95th percentile of
+//! the short-term (3s) loudness, after gating. Computing percentiles exactly
+//! requires retaining every short-term value, which for a program of
+//! unbounded length takes unbounded memory. `LoudnessRangeHistogram` instead
+//! counts the short-term values in bins of 0.1 LU, so it uses a fixed amount
+//! of memory regardless of the duration of the program, at the cost of
+//! quantizing the result to 0.1 LU.
+//!
+//! When all windows are available, such as after measuring a file,
+//! `loudness_range` computes the loudness range exactly.
+
+use alloc::vec::Vec;
+
+use crate::{Power, Windows100ms};
+use crate::histogram::LoudnessHistogram;
+#[cfg(not(feature = "std"))]
+use crate::math::Float;
+
+/// Estimates the loudness range of a program online, with bounded memory.
+///
+/// Feed the short-term loudness every 100ms, for example from
+/// `ProgramMeter::short_term`. Values below the absolute gate of -70 LKFS are
+/// ignored. The relative gate (20 LU below the mean of the remaining values)
+/// is applied when querying the loudness range, so it tracks the program as
+/// it evolves.
+///
+/// ```
+/// # use bs1770::Power;
+/// use bs1770::lra::LoudnessRangeHistogram;
+///
+/// let mut histogram = LoudnessRangeHistogram::new();
+/// for i in 0..100 {
+///     // Short-term loudness that ramps from -30 LKFS to -20.1 LKFS.
+///     histogram.push_short_term(Power::from_lkfs(-30.0 + 0.1 * i as f32));
+/// }
+/// let lra = histogram.loudness_range_lu().unwrap();
+/// assert!((lra - 8.4).abs() < 0.15);
+/// ```
+#[derive(Clone, Debug)]
+pub struct LoudnessRangeHistogram {
+    /// The short-term values that passed the absolute gate.
+    short_term: LoudnessHistogram,
+}
+
+impl Default for LoudnessRangeHistogram {
+    fn default() -> LoudnessRangeHistogram {
+        LoudnessRangeHistogram::new()
+    }
+}
+
+impl LoudnessRangeHistogram {
+    /// Construct an empty histogram.
+    pub fn new() -> LoudnessRangeHistogram {
+        LoudnessRangeHistogram {
+            short_term: LoudnessHistogram::new(),
+        }
+    }
+
+    /// Add one short-term loudness measurement.
+    ///
+    /// EBU Tech 3342 prescribes a short-term value every 100ms or more often.
+    pub fn push_short_term(&mut self, power: Power) {
+        self.short_term.push(power);
+    }
+
+    /// Discard all measurements.
+    pub fn clear(&mut self) {
+        self.short_term.clear();
+    }
+
+    /// Return the number of values that passed the absolute gate.
+    pub fn len(&self) -> u64 {
+        self.short_term.len()
+    }
+
+    /// Return whether no value passed the absolute gate.
+    pub fn is_empty(&self) -> bool {
+        self.short_term.is_empty()
+    }
+
+    /// Return the loudness range in LU.
+    ///
+    /// Returns `None` when no value passed the absolute gate.
+    pub fn loudness_range_lu(&self) -> Option<f32> {
+        let mean_power = self.short_term.mean_power()?;
+        let relative_gate = mean_power.loudness_lkfs() - 20.0;
+
+        // The gate is applied at bin granularity: a bin is included if its
+        // center passes the gate. The percentiles are the values at rank
+        // round((n - 1) * p) in sorted order, as in the reference
+        // implementation of Tech 3342.
+        let high = self.short_term.percentile_lkfs_above(relative_gate, 0.95)?;
+        let low = self.short_term.percentile_lkfs_above(relative_gate, 0.10)?;
+        Some(high - low)
+    }
+}
+
+/// Compute the loudness range (LRA) in LU, per EBU Tech 3342.
+///
+/// The windows are the power of 100ms windows, after the channels have been
+/// combined. The short-term loudness series is gated at -70 LKFS, and then at
+/// 20 LU below the mean power of the remaining values. The loudness range is
+/// the difference between the 10th and 95th percentile of the gated series.
+///
+/// Returns `None` when no short-term value passes the gates, which includes
+/// the case of less than 3 seconds of audio.
+pub fn loudness_range(windows_100ms: Windows100ms<&[Power]>) -> Option<f32> {
+    let absolute_threshold = Power::from_lkfs(-70.0);
+    let mut short_term: Vec<Power> = windows_100ms
+        .short_term()
+        .filter(|&p| p >= absolute_threshold)
+        .collect();
+    if short_term.is_empty() {
+        return None
+    }
+
+    let sum_power: f64 = short_term.iter().map(|p| p.0 as f64).sum();
+    let mean_power = Power((sum_power / short_term.len() as f64) as f32);
+    let relative_threshold = Power::from_lkfs(mean_power.loudness_lkfs() - 20.0);
+    short_term.retain(|&p| p >= relative_threshold);
+    if short_term.is_empty() {
+        return None
+    }
+
+    // Power is monotonic in loudness, so we can sort and index by power. There
+    // are no NaNs, they do not pass the gates.
+    short_term.sort_by(|x, y| x.partial_cmp(y).unwrap());
+    // Percentiles are the values at rank round((n - 1) * p), as in the
+    // reference implementation of Tech 3342.
+    let n = short_term.len();
+    let percentile = |p: f64| short_term[((n - 1) as f64 * p).round() as usize];
+    Some(percentile(0.95).loudness_lkfs() - percentile(0.10).loudness_lkfs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LoudnessRangeHistogram, loudness_range};
+    use crate::{Power, Windows100ms};
+    use crate::compliance::TECH_3342_CASES;
+
+    #[test]
+    fn loudness_range_matches_tech_3342_cases() {
+        for case in TECH_3342_CASES {
+            let windows = case.signal(48_000).windows();
+            let lra = loudness_range(windows.as_ref()).unwrap();
+            assert!(
+                (lra - case.expected).abs() <= case.tolerance_lu,
+                "{}: expected {} LU, got {}.", case.name, case.expected, lra,
+            );
+
+            let mut histogram = LoudnessRangeHistogram::new();
+            for p in windows.short_term() {
+                histogram.push_short_term(p);
+            }
+            let estimate = histogram.loudness_range_lu().unwrap();
+            assert!((estimate - lra).abs() <= 0.15, "Exact {} LU, histogram {}.", lra, estimate);
+        }
+    }
+
+    #[test]
+    fn loudness_range_of_short_or_silent_input_is_none() {
+        let windows = [Power(0.0); 100];
+        assert_eq!(loudness_range(Windows100ms { inner: &windows[..] }), None);
+        let windows = [Power::from_lkfs(-23.0); 29];
+        assert_eq!(loudness_range(Windows100ms { inner: &windows[..] }), None);
+    }
+
+    #[test]
+    fn loudness_range_histogram_ignores_values_below_gates() {
+        let mut histogram = LoudnessRangeHistogram::new();
+        assert_eq!(histogram.loudness_range_lu(), None);
+
+        // Silence does not pass the absolute gate.
+        histogram.push_short_term(Power(0.0));
+        histogram.push_short_term(Power::from_lkfs(-80.0));
+        assert!(histogram.is_empty());
+
+        for _ in 0..50 {
+            histogram.push_short_term(Power::from_lkfs(-20.0));
+            histogram.push_short_term(Power::from_lkfs(-26.0));
+        }
+        // Far below the relative gate, so it should not affect the range.
+        for _ in 0..20 {
+            histogram.push_short_term(Power::from_lkfs(-60.0));
+        }
+
+        let lra = histogram.loudness_range_lu().unwrap();
+        assert!((lra - 6.0).abs() < 0.15, "Unexpected LRA: {}", lra);
+    }
+
+    #[test]
+    fn loudness_range_histogram_clear_discards_values() {
+        let mut histogram = LoudnessRangeHistogram::new();
+        histogram.push_short_term(Power::from_lkfs(-23.0));
+        assert_eq!(histogram.len(), 1);
+        assert_eq!(histogram.loudness_range_lu(), Some(0.0));
+        histogram.clear();
+        assert_eq!(histogram.loudness_range_lu(), None);
+    }
+}
@@ -0,0 +1,59 @@
+// BS1770 -- Loudness analysis library conforming to ITU-R BS.1770
+// Copyright 2020 Ruud van Asseldonk
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! Math functions for builds without the standard library.
+//!
+//! Methods such as `f32::powf` and `f64::tan` need a math library, so `core`
+//! does not provide them. Without the `std` feature, modules import the
+//! `Float` trait, which provides methods with the same names on top of `libm`.
+//! With the standard library the inherent methods take precedence, so the
+//! calling code is the same either way.
+
+/// The floating point methods that this crate uses and that `core` lacks.
+pub trait Float: Sized {
+    // Recent versions of `core` do provide `abs`, older ones do not.
+    #[allow(dead_code)]
+    fn abs(self) -> Self;
+    fn sqrt(self) -> Self;
+    fn powf(self, exponent: Self) -> Self;
+    fn log10(self) -> Self;
+    fn round(self) -> Self;
+    fn sin(self) -> Self;
+    fn cos(self) -> Self;
+    fn tan(self) -> Self;
+    fn rem_euclid(self, rhs: Self) -> Self;
+}
+
+impl Float for f32 {
+    fn abs(self) -> f32 { libm::fabsf(self) }
+    fn sqrt(self) -> f32 { libm::sqrtf(self) }
+    fn powf(self, exponent: f32) -> f32 { libm::powf(self, exponent) }
+    fn log10(self) -> f32 { libm::log10f(self) }
+    fn round(self) -> f32 { libm::roundf(self) }
+    fn sin(self) -> f32 { libm::sinf(self) }
+    fn cos(self) -> f32 { libm::cosf(self) }
+    fn tan(self) -> f32 { libm::tanf(self) }
+    fn rem_euclid(self, rhs: f32) -> f32 {
+        let r = libm::fmodf(self, rhs);
+        if r < 0.0 { r + libm::fabsf(rhs) } else { r }
+    }
+}
+
+impl Float for f64 {
+    fn abs(self) -> f64 { libm::fabs(self) }
+    fn sqrt(self) -> f64 { libm::sqrt(self) }
+    fn powf(self, exponent: f64) -> f64 { libm::pow(self, exponent) }
+    fn log10(self) -> f64 { libm::log10(self) }
+    fn round(self) -> f64 { libm::round(self) }
+    fn sin(self) -> f64 { libm::sin(self) }
+    fn cos(self) -> f64 { libm::cos(self) }
+    fn tan(self) -> f64 { libm::tan(self) }
+    fn rem_euclid(self, rhs: f64) -> f64 {
+        let r = libm::fmod(self, rhs);
+        if r < 0.0 { r + libm::fabs(rhs) } else { r }
+    }
+}
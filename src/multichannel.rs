@@ -0,0 +1,281 @@
+// BS1770 -- Loudness analysis library conforming to ITU-R BS.1770
+// Copyright 2020 Ruud van Asseldonk
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! A loudness meter for all channels of a signal at once.
+//!
+//! The building blocks of this crate are a `ChannelLoudnessMeter` per channel,
+//! a reduction that combines the channels, and `gated_mean`. For the common
+//! case of measuring a complete multi-channel signal, `MultiChannelLoudnessMeter`
+//! wraps these steps: it owns a meter per channel, and applies the channel
+//! weights of a `ChannelMap` when queried. Channels that are not selected in
+//! the map are not filtered at all, so measuring only the front pair of a 7.1
+//! stream costs no more than measuring a stereo stream.
+//!
+//! ```
+//! use bs1770::channels::{ChannelMap, ChannelOrder};
+//! use bs1770::multichannel::MultiChannelLoudnessMeter;
+//!
+//! let channel_map = ChannelMap::from_order(ChannelOrder::Wave, 2).unwrap();
+//! let mut meter = MultiChannelLoudnessMeter::new(44_100, channel_map);
+//!
+//! // One second of interleaved stereo samples.
+//! let samples: Vec<f32> = (0..2 * 44_100).map(|i| 0.1 * ((i / 2) as f32 * 0.1).sin()).collect();
+//! meter.push_interleaved(&samples);
+//!
+//! println!("Integrated loudness: {:.1} LKFS", meter.integrated_lkfs());
+//! ```
+
+use alloc::vec::Vec;
+
+use crate::ballistics::Ballistics;
+use crate::channels::ChannelMap;
+use crate::{ChannelLoudnessMeter, Power, Windows100ms};
+
+/// Measures the loudness of a multi-channel signal.
+#[derive(Clone)]
+pub struct MultiChannelLoudnessMeter {
+    /// The positions of the channels, which determine their weights.
+    channel_map: ChannelMap,
+
+    /// The indices of the selected channels in the channel map.
+    selected: Vec<usize>,
+
+    /// The weight of every selected channel.
+    weights: Vec<f32>,
+
+    /// One meter per selected channel, in the order of the channel map.
+    meters: Vec<ChannelLoudnessMeter>,
+
+    /// The momentary and short-term loudness of the combined windows.
+    ballistics: Ballistics,
+
+    /// The number of windows in the meters that have been fed to `ballistics`.
+    num_fed: usize,
+}
+
+impl MultiChannelLoudnessMeter {
+    /// Construct a meter for a signal with the given sample rate and channels.
+    ///
+    /// Only the channels that are selected in the channel map are measured,
+    /// see `ChannelMap::select`. Panics if the sample rate is less than 10 Hz.
+    pub fn new(sample_rate_hz: u32, channel_map: ChannelMap) -> MultiChannelLoudnessMeter {
+        let selected: Vec<usize> = (0..channel_map.len()).filter(|&i| channel_map.is_selected(i)).collect();
+        let all_weights = channel_map.weights();
+        let weights = selected.iter().map(|&i| all_weights[i]).collect();
+        let meters = selected.iter().map(|_| ChannelLoudnessMeter::new(sample_rate_hz)).collect();
+        MultiChannelLoudnessMeter {
+            channel_map,
+            selected,
+            weights,
+            meters,
+            ballistics: Ballistics::new(),
+            num_fed: 0,
+        }
+    }
+
+    /// Construct a meter that measures only the channels for which `mask` is true.
+    ///
+    /// This is `new` with `channel_map.select(mask)`. Samples of the other
+    /// channels are accepted, but not filtered. Panics if the mask does not
+    /// have one element per channel, or if the sample rate is less than 10 Hz.
+    pub fn with_selection(
+        sample_rate_hz: u32,
+        channel_map: ChannelMap,
+        mask: &[bool],
+    ) -> MultiChannelLoudnessMeter {
+        MultiChannelLoudnessMeter::new(sample_rate_hz, channel_map.select(mask))
+    }
+
+    /// Return the channel map that the meter was constructed with.
+    pub fn channel_map(&self) -> &ChannelMap {
+        &self.channel_map
+    }
+
+    /// Return the meters of the selected channels, in the order of the channel map.
+    pub fn channel_meters(&self) -> &[ChannelLoudnessMeter] {
+        &self.meters
+    }
+
+    /// Feed interleaved samples, one sample per channel per frame.
+    ///
+    /// The number of samples must be a multiple of the number of channels,
+    /// including channels that are not selected. See also
+    /// `bs1770::push_interleaved`.
+    pub fn push_interleaved(&mut self, samples: &[f32]) {
+        let n = self.channel_map.len();
+        if self.meters.len() == n {
+            crate::push_interleaved(&mut self.meters, samples);
+        } else {
+            assert_eq!(samples.len() % n, 0, "Samples must consist of complete frames.");
+            for (meter, &ch) in self.meters.iter_mut().zip(&self.selected) {
+                meter.push(samples.iter().skip(ch).step_by(n).cloned());
+            }
+        }
+        self.feed_ballistics();
+    }
+
+    /// Feed planar samples, one slice per channel.
+    ///
+    /// The slices of the selected channels must have the same length, so the
+    /// channels stay aligned. Slices of channels that are not selected are
+    /// ignored, they may be empty.
+    pub fn push_planar(&mut self, channels: &[&[f32]]) {
+        assert_eq!(channels.len(), self.channel_map.len(), "Need samples for every channel.");
+        let channels: Vec<&[f32]> = self.selected.iter().map(|&i| channels[i]).collect();
+        let len = channels.first().map_or(0, |ch| ch.len());
+        assert!(channels.iter().all(|ch| ch.len() == len), "Channels must have the same length.");
+
+        for (meters, samples) in self.meters.chunks_mut(2).zip(channels.chunks(2)) {
+            match (meters, samples) {
+                ([left_meter, right_meter], &[left, right]) => {
+                    crate::push_stereo(left_meter, right_meter, left, right);
+                }
+                (meters, samples) => {
+                    for (meter, samples) in meters.iter_mut().zip(samples) {
+                        meter.push_slice(samples);
+                    }
+                }
+            }
+        }
+        self.feed_ballistics();
+    }
+
+    /// Combine the windows completed since the last call, and feed them to the ballistics.
+    fn feed_ballistics(&mut self) {
+        let len = self.meters.first().map_or(0, |m| m.as_100ms_windows().len());
+        if len == self.num_fed {
+            return
+        }
+        let channels: Vec<_> = self
+            .meters
+            .iter()
+            .map(|m| Windows100ms { inner: &m.as_100ms_windows().inner[self.num_fed..] })
+            .collect();
+        for &power in &crate::reduce_channels(&channels, &self.weights).inner {
+            self.ballistics.push(power);
+        }
+        self.num_fed = len;
+    }
+
+    /// Return the power of the 100ms windows so far, with the channels combined.
+    pub fn windows(&self) -> Windows100ms<Vec<Power>> {
+        let channels: Vec<_> = self.meters.iter().map(|m| m.as_100ms_windows()).collect();
+        crate::reduce_channels(&channels, &self.weights)
+    }
+
+    /// Remove the windows analyzed so far from the meters, and return them with the channels combined.
+    ///
+    /// See `ChannelLoudnessMeter::drain_windows`. After draining, the
+    /// integrated loudness only covers the windows completed since then. The
+    /// momentary and short-term loudness are not affected.
+    pub fn drain_windows(&mut self) -> Windows100ms<Vec<Power>> {
+        let channels: Vec<_> = self.meters.iter_mut().map(|m| m.drain_windows()).collect();
+        let channels: Vec<_> = channels.iter().map(|w| w.as_ref()).collect();
+        self.num_fed = 0;
+        crate::reduce_channels(&channels, &self.weights)
+    }
+
+    /// Return the momentary loudness, the combined power over the last 400ms.
+    ///
+    /// Returns `None` until 400ms of audio has been pushed.
+    pub fn momentary(&self) -> Option<Power> {
+        self.ballistics.reading().momentary
+    }
+
+    /// Return the short-term loudness, the combined power over the last 3s.
+    ///
+    /// Returns `None` until 3s of audio has been pushed.
+    pub fn short_term(&self) -> Option<Power> {
+        self.ballistics.reading().short_term
+    }
+
+    /// Return the integrated loudness as power, see `gated_mean`.
+    pub fn integrated(&self) -> Option<Power> {
+        crate::gated_mean(self.windows().as_ref())
+    }
+
+    /// Return the integrated loudness in LKFS.
+    ///
+    /// When no signal passes the gate, such as for silence, this is -∞ LKFS.
+    pub fn integrated_lkfs(&self) -> f32 {
+        self.integrated().unwrap_or(Power(0.0)).loudness_lkfs()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MultiChannelLoudnessMeter;
+    use crate::channels::{ChannelMap, ChannelOrder};
+    use crate::test_util::{assert_windows_close, measure_surround_separately, surround_channels};
+
+    #[test]
+    fn multichannel_meter_matches_separate_meters() {
+        let channel_map = ChannelMap::from_order(ChannelOrder::Wave, 6).unwrap();
+        let channels = surround_channels(48_000);
+        let planar: Vec<&[f32]> = channels.iter().map(|ch| &ch[..]).collect();
+        let interleaved: Vec<f32> = (0..48_000).flat_map(|i| channels.iter().map(move |ch| ch[i])).collect();
+
+        let mut meter_planar = MultiChannelLoudnessMeter::new(48_000, channel_map.clone());
+        let mut meter_interleaved = MultiChannelLoudnessMeter::new(48_000, channel_map);
+        meter_planar.push_planar(&planar);
+        meter_interleaved.push_interleaved(&interleaved);
+
+        let expected = measure_surround_separately(48_000, &channels);
+        assert_windows_close(&meter_planar.windows().inner, &expected.inner);
+        assert_windows_close(&meter_interleaved.windows().inner, &expected.inner);
+        assert_eq!(meter_planar.integrated(), meter_interleaved.integrated());
+        assert!(meter_planar.short_term().is_none());
+        assert!(meter_planar.momentary().is_some());
+    }
+
+    #[test]
+    fn multichannel_meter_ignores_deselected_channels() {
+        // WAVE order for three channels is L, R, C; measure only L and R.
+        let channel_map = ChannelMap::from_order(ChannelOrder::Wave, 3).unwrap();
+        let mask = [true, true, false];
+        let channels = surround_channels(48_000);
+        let loud_center: Vec<f32> = (0..48_000).map(|i| if i % 2 == 0 { 0.9 } else { -0.9 }).collect();
+
+        let mut interleaved = MultiChannelLoudnessMeter::with_selection(48_000, channel_map.clone(), &mask);
+        let samples: Vec<f32> = (0..48_000)
+            .flat_map(|i| vec![channels[0][i], channels[1][i], loud_center[i]])
+            .collect();
+        interleaved.push_interleaved(&samples);
+
+        // Excluded channels do not need samples at all.
+        let mut planar = MultiChannelLoudnessMeter::with_selection(48_000, channel_map, &mask);
+        planar.push_planar(&[&channels[0][..], &channels[1][..], &[]]);
+
+        let mut stereo = MultiChannelLoudnessMeter::new(48_000, ChannelMap::from_order(ChannelOrder::Wave, 2).unwrap());
+        stereo.push_planar(&[&channels[0][..], &channels[1][..]]);
+
+        assert_eq!(interleaved.channel_meters().len(), 2);
+        assert_windows_close(&interleaved.windows().inner, &stereo.windows().inner);
+        assert_windows_close(&planar.windows().inner, &stereo.windows().inner);
+        assert_eq!(planar.momentary(), stereo.momentary());
+    }
+
+    #[test]
+    fn multichannel_meter_drains_combined_windows() {
+        let channel_map = ChannelMap::from_order(ChannelOrder::Wave, 2).unwrap();
+        let samples: Vec<f32> = (0..2 * 48_000).map(|i| 0.1 * ((i / 2) as f32 * 0.01).sin()).collect();
+        let mut whole = MultiChannelLoudnessMeter::new(48_000, channel_map.clone());
+        let mut drained = MultiChannelLoudnessMeter::new(48_000, channel_map);
+        whole.push_interleaved(&samples);
+
+        let mut windows = Vec::new();
+        for chunk in samples.chunks(2 * 7_000) {
+            drained.push_interleaved(chunk);
+            windows.extend(drained.drain_windows().inner);
+        }
+
+        assert_eq!(windows, whole.windows().inner);
+        assert!(drained.windows().inner.is_empty());
+        assert_eq!(drained.momentary(), whole.momentary());
+        assert_eq!(drained.short_term(), None);
+    }
+}
@@ -0,0 +1,111 @@
+// BS1770 -- Loudness analysis library conforming to ITU-R BS.1770
+// Copyright 2020 Ruud van Asseldonk
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! Gains for loudness normalization.
+//!
+//! Playback software normalizes tracks to a target loudness, such as -14 LUFS
+//! for streaming services, -16 LUFS for podcasts, or -23 LUFS for broadcast
+//! (EBU R 128). The gain to apply is the difference between the target and the
+//! measured integrated loudness.
+//!
+//! ```
+//! use bs1770::Power;
+//! use bs1770::normalize;
+//!
+//! let measured = Power::from_lkfs(-20.0);
+//! assert!((normalize::gain_to_target(measured, -14.0) - 6.0).abs() < 1e-4);
+//!
+//! // A gain of 6 dB roughly doubles the amplitude.
+//! let factor = normalize::gain_to_target_linear(measured, -14.0);
+//! assert!((factor - 1.995).abs() < 1e-3);
+//! ```
+//!
+//! Raising the level of a track with loud peaks can make it clip. Like the
+//! `-k` option of mp3gain and loudgain, `clipping_safe_gain` limits the gain so
+//! the true peak stays below a ceiling, at the cost of not reaching the target:
+//!
+//! ```
+//! # use bs1770::Power;
+//! # use bs1770::normalize;
+//! let measured = Power::from_lkfs(-20.0);
+//! let gain_db = normalize::clipping_safe_gain(measured, -14.0, -2.0, -1.0);
+//! assert_eq!(gain_db, 1.0);
+//! ```
+
+#[cfg(not(feature = "std"))]
+use crate::math::Float;
+
+use crate::Power;
+
+/// Return the gain in dB that brings the measured loudness to the target loudness.
+///
+/// When the measured power is zero, such as for silence, the gain is +∞ dB.
+pub fn gain_to_target(measured: Power, target_lufs: f32) -> f32 {
+    target_lufs - measured.loudness_lkfs()
+}
+
+/// Return the factor to multiply samples by to bring the measured loudness to the target.
+///
+/// This is the gain of `gain_to_target` as an amplitude ratio.
+pub fn gain_to_target_linear(measured: Power, target_lufs: f32) -> f32 {
+    db_to_linear(gain_to_target(measured, target_lufs))
+}
+
+/// Return the gain in dB towards the target loudness, limited to keep the peak below a ceiling.
+///
+/// The peak and ceiling are in dBTP, see `TruePeakMeter::peak_dbtp`. A sample
+/// peak in dBFS works as well, but does not account for inter-sample peaks. A
+/// ceiling of 0.0 dBTP prevents clipping; streaming services commonly use
+/// -1.0 dBTP to leave room for lossy encoding. The gain is never more than
+/// `gain_to_target`, but it can be less, and it can even be negative when the
+/// peak already exceeds the ceiling.
+pub fn clipping_safe_gain(measured: Power, target_lufs: f32, peak_dbtp: f32, ceiling_dbtp: f32) -> f32 {
+    let gain = gain_to_target(measured, target_lufs);
+    gain.min(ceiling_dbtp - peak_dbtp)
+}
+
+/// Convert a gain in dB into an amplitude ratio.
+pub fn db_to_linear(gain_db: f32) -> f32 {
+    10.0_f32.powf(gain_db / 20.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gain_to_target_reaches_target() {
+        for &target in &[-14.0, -16.0, -23.0] {
+            for &lkfs in &[-40.0, -23.0, -8.5] {
+                let gain = gain_to_target(Power::from_lkfs(lkfs), target);
+                assert!((lkfs + gain - target).abs() < 1e-4, "{} dB for {} LKFS", gain, lkfs);
+
+                // Scaling the amplitude by the factor scales the power by its square.
+                let factor = gain_to_target_linear(Power::from_lkfs(lkfs), target);
+                let scaled = Power(Power::from_lkfs(lkfs).0 * factor * factor);
+                assert!((scaled.loudness_lkfs() - target).abs() < 1e-3);
+            }
+        }
+        assert_eq!(gain_to_target(Power(0.0), -23.0), f32::INFINITY);
+        assert_eq!(db_to_linear(0.0), 1.0);
+    }
+
+    #[test]
+    fn clipping_safe_gain_respects_ceiling() {
+        let measured = Power::from_lkfs(-20.0);
+        let full_gain = gain_to_target(measured, -14.0);
+        // Enough headroom: the target is reached.
+        assert_eq!(clipping_safe_gain(measured, -14.0, -10.0, 0.0), full_gain);
+        // Not enough headroom: the peak ends up exactly at the ceiling.
+        assert_eq!(clipping_safe_gain(measured, -14.0, -3.0, 0.0), 3.0);
+        assert_eq!(clipping_safe_gain(measured, -14.0, -3.0, -1.0), 2.0);
+        // A peak above the ceiling requires attenuation, even below the target.
+        assert_eq!(clipping_safe_gain(measured, -18.0, 1.5, -1.0), -2.5);
+        // A silent signal has no peak to limit the gain.
+        assert_eq!(clipping_safe_gain(measured, -14.0, f32::NEG_INFINITY, 0.0), full_gain);
+    }
+}
@@ -0,0 +1,165 @@
+// BS1770 -- Loudness analysis library conforming to ITU-R BS.1770
+// Copyright 2020 Ruud van Asseldonk
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! Measuring channels and streams in parallel, on the Rayon thread pool.
+//!
+//! A `ChannelLoudnessMeter` measures a single channel on a single thread. The
+//! channels of a signal are independent until they are combined, so they can
+//! be measured in parallel, and so can different streams. The functions in
+//! this module do so on the global Rayon thread pool, or on the pool that they
+//! are called from, and return the combined windows.
+//!
+//! ```
+//! use bs1770::channels::{ChannelMap, ChannelOrder};
+//! use bs1770::parallel::{self, PlanarAudio};
+//!
+//! let left: Vec<f32> = (0..48_000).map(|i| 0.1 * (i as f32 * 0.05).sin()).collect();
+//! let right: Vec<f32> = (0..48_000).map(|i| 0.1 * (i as f32 * 0.07).sin()).collect();
+//! let stream = PlanarAudio {
+//!     sample_rate_hz: 48_000,
+//!     channel_map: ChannelMap::from_order(ChannelOrder::Wave, 2).unwrap(),
+//!     channels: vec![&left[..], &right[..]],
+//! };
+//!
+//! // Measure any number of streams; their channels are measured in parallel.
+//! let windows = parallel::measure_many(&[stream]);
+//! let power = bs1770::gated_mean(windows[0].as_ref()).unwrap();
+//! println!("Integrated loudness: {:.1} LKFS", power.loudness_lkfs());
+//! ```
+//!
+//! This module is only available with the `rayon` feature enabled. To analyze
+//! files rather than samples in memory, see also the `batch` module.
+
+use alloc::vec::Vec;
+use rayon::prelude::*;
+
+use crate::channels::ChannelMap;
+use crate::{ChannelLoudnessMeter, Power, Windows100ms};
+
+/// The samples of a stream in memory, one slice per channel.
+#[derive(Clone, Debug)]
+pub struct PlanarAudio<'a> {
+    /// The sample rate of all channels.
+    pub sample_rate_hz: u32,
+
+    /// The positions of the channels, which determine their weights.
+    pub channel_map: ChannelMap,
+
+    /// The samples of every channel, in the order of the channel map.
+    ///
+    /// Samples are normalized to full scale, as for `ChannelLoudnessMeter::push`.
+    pub channels: Vec<&'a [f32]>,
+}
+
+/// Measure every channel on its own, in parallel, and return the windows per channel.
+///
+/// Panics if the sample rate is less than 10 Hz.
+pub fn measure_channels(sample_rate_hz: u32, channels: &[&[f32]]) -> Vec<Windows100ms<Vec<Power>>> {
+    channels
+        .par_iter()
+        .map(|samples| {
+            let mut meter = ChannelLoudnessMeter::new(sample_rate_hz);
+            meter.push_slice(samples);
+            meter.into_100ms_windows()
+        })
+        .collect()
+}
+
+/// Measure the channels of a stream in parallel, and combine them with the weights of the channel map.
+///
+/// Panics if the sample rate is less than 10 Hz, if the number of channels
+/// does not match the channel map, or if the channels differ in length.
+pub fn measure(audio: &PlanarAudio) -> Windows100ms<Vec<Power>> {
+    let windows = measure_channels(audio.sample_rate_hz, &audio.channels);
+    let windows: Vec<_> = windows.iter().map(|w| w.as_ref()).collect();
+    audio.channel_map.reduce(&windows)
+}
+
+/// Measure multiple streams in parallel, and return the combined windows of every stream.
+///
+/// The channels of every stream are measured in parallel too, so this makes
+/// use of all threads even for a few streams with many channels. The result
+/// is in the same order as the input. Panics under the same conditions as
+/// `measure`.
+pub fn measure_many(streams: &[PlanarAudio]) -> Vec<Windows100ms<Vec<Power>>> {
+    streams.par_iter().map(measure).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PlanarAudio, measure, measure_channels, measure_many};
+    use crate::ChannelLoudnessMeter;
+    use crate::channels::{ChannelMap, ChannelOrder};
+    use crate::test_util::{assert_windows_close, measure_surround_separately, surround_channels};
+
+    #[test]
+    fn parallel_measurement_matches_sequential() {
+        let channels = surround_channels(30_000);
+        let audio = PlanarAudio {
+            sample_rate_hz: 44_100,
+            channel_map: ChannelMap::from_order(ChannelOrder::Wave, 6).unwrap(),
+            channels: channels.iter().map(|ch| &ch[..]).collect(),
+        };
+        let expected = measure_surround_separately(44_100, &channels);
+        assert_windows_close(&measure(&audio).inner, &expected.inner);
+    }
+
+    #[test]
+    fn parallel_measurement_does_not_depend_on_thread_count() {
+        let channels = surround_channels(30_000);
+        let audio = PlanarAudio {
+            sample_rate_hz: 44_100,
+            channel_map: ChannelMap::from_order(ChannelOrder::Wave, 6).unwrap(),
+            channels: channels.iter().map(|ch| &ch[..]).collect(),
+        };
+        let expected = measure(&audio);
+        for &num_threads in &[1, 2, 3, 8] {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(num_threads).build().unwrap();
+            let actual = pool.install(|| measure(&audio));
+            assert_eq!(actual.inner, expected.inner, "Differs with {} threads.", num_threads);
+        }
+    }
+
+    #[test]
+    fn parallel_measurement_matches_chunked_sequential_measurement() {
+        // Lengths and chunks that do not align with the 4410-sample windows.
+        let channels = surround_channels(30_001);
+        let channels: Vec<&[f32]> = channels.iter().map(|ch| &ch[..]).collect();
+        let windows = measure_channels(44_100, &channels);
+        for (samples, actual) in channels.iter().zip(&windows) {
+            let mut meter = ChannelLoudnessMeter::new(44_100);
+            let mut rest = *samples;
+            for &len in [1, 4_409, 4_411, 7, 13_000].iter().cycle() {
+                let (chunk, tail) = rest.split_at(len.min(rest.len()));
+                meter.push_slice(chunk);
+                rest = tail;
+                if rest.is_empty() {
+                    break
+                }
+            }
+            assert_eq!(meter.as_100ms_windows().inner, &actual.inner[..]);
+        }
+    }
+
+    #[test]
+    fn measure_many_measures_streams_independently() {
+        let channels = surround_channels(30_000);
+        let streams: Vec<PlanarAudio> = [30_000, 17_777, 4_409]
+            .iter()
+            .map(|&len| PlanarAudio {
+                sample_rate_hz: 44_100,
+                channel_map: ChannelMap::from_order(ChannelOrder::Wave, 6).unwrap(),
+                channels: channels.iter().map(|ch| &ch[..len]).collect(),
+            })
+            .collect();
+        let many = measure_many(&streams);
+        assert_eq!(many.len(), streams.len());
+        for (stream, windows) in streams.iter().zip(&many) {
+            assert_eq!(windows.inner, measure(stream).inner);
+        }
+    }
+}
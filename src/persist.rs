@@ -0,0 +1,200 @@
+// BS1770 -- Loudness analysis library conforming to ITU-R BS.1770
+// Copyright 2020 Ruud van Asseldonk
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! A compact binary format for storing measured windows.
+//!
+//! Computing the loudness of an album requires the 100ms windows of all of its
+//! tracks. Decoding the audio again is expensive, so a music library can store
+//! the windows of every track when it is first analyzed, and compute album
+//! loudness later from the stored windows alone. The windows of a track take
+//! 40 bytes per second per channel in this format.
+//!
+//! The format is little-endian throughout:
+//!
+//!  * The magic bytes `BS1770W` followed by a format version byte, currently 1.
+//!  * The sample rate in Hz, as `u32`.
+//!  * The number of channels, as `u32`.
+//!  * The number of windows per channel, as `u64`.
+//!  * The power of every window as `f32`, all windows of the first channel
+//!    first, then the second channel, etc.
+//!
+//! The channels are stored separately rather than combined, so the channel
+//! weights can still be chosen when the windows are read back.
+//!
+//! ```
+//! # use bs1770::{Power, Windows100ms};
+//! use bs1770::persist::{read_windows, write_windows};
+//!
+//! let left = [Power(0.1), Power(0.2)];
+//! let right = [Power(0.3), Power(0.4)];
+//! let channels = [Windows100ms { inner: &left[..] }, Windows100ms { inner: &right[..] }];
+//!
+//! let mut buffer = Vec::new();
+//! write_windows(&mut buffer, 44_100, &channels).unwrap();
+//!
+//! let stored = read_windows(&mut &buffer[..]).unwrap();
+//! assert_eq!(stored.sample_rate_hz, 44_100);
+//! assert_eq!(stored.channels[1].inner, &right[..]);
+//! ```
+
+use std::io;
+use std::io::{Read, Write};
+
+use crate::{Power, Windows100ms};
+
+/// The bytes that every file starts with, before the version byte.
+const MAGIC: &[u8; 7] = b"BS1770W";
+
+/// The version of the format that `write_windows` writes.
+const VERSION: u8 = 1;
+
+/// Windows read back with `read_windows`.
+#[derive(Clone, Debug)]
+pub struct StoredWindows {
+    /// The sample rate of the audio that the windows were measured from.
+    pub sample_rate_hz: u32,
+
+    /// The windows of every channel, in the order they were written.
+    pub channels: Vec<Windows100ms<Vec<Power>>>,
+}
+
+fn invalid_data(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_u32_le<R: Read>(input: &mut R) -> io::Result<u32> {
+    let mut buf = [0_u8; 4];
+    input.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64_le<R: Read>(input: &mut R) -> io::Result<u64> {
+    let mut buf = [0_u8; 8];
+    input.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// Write the windows of every channel, in the format described in the module documentation.
+///
+/// Panics if the channels do not all have the same number of windows.
+pub fn write_windows<W: Write>(
+    out: &mut W,
+    sample_rate_hz: u32,
+    channels: &[Windows100ms<&[Power]>],
+) -> io::Result<()> {
+    let num_windows = channels.first().map_or(0, |ch| ch.len());
+    assert!(
+        channels.iter().all(|ch| ch.len() == num_windows),
+        "Channels must have the same number of windows.",
+    );
+
+    out.write_all(MAGIC)?;
+    out.write_all(&[VERSION])?;
+    out.write_all(&sample_rate_hz.to_le_bytes())?;
+    out.write_all(&(channels.len() as u32).to_le_bytes())?;
+    out.write_all(&(num_windows as u64).to_le_bytes())?;
+
+    let mut buffer = Vec::with_capacity(4 * num_windows);
+    for channel in channels {
+        buffer.clear();
+        for power in channel.inner {
+            buffer.extend_from_slice(&power.0.to_le_bytes());
+        }
+        out.write_all(&buffer)?;
+    }
+
+    Ok(())
+}
+
+/// Read windows written by `write_windows`.
+///
+/// Returns an error of kind `InvalidData` if the input is not in this format,
+/// or if it was written by a newer version of the format.
+pub fn read_windows<R: Read>(input: &mut R) -> io::Result<StoredWindows> {
+    let mut header = [0_u8; 8];
+    input.read_exact(&mut header)?;
+    if &header[..7] != MAGIC {
+        return Err(invalid_data("Not a file with stored windows."))
+    }
+    if header[7] != VERSION {
+        return Err(invalid_data("Unsupported version of the stored windows format."))
+    }
+
+    let sample_rate_hz = read_u32_le(input)?;
+    let num_channels = read_u32_le(input)?;
+    let num_windows = read_u64_le(input)?;
+
+    // Read through `take`, rather than allocating up front, so a corrupt
+    // header cannot make us allocate an absurd amount of memory.
+    let mut channels = Vec::new();
+    let mut buffer = Vec::new();
+    for _ in 0..num_channels {
+        let len = num_windows
+            .checked_mul(4)
+            .ok_or_else(|| invalid_data("Invalid number of windows."))?;
+        buffer.clear();
+        input.take(len).read_to_end(&mut buffer)?;
+        if (buffer.len() as u64) < len {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Unexpected end of file."));
+        }
+        let windows = buffer
+            .chunks_exact(4)
+            .map(|b| Power(f32::from_le_bytes([b[0], b[1], b[2], b[3]])))
+            .collect();
+        channels.push(windows);
+    }
+
+    let result = StoredWindows {
+        sample_rate_hz,
+        channels,
+    };
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::{read_windows, write_windows};
+    use crate::{Power, Windows100ms};
+
+    #[test]
+    fn read_windows_returns_written_windows() {
+        let left: Vec<Power> = (0..1_000).map(|i| Power(i as f32 * 1e-4)).collect();
+        let right: Vec<Power> = (0..1_000).map(|i| Power::from_lkfs(-i as f32 * 0.1)).collect();
+        let channels = [Windows100ms { inner: &left[..] }, Windows100ms { inner: &right[..] }];
+
+        let mut buffer = Vec::new();
+        write_windows(&mut buffer, 48_000, &channels).unwrap();
+        assert_eq!(buffer.len(), 24 + 2 * 4 * 1_000);
+
+        let stored = read_windows(&mut &buffer[..]).unwrap();
+        assert_eq!(stored.sample_rate_hz, 48_000);
+        assert_eq!(stored.channels.len(), 2);
+        assert_eq!(stored.channels[0].inner, left);
+        assert_eq!(stored.channels[1].inner, right);
+    }
+
+    #[test]
+    fn read_windows_rejects_invalid_input() {
+        let windows = [Power(0.5); 10];
+        let mut buffer = Vec::new();
+        write_windows(&mut buffer, 44_100, &[Windows100ms { inner: &windows[..] }]).unwrap();
+
+        let truncated = &buffer[..buffer.len() - 1];
+        let err = read_windows(&mut &truncated[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        let mut newer = buffer.clone();
+        newer[7] = 2;
+        let err = read_windows(&mut &newer[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let err = read_windows(&mut &b"RIFF\0\0\0\0"[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,277 @@
+// BS1770 -- Loudness analysis library conforming to ITU-R BS.1770
+// Copyright 2020 Ruud van Asseldonk
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! Live measurement of a program, with EBU mode transport controls.
+//!
+//! An “EBU mode” meter, as specified in EBU Tech 3341, shows the momentary,
+//! short-term, and integrated loudness. The integrated loudness is scoped with
+//! start, pause, continue, and reset controls, so it can measure for example
+//! only the time that a program was on air. The momentary and short-term
+//! loudness are not affected by these controls, they always reflect the most
+//! recent audio. The loudness range is scoped like the integrated loudness.
+//!
+//! `ProgramMeter` implements these controls on top of 100ms windows, after
+//! the channels have been combined with `reduce_stereo` or a `ChannelMap`:
+//!
+//! ```
+//! # use bs1770::{Power, Windows100ms};
+//! use bs1770::program::ProgramMeter;
+//!
+//! let mut meter = ProgramMeter::new();
+//! let on_air = [Power::from_lkfs(-23.0); 50];
+//! let commercial = [Power::from_lkfs(-16.0); 50];
+//!
+//! meter.push(Windows100ms { inner: &on_air[..] });
+//! meter.pause();
+//! meter.push(Windows100ms { inner: &commercial[..] });
+//! meter.resume();
+//! meter.push(Windows100ms { inner: &on_air[..] });
+//!
+//! let integrated = meter.integrated().unwrap();
+//! assert!((integrated.loudness_lkfs() - -23.0).abs() < 1e-3);
+//!
+//! // The short-term loudness still reflects the most recent audio.
+//! assert!((meter.short_term().unwrap().loudness_lkfs() - -23.0).abs() < 1e-3);
+//! ```
+
+use crate::{Power, Windows100ms};
+use crate::ballistics::Ballistics;
+use crate::gating::GatingHistogram;
+use crate::lra::LoudnessRangeHistogram;
+
+/// Measures momentary, short-term, and integrated loudness of a live program.
+#[derive(Clone, Debug)]
+pub struct ProgramMeter {
+    /// Momentary and short-term loudness, regardless of whether integration is running.
+    ballistics: Ballistics,
+
+    /// The gating blocks of the windows pushed while integration was running.
+    integrated: GatingHistogram,
+
+    /// The short-term loudness, sampled every 100ms while integration was running.
+    range: LoudnessRangeHistogram,
+
+    /// The maximum momentary loudness while integration was running.
+    max_momentary: Option<Power>,
+
+    /// The maximum short-term loudness while integration was running.
+    max_short_term: Option<Power>,
+
+    /// Whether windows are currently added to the integrated loudness.
+    is_running: bool,
+}
+
+/// Replace `max` with `power` if `power` is louder.
+fn update_max(max: &mut Option<Power>, power: Option<Power>) {
+    match (*max, power) {
+        (Some(m), Some(p)) if m >= p => {}
+        (_, Some(p)) => *max = Some(p),
+        (_, None) => {}
+    }
+}
+
+impl Default for ProgramMeter {
+    fn default() -> ProgramMeter {
+        ProgramMeter::new()
+    }
+}
+
+impl ProgramMeter {
+    /// Construct a new meter, with integration running.
+    pub fn new() -> ProgramMeter {
+        ProgramMeter {
+            ballistics: Ballistics::new(),
+            integrated: GatingHistogram::new(),
+            range: LoudnessRangeHistogram::new(),
+            max_momentary: None,
+            max_short_term: None,
+            is_running: true,
+        }
+    }
+
+    /// Feed the power of 100ms windows, with the channels already combined.
+    pub fn push(&mut self, windows: Windows100ms<&[Power]>) {
+        for &power in windows.inner {
+            let reading = self.ballistics.push(power);
+            if self.is_running {
+                if let Some(short_term) = reading.short_term {
+                    self.range.push_short_term(short_term);
+                }
+                update_max(&mut self.max_momentary, reading.momentary);
+                update_max(&mut self.max_short_term, reading.short_term);
+            }
+        }
+        if self.is_running {
+            self.integrated.push(windows);
+        }
+    }
+
+    /// Reset the integrated loudness, and start integrating.
+    ///
+    /// This is the “start” control of an EBU mode meter.
+    pub fn start(&mut self) {
+        self.reset();
+        self.resume();
+    }
+
+    /// Stop adding windows to the integrated loudness, but keep its state.
+    pub fn pause(&mut self) {
+        self.is_running = false;
+    }
+
+    /// Continue adding windows to the integrated loudness after `pause`.
+    ///
+    /// Gating blocks are formed from consecutive integrated windows, so a
+    /// block may straddle the pause. The effect on the measurement is small,
+    /// at most three 400ms blocks contain audio from both sides of the pause.
+    pub fn resume(&mut self) {
+        self.is_running = true;
+    }
+
+    /// Discard the integrated loudness, loudness range, and maxima, without changing whether integration is running.
+    ///
+    /// The momentary and short-term loudness are not affected.
+    pub fn reset(&mut self) {
+        self.integrated.clear();
+        self.range.clear();
+        self.max_momentary = None;
+        self.max_short_term = None;
+    }
+
+    /// Return whether windows are currently added to the integrated loudness.
+    pub fn is_running(&self) -> bool {
+        self.is_running
+    }
+
+    /// Return the total duration integrated so far, as a number of 100ms windows.
+    pub fn integrated_len(&self) -> usize {
+        self.integrated.len() as usize
+    }
+
+    /// Return the momentary loudness, the mean power over the last 400ms.
+    ///
+    /// Returns `None` until at least 400ms of audio has been pushed.
+    pub fn momentary(&self) -> Option<Power> {
+        self.ballistics.reading().momentary
+    }
+
+    /// Return the short-term loudness, the mean power over the last 3s.
+    ///
+    /// Returns `None` until at least 3s of audio has been pushed.
+    pub fn short_term(&self) -> Option<Power> {
+        self.ballistics.reading().short_term
+    }
+
+    /// Return the maximum momentary loudness while running.
+    ///
+    /// This is the Max Momentary loudness of EBU R128. Like the integrated
+    /// loudness, it is scoped by the transport controls.
+    pub fn max_momentary(&self) -> Option<Power> {
+        self.max_momentary
+    }
+
+    /// Return the maximum short-term loudness while running.
+    ///
+    /// This is the Max Short-term loudness of EBU R128. Like the integrated
+    /// loudness, it is scoped by the transport controls.
+    pub fn max_short_term(&self) -> Option<Power> {
+        self.max_short_term
+    }
+
+    /// Return the gated mean power of the windows pushed while running.
+    ///
+    /// Returns `None` when no window passes the gate, see `gated_mean`. The
+    /// meter uses bounded memory, see `GatingHistogram` for the accuracy.
+    pub fn integrated(&self) -> Option<Power> {
+        self.integrated.gated_mean()
+    }
+
+    /// Return the loudness range in LU of the short-term loudness while running.
+    ///
+    /// Returns `None` until a short-term value passed the gate, see
+    /// `LoudnessRangeHistogram::loudness_range_lu`.
+    pub fn loudness_range_lu(&self) -> Option<f32> {
+        self.range.loudness_range_lu()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ProgramMeter;
+    use crate::{Power, Windows100ms};
+
+    fn push_lkfs(meter: &mut ProgramMeter, lkfs: f32, num_windows: usize) {
+        let windows = vec![Power::from_lkfs(lkfs); num_windows];
+        meter.push(Windows100ms { inner: &windows[..] });
+    }
+
+    #[test]
+    fn program_meter_reset_keeps_short_term_history() {
+        let mut meter = ProgramMeter::new();
+        push_lkfs(&mut meter, -30.0, 40);
+        meter.reset();
+        assert!(meter.integrated().is_none());
+        assert!((meter.short_term().unwrap().loudness_lkfs() - -30.0).abs() < 1e-3);
+
+        push_lkfs(&mut meter, -20.0, 10);
+        assert_eq!(meter.integrated_len(), 10);
+        assert!((meter.integrated().unwrap().loudness_lkfs() - -20.0).abs() < 1e-3);
+        assert!((meter.momentary().unwrap().loudness_lkfs() - -20.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn program_meter_does_not_integrate_while_paused() {
+        let mut meter = ProgramMeter::new();
+        meter.pause();
+        assert!(!meter.is_running());
+        push_lkfs(&mut meter, -10.0, 40);
+        assert!(meter.integrated().is_none());
+        assert!(meter.short_term().is_some());
+
+        assert!(meter.loudness_range_lu().is_none());
+
+        meter.start();
+        push_lkfs(&mut meter, -25.0, 20);
+        assert!((meter.integrated().unwrap().loudness_lkfs() - -25.0).abs() < 1e-3);
+        // The short-term loudness still covers some of the -10 LKFS audio
+        // pushed while paused, so the range is not zero.
+        assert!(meter.loudness_range_lu().unwrap() > 1.0);
+    }
+
+    #[test]
+    fn program_meter_momentary_needs_400ms() {
+        let mut meter = ProgramMeter::new();
+        push_lkfs(&mut meter, -23.0, 3);
+        assert!(meter.momentary().is_none());
+        push_lkfs(&mut meter, -23.0, 1);
+        assert!(meter.momentary().is_some());
+        assert!(meter.short_term().is_none());
+    }
+
+    #[test]
+    fn program_meter_tracks_maxima_while_running() {
+        let mut meter = ProgramMeter::new();
+        push_lkfs(&mut meter, -23.0, 50);
+        meter.pause();
+        push_lkfs(&mut meter, -10.0, 50);
+        meter.resume();
+        push_lkfs(&mut meter, -23.0, 50);
+
+        // The loud part was not integrated, but the windows right after the
+        // pause still overlap it, with one quiet window.
+        let max_momentary = meter.max_momentary().unwrap().loudness_lkfs();
+        let max_short_term = meter.max_short_term().unwrap().loudness_lkfs();
+        assert!(max_momentary > -12.0 && max_momentary < -10.0, "{}", max_momentary);
+        assert!(max_short_term > -15.0 && max_short_term < -10.0, "{}", max_short_term);
+
+        meter.reset();
+        assert_eq!(meter.max_momentary(), None);
+        push_lkfs(&mut meter, -23.0, 50);
+        let max_momentary = meter.max_momentary().unwrap().loudness_lkfs();
+        assert!((max_momentary - -23.0).abs() < 1e-3);
+    }
+}
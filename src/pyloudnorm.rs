@@ -0,0 +1,136 @@
+// BS1770 -- Loudness analysis library conforming to ITU-R BS.1770
+// Copyright 2020 Ruud van Asseldonk
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! A meter with the interface of [pyloudnorm][pyloudnorm].
+//!
+//! Analysis scripts in Python often measure loudness with pyloudnorm:
+//!
+//! ```python
+//! meter = pyln.Meter(rate)
+//! loudness = meter.integrated_loudness(data)
+//! ```
+//!
+//! `Meter` offers the same interface, to make porting such scripts
+//! straightforward. The one difference is that pyloudnorm takes a single
+//! array with a column per channel, whereas `Meter` takes a slice per channel:
+//!
+//! ```
+//! use bs1770::pyloudnorm::Meter;
+//!
+//! # let left = vec![0.0_f32; 48_000];
+//! # let right = vec![0.0_f32; 48_000];
+//! let meter = Meter::new(48_000);
+//! let loudness = meter.integrated_loudness(&[&left, &right]);
+//! println!("{:.1} LUFS", loudness);
+//! ```
+//!
+//! For the loudness range, maxima, and true peak too, see `analyze`.
+//!
+//! [pyloudnorm]: https://github.com/csteinmetz1/pyloudnorm
+
+use alloc::vec::Vec;
+
+use crate::{ChannelLoudnessMeter, Power};
+
+/// The channel weights of pyloudnorm, for the channel order L, R, C, Ls, Rs.
+const WEIGHTS: [f32; 5] = [1.0, 1.0, 1.0, 1.41, 1.41];
+
+/// Measures integrated loudness, with the interface of `pyloudnorm.Meter`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Meter {
+    sample_rate_hz: u32,
+}
+
+impl Meter {
+    /// Construct a meter for audio at the given sample rate.
+    ///
+    /// Panics if the sample rate is not supported, see `ChannelLoudnessMeter::new`.
+    pub fn new(sample_rate_hz: u32) -> Meter {
+        // Construct a meter once to validate the sample rate up front, like
+        // pyloudnorm does in its constructor.
+        ChannelLoudnessMeter::new(sample_rate_hz);
+        Meter { sample_rate_hz }
+    }
+
+    /// Return the sample rate that the meter was constructed for.
+    pub fn sample_rate_hz(&self) -> u32 {
+        self.sample_rate_hz
+    }
+
+    /// Measure the integrated loudness of the audio in LUFS.
+    ///
+    /// The samples of every channel are normalized to the range [-1.0, 1.0],
+    /// and all channels have the same length. Like pyloudnorm, this supports
+    /// up to five channels, in the order left, right, center, left surround,
+    /// and right surround, and panics for more. For other layouts, combine
+    /// the channels with a `channels::ChannelMap`.
+    ///
+    /// Like pyloudnorm, this returns -∞ when no block passes the gates, such as
+    /// for silence. pyloudnorm rejects audio shorter than 400ms, whereas this
+    /// measures it as a single block, see `gated_mean`.
+    pub fn integrated_loudness(&self, data: &[&[f32]]) -> f32 {
+        assert!(
+            data.len() <= WEIGHTS.len(),
+            "Meter supports at most {} channels, got {}.", WEIGHTS.len(), data.len(),
+        );
+        let channel_windows: Vec<_> = data
+            .iter()
+            .map(|samples| {
+                let mut meter = ChannelLoudnessMeter::new(self.sample_rate_hz);
+                meter.push_slice(samples);
+                meter.into_100ms_windows()
+            })
+            .collect();
+        let channel_windows: Vec<_> = channel_windows.iter().map(|w| w.as_ref()).collect();
+        let windows = crate::reduce_channels(&channel_windows, &WEIGHTS[..data.len()]);
+        crate::gated_mean(windows.as_ref()).unwrap_or(Power(0.0)).loudness_lkfs()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Meter;
+
+    fn sine(amplitude: f32, num_samples: usize) -> Vec<f32> {
+        (0..num_samples)
+            .map(|i| amplitude * (2.0 * std::f32::consts::PI * i as f32 / 48.0).sin())
+            .collect()
+    }
+
+    #[test]
+    fn meter_measures_tech_3341_case_1() {
+        // A stereo 1 kHz sine at -23 dBFS measures -23 LUFS.
+        let samples = sine(10.0_f32.powf(-23.0 / 20.0), 48_000 * 20);
+        let meter = Meter::new(48_000);
+        let loudness = meter.integrated_loudness(&[&samples, &samples]);
+        assert!((loudness - -23.0).abs() < 0.1, "{}", loudness);
+    }
+
+    #[test]
+    fn meter_weights_surround_channels() {
+        let samples = sine(0.1, 48_000);
+        let silence = vec![0.0; 48_000];
+        let meter = Meter::new(48_000);
+        let front = meter.integrated_loudness(&[&samples, &silence, &silence, &silence, &silence]);
+        let surround = meter.integrated_loudness(&[&silence, &silence, &silence, &samples, &silence]);
+        assert!((surround - front - 10.0 * 1.41_f32.log10()).abs() < 1e-3);
+    }
+
+    #[test]
+    fn meter_returns_negative_infinity_for_silence() {
+        let silence = vec![0.0; 48_000];
+        let meter = Meter::new(44_100);
+        assert_eq!(meter.integrated_loudness(&[&silence]), f32::NEG_INFINITY);
+    }
+
+    #[test]
+    #[should_panic]
+    fn meter_rejects_more_than_five_channels() {
+        let silence = vec![0.0; 48_000];
+        Meter::new(48_000).integrated_loudness(&[&silence[..]; 6]);
+    }
+}
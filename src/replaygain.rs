@@ -0,0 +1,77 @@
+// BS1770 -- Loudness analysis library conforming to ITU-R BS.1770
+// Copyright 2020 Ruud van Asseldonk
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! Gains for the [ReplayGain 2.0][rg2] tags.
+//!
+//! ReplayGain 2.0 measures loudness with BS.1770, and stores the gain that
+//! brings a track or album to a reference loudness of -18 LUFS in the
+//! `REPLAYGAIN_TRACK_GAIN` and `REPLAYGAIN_ALBUM_GAIN` tags, formatted like
+//! `-7.25 dB`.
+//!
+//! [rg2]: https://wiki.hydrogenaud.io/index.php?title=ReplayGain_2.0_specification
+//!
+//! ```
+//! use bs1770::{Power, replaygain};
+//!
+//! let track_power = Power::from_lkfs(-10.75);
+//! let gain_db = replaygain::gain_db(track_power).unwrap();
+//! assert_eq!(replaygain::format_gain(gain_db), "-7.25 dB");
+//! assert_eq!(replaygain::parse_gain("-7.25 dB"), Some(-7.25));
+//! ```
+
+use alloc::string::String;
+
+use crate::{Power, normalize, tags};
+
+/// The loudness that ReplayGain 2.0 gains normalize to, in LUFS.
+pub const REFERENCE_LUFS: f32 = -18.0;
+
+/// Return the gain in dB that brings a measured loudness to the reference loudness.
+///
+/// Returns `None` when the power is zero, such as for silence, because no
+/// finite gain makes silence reach the reference loudness.
+pub fn gain_db(loudness: Power) -> Option<f32> {
+    if loudness.0 > 0.0 {
+        Some(normalize::gain_to_target(loudness, REFERENCE_LUFS))
+    } else {
+        None
+    }
+}
+
+/// Format a gain as stored in the `REPLAYGAIN_*_GAIN` tags, such as `-7.25 dB`.
+pub fn format_gain(gain_db: f32) -> String {
+    tags::format_db(gain_db)
+}
+
+/// Parse a gain as stored in the `REPLAYGAIN_*_GAIN` tags, and return it in dB.
+///
+/// This is lenient about whitespace, case, and the unit, see `tags::parse_db`.
+pub fn parse_gain(value: &str) -> Option<f32> {
+    tags::parse_db(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gain_db_brings_loudness_to_reference() {
+        for &lkfs in &[-30.0, -23.0, -18.0, -9.5, 0.0] {
+            let gain = gain_db(Power::from_lkfs(lkfs)).unwrap();
+            assert!((lkfs + gain - REFERENCE_LUFS).abs() < 1e-4, "{} dB for {} LKFS", gain, lkfs);
+        }
+        assert_eq!(gain_db(Power(0.0)), None);
+    }
+
+    #[test]
+    fn format_gain_matches_replaygain_tags() {
+        assert_eq!(format_gain(-7.25), "-7.25 dB");
+        assert_eq!(format_gain(3.0), "3.00 dB");
+        assert_eq!(parse_gain("+3.00 dB"), Some(3.0));
+        assert_eq!(parse_gain("loud"), None);
+    }
+}
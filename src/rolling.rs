@@ -0,0 +1,188 @@
+// BS1770 -- Loudness analysis library conforming to ITU-R BS.1770
+// Copyright 2020 Ruud van Asseldonk
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! Integrated loudness and loudness range over the most recent audio.
+//!
+//! The integrated loudness of `ProgramMeter` covers everything since the meter
+//! was started. A monitor for a stream that runs indefinitely, such as a radio
+//! station, is more interested in the loudness of the last few minutes.
+//! `RollingMeter` retains the most recent 100ms windows, up to a fixed
+//! duration, and measures over any trailing part of them. Older windows are
+//! discarded, so the memory is bounded by the retained duration, about 141 KiB
+//! for an hour.
+//!
+//! ```
+//! # use bs1770::{Power, Windows100ms};
+//! use std::time::Duration;
+//! use bs1770::rolling::RollingMeter;
+//!
+//! let mut meter = RollingMeter::new(Duration::from_secs(600));
+//! let quiet = [Power::from_lkfs(-30.0); 3000];
+//! let loud = [Power::from_lkfs(-18.0); 3000];
+//! meter.push(Windows100ms { inner: &quiet[..] });
+//! meter.push(Windows100ms { inner: &loud[..] });
+//!
+//! let last_5_min = meter.integrated_last(Duration::from_secs(300)).unwrap();
+//! assert!((last_5_min.loudness_lkfs() - -18.0).abs() < 1e-3);
+//! ```
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use crate::{Power, Windows100ms};
+
+/// Convert a duration into a number of 100ms windows, rounding down.
+fn duration_to_windows(duration: Duration) -> usize {
+    (duration.as_secs() * 10 + duration.subsec_millis() as u64 / 100) as usize
+}
+
+/// Retains the most recent windows, to measure over a trailing duration.
+#[derive(Clone, Debug)]
+pub struct RollingMeter {
+    /// The most recent windows, at most `capacity` of them.
+    recent: VecDeque<Power>,
+
+    /// The maximum number of windows to retain.
+    capacity: usize,
+}
+
+impl RollingMeter {
+    /// Construct a meter that retains windows for up to `max_duration`.
+    ///
+    /// The duration is rounded down to a multiple of 100ms. Panics if it is
+    /// less than 100ms.
+    pub fn new(max_duration: Duration) -> RollingMeter {
+        let capacity = duration_to_windows(max_duration);
+        assert!(capacity > 0, "RollingMeter must retain at least one 100ms window.");
+        RollingMeter {
+            recent: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Feed the power of 100ms windows, with the channels already combined.
+    ///
+    /// When more than the maximum duration has been pushed, the oldest
+    /// windows are discarded.
+    pub fn push(&mut self, windows: Windows100ms<&[Power]>) {
+        // Only the tail of a long batch can be retained.
+        let skip = windows.len().saturating_sub(self.capacity);
+        for &power in &windows.inner[skip..] {
+            if self.recent.len() == self.capacity {
+                self.recent.pop_front();
+            }
+            self.recent.push_back(power);
+        }
+    }
+
+    /// Discard all retained windows.
+    pub fn clear(&mut self) {
+        self.recent.clear();
+    }
+
+    /// Return the maximum duration that the meter retains.
+    pub fn max_duration(&self) -> Duration {
+        Duration::from_millis(100 * self.capacity as u64)
+    }
+
+    /// Return the duration currently retained, which is less than the maximum until the meter has filled up.
+    pub fn retained_duration(&self) -> Duration {
+        Duration::from_millis(100 * self.recent.len() as u64)
+    }
+
+    /// Return the windows that cover the last `duration`, oldest first.
+    ///
+    /// The duration is rounded down to a multiple of 100ms. When less than
+    /// `duration` is retained, this returns all retained windows.
+    pub fn windows_last(&self, duration: Duration) -> Windows100ms<Vec<Power>> {
+        let n = duration_to_windows(duration).min(self.recent.len());
+        let skip = self.recent.len() - n;
+        Windows100ms { inner: self.recent.iter().skip(skip).cloned().collect() }
+    }
+
+    /// Return the gated mean power over the last `duration`.
+    ///
+    /// This is the integrated loudness of only the most recent audio, see
+    /// `gated_mean`. Returns `None` when no window passes the gate.
+    pub fn integrated_last(&self, duration: Duration) -> Option<Power> {
+        crate::gated_mean(self.windows_last(duration).as_ref())
+    }
+
+    /// Return the loudness range in LU over the last `duration`.
+    ///
+    /// This is computed exactly, see `lra::loudness_range`. Returns `None`
+    /// when no short-term value passes the gates, which includes the case of
+    /// less than 3 seconds of audio.
+    pub fn lra_last(&self, duration: Duration) -> Option<f32> {
+        crate::lra::loudness_range(self.windows_last(duration).as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::RollingMeter;
+    use crate::{Power, Windows100ms};
+
+    fn push_lkfs(meter: &mut RollingMeter, lkfs: f32, num_windows: usize) {
+        let windows = vec![Power::from_lkfs(lkfs); num_windows];
+        meter.push(Windows100ms { inner: &windows[..] });
+    }
+
+    #[test]
+    fn rolling_meter_discards_oldest_windows() {
+        let mut meter = RollingMeter::new(Duration::from_secs(10));
+        push_lkfs(&mut meter, -10.0, 50);
+        assert_eq!(meter.retained_duration(), Duration::from_secs(5));
+        push_lkfs(&mut meter, -30.0, 80);
+        assert_eq!(meter.retained_duration(), Duration::from_secs(10));
+
+        // 2 seconds of the loud audio are still retained.
+        let windows = meter.windows_last(Duration::from_secs(60));
+        assert_eq!(windows.len(), 100);
+        assert_eq!(windows.inner[19], Power::from_lkfs(-10.0));
+        assert_eq!(windows.inner[20], Power::from_lkfs(-30.0));
+
+        // A batch longer than the meter retains only its tail.
+        let mut batch = vec![Power::from_lkfs(-10.0); 150];
+        batch[149] = Power::from_lkfs(-20.0);
+        meter.push(Windows100ms { inner: &batch[..] });
+        let windows = meter.windows_last(Duration::from_millis(250));
+        assert_eq!(windows.inner, vec![Power::from_lkfs(-10.0), Power::from_lkfs(-20.0)]);
+    }
+
+    #[test]
+    fn rolling_meter_measures_trailing_duration() {
+        let mut meter = RollingMeter::new(Duration::from_secs(600));
+        push_lkfs(&mut meter, -30.0, 3000);
+        push_lkfs(&mut meter, -20.0, 600);
+
+        let last_minute = meter.integrated_last(Duration::from_secs(60)).unwrap();
+        assert!((last_minute.loudness_lkfs() - -20.0).abs() < 1e-3);
+        assert!(meter.lra_last(Duration::from_secs(60)).unwrap() < 1e-3);
+
+        // Over the last 5 minutes, the quiet part is within 20 LU of the mean
+        // of the short-term loudness, so it widens the range.
+        let all = meter.integrated_last(Duration::from_secs(300)).unwrap();
+        assert!(all.loudness_lkfs() < -21.0);
+        let lra = meter.lra_last(Duration::from_secs(300)).unwrap();
+        assert!((lra - 10.0).abs() < 1e-3, "{}", lra);
+    }
+
+    #[test]
+    fn rolling_meter_needs_audio() {
+        let mut meter = RollingMeter::new(Duration::from_secs(60));
+        assert_eq!(meter.integrated_last(Duration::from_secs(10)), None);
+        push_lkfs(&mut meter, -23.0, 20);
+        assert!(meter.integrated_last(Duration::from_secs(10)).is_some());
+        assert_eq!(meter.lra_last(Duration::from_secs(10)), None);
+        meter.clear();
+        assert_eq!(meter.retained_duration(), Duration::from_secs(0));
+    }
+}
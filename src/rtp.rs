@@ -0,0 +1,323 @@
+// BS1770 -- Loudness analysis library conforming to ITU-R BS.1770
+// Copyright 2020 Ruud van Asseldonk
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! Receiving AES67 and other RTP streams of linear PCM audio.
+//!
+//! AES67 transports uncompressed audio over RTP, as 16-bit (L16) or 24-bit
+//! (L24) big-endian samples, interleaved per channel. `RtpDepacketizer`
+//! decodes the payload of such packets into float samples, and `RtpReceiver`
+//! receives the packets from a UDP socket. The stream parameters (encoding,
+//! channel count, sample rate) are not part of the stream itself; they are
+//! usually announced through SDP, and must be provided by the caller.
+//!
+//! ```no_run
+//! # fn main() -> std::io::Result<()> {
+//! use std::net::Ipv4Addr;
+//! use bs1770::rtp::{Encoding, RtpReceiver};
+//!
+//! let group = Ipv4Addr::new(239, 69, 0, 1);
+//! let interface = Ipv4Addr::UNSPECIFIED;
+//! let mut receiver = RtpReceiver::join_multicast_v4(group, 5004, interface, Encoding::L24, 2)?;
+//! let mut meters = vec![bs1770::ChannelLoudnessMeter::new(48_000); 2];
+//!
+//! let mut samples = Vec::new();
+//! loop {
+//!     samples.clear();
+//!     receiver.receive(&mut samples)?;
+//!     for (ch, meter) in meters.iter_mut().enumerate() {
+//!         meter.push(samples.iter().skip(ch).step_by(2).cloned());
+//!     }
+//! }
+//! # }
+//! ```
+//!
+//! This module is only available with the `rtp` feature enabled.
+
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+
+/// The largest gap in RTP timestamps, in frames, that is filled with silence.
+///
+/// A larger gap is treated as a restart of the stream rather than as packet
+/// loss. This is one second at 48 kHz.
+const MAX_GAP_FRAMES: u32 = 48_000;
+
+/// The sample format of the RTP payload.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Encoding {
+    /// 16-bit big-endian signed integers (RFC 3551).
+    L16,
+    /// 24-bit big-endian signed integers (RFC 3190).
+    L24,
+}
+
+impl Encoding {
+    fn bytes_per_sample(self) -> usize {
+        match self {
+            Encoding::L16 => 2,
+            Encoding::L24 => 3,
+        }
+    }
+}
+
+fn invalid_data(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Decodes RTP packets with linear PCM payload into interleaved float samples.
+///
+/// The depacketizer tracks sequence numbers and timestamps. Late and duplicate
+/// packets are dropped, and gaps due to lost packets are filled with silence,
+/// so the meter stays in sync with real time.
+#[derive(Clone, Debug)]
+pub struct RtpDepacketizer {
+    encoding: Encoding,
+    channels: u32,
+
+    /// The sequence number and timestamp that we expect for the next packet.
+    expected: Option<(u16, u32)>,
+
+    /// The number of packets that were lost or dropped so far.
+    num_lost: u64,
+}
+
+impl RtpDepacketizer {
+    /// Construct a depacketizer for a stream with the given format.
+    pub fn new(encoding: Encoding, channels: u32) -> RtpDepacketizer {
+        assert!(channels > 0, "Need at least one channel.");
+        RtpDepacketizer {
+            encoding,
+            channels,
+            expected: None,
+            num_lost: 0,
+        }
+    }
+
+    /// Return the number of packets that were lost, late, or duplicated so far.
+    pub fn num_lost(&self) -> u64 {
+        self.num_lost
+    }
+
+    /// Decode one RTP packet and append its samples to `out`.
+    ///
+    /// Samples are interleaved, and normalized to full scale [-1.0, 1.0].
+    /// Returns an error with kind `InvalidData` if the packet is not a valid
+    /// RTP packet, or if the payload is not a whole number of frames.
+    pub fn decode(&mut self, packet: &[u8], out: &mut Vec<f32>) -> io::Result<()> {
+        let payload = rtp_payload(packet)?;
+        let sequence = u16::from_be_bytes([packet[2], packet[3]]);
+        let timestamp = u32::from_be_bytes([packet[4], packet[5], packet[6], packet[7]]);
+
+        let frame_len = self.encoding.bytes_per_sample() * self.channels as usize;
+        if payload.len() % frame_len != 0 {
+            return Err(invalid_data("RTP payload is not a whole number of frames."))
+        }
+        let num_frames = (payload.len() / frame_len) as u32;
+
+        if let Some((expected_sequence, expected_timestamp)) = self.expected {
+            // Interpret the differences as signed, to handle wraparound.
+            let sequence_gap = sequence.wrapping_sub(expected_sequence) as i16;
+            if sequence_gap < 0 {
+                // A late or duplicate packet; we already moved past it.
+                self.num_lost += 1;
+                return Ok(())
+            }
+            self.num_lost += sequence_gap as u64;
+
+            let gap_frames = timestamp.wrapping_sub(expected_timestamp);
+            if gap_frames > 0 && gap_frames <= MAX_GAP_FRAMES {
+                let num_samples = gap_frames as usize * self.channels as usize;
+                out.extend((0..num_samples).map(|_| 0.0));
+            }
+        }
+
+        self.expected = Some((
+            sequence.wrapping_add(1),
+            timestamp.wrapping_add(num_frames),
+        ));
+
+        match self.encoding {
+            Encoding::L16 => out.extend(payload.chunks(2).map(|b| {
+                i16::from_be_bytes([b[0], b[1]]) as f32 / (1 << 15) as f32
+            })),
+            Encoding::L24 => out.extend(payload.chunks(3).map(|b| {
+                // Put the sample in the high bytes of an i32 to sign-extend.
+                let x = i32::from_be_bytes([b[0], b[1], b[2], 0]);
+                x as f32 / (1_u64 << 31) as f32
+            })),
+        }
+
+        Ok(())
+    }
+}
+
+/// Return the payload of an RTP packet, after validating the header.
+fn rtp_payload(packet: &[u8]) -> io::Result<&[u8]> {
+    // The fixed header is 12 bytes, see section 5.1 of RFC 3550.
+    if packet.len() < 12 {
+        return Err(invalid_data("RTP packet is shorter than the fixed header."))
+    }
+    if packet[0] >> 6 != 2 {
+        return Err(invalid_data("RTP packet does not have version 2."))
+    }
+    let has_padding = packet[0] & 0b0010_0000 != 0;
+    let has_extension = packet[0] & 0b0001_0000 != 0;
+    let csrc_count = (packet[0] & 0b0000_1111) as usize;
+
+    let mut start = 12 + 4 * csrc_count;
+    if has_extension {
+        // The extension header is 4 bytes, the last 2 of which are the length
+        // of the extension in 32-bit words, excluding the header.
+        if packet.len() < start + 4 {
+            return Err(invalid_data("RTP header extension is truncated."))
+        }
+        let len = u16::from_be_bytes([packet[start + 2], packet[start + 3]]) as usize;
+        start += 4 + 4 * len;
+    }
+
+    let mut end = packet.len();
+    if has_padding {
+        // The last byte contains the number of padding bytes, including itself.
+        let padding = packet[end - 1] as usize;
+        if padding > end {
+            return Err(invalid_data("RTP padding exceeds the packet length."))
+        }
+        end -= padding;
+    }
+
+    if start > end {
+        return Err(invalid_data("RTP packet is truncated."))
+    }
+
+    Ok(&packet[start..end])
+}
+
+/// Receives an RTP stream of linear PCM audio from a UDP socket.
+pub struct RtpReceiver {
+    socket: UdpSocket,
+    depacketizer: RtpDepacketizer,
+    buffer: Vec<u8>,
+}
+
+impl RtpReceiver {
+    /// Receive a unicast stream on the given local address.
+    pub fn bind(addr: SocketAddr, encoding: Encoding, channels: u32) -> io::Result<RtpReceiver> {
+        let socket = UdpSocket::bind(addr)?;
+        Ok(RtpReceiver::from_socket(socket, encoding, channels))
+    }
+
+    /// Receive a multicast stream, as is usual for AES67.
+    ///
+    /// Joins `group` on the interface with address `interface`, pass
+    /// `Ipv4Addr::UNSPECIFIED` to let the operating system choose.
+    pub fn join_multicast_v4(
+        group: Ipv4Addr,
+        port: u16,
+        interface: Ipv4Addr,
+        encoding: Encoding,
+        channels: u32,
+    ) -> io::Result<RtpReceiver> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port))?;
+        socket.join_multicast_v4(&group, &interface)?;
+        Ok(RtpReceiver::from_socket(socket, encoding, channels))
+    }
+
+    /// Receive from a socket that was set up by the caller.
+    pub fn from_socket(socket: UdpSocket, encoding: Encoding, channels: u32) -> RtpReceiver {
+        RtpReceiver {
+            socket,
+            depacketizer: RtpDepacketizer::new(encoding, channels),
+            // Larger than any packet that fits in a standard Ethernet frame.
+            buffer: vec![0; 2048],
+        }
+    }
+
+    /// Return the underlying socket, for example to set a read timeout.
+    pub fn socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    /// Return the depacketizer, for example to inspect packet loss.
+    pub fn depacketizer(&self) -> &RtpDepacketizer {
+        &self.depacketizer
+    }
+
+    /// Wait for the next valid packet, and append its samples to `out`.
+    ///
+    /// Packets that are not valid RTP packets, or that do not match the
+    /// stream format, are skipped.
+    pub fn receive(&mut self, out: &mut Vec<f32>) -> io::Result<()> {
+        loop {
+            let len = self.socket.recv(&mut self.buffer)?;
+            match self.depacketizer.decode(&self.buffer[..len], out) {
+                Ok(()) => return Ok(()),
+                Err(ref e) if e.kind() == io::ErrorKind::InvalidData => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Encoding, RtpDepacketizer};
+
+    fn packet(sequence: u16, timestamp: u32, payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x80, 97];
+        packet.extend_from_slice(&sequence.to_be_bytes());
+        packet.extend_from_slice(&timestamp.to_be_bytes());
+        packet.extend_from_slice(&[0, 0, 0, 1]);
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    #[test]
+    fn decode_l16_and_l24() {
+        let mut out = Vec::new();
+        let mut l16 = RtpDepacketizer::new(Encoding::L16, 2);
+        l16.decode(&packet(0, 0, &[0x40, 0x00, 0xc0, 0x00]), &mut out).unwrap();
+        assert_eq!(out, vec![0.5, -0.5]);
+
+        out.clear();
+        let mut l24 = RtpDepacketizer::new(Encoding::L24, 1);
+        l24.decode(&packet(0, 0, &[0x40, 0x00, 0x00, 0xff, 0xff, 0xff]), &mut out).unwrap();
+        assert_eq!(out[0], 0.5);
+        assert!(out[1] < 0.0 && out[1] > -1e-6);
+    }
+
+    #[test]
+    fn decode_rejects_invalid_packets() {
+        let mut out = Vec::new();
+        let mut depacketizer = RtpDepacketizer::new(Encoding::L24, 2);
+        // Too short.
+        assert!(depacketizer.decode(&[0x80, 97, 0, 0], &mut out).is_err());
+        // Not a whole number of frames.
+        assert!(depacketizer.decode(&packet(0, 0, &[0; 5]), &mut out).is_err());
+        // Version 1.
+        let mut p = packet(0, 0, &[0; 6]);
+        p[0] = 0x40;
+        assert!(depacketizer.decode(&p, &mut out).is_err());
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn decode_fills_lost_packets_with_silence_and_drops_late_ones() {
+        let mut out = Vec::new();
+        let mut depacketizer = RtpDepacketizer::new(Encoding::L16, 1);
+        let payload = [0x40, 0x00, 0x40, 0x00];
+        depacketizer.decode(&packet(65_535, 100, &payload), &mut out).unwrap();
+        // Packet 0 is lost; sequence numbers wrap around.
+        depacketizer.decode(&packet(1, 104, &payload), &mut out).unwrap();
+        assert_eq!(out, vec![0.5, 0.5, 0.0, 0.0, 0.5, 0.5]);
+        assert_eq!(depacketizer.num_lost(), 1);
+
+        // Packet 0 arrives late, it should be dropped.
+        depacketizer.decode(&packet(0, 102, &payload), &mut out).unwrap();
+        assert_eq!(out.len(), 6);
+        assert_eq!(depacketizer.num_lost(), 2);
+    }
+}
@@ -0,0 +1,128 @@
+// BS1770 -- Loudness analysis library conforming to ITU-R BS.1770
+// Copyright 2020 Ruud van Asseldonk
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! Filtering two channels at once, for `push_stereo`.
+//!
+//! The filters are a chain of two biquads, and every output sample depends on
+//! the previous one, so the filter of a single channel cannot be vectorized
+//! over time. The filters of two channels are independent though, so we can
+//! run them side by side in the two `f64` lanes of an SSE2 register. SSE2 is
+//! part of the x86_64 baseline, so this needs no runtime feature detection.
+//!
+//! The vector code performs the same operations in the same order as
+//! `Filter::apply`, so it produces bit-identical results.
+
+use crate::ChannelLoudnessMeter;
+
+/// Filter the samples of two channels, and add their squares to the window sums.
+///
+/// The window bookkeeping is left to the caller; the samples must not extend
+/// past the end of the current window.
+#[cfg(target_arch = "x86_64")]
+pub fn filter_pair(
+    left_meter: &mut ChannelLoudnessMeter,
+    right_meter: &mut ChannelLoudnessMeter,
+    left: &[f32],
+    right: &[f32],
+) {
+    use core::arch::x86_64::*;
+
+    use crate::Filter;
+
+    /// The coefficients and state of two filters, one per lane.
+    struct FilterPair {
+        a1: __m128d,
+        a2: __m128d,
+        b0: __m128d,
+        b1: __m128d,
+        b2: __m128d,
+        x1: __m128d,
+        x2: __m128d,
+        y1: __m128d,
+        y2: __m128d,
+    }
+
+    // Safety of the intrinsics below: SSE2 is always available on x86_64.
+    // Lane 0 holds the left channel, lane 1 the right channel.
+    let load = |l: &Filter, r: &Filter| unsafe {
+        FilterPair {
+            a1: _mm_set_pd(r.a1, l.a1),
+            a2: _mm_set_pd(r.a2, l.a2),
+            b0: _mm_set_pd(r.b0, l.b0),
+            b1: _mm_set_pd(r.b1, l.b1),
+            b2: _mm_set_pd(r.b2, l.b2),
+            x1: _mm_set_pd(r.x1, l.x1),
+            x2: _mm_set_pd(r.x2, l.x2),
+            y1: _mm_set_pd(r.y1, l.y1),
+            y2: _mm_set_pd(r.y2, l.y2),
+        }
+    };
+    let unpack = |v: __m128d| -> (f64, f64) {
+        let mut lanes = [0.0; 2];
+        unsafe { _mm_storeu_pd(lanes.as_mut_ptr(), v) };
+        (lanes[0], lanes[1])
+    };
+    let store = |f: &FilterPair, l: &mut Filter, r: &mut Filter| {
+        let (l_x1, r_x1) = unpack(f.x1);
+        let (l_x2, r_x2) = unpack(f.x2);
+        let (l_y1, r_y1) = unpack(f.y1);
+        let (l_y2, r_y2) = unpack(f.y2);
+        l.x1 = l_x1; l.x2 = l_x2; l.y1 = l_y1; l.y2 = l_y2;
+        r.x1 = r_x1; r.x2 = r_x2; r.y1 = r_y1; r.y2 = r_y2;
+    };
+
+    #[inline(always)]
+    unsafe fn apply(f: &mut FilterPair, x0: __m128d) -> __m128d {
+        let y0 = _mm_mul_pd(f.b0, x0);
+        let y0 = _mm_add_pd(y0, _mm_mul_pd(f.b1, f.x1));
+        let y0 = _mm_add_pd(y0, _mm_mul_pd(f.b2, f.x2));
+        let y0 = _mm_sub_pd(y0, _mm_mul_pd(f.a1, f.y1));
+        let y0 = _mm_sub_pd(y0, _mm_mul_pd(f.a2, f.y2));
+        f.x2 = f.x1;
+        f.x1 = x0;
+        f.y2 = f.y1;
+        f.y1 = y0;
+        y0
+    }
+
+    let mut stage1 = load(&left_meter.filter_stage1, &right_meter.filter_stage1);
+    let mut stage2 = load(&left_meter.filter_stage2, &right_meter.filter_stage2);
+
+    for (&xl, &xr) in left.iter().zip(right) {
+        let z = unsafe {
+            let x = _mm_set_pd(xr as f64, xl as f64);
+            let y = apply(&mut stage1, x);
+            apply(&mut stage2, y)
+        };
+        let (zl, zr) = unpack(z);
+        left_meter.square_sum.add((zl * zl) as f32);
+        right_meter.square_sum.add((zr * zr) as f32);
+    }
+
+    store(&stage1, &mut left_meter.filter_stage1, &mut right_meter.filter_stage1);
+    store(&stage2, &mut left_meter.filter_stage2, &mut right_meter.filter_stage2);
+}
+
+/// Filter the samples of two channels, and add their squares to the window sums.
+///
+/// This is the scalar fallback for platforms without a vector implementation.
+#[cfg(not(target_arch = "x86_64"))]
+pub fn filter_pair(
+    left_meter: &mut ChannelLoudnessMeter,
+    right_meter: &mut ChannelLoudnessMeter,
+    left: &[f32],
+    right: &[f32],
+) {
+    for (&xl, &xr) in left.iter().zip(right) {
+        let yl = left_meter.filter_stage1.apply(xl as f64);
+        let yr = right_meter.filter_stage1.apply(xr as f64);
+        let zl = left_meter.filter_stage2.apply(yl);
+        let zr = right_meter.filter_stage2.apply(yr);
+        left_meter.square_sum.add((zl * zl) as f32);
+        right_meter.square_sum.add((zr * zr) as f32);
+    }
+}
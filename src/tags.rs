@@ -0,0 +1,221 @@
+// BS1770 -- Loudness analysis library conforming to ITU-R BS.1770
+// Copyright 2020 Ruud van Asseldonk
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! Parsing and formatting of loudness values stored in metadata tags.
+//!
+//! Tagging tools store loudness and gain in a few common textual forms:
+//!
+//!  * Loudness with unit, such as `-9.234 LUFS`, as written by `flacgain`.
+//!  * Gain in decibels, such as `-3.10 dB`, as in the `REPLAYGAIN_*_GAIN` tags.
+//!  * Gain as a signed Q7.8 fixed-point integer, such as `-794`, as in the
+//!    `R128_TRACK_GAIN` and `R128_ALBUM_GAIN` tags of Opus (RFC 7845).
+//!  * The hexadecimal `iTunNORM` comment of iTunes Sound Check.
+//!
+//! For every form there is a `parse_*` function that is lenient about
+//! whitespace, case, an explicit plus sign, and a comma as decimal separator,
+//! and a `format_*` function whose output the corresponding `parse_*` function
+//! accepts. Taggers that share these functions agree on the representation.
+//!
+//! ```
+//! use bs1770::tags;
+//!
+//! assert_eq!(tags::parse_lufs("-9.234 LUFS"), Some(-9.234));
+//! assert_eq!(tags::parse_lufs("-9,234 dB"), Some(-9.234));
+//! assert_eq!(tags::format_lufs(-9.234), "-9.234 LUFS");
+//! assert_eq!(tags::parse_r128_gain("-794"), Some(-794.0 / 256.0));
+//! ```
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use core::str::FromStr;
+
+#[cfg(not(feature = "std"))]
+use crate::math::Float;
+
+/// Strip a case-insensitive suffix, and any whitespace before it.
+fn strip_unit<'a>(value: &'a str, unit: &str) -> Option<&'a str> {
+    let value = value.trim();
+    let split = value.len().checked_sub(unit.len())?;
+    if !value.is_char_boundary(split) || !value[split..].eq_ignore_ascii_case(unit) {
+        return None
+    }
+    Some(value[..split].trim_end())
+}
+
+/// Parse a decimal number, allowing an explicit plus sign and a decimal comma.
+///
+/// Some taggers format numbers with the locale of the user, which may use a
+/// comma as decimal separator. There are no thousands separators in loudness
+/// values, so a single comma is unambiguous.
+fn parse_number(value: &str) -> Option<f32> {
+    let value = value.trim();
+    let value = value.strip_prefix('+').unwrap_or(value);
+    let value = match value.matches(',').count() {
+        0 => value.to_string(),
+        1 if !value.contains('.') => value.replace(',', "."),
+        _ => return None,
+    };
+    f32::from_str(&value).ok().filter(|x| !x.is_nan())
+}
+
+/// Parse a loudness value such as `-9.234 LUFS`.
+///
+/// The units `LUFS` and `LKFS` are accepted, in any case, with or without a
+/// space between the number and the unit. Some taggers write loudness in `LU`
+/// or `dB` instead, which are accepted too. A number without unit is rejected,
+/// because it could as well be a gain.
+pub fn parse_lufs(value: &str) -> Option<f32> {
+    ["LUFS", "LKFS", "LU", "dB"]
+        .iter()
+        .filter_map(|unit| strip_unit(value, unit))
+        .next()
+        .and_then(parse_number)
+}
+
+/// Format a loudness value in LUFS with three decimals, such as `-9.234 LUFS`.
+pub fn format_lufs(lkfs: f32) -> String {
+    format!("{:.3} LUFS", lkfs)
+}
+
+/// Parse a gain such as `-3.10 dB`, as stored in ReplayGain tags.
+///
+/// The unit is case-insensitive, and may be omitted.
+pub fn parse_db(value: &str) -> Option<f32> {
+    match strip_unit(value, "dB") {
+        Some(num) => parse_number(num),
+        None => parse_number(value),
+    }
+}
+
+/// Format a gain in decibels with two decimals, such as `-3.10 dB`.
+pub fn format_db(gain_db: f32) -> String {
+    format!("{:.2} dB", gain_db)
+}
+
+/// Parse a gain stored as a Q7.8 fixed-point integer, and return it in dB.
+///
+/// This is the format of the `R128_TRACK_GAIN` and `R128_ALBUM_GAIN` tags in
+/// Ogg Opus files. The integer is the gain in units of 1/256 dB.
+pub fn parse_r128_gain(value: &str) -> Option<f32> {
+    let value = value.trim();
+    let value = value.strip_prefix('+').unwrap_or(value);
+    i16::from_str(value).ok().map(|q| q as f32 / 256.0)
+}
+
+/// Format a gain in dB as a Q7.8 fixed-point integer.
+///
+/// The gain is rounded to the nearest 1/256 dB, and clamped to the range of
+/// the format, about ±128 dB.
+pub fn format_r128_gain(gain_db: f32) -> String {
+    let q = (gain_db * 256.0).round().max(i16::MIN as f32).min(i16::MAX as f32);
+    format!("{}", q as i16)
+}
+
+/// Parse an iTunes Sound Check `iTunNORM` value, and return the gain in dB.
+///
+/// The value consists of ten hexadecimal numbers. The first two hold the
+/// adjustment for the left and right channel, as 1000 times the ratio of the
+/// power of the track to the reference power. When the channels differ, this
+/// returns the gain for the louder one, so applying it does not cause clipping
+/// in either channel.
+pub fn parse_itunnorm(value: &str) -> Option<f32> {
+    let mut fields = value.split_whitespace().map(|f| u32::from_str_radix(f, 16));
+    let mut ratios = [0_u32; 2];
+    for ratio in ratios.iter_mut() {
+        *ratio = fields.next()?.ok()?;
+    }
+    // Validate the remaining fields, to reject values that are not iTunNORM.
+    let num_remaining = fields.try_fold(0, |n, f| f.map(|_| n + 1)).ok()?;
+    if num_remaining != 8 {
+        return None
+    }
+    let ratio = ratios[0].max(ratios[1]);
+    if ratio == 0 {
+        return None
+    }
+    Some(-10.0 * (ratio as f32 / 1000.0).log10())
+}
+
+/// Format a gain in dB as an iTunes Sound Check `iTunNORM` value.
+///
+/// Only the adjustment fields are meaningful; the fields that iTunes uses for
+/// peak values and other statistics are written as zero, which iTunes accepts.
+pub fn format_itunnorm(gain_db: f32) -> String {
+    let ratio = 10.0_f32.powf(-gain_db * 0.1);
+    let to_field = |scale: f32| (ratio * scale).round().max(0.0).min(u32::MAX as f32) as u32;
+    let a = to_field(1000.0);
+    let b = to_field(2500.0);
+    format!(
+        " {:08X} {:08X} {:08X} {:08X} {:08X} {:08X} {:08X} {:08X} {:08X} {:08X}",
+        a, a, b, b, 0, 0, 0, 0, 0, 0,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_lufs_is_lenient() {
+        assert_eq!(parse_lufs("-9.234 LUFS"), Some(-9.234));
+        assert_eq!(parse_lufs(" -9.234lufs "), Some(-9.234));
+        assert_eq!(parse_lufs("+1.5 LKFS"), Some(1.5));
+        assert_eq!(parse_lufs("-inf LUFS"), Some(f32::NEG_INFINITY));
+        assert_eq!(parse_lufs("-9.234 dB"), Some(-9.234));
+        assert_eq!(parse_lufs("-9.234 lu"), Some(-9.234));
+        assert_eq!(parse_lufs("-9.234"), None);
+        assert_eq!(parse_lufs("-9.234 LUX"), None);
+        assert_eq!(parse_lufs("LUFS"), None);
+        assert_eq!(parse_lufs("NaN LUFS"), None);
+    }
+
+    #[test]
+    fn parse_accepts_decimal_comma() {
+        assert_eq!(parse_lufs("-9,234 LUFS"), Some(-9.234));
+        assert_eq!(parse_db("+2,5 dB"), Some(2.5));
+        assert_eq!(parse_db("-1,000.5 dB"), None);
+        assert_eq!(parse_db("1,2,3"), None);
+    }
+
+    #[test]
+    fn parse_db_accepts_replaygain_values() {
+        assert_eq!(parse_db("-3.10 dB"), Some(-3.1));
+        assert_eq!(parse_db("+2 DB"), Some(2.0));
+        assert_eq!(parse_db("0.5"), Some(0.5));
+        assert_eq!(parse_db("loud"), None);
+    }
+
+    #[test]
+    fn formatted_values_round_trip() {
+        for &x in &[-23.0, -9.234, 0.0, 5.75, -31.5] {
+            assert_eq!(parse_lufs(&format_lufs(x)), Some(x));
+            let db = parse_db(&format_db(x)).unwrap();
+            assert!((db - x).abs() <= 0.005, "{} became {}", x, db);
+            let r128 = parse_r128_gain(&format_r128_gain(x)).unwrap();
+            assert!((r128 - x).abs() <= 0.5 / 256.0, "{} became {}", x, r128);
+            let itunnorm = parse_itunnorm(&format_itunnorm(x)).unwrap();
+            assert!((itunnorm - x).abs() < 0.01, "{} became {}", x, itunnorm);
+        }
+    }
+
+    #[test]
+    fn r128_gain_is_clamped_and_rejects_out_of_range() {
+        assert_eq!(format_r128_gain(-200.0), "-32768");
+        assert_eq!(format_r128_gain(-3.1), "-794");
+        assert_eq!(parse_r128_gain("40000"), None);
+        assert_eq!(parse_r128_gain(" +256 "), Some(1.0));
+    }
+
+    #[test]
+    fn parse_itunnorm_uses_louder_channel() {
+        let value = " 000003E8 000007D0 00000000 00000000 00024CA8 00024CA8 00007FFF 00007FFF 00024CA8 00024CA8";
+        let gain = parse_itunnorm(value).unwrap();
+        assert!((gain - -3.0103).abs() < 1e-3);
+        assert_eq!(parse_itunnorm(" 000003E8 000003E8"), None);
+        assert_eq!(parse_itunnorm("not hex at all"), None);
+    }
+}
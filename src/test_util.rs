@@ -0,0 +1,42 @@
+// BS1770 -- Loudness analysis library conforming to ITU-R BS.1770
+// Copyright 2020 Ruud van Asseldonk
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! Fixtures shared by the tests of multiple modules.
+
+use alloc::vec::Vec;
+
+use crate::{ChannelLoudnessMeter, Power, Windows100ms, reduce_surround};
+
+/// Generate a 5.1 signal in WAVE order, with a sine of a different frequency on every channel.
+pub fn surround_channels(num_samples: usize) -> Vec<Vec<f32>> {
+    (0..6)
+        .map(|ch| (0..num_samples).map(|i| 0.1 * (i as f32 * 0.01 * (ch + 1) as f32).sin()).collect())
+        .collect()
+}
+
+/// Measure every channel of `surround_channels` with its own meter, and combine them.
+pub fn measure_surround_separately(sample_rate_hz: u32, channels: &[Vec<f32>]) -> Windows100ms<Vec<Power>> {
+    let meters: Vec<_> = channels
+        .iter()
+        .map(|ch| {
+            let mut meter = ChannelLoudnessMeter::new(sample_rate_hz);
+            meter.push(ch.iter().cloned());
+            meter
+        })
+        .collect();
+    // WAVE order is L, R, C, LFE, Ls, Rs.
+    let w: Vec<_> = meters.iter().map(|m| m.as_100ms_windows()).collect();
+    reduce_surround(w[0], w[1], w[2], w[4], w[5])
+}
+
+/// Assert that two series of windows are equal up to rounding errors.
+pub fn assert_windows_close(actual: &[Power], expected: &[Power]) {
+    assert_eq!(actual.len(), expected.len());
+    for (p, q) in actual.iter().zip(expected) {
+        assert!((p.0 - q.0).abs() < 1e-6 * q.0, "{:?} != {:?}", p, q);
+    }
+}
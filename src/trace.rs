@@ -0,0 +1,111 @@
+// BS1770 -- Loudness analysis library conforming to ITU-R BS.1770
+// Copyright 2020 Ruud van Asseldonk
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! Loudness over time, for plotting a loudness profile.
+//!
+//! A trace has a reading for every 100ms window, with the momentary (400ms)
+//! and short-term (3s) loudness at the end of that window, like a live meter
+//! would show them, see `ballistics::readings`. This can be plotted to see how
+//! the loudness of a program evolves, or searched for sections that are too
+//! loud, such as a shouting guest in a podcast episode.
+//!
+//! ```
+//! # use bs1770::{Power, Windows100ms};
+//! use bs1770::ballistics::{self, Reading};
+//! use bs1770::trace::write_csv;
+//!
+//! let windows = Windows100ms { inner: vec![Power::from_lkfs(-23.0); 50] };
+//! let trace: Vec<Reading> = ballistics::readings(windows.as_ref()).collect();
+//! assert_eq!(trace.len(), 50);
+//!
+//! let mut csv = Vec::new();
+//! write_csv(&mut csv, &trace).unwrap();
+//! let csv = String::from_utf8(csv).unwrap();
+//! assert_eq!(csv.lines().nth(30), Some("3.0,-23.000,-23.000"));
+//! ```
+
+use std::io;
+use std::io::Write;
+
+use crate::Power;
+use crate::ballistics::Reading;
+
+/// Format loudness with three decimals, or as `inf_value` for silence.
+fn format_lkfs(power: Power, inf_value: &str) -> String {
+    let lkfs = power.loudness_lkfs();
+    if lkfs.is_finite() {
+        format!("{:.3}", lkfs)
+    } else {
+        inf_value.to_string()
+    }
+}
+
+/// Write the trace as CSV, with a header and one line per reading.
+///
+/// The columns are the time in seconds, the momentary loudness, and the
+/// short-term loudness, in LKFS. Loudness that is not available yet is an
+/// empty field, silence is `-inf`.
+pub fn write_csv<W: Write>(out: &mut W, trace: &[Reading]) -> io::Result<()> {
+    writeln!(out, "time_s,momentary_lkfs,short_term_lkfs")?;
+    for reading in trace {
+        writeln!(
+            out,
+            "{:.1},{},{}",
+            reading.time.as_secs_f64(),
+            reading.momentary.map_or(String::new(), |p| format_lkfs(p, "-inf")),
+            reading.short_term.map_or(String::new(), |p| format_lkfs(p, "-inf")),
+        )?;
+    }
+    Ok(())
+}
+
+/// Write the trace as a json array of objects.
+///
+/// Every object has the keys `time_s`, `momentary_lkfs`, and
+/// `short_term_lkfs`. Json has no representation for -∞, so both silence and
+/// loudness that is not available yet are `null`.
+pub fn write_json<W: Write>(out: &mut W, trace: &[Reading]) -> io::Result<()> {
+    writeln!(out, "[")?;
+    for (i, reading) in trace.iter().enumerate() {
+        writeln!(
+            out,
+            "  {{\"time_s\": {:.1}, \"momentary_lkfs\": {}, \"short_term_lkfs\": {}}}{}",
+            reading.time.as_secs_f64(),
+            reading.momentary.map_or("null".to_string(), |p| format_lkfs(p, "null")),
+            reading.short_term.map_or("null".to_string(), |p| format_lkfs(p, "null")),
+            if i + 1 < trace.len() { "," } else { "" },
+        )?;
+    }
+    writeln!(out, "]")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_csv, write_json};
+    use crate::ballistics::{Reading, readings};
+    use crate::{Power, Windows100ms};
+
+    #[test]
+    fn write_formats_silence_and_missing_values() {
+        let windows = Windows100ms { inner: vec![Power(0.0); 4] };
+        let trace: Vec<Reading> = readings(windows.as_ref()).collect();
+
+        let mut csv = Vec::new();
+        write_csv(&mut csv, &trace).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().nth(1), Some("0.1,,"));
+        assert_eq!(csv.lines().nth(4), Some("0.4,-inf,"));
+
+        let mut json = Vec::new();
+        write_json(&mut json, &trace[3..]).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert_eq!(
+            json,
+            "[\n  {\"time_s\": 0.4, \"momentary_lkfs\": null, \"short_term_lkfs\": null}\n]\n",
+        );
+    }
+}
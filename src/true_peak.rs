@@ -0,0 +1,410 @@
+// BS1770 -- Loudness analysis library conforming to ITU-R BS.1770
+// Copyright 2020 Ruud van Asseldonk
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! True-peak measurement, as specified in Annex 2 of BS.1770-4.
+//!
+//! The sample peak, the maximum absolute sample value, underestimates the peak
+//! of the reconstructed analog signal, because the signal can peak between two
+//! samples. For example, a sine wave at a quarter of the sample rate, sampled
+//! at 45° and 135° of its phase, has samples of at most 0.71 times its true
+//! amplitude. The true peak is estimated by oversampling the signal 4×, and
+//! taking the maximum absolute value of the oversampled signal.
+//!
+//! Delivery specifications sometimes require a different factor, such as 8×,
+//! and at high sample rates 2× can be enough. `TruePeakMeter::with_oversampling`
+//! constructs a meter for any factor:
+//!
+//! ```
+//! use bs1770::true_peak::TruePeakMeter;
+//!
+//! let mut meter = TruePeakMeter::with_oversampling(8);
+//! meter.push([0.0, 0.5, -0.5, 0.0].iter().cloned());
+//! assert_eq!(meter.oversampling(), 8);
+//! assert!(meter.peak() > 0.5);
+//! ```
+//!
+//! ```
+//! use bs1770::true_peak::TruePeakMeter;
+//!
+//! // A full-scale sine at a quarter of the sample rate, sampled off its peaks.
+//! let x = 0.5_f32.sqrt();
+//! let samples = (0..4800).map(|i| if i % 4 < 2 { x } else { -x });
+//!
+//! let mut meter = TruePeakMeter::new();
+//! meter.push(samples);
+//! assert!(meter.peak_dbtp() > -0.5);
+//! ```
+//!
+//! For multichannel audio, `ChannelPeaks` collects the peak of every channel,
+//! and the maximum over all channels, which is the peak of the program:
+//!
+//! ```
+//! use bs1770::true_peak::{ChannelPeaks, TruePeakMeter};
+//!
+//! let mut meters = vec![TruePeakMeter::new(), TruePeakMeter::new()];
+//! meters[0].push([0.0, 0.25, 0.0].iter().cloned());
+//! meters[1].push([0.0, -0.5, 0.0].iter().cloned());
+//!
+//! let peaks = ChannelPeaks::from_meters(&meters);
+//! assert_eq!(peaks.channels.len(), 2);
+//! assert_eq!(peaks.max, peaks.channels[1]);
+//! assert_eq!(peaks.max.linear(), meters[1].peak());
+//! ```
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::f64::consts::PI;
+use core::iter::FromIterator;
+
+#[cfg(not(feature = "std"))]
+use crate::math::Float;
+
+/// The number of taps of every phase of the interpolation filter.
+const TAPS: usize = 12;
+
+/// Coefficients of the 4× oversampling interpolation filter, per phase.
+///
+/// These are the 48 coefficients of the FIR filter in Annex 2 of BS.1770-4,
+/// split into the 4 phases of a polyphase implementation. They are exact
+/// binary fractions, so they are also exact as `f32`.
+const PHASES: [[f64; TAPS]; 4] = [
+    [
+        0.001708984375, 0.010986328125, -0.0196533203125,
+        0.033203125, -0.0594482421875, 0.1373291015625,
+        0.97216796875, -0.102294921875, 0.047607421875,
+        -0.026611328125, 0.014892578125, -0.00830078125,
+    ],
+    [
+        -0.0291748046875, 0.029296875, -0.0517578125,
+        0.089111328125, -0.16650390625, 0.465087890625,
+        0.77978515625, -0.2003173828125, 0.1015625,
+        -0.0582275390625, 0.0330810546875, -0.0189208984375,
+    ],
+    [
+        -0.0189208984375, 0.0330810546875, -0.0582275390625,
+        0.1015625, -0.2003173828125, 0.77978515625,
+        0.465087890625, -0.16650390625, 0.089111328125,
+        -0.0517578125, 0.029296875, -0.0291748046875,
+    ],
+    [
+        -0.00830078125, 0.014892578125, -0.026611328125,
+        0.047607421875, -0.102294921875, 0.97216796875,
+        0.1373291015625, -0.0594482421875, 0.033203125,
+        -0.0196533203125, 0.010986328125, 0.001708984375,
+    ],
+];
+
+/// Measures the true peak of a single channel of audio.
+///
+/// Like `ChannelLoudnessMeter`, construct one meter per channel, and feed it
+/// the samples of that channel, normalized to the range [-1.0, 1.0]. The meter
+/// can be fed the same samples as the loudness meter.
+///
+/// BS.1770-4 specifies 4× oversampling for a sample rate of 48 kHz. The
+/// meter that `new` constructs oversamples 4× at every sample rate, which at
+/// higher sample rates is more than needed, but not less accurate. Use
+/// `with_oversampling` for a different factor.
+#[derive(Clone)]
+pub struct TruePeakMeter {
+    /// The most recent samples, `history[0]` is the most recent one.
+    history: [f32; TAPS],
+
+    /// The interpolation filter, one set of coefficients per output sample.
+    phases: Vec<[f32; TAPS]>,
+
+    /// The maximum absolute value of the oversampled signal so far.
+    peak: f32,
+}
+
+impl Default for TruePeakMeter {
+    fn default() -> TruePeakMeter {
+        TruePeakMeter::new()
+    }
+}
+
+impl TruePeakMeter {
+    /// Construct a new meter that oversamples 4× with the filter of BS.1770-4.
+    pub fn new() -> TruePeakMeter {
+        let phases = PHASES
+            .iter()
+            .map(|phase| {
+                let mut coefs = [0.0_f32; TAPS];
+                for (c, &h) in coefs.iter_mut().zip(phase.iter()) {
+                    *c = h as f32;
+                }
+                coefs
+            })
+            .collect();
+        TruePeakMeter {
+            history: [0.0; TAPS],
+            phases,
+            peak: 0.0,
+        }
+    }
+
+    /// Construct a meter that oversamples by `factor`, such as 2, 4, or 8.
+    ///
+    /// For a factor of 4 this is the same as `new`, with the filter of
+    /// BS.1770-4. For other factors, the interpolation filter is a
+    /// Hann-windowed sinc with the same number of taps per phase, designed
+    /// here. A factor of 1 measures the sample peak.
+    ///
+    /// Panics if `factor` is zero.
+    pub fn with_oversampling(factor: u32) -> TruePeakMeter {
+        assert!(factor > 0, "Oversampling factor must be at least 1.");
+        if factor == 4 {
+            return TruePeakMeter::new()
+        }
+
+        // The filter has `TAPS` taps per phase, and is centered on a tap of
+        // phase 0, so that phase reproduces the input samples. The cutoff is at
+        // the Nyquist frequency of the input.
+        let factor = factor as usize;
+        let len = TAPS * factor;
+        let center = (TAPS / 2 * factor) as f64;
+        let mut phases = vec![[0.0_f32; TAPS]; factor];
+        for (p, phase) in phases.iter_mut().enumerate() {
+            let mut coefs = [0.0_f64; TAPS];
+            for (j, c) in coefs.iter_mut().enumerate() {
+                let n = (p + factor * j) as f64;
+                let t = (n - center) / factor as f64;
+                let sinc = if t == 0.0 { 1.0 } else { (PI * t).sin() / (PI * t) };
+                let window = 0.5 - 0.5 * (2.0 * PI * n / len as f64).cos();
+                *c = sinc * window;
+            }
+            // Normalize every phase to unit gain at DC, so a constant signal
+            // has the same peak at every phase.
+            let sum: f64 = coefs.iter().sum();
+            for (dst, c) in phase.iter_mut().zip(coefs.iter()) {
+                *dst = (c / sum) as f32;
+            }
+        }
+
+        TruePeakMeter {
+            history: [0.0; TAPS],
+            phases,
+            peak: 0.0,
+        }
+    }
+
+    /// Return the oversampling factor.
+    pub fn oversampling(&self) -> u32 {
+        self.phases.len() as u32
+    }
+
+    /// Shift in one sample, and return the largest absolute value of the interpolated samples.
+    #[inline(always)]
+    fn step(phases: &[[f32; TAPS]], history: &mut [f32; TAPS], sample: f32) -> f32 {
+        for i in (1..TAPS).rev() {
+            history[i] = history[i - 1];
+        }
+        history[0] = sample;
+
+        let mut peak = 0.0_f32;
+        for phase in phases {
+            let y: f32 = phase.iter().zip(history.iter()).map(|(h, x)| h * x).sum();
+            peak = peak.max(y.abs());
+        }
+        peak
+    }
+
+    /// Feed input samples for true-peak measurement.
+    ///
+    /// Samples are expected to be normalized to the range [-1.0, 1.0].
+    pub fn push<I: Iterator<Item = f32>>(&mut self, samples: I) {
+        let mut peak = self.peak;
+        for sample in samples {
+            peak = peak.max(TruePeakMeter::step(&self.phases, &mut self.history, sample));
+        }
+        self.peak = peak;
+    }
+
+    /// Return the true peak so far, as a linear amplitude relative to full scale.
+    ///
+    /// The interpolation filter delays the signal by a few samples. To account
+    /// for the most recent samples, this includes the response of the filter
+    /// to silence after the last sample.
+    pub fn peak(&self) -> f32 {
+        let mut history = self.history;
+        let mut peak = self.peak;
+        for _ in 1..TAPS {
+            peak = peak.max(TruePeakMeter::step(&self.phases, &mut history, 0.0));
+        }
+        peak
+    }
+
+    /// Return the true peak so far, in dB relative to full scale (dBTP).
+    ///
+    /// Returns -∞ if all samples were zero.
+    pub fn peak_dbtp(&self) -> f32 {
+        20.0 * self.peak().log10()
+    }
+}
+
+/// A peak level, as a linear amplitude relative to full scale.
+///
+/// This can hold a true peak as measured by `TruePeakMeter`, or a sample peak
+/// as returned by `sample_peak`.
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct Peak(pub f32);
+
+impl Peak {
+    /// Return the peak as a linear amplitude, where 1.0 is full scale.
+    ///
+    /// This is the form of the `REPLAYGAIN_*_PEAK` tags.
+    pub fn linear(&self) -> f32 {
+        self.0
+    }
+
+    /// Return the peak in dB relative to full scale (dBTP for a true peak).
+    ///
+    /// Returns -∞ for a peak of zero.
+    pub fn dbtp(&self) -> f32 {
+        20.0 * self.0.log10()
+    }
+}
+
+/// Return the sample peak, the maximum absolute sample value.
+pub fn sample_peak<I: Iterator<Item = f32>>(samples: I) -> Peak {
+    Peak(samples.fold(0.0_f32, |peak, x| peak.max(x.abs())))
+}
+
+/// The peaks of every channel, and the maximum over all channels.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChannelPeaks {
+    /// The peak of every channel, in the order of the channels.
+    pub channels: Vec<Peak>,
+
+    /// The largest of the channel peaks, or zero if there are no channels.
+    pub max: Peak,
+}
+
+impl ChannelPeaks {
+    /// Collect the true peaks of meters that measured one channel each.
+    pub fn from_meters(meters: &[TruePeakMeter]) -> ChannelPeaks {
+        meters.iter().map(|meter| Peak(meter.peak())).collect()
+    }
+}
+
+impl FromIterator<Peak> for ChannelPeaks {
+    fn from_iter<I: IntoIterator<Item = Peak>>(iter: I) -> ChannelPeaks {
+        let channels: Vec<Peak> = iter.into_iter().collect();
+        let max = channels.iter().fold(0.0_f32, |max, peak| max.max(peak.0));
+        ChannelPeaks {
+            channels,
+            max: Peak(max),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChannelPeaks, Peak, TruePeakMeter, sample_peak};
+
+    fn sine(amplitude: f32, frequency_hz: f32, phase: f32, num_samples: usize) -> Vec<f32> {
+        (0..num_samples)
+            .map(|i| {
+                let t = i as f32 / 48_000.0;
+                amplitude * (2.0 * std::f32::consts::PI * frequency_hz * t + phase).sin()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn true_peak_finds_inter_sample_peaks() {
+        // A sine at 12 kHz, sampled at 45° of its phase, has a sample peak of
+        // -3.01 dBFS, but a true peak of 0 dBTP.
+        let samples = sine(1.0, 12_000.0, 0.25 * std::f32::consts::PI, 4_800);
+        let sample_peak = samples.iter().fold(0.0_f32, |m, x| m.max(x.abs()));
+        assert!((20.0 * sample_peak.log10() - -3.01).abs() < 0.01);
+
+        let mut meter = TruePeakMeter::new();
+        meter.push(samples.iter().cloned());
+        assert!(meter.peak_dbtp().abs() < 0.5, "Unexpected true peak: {}", meter.peak_dbtp());
+    }
+
+    #[test]
+    fn true_peak_matches_sample_peak_for_low_frequencies() {
+        let samples = sine(0.5, 997.0, 0.0, 48_000);
+        let mut meter = TruePeakMeter::new();
+        meter.push(samples.iter().cloned());
+        assert!((meter.peak_dbtp() - -6.02).abs() < 0.1, "Unexpected true peak: {}", meter.peak_dbtp());
+    }
+
+    #[test]
+    fn true_peak_includes_last_sample_and_silence() {
+        let mut meter = TruePeakMeter::new();
+        assert_eq!(meter.peak_dbtp(), f32::NEG_INFINITY);
+        meter.push([0.0, 0.0, 0.9].iter().cloned());
+        assert!(meter.peak() > 0.85);
+    }
+
+    #[test]
+    fn true_peak_with_oversampling_finds_inter_sample_peaks() {
+        // At 45° of its phase, 12 kHz has samples halfway between the peaks.
+        let samples = sine(1.0, 12_000.0, 0.25 * std::f32::consts::PI, 4_800);
+        for &factor in &[2, 4, 8] {
+            let mut meter = TruePeakMeter::with_oversampling(factor);
+            meter.push(samples.iter().cloned());
+            assert_eq!(meter.oversampling(), factor);
+            assert!(meter.peak_dbtp().abs() < 0.5, "Unexpected true peak at {}×: {}", factor, meter.peak_dbtp());
+        }
+    }
+
+    #[test]
+    fn true_peak_with_oversampling_interpolates_accurately() {
+        // A sine at 3 kHz, sampled 11.25° away from its peaks. The 2× and 8×
+        // filters have an output sample exactly at the peak.
+        let samples = sine(1.0, 3_000.0, 0.0625 * std::f32::consts::PI, 4_800);
+        let error = |factor| {
+            let mut meter = TruePeakMeter::with_oversampling(factor);
+            meter.push(samples.iter().cloned());
+            meter.peak_dbtp().abs()
+        };
+        assert!(error(1) > 0.1);
+        assert!(error(2) < 0.01, "Unexpected error at 2×: {}", error(2));
+        assert!(error(8) < 0.01, "Unexpected error at 8×: {}", error(8));
+    }
+
+    #[test]
+    fn true_peak_with_oversampling_one_is_sample_peak() {
+        let samples = sine(0.8, 12_000.0, 0.25 * std::f32::consts::PI, 480);
+        let mut meter = TruePeakMeter::with_oversampling(1);
+        meter.push(samples.iter().cloned());
+        assert!((meter.peak() - sample_peak(samples.iter().cloned()).0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn true_peak_with_oversampling_four_uses_bs1770_filter() {
+        let samples = sine(1.0, 9_000.0, 0.3, 4_800);
+        let mut meter = TruePeakMeter::new();
+        let mut meter_4x = TruePeakMeter::with_oversampling(4);
+        meter.push(samples.iter().cloned());
+        meter_4x.push(samples.iter().cloned());
+        assert_eq!(meter.peak(), meter_4x.peak());
+    }
+
+    #[test]
+    fn channel_peaks_include_max() {
+        let left = [0.1, -0.3, 0.2];
+        let right = [0.0, 0.6, -0.9];
+        let peaks: ChannelPeaks = [&left, &right]
+            .iter()
+            .map(|channel| sample_peak(channel.iter().cloned()))
+            .collect();
+        assert_eq!(peaks.channels, vec![Peak(0.3), Peak(0.9)]);
+        assert_eq!(peaks.max, Peak(0.9));
+        assert_eq!(peaks.max.linear(), 0.9);
+        assert!((peaks.max.dbtp() - -0.915).abs() < 1e-3);
+
+        let empty: ChannelPeaks = Vec::new().into_iter().collect();
+        assert_eq!(empty.max.dbtp(), f32::NEG_INFINITY);
+    }
+}
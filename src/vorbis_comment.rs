@@ -0,0 +1,224 @@
+// BS1770 -- Loudness analysis library conforming to ITU-R BS.1770
+// Copyright 2020 Ruud van Asseldonk
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! Parsing and serializing Vorbis comments (tags).
+//!
+//! Vorbis comments are the tags of FLAC, Ogg Vorbis, and Ogg Opus files. The
+//! comments are stored the same way in all of these: as a vendor string and a
+//! list of `KEY=value` strings, each prefixed with its length. Only the
+//! container differs, a `VORBIS_COMMENT` metadata block in FLAC, and the
+//! comment header packet in Ogg. `Tags` parses and serializes the comments
+//! themselves; `flac_tags` reads and writes them in FLAC files.
+//!
+//! ```
+//! use bs1770::vorbis_comment::Tags;
+//!
+//! let mut tags = Tags::new("bs1770".to_string());
+//! tags.add("TITLE", "Intro");
+//! tags.set("R128_TRACK_GAIN", "-794");
+//!
+//! let block = tags.serialize();
+//! let parsed = Tags::parse(&block).unwrap();
+//! assert_eq!(parsed.get("r128_track_gain").next(), Some("-794"));
+//! assert_eq!(parsed, tags);
+//! ```
+//!
+//! This module is only available with the `vorbis_comment` feature enabled,
+//! which the `flac_tags` feature enables too.
+
+use std::io;
+use std::io::Read;
+
+/// The vendor string and comments of a `VORBIS_COMMENT` block.
+///
+/// Comments are stored as `KEY=value` strings, in the order of the file.
+/// Keys are compared case-insensitively, as the Vorbis specification requires.
+/// Comments without `=` are preserved when rewriting, but are not returned by
+/// `iter` or `get`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Tags {
+    vendor: String,
+    comments: Vec<String>,
+}
+
+/// Return whether `key` is a valid Vorbis comment field name.
+///
+/// Field names consist of ASCII 0x20 through 0x7d, excluding `=` (0x3d).
+fn is_valid_key(key: &str) -> bool {
+    !key.is_empty() && key.bytes().all(|b| (0x20..=0x7d).contains(&b) && b != b'=')
+}
+
+/// Split a comment into key and value, if it contains a `=`.
+fn split_comment(comment: &str) -> Option<(&str, &str)> {
+    let i = comment.find('=')?;
+    Some((&comment[..i], &comment[i + 1..]))
+}
+
+impl Tags {
+    /// Construct tags without comments, with the given vendor string.
+    pub fn new(vendor: String) -> Tags {
+        Tags { vendor, comments: Vec::new() }
+    }
+
+    /// Return the vendor string, which identifies the encoder.
+    pub fn vendor(&self) -> &str {
+        &self.vendor
+    }
+
+    /// Iterate over all `(key, value)` pairs, in order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.comments.iter().filter_map(|c| split_comment(c))
+    }
+
+    /// Iterate over the values of all comments with the given key.
+    pub fn get<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.iter()
+            .filter(move |&(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v)
+    }
+
+    /// Append a comment. Existing comments with the same key are kept.
+    ///
+    /// Panics if the key is not a valid field name: it must be non-empty
+    /// printable ASCII, without `=`.
+    pub fn add(&mut self, key: &str, value: &str) {
+        assert!(is_valid_key(key), "Invalid Vorbis comment field name: {:?}", key);
+        let mut comment = String::with_capacity(key.len() + value.len() + 1);
+        comment.push_str(key);
+        comment.push('=');
+        comment.push_str(value);
+        self.comments.push(comment);
+    }
+
+    /// Remove all comments with the given key, and return how many were removed.
+    pub fn remove(&mut self, key: &str) -> usize {
+        let len_before = self.comments.len();
+        self.comments.retain(|c| match split_comment(c) {
+            Some((k, _)) => !k.eq_ignore_ascii_case(key),
+            None => true,
+        });
+        len_before - self.comments.len()
+    }
+
+    /// Replace all comments with the given key by a single comment.
+    pub fn set(&mut self, key: &str, value: &str) {
+        self.remove(key);
+        self.add(key, value);
+    }
+
+    /// Parse the body of a `VORBIS_COMMENT` block, excluding the block header.
+    ///
+    /// This is also the layout of the comment header of Ogg Vorbis and Opus
+    /// streams, after the packet type and magic. Bytes after the last comment
+    /// are ignored.
+    pub fn parse(block: &[u8]) -> io::Result<Tags> {
+        let mut cursor = block;
+        let vendor = read_string(&mut cursor)?;
+        let num_comments = read_u32_le(&mut cursor)?;
+        // Do not trust the count for the allocation, every comment takes at
+        // least 4 bytes, which bounds the count by the block size.
+        let mut comments = Vec::with_capacity((num_comments as usize).min(cursor.len() / 4));
+        for _ in 0..num_comments {
+            comments.push(read_string(&mut cursor)?);
+        }
+        Ok(Tags { vendor, comments })
+    }
+
+    /// Serialize the tags as the body of a `VORBIS_COMMENT` block.
+    ///
+    /// For an Ogg Vorbis comment header, the caller must add the packet type
+    /// and magic before, and the framing bit after the result.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut block = Vec::new();
+        // The block starts with the length-prefixed vendor string as UTF-8.
+        block.extend_from_slice(&(self.vendor.len() as u32).to_le_bytes());
+        block.extend_from_slice(self.vendor.as_bytes());
+        // Then the length-prefixed list of Vorbis comments follows.
+        block.extend_from_slice(&(self.comments.len() as u32).to_le_bytes());
+        for comment in &self.comments {
+            block.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+            block.extend_from_slice(comment.as_bytes());
+        }
+        block
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_u32_le(cursor: &mut &[u8]) -> io::Result<u32> {
+    let mut buf = [0_u8; 4];
+    cursor.read_exact(&mut buf).map_err(|_| {
+        invalid_data("VORBIS_COMMENT block is truncated.".to_string())
+    })?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_string(cursor: &mut &[u8]) -> io::Result<String> {
+    let len = read_u32_le(cursor)? as usize;
+    if len > cursor.len() {
+        return Err(invalid_data("VORBIS_COMMENT block is truncated.".to_string()))
+    }
+    let (bytes, rest) = cursor.split_at(len);
+    *cursor = rest;
+    String::from_utf8(bytes.to_vec()).map_err(|_| {
+        invalid_data("VORBIS_COMMENT block contains invalid UTF-8.".to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Tags;
+
+    #[test]
+    fn tags_keys_are_case_insensitive() {
+        let mut tags = Tags::new("test".to_string());
+        tags.add("Artist", "A");
+        tags.add("ARTIST", "B");
+        tags.add("TITLE", "T=1");
+        assert_eq!(tags.get("artist").collect::<Vec<_>>(), vec!["A", "B"]);
+        assert_eq!(tags.get("title").next(), Some("T=1"));
+        assert_eq!(tags.remove("aRtIsT"), 2);
+        tags.set("title", "U");
+        assert_eq!(tags.iter().collect::<Vec<_>>(), vec![("title", "U")]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn tags_add_rejects_invalid_key() {
+        Tags::new(String::new()).add("A=B", "C");
+    }
+
+    #[test]
+    fn parse_reads_serialized_layout() {
+        let block = b"\x06\0\0\0vendor\x02\0\0\0\x03\0\0\0A=B\x04\0\0\0junk";
+        let tags = Tags::parse(block).unwrap();
+        assert_eq!(tags.vendor(), "vendor");
+        assert_eq!(tags.iter().collect::<Vec<_>>(), vec![("A", "B")]);
+        // The comment without `=` is preserved.
+        assert_eq!(tags.serialize(), &block[..]);
+
+        // Bytes after the last comment, such as the Vorbis framing bit, are ignored.
+        let mut with_framing = block.to_vec();
+        with_framing.push(1);
+        assert_eq!(Tags::parse(&with_framing).unwrap(), tags);
+    }
+
+    #[test]
+    fn parse_rejects_truncated_and_invalid_blocks() {
+        let mut tags = Tags::new("vendor".to_string());
+        tags.add("TITLE", "Test");
+        let block = tags.serialize();
+        for len in 0..block.len() {
+            assert!(Tags::parse(&block[..len]).is_err(), "Accepted {} bytes", len);
+        }
+        // A count of comments that cannot fit in the block.
+        assert!(Tags::parse(b"\0\0\0\0\xff\xff\xff\xff").is_err());
+        assert!(Tags::parse(b"\x01\0\0\0\xff\0\0\0\0").is_err());
+    }
+}
@@ -0,0 +1,110 @@
+// BS1770 -- Loudness analysis library conforming to ITU-R BS.1770
+// Copyright 2020 Ruud van Asseldonk
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// A copy of the License has been included in the root of the repository.
+
+//! WebAssembly bindings for the `bs1770` crate, through wasm-bindgen.
+//!
+//! The Web Audio API delivers audio as one `Float32Array` per channel, for
+//! example in the `process` method of an `AudioWorkletProcessor`, so the meter
+//! accepts one channel at a time. Call `push` for every channel of a block,
+//! in WAVE channel order (L, R, C, LFE, Ls, Rs for 5.1), and query the
+//! loudness at any time. Loudness is in LKFS, and `-Infinity` for silence or
+//! when not enough audio was pushed for the measurement.
+
+use bs1770::channels::{ChannelMap, ChannelOrder};
+use bs1770::multichannel::MultiChannelLoudnessMeter;
+use bs1770::Power;
+use wasm_bindgen::prelude::*;
+
+fn to_lkfs(power: Option<Power>) -> f64 {
+    power.unwrap_or(Power(0.0)).loudness_lkfs() as f64
+}
+
+/// A meter for planar multichannel audio.
+#[wasm_bindgen]
+pub struct LoudnessMeter {
+    meter: MultiChannelLoudnessMeter,
+
+    /// Channels of the current block that were pushed already.
+    pending: Vec<Vec<f32>>,
+}
+
+#[wasm_bindgen]
+impl LoudnessMeter {
+    /// Create a meter for 1 to 8 channels at a sample rate of at least 10 Hz.
+    #[wasm_bindgen(constructor)]
+    pub fn new(sample_rate_hz: u32, channels: u32) -> Result<LoudnessMeter, JsValue> {
+        if sample_rate_hz < 10 {
+            return Err(JsValue::from_str("Sample rate must be at least 10 Hz."))
+        }
+        let channel_map = match ChannelMap::from_order(ChannelOrder::Wave, channels) {
+            Some(map) => map,
+            None => return Err(JsValue::from_str(&format!("Unsupported number of channels: {}.", channels))),
+        };
+        Ok(LoudnessMeter {
+            meter: MultiChannelLoudnessMeter::new(sample_rate_hz, channel_map),
+            pending: Vec::with_capacity(channels as usize),
+        })
+    }
+
+    /// Feed the samples of the next channel of the current block.
+    ///
+    /// After the last channel, the block is measured. All channels of a block
+    /// must have the same length.
+    pub fn push(&mut self, samples: &[f32]) -> Result<(), JsValue> {
+        if let Some(first) = self.pending.first() {
+            if first.len() != samples.len() {
+                return Err(JsValue::from_str("All channels of a block must have the same length."))
+            }
+        }
+        self.pending.push(samples.to_vec());
+        if self.pending.len() == self.meter.channel_map().len() {
+            let channels: Vec<&[f32]> = self.pending.iter().map(|ch| &ch[..]).collect();
+            self.meter.push_planar(&channels);
+            self.pending.clear();
+        }
+        Ok(())
+    }
+
+    /// Feed a block of interleaved samples, such as from a decoded file.
+    #[wasm_bindgen(js_name = pushInterleaved)]
+    pub fn push_interleaved(&mut self, samples: &[f32]) -> Result<(), JsValue> {
+        if !self.pending.is_empty() {
+            return Err(JsValue::from_str("Not all channels of the previous block were pushed."))
+        }
+        if !samples.len().is_multiple_of(self.meter.channel_map().len()) {
+            return Err(JsValue::from_str("Number of samples is not a multiple of the number of channels."))
+        }
+        self.meter.push_interleaved(samples);
+        Ok(())
+    }
+
+    /// Return the momentary loudness (the last 400ms), in LKFS.
+    pub fn momentary(&self) -> f64 {
+        to_lkfs(self.meter.momentary())
+    }
+
+    /// Return the short-term loudness (the last 3s), in LKFS.
+    #[wasm_bindgen(js_name = shortTerm)]
+    pub fn short_term(&self) -> f64 {
+        to_lkfs(self.meter.short_term())
+    }
+
+    /// Return the integrated loudness of the audio so far, in LKFS.
+    pub fn integrated(&self) -> f64 {
+        to_lkfs(self.meter.integrated())
+    }
+
+    /// Return the integrated loudness, and free the meter.
+    ///
+    /// Returns an error if not all channels of the last block were pushed.
+    pub fn finish(self) -> Result<f64, JsValue> {
+        if !self.pending.is_empty() {
+            return Err(JsValue::from_str("Not all channels of the last block were pushed."))
+        }
+        Ok(self.integrated())
+    }
+}